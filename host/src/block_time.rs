@@ -0,0 +1,244 @@
+//! Conversion between block numbers and timestamps, read from the headers of the blocks, and from
+//! timestamps to beacon chain epochs. Put a cache in front of the provider to keep the headers
+//! across runs; within one, [`BlockTimes`] remembers every timestamp it has looked up.
+
+use anyhow::{bail, ensure, Context, Result};
+use risc0_steel::host::provider::Provider;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use tokemak::{chain::ChainHeader, DAY_IN_SECONDS};
+
+/// Seconds per beacon chain slot. Every beacon chain so far has kept the 12 seconds it launched
+/// with; the slot arithmetic here assumes it stays that way, which an upgrade changing the slot
+/// time would break.
+pub const SECONDS_PER_SLOT: u64 = 12;
+pub const SLOTS_PER_EPOCH: u64 = 32;
+/// Epochs behind the current one after which an epoch is finalized, with the chain finalizing
+/// normally.
+pub const FINALITY_EPOCHS: u64 = 2;
+
+/// The slot schedule of a beacon chain, counted from its genesis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BeaconSchedule {
+    pub genesis_timestamp: u64,
+}
+
+impl BeaconSchedule {
+    pub const MAINNET: BeaconSchedule = BeaconSchedule { genesis_timestamp: 1_606_824_023 };
+
+    /// The epoch of the slot at `timestamp`.
+    pub fn epoch_at(&self, timestamp: u64) -> u64 {
+        timestamp.saturating_sub(self.genesis_timestamp) / (SECONDS_PER_SLOT * SLOTS_PER_EPOCH)
+    }
+
+    /// The timestamp of the first slot of `epoch`.
+    pub fn epoch_start(&self, epoch: u64) -> u64 {
+        self.genesis_timestamp + epoch * SECONDS_PER_SLOT * SLOTS_PER_EPOCH
+    }
+
+    pub fn is_epoch_start(&self, timestamp: u64) -> bool {
+        timestamp >= self.genesis_timestamp
+            && self.epoch_start(self.epoch_at(timestamp)) == timestamp
+    }
+}
+
+pub struct BlockTimes<'a, P> {
+    provider: &'a P,
+    timestamps: RefCell<HashMap<u64, u64>>,
+}
+
+impl<'a, P> BlockTimes<'a, P>
+where
+    P: Provider,
+    P::Header: ChainHeader,
+{
+    pub fn new(provider: &'a P) -> Self {
+        BlockTimes { provider, timestamps: RefCell::default() }
+    }
+
+    /// The timestamp of block `number`.
+    pub fn timestamp_at_block(&self, number: u64) -> Result<u64> {
+        if let Some(&timestamp) = self.timestamps.borrow().get(&number) {
+            return Ok(timestamp);
+        }
+        let header = self
+            .provider
+            .get_block_header(number)
+            .with_context(|| format!("could not retrieve block {number}"))?
+            .with_context(|| format!("block at height {number} not found"))?;
+        self.timestamps.borrow_mut().insert(number, header.timestamp());
+
+        Ok(header.timestamp())
+    }
+
+    /// The last block at or before `timestamp`, out of the blocks up to `head`.
+    pub fn block_at_timestamp(&self, timestamp: u64, head: u64) -> Result<u64> {
+        if self.timestamp_at_block(0)? > timestamp {
+            bail!("timestamp {timestamp} is before the first block");
+        }
+
+        if self.timestamp_at_block(head)? <= timestamp {
+            return Ok(head);
+        }
+
+        // timestamps strictly increase with the block number, so the block sought is the one
+        // before the first that is too late
+        let (mut low, mut high) = (0, head);
+        while high - low > 1 {
+            let mid = low + (high - low) / 2;
+            if self.timestamp_at_block(mid)? <= timestamp {
+                low = mid;
+            } else {
+                high = mid;
+            }
+        }
+
+        Ok(low)
+    }
+
+    /// The closing blocks, each the last block before a UTC midnight, of the `days` days up to the
+    /// last midnight at or before block `end` and of the day before them, oldest first.
+    pub fn daily_closes(&self, days: u64, end: u64) -> Result<Vec<u64>> {
+        let last_midnight = self.timestamp_at_block(end)? / DAY_IN_SECONDS * DAY_IN_SECONDS;
+        let closes = (0..=days)
+            .rev()
+            .map(|back| {
+                let midnight = last_midnight.saturating_sub(back * DAY_IN_SECONDS);
+                self.block_at_timestamp(midnight.saturating_sub(1), end)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        // a day without any block closes on the same block as the one before
+        ensure!(
+            closes.windows(2).all(|pair| pair[0] < pair[1]),
+            "no block on some day of the {days} days before block {end}"
+        );
+
+        Ok(closes)
+    }
+
+    /// The first block of the latest epoch that starts at or before block `end` and is finalized as
+    /// of `latest`, the chain head.
+    pub fn finalized_epoch_start(
+        &self,
+        schedule: &BeaconSchedule,
+        end: u64,
+        latest: u64,
+    ) -> Result<u64> {
+        let finalized =
+            schedule.epoch_at(self.timestamp_at_block(latest)?).saturating_sub(FINALITY_EPOCHS);
+        let epoch = schedule.epoch_at(self.timestamp_at_block(end)?).min(finalized);
+
+        self.first_block_of(schedule, epoch, latest)
+    }
+
+    /// The first block of the epoch `epochs` epochs before the one of block `end`, the start of a
+    /// window of whole epochs up to an `end` on an epoch start.
+    pub fn epoch_window_start(
+        &self,
+        schedule: &BeaconSchedule,
+        end: u64,
+        epochs: u64,
+    ) -> Result<u64> {
+        let end_epoch = schedule.epoch_at(self.timestamp_at_block(end)?);
+        let epoch = end_epoch.checked_sub(epochs).with_context(|| {
+            format!("a window of {epochs} epochs to block {end} starts before the beacon chain")
+        })?;
+
+        self.first_block_of(schedule, epoch, end)
+    }
+
+    /// The first block of `epoch`, out of the blocks up to `head`.
+    fn first_block_of(&self, schedule: &BeaconSchedule, epoch: u64, head: u64) -> Result<u64> {
+        // the first slot of the epoch may have been missed, so look for the block after the last
+        // one before it
+        let start = schedule.epoch_start(epoch);
+        if self.timestamp_at_block(0)? >= start {
+            return Ok(0);
+        }
+        Ok(self.block_at_timestamp(start - 1, head)? + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockProvider;
+
+    // the mock chain's blocks are 12 seconds apart from this timestamp at block 0
+    const GENESIS: u64 = 1_700_000_000;
+
+    #[test]
+    fn it_should_convert_between_blocks_and_timestamps() {
+        let provider = MockProvider::with_chain(0, 1000);
+        let times = BlockTimes::new(&provider);
+
+        for (block, timestamp) in [(0, GENESIS), (1, GENESIS + 12), (500, GENESIS + 6000)] {
+            assert_eq!(times.timestamp_at_block(block).unwrap(), timestamp);
+            assert_eq!(times.block_at_timestamp(timestamp, 999).unwrap(), block);
+            // between two blocks, the earlier one
+            assert_eq!(times.block_at_timestamp(timestamp + 11, 999).unwrap(), block);
+        }
+        assert_eq!(times.block_at_timestamp(GENESIS + 1_000_000, 999).unwrap(), 999);
+        assert!(times.block_at_timestamp(GENESIS - 1, 999).is_err());
+    }
+
+    #[test]
+    fn it_should_snap_to_the_start_of_a_finalized_epoch() {
+        // the mock chain's blocks are on the slots of a beacon chain starting with it, 100 epochs
+        let schedule = BeaconSchedule { genesis_timestamp: GENESIS };
+        let provider = MockProvider::with_chain(0, 100 * SLOTS_PER_EPOCH);
+        let times = BlockTimes::new(&provider);
+        let latest = 100 * SLOTS_PER_EPOCH - 1;
+
+        let end = times.finalized_epoch_start(&schedule, 50 * SLOTS_PER_EPOCH + 7, latest).unwrap();
+        assert_eq!(end, 50 * SLOTS_PER_EPOCH);
+        assert!(schedule.is_epoch_start(times.timestamp_at_block(end).unwrap()));
+        // a whole number of epochs back, the window starts on an epoch too
+        let start = times.epoch_window_start(&schedule, end, 21).unwrap();
+        assert_eq!(start, end - 21 * SLOTS_PER_EPOCH);
+        assert!(schedule.is_epoch_start(times.timestamp_at_block(start).unwrap()));
+        assert!(!schedule.is_epoch_start(times.timestamp_at_block(start + 1).unwrap()));
+        let err = times.epoch_window_start(&schedule, end, 51).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("a window of 51 epochs to block {end} starts before the beacon chain")
+        );
+
+        // at the head, the last two epochs aren't final yet
+        let end = times.finalized_epoch_start(&schedule, latest, latest).unwrap();
+        assert_eq!(end, (99 - FINALITY_EPOCHS) * SLOTS_PER_EPOCH);
+        // so does the default window
+        assert_eq!(tokemak::BLOCKS_TO_QUERY % SLOTS_PER_EPOCH, 0);
+    }
+
+    #[test]
+    fn it_should_pick_the_last_block_before_each_midnight() {
+        let provider = MockProvider::with_chain(0, 30_000);
+        let times = BlockTimes::new(&provider);
+
+        // the last midnight is 1_700_352_000, past block 29_333
+        let closes = times.daily_closes(2, 29_999).unwrap();
+        assert_eq!(closes, vec![14_933, 22_133, 29_333]);
+        for close in closes {
+            let day = times.timestamp_at_block(close).unwrap() / DAY_IN_SECONDS;
+            assert!(times.timestamp_at_block(close + 1).unwrap() / DAY_IN_SECONDS > day);
+        }
+        // ending on a close, the window ends on the close of the day before
+        assert_eq!(times.daily_closes(1, 29_333).unwrap(), vec![14_933, 22_133]);
+    }
+
+    #[test]
+    fn it_should_remember_looked_up_timestamps() {
+        let provider = MockProvider::with_chain(0, 1 << 16);
+        let times = BlockTimes::new(&provider);
+
+        times.block_at_timestamp(GENESIS + 12 * 40_000, (1 << 16) - 1).unwrap();
+        // a binary search, plus the bounds
+        let requests = provider.request_count();
+        assert!(requests <= 16 + 2, "{requests} requests");
+
+        // a second search over the same range is served from memory
+        assert_eq!(times.block_at_timestamp(GENESIS + 12 * 40_000, (1 << 16) - 1).unwrap(), 40_000);
+        assert_eq!(provider.request_count(), requests);
+    }
+}
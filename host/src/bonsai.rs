@@ -0,0 +1,113 @@
+//! Proving the guest on the Bonsai proving service, for `--bonsai`. Locally the host only executes
+//! the guest; Bonsai returns a receipt, which is verified against the image ID before its journal
+//! is used, so that a faulty or compromised service can't slip in an unproven result.
+
+use anyhow::{bail, Context, Result};
+use risc0_zkvm::{BonsaiProver, ExecutorEnv, Journal, Prover, Receipt};
+
+/// Environment variables the service is configured with, as read by the risc0 SDK.
+pub const API_URL_VAR: &str = "BONSAI_API_URL";
+pub const API_KEY_VAR: &str = "BONSAI_API_KEY";
+
+/// Fails unless both credentials are set, naming the missing ones, so that a run without them
+/// stops before fetching anything rather than once the input is assembled.
+pub fn check_credentials(var: impl Fn(&str) -> Option<String>) -> Result<()> {
+    let missing: Vec<_> = [API_URL_VAR, API_KEY_VAR]
+        .into_iter()
+        .filter(|name| var(name).map_or(true, |value| value.trim().is_empty()))
+        .collect();
+    if !missing.is_empty() {
+        let verb = if missing.len() == 1 { "is" } else { "are" };
+        bail!(
+            "--bonsai requires the Bonsai API credentials, but {} {verb} not set",
+            missing.join(" and ")
+        );
+    }
+
+    Ok(())
+}
+
+/// The part of a Bonsai client a run uses; stubbed in the tests.
+pub trait BonsaiClient {
+    /// Uploads the guest image and its input, waits for the proving session to finish and returns
+    /// its receipt.
+    fn prove(&self, env: ExecutorEnv<'_>, elf: &[u8]) -> Result<Receipt>;
+}
+
+/// The client of the risc0 SDK, which polls the session until it succeeds or fails.
+pub struct SdkClient(BonsaiProver);
+
+impl Default for SdkClient {
+    fn default() -> Self {
+        SdkClient(BonsaiProver::new("lst-dex-stats"))
+    }
+}
+
+impl BonsaiClient for SdkClient {
+    fn prove(&self, env: ExecutorEnv<'_>, elf: &[u8]) -> Result<Receipt> {
+        self.0.prove(env, elf)
+    }
+}
+
+/// Proves `elf` over `env` with `client` and returns the journal of the receipt, once the receipt
+/// verifies against `image_id`.
+pub fn prove(
+    client: &impl BonsaiClient,
+    env: ExecutorEnv<'_>,
+    elf: &[u8],
+    image_id: [u32; 8],
+) -> Result<Journal> {
+    let receipt = client.prove(env, elf).context("proving on Bonsai failed")?;
+    receipt.verify(image_id).context("the receipt returned by Bonsai does not verify")?;
+
+    Ok(receipt.journal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::cell::Cell;
+
+    /// A service whose sessions all fail, counting the jobs submitted to it.
+    #[derive(Default)]
+    struct FailingClient {
+        submitted: Cell<usize>,
+    }
+
+    impl BonsaiClient for FailingClient {
+        fn prove(&self, _: ExecutorEnv<'_>, _: &[u8]) -> Result<Receipt> {
+            self.submitted.set(self.submitted.get() + 1);
+            Err(anyhow!("session failed: out of cycles"))
+        }
+    }
+
+    #[test]
+    fn it_should_require_both_credentials() {
+        let vars = |set: &'static [&'static str]| {
+            move |name: &str| set.contains(&name).then(|| "value".to_owned())
+        };
+
+        assert!(check_credentials(vars(&[API_URL_VAR, API_KEY_VAR])).is_ok());
+        let err = check_credentials(vars(&[API_URL_VAR])).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--bonsai requires the Bonsai API credentials, but BONSAI_API_KEY is not set"
+        );
+        let err = check_credentials(vars(&[])).unwrap_err();
+        assert!(
+            err.to_string().ends_with("BONSAI_API_URL and BONSAI_API_KEY are not set"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn it_should_surface_a_failed_session() {
+        let client = FailingClient::default();
+        let env = ExecutorEnv::builder().write(&1_u32).unwrap().build().unwrap();
+
+        let err = prove(&client, env, &[], [0; 8]).unwrap_err();
+        assert_eq!(format!("{err:#}"), "proving on Bonsai failed: session failed: out of cycles");
+        assert_eq!(client.submitted.get(), 1);
+    }
+}
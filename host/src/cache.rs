@@ -0,0 +1,736 @@
+//! The response cache. A `CachedProvider` holds the cache file in memory and writes it back when
+//! dropped; [`Cache`] wraps it to store that file in a [`CacheBackend`], by default the filesystem,
+//! and optionally gzip-compressed. Both forms are read, so caches written before compression was
+//! enabled keep working.
+//!
+//! Next to the file, under [`head_key`], the cache keeps the chain head last read through it, which
+//! it serves once the provider can't be reached. An offline run then bounds its searches for blocks
+//! by time by the same head as the run that cached them, and so reads the same blocks.
+
+use alloy_primitives::{Address, Bytes, StorageKey, StorageValue, TxNumber, U256};
+use anyhow::{ensure, Context, Result};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use risc0_steel::host::provider::{CachedProvider, EIP1186Proof, Provider};
+use serde_json::{Map, Value};
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tokemak::chain::ChainHeader;
+
+/// The magic bytes every gzip stream starts with; an uncompressed cache is JSON and never does.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Where a cache is stored, as bytes by key, e.g. a directory, an object store or a key-value
+/// store.
+pub trait CacheBackend {
+    /// The bytes stored at `key`; `None` if nothing is.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<()>;
+
+    /// Discards what is stored at `key`, if anything.
+    fn remove(&self, key: &str) -> Result<()>;
+}
+
+/// Stores each cache in a file, the key being its path.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FsBackend;
+
+impl CacheBackend for FsBackend {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match fs::read(key) {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<()> {
+        Ok(fs::write(key, bytes)?)
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        match fs::remove_file(key) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Where the response cache is stored and in which form.
+#[derive(Debug, Clone)]
+pub struct CacheConfig<B = FsBackend> {
+    pub backend: B,
+    pub key: String,
+    /// Write the cache gzip-compressed.
+    pub compress: bool,
+}
+
+impl<B: CacheBackend + Clone> CacheConfig<B> {
+    /// Puts the cache in front of `provider`.
+    pub fn open<P: Provider>(&self, provider: P) -> Result<Cache<P, B>> {
+        self.open_with(provider, false)
+    }
+
+    /// The cache as stored, serving only what it holds and never written back, e.g. to compare it
+    /// with another one. Fails if there is no cache at the key or it isn't a cache file.
+    pub fn snapshot<H>(&self) -> Result<Cache<Offline<H>, B>>
+    where
+        Offline<H>: Provider,
+    {
+        read_json(&self.backend, &self.key)?
+            .with_context(|| format!("no cache at {}", self.key))?;
+        self.open_with(Offline::default(), true)
+            .with_context(|| format!("cache {} is not a cache file", self.key))
+    }
+
+    fn open_with<P: Provider>(&self, provider: P, read_only: bool) -> Result<Cache<P, B>> {
+        // the cached provider only reads plain files, so it works on a decompressed copy of its own
+        let plain = scratch_path();
+        let bytes = self
+            .backend
+            .get(&self.key)
+            .with_context(|| format!("failed to read cache {}", self.key))?;
+        if let Some(bytes) = bytes {
+            fs::write(&plain, decode(&bytes)?)?;
+        }
+        let inner = CachedProvider::new(plain.clone(), provider)?;
+        let head = self.head()?;
+
+        Ok(Cache {
+            inner: Some(inner),
+            plain,
+            config: self.clone(),
+            head: Cell::new(head),
+            read_only,
+        })
+    }
+
+    /// The chain head last read through the cache, if any was.
+    pub fn head(&self) -> Result<Option<u64>> {
+        let key = head_key(&self.key);
+        let Some(bytes) =
+            self.backend.get(&key).with_context(|| format!("failed to read {key}"))?
+        else {
+            return Ok(None);
+        };
+        let head = String::from_utf8_lossy(&bytes)
+            .trim()
+            .parse()
+            .with_context(|| format!("{key} is not a block number"))?;
+
+        Ok(Some(head))
+    }
+
+    /// Compares the cached headers of the `depth` blocks up to `head`, and of those up to
+    /// `previous`, the head the cache last read, with the ones `live` serves now: a header that
+    /// wasn't final yet when an earlier run cached it may have changed since. Everything cached
+    /// for the blocks whose headers changed, as after a reorg, is stale and discarded, and the
+    /// blocks are returned.
+    pub fn invalidate_reorged<L>(
+        &self,
+        live: &L,
+        previous: Option<u64>,
+        head: u64,
+        depth: u64,
+    ) -> Result<Vec<u64>>
+    where
+        L: Provider,
+        L::Header: ChainHeader,
+        CachedProvider<Offline<L::Header>>: Provider<Header = L::Header>,
+    {
+        let Some(mut json) = read_json(&self.backend, &self.key)? else {
+            return Ok(Vec::new());
+        };
+        let mut blocks: Vec<u64> = [Some(head), previous]
+            .into_iter()
+            .flatten()
+            .flat_map(|head| head.saturating_sub(depth)..=head)
+            .collect();
+        blocks.sort_unstable();
+        blocks.dedup();
+
+        let mut reorged = Vec::new();
+        {
+            let cache = self.snapshot::<L::Header>()?;
+            for number in blocks {
+                let Some(cached) = cached_header(&cache, number)? else {
+                    continue;
+                };
+                let current = live
+                    .get_block_header(number)
+                    .with_context(|| format!("could not retrieve block {number}"))?
+                    .with_context(|| format!("block at height {number} not found"))?;
+                if current.hash() != cached.hash() {
+                    reorged.push(number);
+                }
+            }
+        }
+        if !reorged.is_empty() {
+            discard_blocks(&mut json, &reorged);
+            let plain = serde_json::to_vec(&json)?;
+            let bytes = if self.compress { encode(&plain)? } else { plain };
+            self.backend
+                .put(&self.key, &bytes)
+                .with_context(|| format!("failed to write cache {}", self.key))?;
+        }
+
+        Ok(reorged)
+    }
+
+    /// Compares the headers this cache and `other` hold of `blocks`, e.g. two snapshots taken at
+    /// different times, returning the blocks whose hashes differ, as after a reorg. A block either
+    /// of them lacks isn't compared. Neither cache is written, and both must exist.
+    pub fn diff_headers<H, S>(
+        &self,
+        other: &CacheConfig<S>,
+        blocks: RangeInclusive<u64>,
+    ) -> Result<HeaderDiff>
+    where
+        H: ChainHeader,
+        S: CacheBackend + Clone,
+        CachedProvider<Offline<H>>: Provider<Header = H>,
+    {
+        let ours = self.snapshot::<H>()?;
+        let theirs = other.snapshot::<H>()?;
+        let mut diff = HeaderDiff::default();
+        for number in blocks {
+            let (Some(ours), Some(theirs)) =
+                (cached_header(&ours, number)?, cached_header(&theirs, number)?)
+            else {
+                continue;
+            };
+            diff.compared += 1;
+            if ours.hash() != theirs.hash() {
+                diff.differing.push(number);
+            }
+        }
+
+        Ok(diff)
+    }
+
+    /// Merges the caches of `sources` into this one, which is created if it doesn't exist, e.g. to
+    /// pool what runs on several machines fetched. An entry cached by more than one of them must be
+    /// the same in all; a mismatch, as after a reorg or a corruption, fails the merge before
+    /// anything is written.
+    pub fn merge<S: CacheBackend>(&self, sources: &[CacheConfig<S>]) -> Result<()> {
+        let mut merged = match read_json(&self.backend, &self.key)? {
+            Some(json) => json,
+            None => Value::Object(Map::new()),
+        };
+        for source in sources {
+            let json = read_json(&source.backend, &source.key)?
+                .with_context(|| format!("no cache at {}", source.key))?;
+            merge_json(&mut merged, json, "cache")
+                .with_context(|| format!("failed to merge cache {}", source.key))?;
+        }
+        let plain = serde_json::to_vec(&merged)?;
+        let bytes = if self.compress { encode(&plain)? } else { plain };
+
+        self.backend.put(&self.key, &bytes)
+    }
+}
+
+/// The outcome of [`CacheConfig::diff_headers`].
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct HeaderDiff {
+    /// Number of blocks both caches hold a header of.
+    pub compared: usize,
+    /// The blocks whose headers differ, in ascending order.
+    pub differing: Vec<u64>,
+}
+
+/// The header of block `number` if `cache`, served offline, holds it.
+fn cached_header<C: Provider>(cache: &C, number: u64) -> Result<Option<C::Header>> {
+    match cache.get_block_header(number) {
+        Ok(header) => Ok(header),
+        Err(err) => {
+            let err = anyhow::Error::from(err);
+            // a miss is an error of the offline provider; anything else is a broken cache
+            let missed = err.chain().any(|cause| {
+                cause
+                    .downcast_ref::<io::Error>()
+                    .is_some_and(|err| err.kind() == io::ErrorKind::NotConnected)
+            });
+            if missed {
+                Ok(None)
+            } else {
+                Err(err.context(format!("failed to read the header of block {number}")))
+            }
+        }
+    }
+}
+
+/// The cache file stored at `key` in `backend`, parsed.
+fn read_json(backend: &impl CacheBackend, key: &str) -> Result<Option<Value>> {
+    let Some(bytes) = backend.get(key).with_context(|| format!("failed to read cache {key}"))?
+    else {
+        return Ok(None);
+    };
+    let json = serde_json::from_slice(&decode(&bytes)?)
+        .with_context(|| format!("cache {key} is not a cache file"))?;
+
+    Ok(Some(json))
+}
+
+/// Drops the entries of the cache file `json` that were read at any of `blocks`: those under a
+/// query naming one of them as its block number.
+fn discard_blocks(json: &mut Value, blocks: &[u64]) {
+    let names_block = |query: &Value| match query {
+        Value::Number(number) => number.as_u64().is_some_and(|number| blocks.contains(&number)),
+        Value::Object(fields) => fields
+            .values()
+            .any(|field| field.as_u64().is_some_and(|number| blocks.contains(&number))),
+        _ => false,
+    };
+    let Value::Object(entries) = json else {
+        return;
+    };
+    for entries in entries.values_mut() {
+        if let Value::Array(pairs) = entries {
+            pairs.retain(|pair| {
+                !pair.as_array().and_then(|pair| pair.first()).is_some_and(names_block)
+            });
+        }
+    }
+}
+
+/// Merges the cache file `from` into `into`, at `path` within them. Objects are merged by key, and
+/// so are lists of `[key, value]` pairs, as which maps with keys other than strings are written;
+/// anything else must be equal.
+fn merge_json(into: &mut Value, from: Value, path: &str) -> Result<()> {
+    let is_pair_list = |list: &[Value]| {
+        list.iter().all(|item| item.as_array().is_some_and(|pair| pair.len() == 2))
+    };
+
+    match (into, from) {
+        (Value::Object(into), Value::Object(from)) => {
+            for (key, value) in from {
+                match into.get_mut(&key) {
+                    Some(existing) => merge_json(existing, value, &format!("{path}.{key}"))?,
+                    None => {
+                        into.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(into), Value::Array(from))
+            if is_pair_list(into.as_slice()) && is_pair_list(&from) =>
+        {
+            let mut index: HashMap<String, usize> =
+                into.iter().enumerate().map(|(i, pair)| (pair[0].to_string(), i)).collect();
+            for mut pair in from {
+                let key = pair[0].to_string();
+                match index.get(&key) {
+                    Some(&i) => {
+                        merge_json(&mut into[i][1], pair[1].take(), &format!("{path}[{key}]"))?
+                    }
+                    None => {
+                        index.insert(key, into.len());
+                        into.push(pair);
+                    }
+                }
+            }
+        }
+        (into, from) => ensure!(
+            *into == from,
+            "the caches disagree at {path}, as after a reorg or a corruption"
+        ),
+    }
+
+    Ok(())
+}
+
+/// A `CachedProvider` whose file is written back to the configured backend in the configured form
+/// when it is dropped.
+pub struct Cache<P, B: CacheBackend = FsBackend> {
+    inner: Option<CachedProvider<P>>,
+    /// The decompressed copy the cached provider works on.
+    plain: PathBuf,
+    config: CacheConfig<B>,
+    /// The chain head last read, stored along with the file.
+    head: Cell<Option<u64>>,
+    /// Discard the copy rather than writing it back.
+    read_only: bool,
+}
+
+impl<P, B: CacheBackend> Cache<P, B> {
+    fn inner(&self) -> &CachedProvider<P> {
+        self.inner.as_ref().unwrap()
+    }
+
+    fn persist(&self) -> Result<()> {
+        if self.read_only {
+            return match fs::remove_file(&self.plain) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err.into()),
+                _ => Ok(()),
+            };
+        }
+        if let Some(head) = self.head.get() {
+            let key = head_key(&self.config.key);
+            self.config.backend.put(&key, head.to_string().as_bytes())?;
+        }
+        let plain = match fs::read(&self.plain) {
+            Ok(plain) => plain,
+            // nothing was cached
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
+        fs::remove_file(&self.plain)?;
+        let bytes = if self.config.compress { encode(&plain)? } else { plain };
+
+        self.config.backend.put(&self.config.key, &bytes)
+    }
+}
+
+impl<P, B: CacheBackend> Drop for Cache<P, B> {
+    fn drop(&mut self) {
+        // the cached provider writes the plain copy as it is dropped
+        drop(self.inner.take());
+        if let Err(err) = self.persist() {
+            warning!("failed to write cache {}: {err:#}", self.config.key);
+        }
+    }
+}
+
+impl<P, B: CacheBackend> Provider for Cache<P, B>
+where
+    CachedProvider<P>: Provider,
+{
+    type Error = <CachedProvider<P> as Provider>::Error;
+    type Header = <CachedProvider<P> as Provider>::Header;
+
+    fn get_block_number(&self) -> Result<u64, Self::Error> {
+        match self.inner().get_block_number() {
+            Ok(head) => {
+                self.head.set(Some(head));
+                Ok(head)
+            }
+            Err(err) => self.head.get().ok_or(err),
+        }
+    }
+
+    fn get_block_header(&self, block: u64) -> Result<Option<Self::Header>, Self::Error> {
+        self.inner().get_block_header(block)
+    }
+
+    fn get_transaction_count(&self, address: Address, block: u64) -> Result<TxNumber, Self::Error> {
+        self.inner().get_transaction_count(address, block)
+    }
+
+    fn get_balance(&self, address: Address, block: u64) -> Result<U256, Self::Error> {
+        self.inner().get_balance(address, block)
+    }
+
+    fn get_code(&self, address: Address, block: u64) -> Result<Bytes, Self::Error> {
+        self.inner().get_code(address, block)
+    }
+
+    fn get_storage_at(
+        &self,
+        address: Address,
+        key: StorageKey,
+        block: u64,
+    ) -> Result<StorageValue, Self::Error> {
+        self.inner().get_storage_at(address, key, block)
+    }
+
+    fn get_proof(
+        &self,
+        address: Address,
+        storage_keys: Vec<StorageKey>,
+        block: u64,
+    ) -> Result<EIP1186Proof, Self::Error> {
+        self.inner().get_proof(address, storage_keys, block)
+    }
+}
+
+/// A provider that fails every request, so that only cached responses are served.
+pub struct Offline<H>(PhantomData<H>);
+
+impl<H> Default for Offline<H> {
+    fn default() -> Self {
+        Offline(PhantomData)
+    }
+}
+
+impl<H> Offline<H> {
+    fn offline<T>(&self) -> io::Result<T> {
+        Err(io::Error::new(io::ErrorKind::NotConnected, "not cached"))
+    }
+}
+
+impl<H> Provider for Offline<H> {
+    type Error = io::Error;
+    type Header = H;
+
+    fn get_block_number(&self) -> Result<u64, Self::Error> {
+        self.offline()
+    }
+
+    fn get_block_header(&self, _: u64) -> Result<Option<Self::Header>, Self::Error> {
+        self.offline()
+    }
+
+    fn get_transaction_count(&self, _: Address, _: u64) -> Result<TxNumber, Self::Error> {
+        self.offline()
+    }
+
+    fn get_balance(&self, _: Address, _: u64) -> Result<U256, Self::Error> {
+        self.offline()
+    }
+
+    fn get_code(&self, _: Address, _: u64) -> Result<Bytes, Self::Error> {
+        self.offline()
+    }
+
+    fn get_storage_at(
+        &self,
+        _: Address,
+        _: StorageKey,
+        _: u64,
+    ) -> Result<StorageValue, Self::Error> {
+        self.offline()
+    }
+
+    fn get_proof(
+        &self,
+        _: Address,
+        _: Vec<StorageKey>,
+        _: u64,
+    ) -> Result<EIP1186Proof, Self::Error> {
+        self.offline()
+    }
+}
+
+/// The key the chain head last read through the cache at `key` is stored at.
+fn head_key(key: &str) -> String {
+    format!("{key}.head")
+}
+
+/// A temporary path unique to this cache instance, as several may be open at once.
+fn scratch_path() -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    std::env::temp_dir().join(format!("host-cache-{}-{count}.tmp", process::id()))
+}
+
+fn encode(plain: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(plain)?;
+
+    Ok(encoder.finish()?)
+}
+
+/// Whether the stored `bytes` of a cache are gzip-compressed.
+pub fn is_compressed(bytes: &[u8]) -> bool {
+    bytes.starts_with(&GZIP_MAGIC)
+}
+
+fn decode(bytes: &[u8]) -> Result<Vec<u8>> {
+    if !is_compressed(bytes) {
+        return Ok(bytes.to_vec());
+    }
+    let mut plain = Vec::new();
+    GzDecoder::new(bytes).read_to_end(&mut plain).context("corrupt compressed cache")?;
+
+    Ok(plain)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MemoryBackend, MockProvider};
+    use risc0_steel::ethereum::EthBlockHeader;
+
+    #[test]
+    fn it_should_round_trip_a_compressed_cache() {
+        let dir = std::env::temp_dir().join(format!("host-cache-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = |name: &str, compress| CacheConfig {
+            backend: FsBackend,
+            key: dir.join(name).to_string_lossy().into_owned(),
+            compress,
+        };
+
+        let chain = MockProvider::with_chain(100, 50);
+        for (name, compress) in [("plain.json", false), ("compressed.json", true)] {
+            let cache = config(name, compress).open(chain.clone()).unwrap();
+            for number in 100..150 {
+                cache.get_block_header(number).unwrap().unwrap();
+            }
+        }
+        let plain = fs::read(dir.join("plain.json")).unwrap();
+        let compressed = fs::read(dir.join("compressed.json")).unwrap();
+        assert!(!plain.starts_with(&GZIP_MAGIC));
+        assert!(compressed.starts_with(&GZIP_MAGIC));
+        assert!(compressed.len() < plain.len(), "{} >= {} bytes", compressed.len(), plain.len());
+
+        // either form is served from the cache alone, and the legacy one is compressed on the way
+        for name in ["plain.json", "compressed.json"] {
+            let cache = config(name, true).open(MockProvider::failing()).unwrap();
+            assert_eq!(cache.get_block_header(120).unwrap().unwrap().number, 120);
+        }
+        assert!(fs::read(dir.join("plain.json")).unwrap().starts_with(&GZIP_MAGIC));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_should_serve_the_last_head_offline() {
+        let backend = MemoryBackend::default();
+        let config = CacheConfig { backend: backend.clone(), key: "cache".into(), compress: false };
+        assert!(config.open(MockProvider::failing()).unwrap().get_block_number().is_err());
+
+        let chain = MockProvider::with_chain(100, 50);
+        assert_eq!(config.open(chain.clone()).unwrap().get_block_number().unwrap(), 149);
+        assert_eq!(config.head().unwrap(), Some(149));
+        assert_eq!(config.open(MockProvider::failing()).unwrap().get_block_number().unwrap(), 149);
+
+        // online, the head is the provider's
+        chain.insert_header(EthBlockHeader { number: 150, ..Default::default() });
+        assert_eq!(config.open(chain).unwrap().get_block_number().unwrap(), 150);
+        assert_eq!(backend.get("cache.head").unwrap().unwrap(), b"150");
+    }
+
+    #[test]
+    fn it_should_merge_caches() {
+        let backend = MemoryBackend::default();
+        let config =
+            |key: &str| CacheConfig { backend: backend.clone(), key: key.into(), compress: true };
+        let fetch = |key: &str, chain: &MockProvider, blocks: std::ops::Range<u64>| {
+            let cache = config(key).open(chain.clone()).unwrap();
+            for number in blocks {
+                cache.get_block_header(number).unwrap().unwrap();
+            }
+        };
+
+        // two runs cached overlapping ranges, a third a different chain
+        let chain = MockProvider::with_chain(100, 50);
+        fetch("first", &chain, 100..110);
+        fetch("second", &chain, 105..120);
+        let forked = MockProvider::with_chain(100, 50);
+        let mut replaced = forked.header(107).unwrap();
+        replaced.gas_used = 21_000;
+        forked.insert_header(replaced);
+        fetch("forked", &forked, 107..108);
+
+        config("merged").merge(&[config("first"), config("second")]).unwrap();
+        let cache = config("merged").open(MockProvider::failing()).unwrap();
+        for number in 100..120 {
+            assert_eq!(cache.get_block_header(number).unwrap(), chain.header(number));
+        }
+        assert!(cache.get_block_header(120).is_err());
+        drop(cache);
+
+        // a conflicting entry leaves the merged cache as it was
+        let before = backend.get("merged").unwrap();
+        let err = config("merged").merge(&[config("forked")]).unwrap_err();
+        assert!(format!("{err:#}").contains("the caches disagree at cache."), "{err:#}");
+        assert_eq!(backend.get("merged").unwrap(), before);
+        assert!(config("merged").merge(&[config("missing")]).is_err());
+    }
+
+    #[test]
+    fn it_should_diff_the_headers_of_two_caches() {
+        let backend = MemoryBackend::default();
+        let config =
+            |key: &str| CacheConfig { backend: backend.clone(), key: key.into(), compress: false };
+        let fetch = |key: &str, chain: &MockProvider, blocks: std::ops::Range<u64>| {
+            let cache = config(key).open(chain.clone()).unwrap();
+            for number in blocks {
+                cache.get_block_header(number).unwrap().unwrap();
+            }
+        };
+
+        // a later snapshot of a chain on which blocks 112 and 118 were since replaced
+        let chain = MockProvider::with_chain(100, 50);
+        fetch("before", &chain, 100..120);
+        for number in [112, 118] {
+            let mut replaced = chain.header(number).unwrap();
+            replaced.gas_used = 21_000;
+            chain.insert_header(replaced);
+        }
+        fetch("after", &chain, 110..130);
+        let before = backend.get("before").unwrap().unwrap();
+
+        let diff = config("before")
+            .diff_headers::<EthBlockHeader, _>(&config("after"), 100..=149)
+            .unwrap();
+        assert_eq!(diff, HeaderDiff { compared: 10, differing: vec![112, 118] });
+        let diff = config("before")
+            .diff_headers::<EthBlockHeader, _>(&config("before"), 100..=149)
+            .unwrap();
+        assert_eq!(diff, HeaderDiff { compared: 20, differing: Vec::new() });
+        // the caches are left as they were
+        assert_eq!(backend.keys(), vec!["after".to_owned(), "before".to_owned()]);
+        assert_eq!(backend.get("before").unwrap(), Some(before));
+
+        // a missing or broken cache fails the diff rather than agreeing
+        let err = config("before")
+            .diff_headers::<EthBlockHeader, _>(&config("missing"), 100..=149)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "no cache at missing");
+        backend.put("broken", b"{").unwrap();
+        let err = config("broken")
+            .diff_headers::<EthBlockHeader, _>(&config("before"), 100..=149)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "cache broken is not a cache file");
+        assert_eq!(backend.get("broken").unwrap().unwrap(), b"{");
+    }
+
+    #[test]
+    fn it_should_refetch_reorged_headers() {
+        let dir = std::env::temp_dir().join(format!("host-reorg-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = CacheConfig {
+            backend: FsBackend,
+            key: dir.join("cache.json").to_string_lossy().into_owned(),
+            compress: false,
+        };
+
+        // yesterday's run cached the chain up to its head
+        let chain = MockProvider::with_chain(100, 50);
+        {
+            let cache = config.open(chain.clone()).unwrap();
+            assert_eq!(cache.get_block_number().unwrap(), 149);
+            for number in 100..150 {
+                cache.get_block_header(number).unwrap().unwrap();
+            }
+        }
+        let previous = config.head().unwrap();
+        assert_eq!(previous, Some(149));
+
+        // since then, the chain grew by 50 blocks and block 147 was reorged out
+        let live = MockProvider::with_chain(100, 100);
+        let mut replaced = live.header(147).unwrap();
+        replaced.gas_used = 21_000;
+        live.insert_header(replaced.clone());
+
+        // checked below today's head alone it goes unnoticed, but it was near yesterday's
+        assert!(config.invalidate_reorged(&live, None, 199, 10).unwrap().is_empty());
+        assert_eq!(config.invalidate_reorged(&live, previous, 199, 10).unwrap(), vec![147]);
+        assert!(config.invalidate_reorged(&live, previous, 199, 10).unwrap().is_empty());
+
+        // only what was cached for block 147 is discarded
+        let offline = config.open(MockProvider::failing()).unwrap();
+        assert!(offline.get_block_header(147).is_err());
+        assert_eq!(offline.get_block_header(146).unwrap(), chain.header(146));
+        assert_eq!(offline.get_block_header(148).unwrap(), chain.header(148));
+        drop(offline);
+
+        let cache = config.open(live.clone()).unwrap();
+        let refetched = cache.get_block_header(147).unwrap().unwrap();
+        assert_eq!(refetched, replaced);
+        drop(cache);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,164 @@
+//! Local proving of the guest one segment at a time, for `--resume`. The receipt of each segment
+//! is written to a session directory as soon as it is proven; a prove cut short, e.g. by a crash,
+//! executes the guest again, which splits into the same segments over the same input, and proves
+//! only the segments without a saved receipt. The receipt composed of all of them is verified
+//! against the image ID, so that a leftover receipt of another input fails the run rather than
+//! slipping through.
+
+use anyhow::{Context, Result};
+use risc0_zkvm::{
+    get_prover_server, CompositeReceipt, ExecutorEnv, ExecutorImpl, InnerReceipt, Journal,
+    ProverOpts, Receipt, VerifierContext,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// A directory holding the receipts of the segments proven so far.
+#[derive(Debug, Clone)]
+pub struct SessionDir {
+    path: PathBuf,
+}
+
+impl SessionDir {
+    /// Opens the session directory at `path`, creating it if it doesn't exist.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        fs::create_dir_all(&path)
+            .with_context(|| format!("failed to create session directory {}", path.display()))?;
+
+        Ok(SessionDir { path })
+    }
+
+    fn receipt_path(&self, index: usize) -> PathBuf {
+        self.path.join(format!("segment-{index}.json"))
+    }
+
+    /// The saved receipt of segment `index`; `None` if it isn't proven yet.
+    pub fn load<R: DeserializeOwned>(&self, index: usize) -> Result<Option<R>> {
+        let path = self.receipt_path(index);
+        let bytes = match fs::read(&path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let receipt = serde_json::from_slice(&bytes)
+            .with_context(|| format!("corrupt segment receipt {}", path.display()))?;
+
+        Ok(Some(receipt))
+    }
+
+    /// Saves the receipt of segment `index`, through a temporary file, so that a crash while
+    /// writing leaves no truncated receipt behind.
+    pub fn save<R: Serialize>(&self, index: usize, receipt: &R) -> Result<()> {
+        let path = self.receipt_path(index);
+        let partial = path.with_extension("json.partial");
+        fs::write(&partial, serde_json::to_vec(receipt)?)?;
+
+        Ok(fs::rename(&partial, &path)?)
+    }
+}
+
+/// The receipts of `count` segments, each loaded from `dir` or else proven with `prove` and saved
+/// before the next one is proven. Returns them in order, with the number loaded.
+pub fn prove_segments<R: Serialize + DeserializeOwned>(
+    dir: &SessionDir,
+    count: usize,
+    mut prove: impl FnMut(usize) -> Result<R>,
+) -> Result<(Vec<R>, usize)> {
+    let mut receipts = Vec::with_capacity(count);
+    let mut resumed = 0;
+    for index in 0..count {
+        let receipt = match dir.load(index)? {
+            Some(receipt) => {
+                resumed += 1;
+                receipt
+            }
+            None => {
+                let receipt =
+                    prove(index).with_context(|| format!("failed to prove segment {index}"))?;
+                dir.save(index, &receipt)?;
+                receipt
+            }
+        };
+        receipts.push(receipt);
+    }
+
+    Ok((receipts, resumed))
+}
+
+/// The journal of a local prove, and how much of it was resumed.
+pub struct LocalProof {
+    pub journal: Journal,
+    pub segments: usize,
+    pub resumed: usize,
+}
+
+/// Proves `elf` over `env` locally, checkpointing every segment receipt in `dir`, and returns the
+/// journal once the composed receipt verifies against `image_id`.
+pub fn prove(
+    env: ExecutorEnv<'_>,
+    elf: &[u8],
+    image_id: [u32; 8],
+    dir: &SessionDir,
+) -> Result<LocalProof> {
+    let session = ExecutorImpl::from_elf(env, elf)?.run().context("failed to run executor")?;
+    let journal = session.journal.clone().context("the guest committed no journal")?;
+    let prover = get_prover_server(&ProverOpts::default())?;
+    let ctx = VerifierContext::default();
+
+    let (segments, resumed) = prove_segments(dir, session.segments.len(), |index| {
+        prover.prove_segment(&ctx, &session.segments[index].resolve()?)
+    })?;
+    let count = segments.len();
+    let composite = CompositeReceipt { segments, assumptions: Vec::new(), journal_digest: None };
+    let receipt = Receipt::new(InnerReceipt::Composite(composite), journal.bytes);
+    receipt.verify(image_id).context(
+        "the proven receipt does not verify; the session directory may hold receipts of another \
+         input, start over with an empty one",
+    )?;
+
+    Ok(LocalProof { journal: receipt.journal, segments: count, resumed })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::bail;
+    use std::process;
+
+    #[test]
+    fn it_should_resume_from_the_saved_segments() {
+        let path = std::env::temp_dir().join(format!("host-session-test-{}", process::id()));
+        let dir = SessionDir::open(&path).unwrap();
+        let receipt = |index: usize| format!("receipt of segment {index}");
+
+        // the first attempt crashes while proving the third of five segments
+        let mut proven = Vec::new();
+        let err = prove_segments(&dir, 5, |index| {
+            if index == 2 {
+                bail!("out of memory");
+            }
+            proven.push(index);
+            Ok(receipt(index))
+        })
+        .unwrap_err();
+        assert_eq!(format!("{err:#}"), "failed to prove segment 2: out of memory");
+        assert_eq!(proven, [0, 1]);
+
+        // the resumed one proves only the rest
+        let mut proven = Vec::new();
+        let (receipts, resumed) = prove_segments(&dir, 5, |index| {
+            proven.push(index);
+            Ok(receipt(index))
+        })
+        .unwrap();
+        assert_eq!(proven, [2, 3, 4]);
+        assert_eq!(resumed, 2);
+        assert_eq!(receipts, (0..5).map(receipt).collect::<Vec<_>>());
+        assert!(!path.join("segment-2.json.partial").exists());
+
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
@@ -0,0 +1,440 @@
+//! Parsers for command line values.
+
+use alloy_primitives::{hex, U256};
+use anyhow::{bail, ensure, Context, Error, Result};
+use std::fmt;
+use std::io::BufRead;
+use std::str::FromStr;
+use tokemak::{
+    portfolio::{Allocation, AllocationEntry, AllocationSchedule},
+    PoolConfig,
+};
+
+/// A block given on the command line: a decimal or `0x`-prefixed hex number, or `latest`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockSpec {
+    Number(u64),
+    Latest,
+}
+
+impl BlockSpec {
+    /// Resolves the block number, calling `latest` only if the block is the provider head.
+    pub fn resolve<E>(&self, latest: impl FnOnce() -> Result<u64, E>) -> Result<u64, E> {
+        match self {
+            BlockSpec::Number(number) => Ok(*number),
+            BlockSpec::Latest => latest(),
+        }
+    }
+}
+
+impl FromStr for BlockSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        if s.eq_ignore_ascii_case("latest") {
+            return Ok(BlockSpec::Latest);
+        }
+
+        let number = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => s.parse::<u64>(),
+        };
+        match number {
+            Ok(number) => Ok(BlockSpec::Number(number)),
+            Err(_) if s.is_empty() => bail!("empty block number"),
+            Err(err) => Err(err).with_context(|| {
+                format!(
+                    "invalid block '{s}', expected a decimal or 0x-prefixed hex number or 'latest'"
+                )
+            }),
+        }
+    }
+}
+
+/// Reads a newline-separated list of blocks to sample, each as for [`BlockSpec`] but never
+/// `latest`, and returns them sorted and without duplicates. Blank lines are skipped.
+pub fn read_block_list(input: impl BufRead) -> Result<Vec<u64>> {
+    let mut blocks = Vec::new();
+    for (index, line) in input.lines().enumerate() {
+        let line = line.context("failed to read the block list")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match line.parse().with_context(|| format!("invalid block list line {}", index + 1))? {
+            BlockSpec::Number(number) => blocks.push(number),
+            BlockSpec::Latest => {
+                bail!("invalid block list line {}: no 'latest' in a list", index + 1)
+            }
+        }
+    }
+    blocks.sort_unstable();
+    blocks.dedup();
+    ensure!(blocks.len() >= 2, "the block list needs at least two blocks, got {}", blocks.len());
+
+    Ok(blocks)
+}
+
+/// A guest image ID, given as the 64 hex digits of its bytes, with or without a `0x` prefix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageId(pub [u8; 32]);
+
+impl From<[u32; 8]> for ImageId {
+    /// The ID as the build exports it, in little-endian words.
+    fn from(words: [u32; 8]) -> Self {
+        let mut bytes = [0; 32];
+        for (chunk, word) in bytes.chunks_exact_mut(4).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        ImageId(bytes)
+    }
+}
+
+impl FromStr for ImageId {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let bytes = hex::decode(s.trim()).with_context(|| format!("invalid image ID '{s}'"))?;
+        ensure!(
+            bytes.len() == 32,
+            "invalid image ID '{s}', expected 32 bytes, got {}",
+            bytes.len()
+        );
+
+        Ok(ImageId(bytes.try_into().unwrap()))
+    }
+}
+
+impl fmt::Display for ImageId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+/// A point in time given as an ISO-8601 date, `2024-05-01`, for midnight UTC, or date and time,
+/// `2024-05-01T12:00:00`, in UTC unless it ends in an offset such as `+02:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl FromStr for DateTime {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid =
+            || format!("invalid date '{s}', expected e.g. 2024-05-01 or 2024-05-01T12:00Z");
+        let s = s.trim();
+        let (date, time) = match s.split_once(['T', ' ']) {
+            Some((date, time)) => (date, Some(time)),
+            None => (s, None),
+        };
+
+        let fields: Vec<_> = date.split('-').collect();
+        ensure!(fields.len() == 3 && fields[0].len() == 4, invalid());
+        let year: i64 = fields[0].parse().with_context(invalid)?;
+        let month: u32 = fields[1].parse().with_context(invalid)?;
+        let day: u32 = fields[2].parse().with_context(invalid)?;
+        ensure!((1..=12).contains(&month), "{}: no month {month}", invalid());
+        ensure!(
+            (1..=days_in_month(year, month)).contains(&day),
+            "{}: no day {day} in {year}-{month:02}",
+            invalid()
+        );
+
+        let (seconds, offset) = match time {
+            Some(time) => parse_time(time).with_context(invalid)?,
+            None => (0, 0),
+        };
+        let timestamp = days_from_civil(year, month, day) * 86_400 + seconds - offset;
+        let timestamp = u64::try_from(timestamp).with_context(|| format!("{s} is before 1970"))?;
+
+        Ok(DateTime { timestamp })
+    }
+}
+
+/// Parses `HH:MM[:SS]` with an optional `Z` or `±HH:MM` suffix into the seconds into the day and
+/// the offset from UTC in seconds.
+fn parse_time(time: &str) -> Result<(i64, i64)> {
+    let (time, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(at) => time.split_at(at),
+        None => (time, ""),
+    };
+    let clock = |fields: &[&str], max: [i64; 3]| -> Result<i64> {
+        ensure!((2..=3).contains(&fields.len()), "expected HH:MM or HH:MM:SS");
+        let mut seconds = 0;
+        for (field, (max, scale)) in fields.iter().zip(max.iter().zip([3600, 60, 1])) {
+            ensure!(field.len() == 2, "expected two digits, got '{field}'");
+            let value: i64 = field.parse()?;
+            ensure!(value <= *max, "{value} is out of range");
+            seconds += value * scale;
+        }
+        Ok(seconds)
+    };
+
+    let seconds = clock(&time.split(':').collect::<Vec<_>>(), [23, 59, 59])?;
+    let offset = match offset.as_bytes().first() {
+        None | Some(b'Z' | b'z') => {
+            ensure!(offset.len() <= 1, "unexpected '{offset}' after Z");
+            0
+        }
+        Some(&sign) => {
+            let offset = clock(&offset[1..].split(':').collect::<Vec<_>>(), [23, 59, 0])?;
+            if sign == b'-' {
+                -offset
+            } else {
+                offset
+            }
+        }
+    };
+
+    Ok((seconds, offset))
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Days from 1970-01-01 to the given date of the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    // count from March, so that the leap day ends the year
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month as i64 + 9) % 12) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// A known pool and the share of a portfolio's capital in it, given as `<name>:<weight>`, e.g.
+/// `cbeth:0.6`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolWeight {
+    pub pool: PoolConfig,
+    pub allocation: Allocation,
+}
+
+impl FromStr for PoolWeight {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (name, weight) = s
+            .split_once(':')
+            .with_context(|| format!("invalid pool '{s}', expected name:weight"))?;
+        let (name, pool) = PoolConfig::by_name(name.trim()).with_context(|| {
+            let known: Vec<_> = PoolConfig::KNOWN.iter().map(|(name, _)| *name).collect();
+            format!("unknown pool '{name}', expected one of {}", known.join(", "))
+        })?;
+        let weight = weight.trim().parse().with_context(|| format!("invalid weight '{weight}'"))?;
+
+        Ok(PoolWeight { pool, allocation: Allocation { name, weight } })
+    }
+}
+
+/// Reads an autopool's allocation schedule as CSV: a header of `block_number` and the known pools
+/// of its destinations, then a row per reallocation of the block and each destination's weight,
+/// e.g. `block_number,cbeth,reth` and `19000000,0.7,0.3`. Returns the pools in the order of the
+/// schedule's destinations. Blank lines are skipped.
+pub fn read_allocation_schedule(
+    input: impl BufRead,
+) -> Result<(Vec<PoolConfig>, AllocationSchedule)> {
+    let mut lines = input
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| line.as_ref().map_or(true, |line| !line.trim().is_empty()));
+    let (_, header) = lines.next().context("empty allocation schedule")?;
+    let header = header.context("failed to read the allocation schedule")?;
+    let mut columns = header.split(',').map(str::trim);
+    ensure!(
+        columns.next() == Some("block_number"),
+        "the allocation schedule header must start with block_number"
+    );
+    let (names, pools): (Vec<_>, Vec<_>) = columns
+        .map(|name| {
+            PoolConfig::by_name(name).with_context(|| format!("unknown destination pool '{name}'"))
+        })
+        .collect::<Result<Vec<_>>>()?
+        .into_iter()
+        .unzip();
+
+    let mut entries = Vec::new();
+    for (index, line) in lines {
+        let line = line.context("failed to read the allocation schedule")?;
+        let context = || format!("invalid allocation schedule line {}", index + 1);
+        let mut fields = line.split(',').map(str::trim);
+        let block_number = match fields.next().unwrap_or_default().parse().with_context(context)? {
+            BlockSpec::Number(number) => number,
+            BlockSpec::Latest => bail!("{}: no 'latest' in a schedule", context()),
+        };
+        let weights = fields
+            .map(|weight| weight.parse::<f64>())
+            .collect::<Result<_, _>>()
+            .with_context(context)?;
+        entries.push(AllocationEntry { block_number, weights });
+    }
+
+    Ok((pools, AllocationSchedule::new(names, entries)?))
+}
+
+/// A token amount that has to be positive, e.g. the `--reference-principal` a balance is divided
+/// by, in the token's smallest unit.
+pub fn parse_positive_amount(s: &str) -> Result<U256> {
+    let amount: U256 = s.trim().parse().with_context(|| format!("invalid amount '{s}'"))?;
+    ensure!(!amount.is_zero(), "the amount must be positive");
+
+    Ok(amount)
+}
+
+/// An annual fee rate, as a fraction of the assets: at least 0 and less than 1, as a fee of the
+/// whole of the assets leaves the depositors nothing.
+pub fn parse_fee_rate(s: &str) -> Result<f64> {
+    let rate: f64 = s.trim().parse().with_context(|| format!("invalid fee rate '{s}'"))?;
+    ensure!((0.0..1.0).contains(&rate), "the fee rate {rate} is not from 0 to less than 1");
+
+    Ok(rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_parse_decimal_blocks() {
+        assert_eq!("19876543".parse::<BlockSpec>().unwrap(), BlockSpec::Number(19876543));
+    }
+
+    #[test]
+    fn it_should_parse_hex_blocks() {
+        assert_eq!("0x12f4d3f".parse::<BlockSpec>().unwrap(), BlockSpec::Number(0x12f4d3f));
+        assert_eq!("0X12F4D3F".parse::<BlockSpec>().unwrap(), BlockSpec::Number(0x12f4d3f));
+    }
+
+    #[test]
+    fn it_should_parse_latest() {
+        let spec = "latest".parse::<BlockSpec>().unwrap();
+        assert_eq!(spec, BlockSpec::Latest);
+        assert_eq!(spec.resolve(|| Ok::<_, Error>(123)).unwrap(), 123);
+        assert_eq!(BlockSpec::Number(7).resolve(|| -> Result<u64> { panic!() }).unwrap(), 7);
+    }
+
+    #[test]
+    fn it_should_reject_garbage() {
+        for garbage in ["", "19,876,543", "0x", "0xzz", "-1", "newest", "99999999999999999999"] {
+            let err = garbage.parse::<BlockSpec>().unwrap_err();
+            assert!(err.to_string().contains("block"), "{garbage}: {err}");
+        }
+    }
+
+    #[test]
+    fn it_should_parse_image_ids() {
+        let id = ImageId::from([1, 2, 3, 4, 5, 6, 7, 0xdeadbeef]);
+        let hex = id.to_string();
+        assert!(hex.starts_with("01000000020000"));
+        assert!(hex.ends_with("efbeadde"));
+
+        assert_eq!(hex.parse::<ImageId>().unwrap(), id);
+        assert_eq!(format!("0x{hex}").parse::<ImageId>().unwrap(), id);
+        assert!(hex[2..].parse::<ImageId>().is_err());
+        assert!("0xzz".parse::<ImageId>().is_err());
+    }
+
+    #[test]
+    fn it_should_read_a_block_list() {
+        let stdin = "19000200\n0x121eac8\n\n19000100\n19000200\n".as_bytes();
+        assert_eq!(read_block_list(stdin).unwrap(), vec![0x121eac8, 19000100, 19000200]);
+
+        let err = read_block_list("19000100\nlatest\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "invalid block list line 2: no 'latest' in a list");
+        let err = read_block_list("19000100\n19,000,200\n".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "invalid block list line 2");
+        assert!(read_block_list("19000100\n19000100\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn it_should_parse_dates_as_utc() {
+        let parse = |s: &str| s.parse::<DateTime>().map(|date| date.timestamp);
+        assert_eq!(parse("1970-01-01").unwrap(), 0);
+        assert_eq!(parse("2024-05-01").unwrap(), 1_714_521_600);
+        assert_eq!(parse("2024-02-29").unwrap(), 1_709_164_800);
+        assert_eq!(parse("2024-05-01T12:30").unwrap(), 1_714_521_600 + 45_000);
+        assert_eq!(parse("2024-05-01T12:30:15Z").unwrap(), 1_714_521_600 + 45_015);
+        // the same instant two hours east of UTC
+        assert_eq!(parse("2024-05-01T14:30:15+02:00").unwrap(), 1_714_521_600 + 45_015);
+        assert_eq!(parse("2024-04-30T22:00-02:00").unwrap(), 1_714_521_600);
+
+        for garbage in
+            ["", "2024-5-1x", "2023-02-29", "2024-13-01", "2024-05-01T24:00", "1969-12-31"]
+        {
+            assert!(parse(garbage).is_err(), "{garbage}");
+        }
+    }
+
+    #[test]
+    fn it_should_parse_pool_weights() {
+        let weight = "RETH:0.4".parse::<PoolWeight>().unwrap();
+        assert_eq!(weight.pool, PoolConfig::RETH_ETH);
+        assert_eq!(weight.allocation, Allocation { name: "reth", weight: 0.4 });
+
+        let err = "steth:0.4".parse::<PoolWeight>().unwrap_err();
+        assert_eq!(err.to_string(), "unknown pool 'steth', expected one of cbeth, reth");
+        assert!("cbeth".parse::<PoolWeight>().is_err());
+        assert!("cbeth:sixty".parse::<PoolWeight>().is_err());
+    }
+
+    #[test]
+    fn it_should_parse_positive_amounts() {
+        assert_eq!(
+            parse_positive_amount("1000000000000000000000").unwrap(),
+            U256::from(1_000_000_000_000_000_000_000_u128)
+        );
+        assert_eq!(
+            parse_positive_amount("0").unwrap_err().to_string(),
+            "the amount must be positive"
+        );
+        assert_eq!(parse_positive_amount("1e18").unwrap_err().to_string(), "invalid amount '1e18'");
+    }
+
+    #[test]
+    fn it_should_parse_fee_rates() {
+        assert_eq!(parse_fee_rate("0.02").unwrap(), 0.02);
+        assert_eq!(parse_fee_rate("0").unwrap(), 0.0);
+        assert_eq!(
+            parse_fee_rate("2").unwrap_err().to_string(),
+            "the fee rate 2 is not from 0 to less than 1"
+        );
+        assert!(parse_fee_rate("-0.01").is_err());
+        assert!(parse_fee_rate("1").is_err());
+        assert!(parse_fee_rate("NaN").is_err());
+        assert_eq!(parse_fee_rate("2%").unwrap_err().to_string(), "invalid fee rate '2%'");
+    }
+
+    #[test]
+    fn it_should_read_an_allocation_schedule() {
+        let csv = "block_number, cbeth, reth\n19000000,1,0\n\n19050000,0.4,0.6\n".as_bytes();
+        let (pools, schedule) = read_allocation_schedule(csv).unwrap();
+        assert_eq!(pools, [PoolConfig::CBETH_ETH, PoolConfig::RETH_ETH]);
+        assert_eq!(schedule.destinations(), ["cbeth", "reth"]);
+        assert_eq!(schedule.average_weights(19_050_000, 19_060_000).unwrap(), [0.4, 0.6]);
+
+        let err =
+            read_allocation_schedule("block_number,cbeth\nlatest,1\n".as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid allocation schedule line 2: no 'latest' in a schedule"
+        );
+        let err = read_allocation_schedule("block,cbeth\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("block_number"));
+        // the weights of a row must add up
+        assert!(
+            read_allocation_schedule("block_number,cbeth,reth\n1,0.5,0.4\n".as_bytes()).is_err()
+        );
+        assert!(read_allocation_schedule("block_number,cbeth,reth\n1,0.5\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! The continuity check of `--previous-run`, for a run repeated e.g. daily: today's window has to
+//! overlap yesterday's, and the yield recomputed over the part they share has to agree with the
+//! one yesterday's run committed. A change of data source or methodology between the runs shows up
+//! as a discontinuity rather than as a silent jump of the yield.
+//!
+//! The previous yield is over the previous window as a whole, the recomputed one over the overlap
+//! only, so the two differ by more than rounding whenever the yield moved over the days they don't
+//! share; the tolerance has to allow for that, the less the larger the overlap.
+
+use alloy_primitives::{hex, Address};
+use alloy_sol_types::SolValue;
+use anyhow::{bail, ensure, Context, Result};
+use core::fmt;
+use serde::Deserialize;
+use tokemak::{try_calculate_dex_stats, wad_to_yield, DexStatsInput, DexStatsParams, LstDexStats};
+
+/// The window and yield a previous run committed to, and the pool, LST and granularity it was
+/// computed for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PriorRun {
+    pub pool: Address,
+    pub lst: Address,
+    pub granularity_blocks: u64,
+    pub first_block: u64,
+    pub head: u64,
+    pub base_yield: f64,
+}
+
+impl PriorRun {
+    /// The run of the journal in an `--output json` output.
+    pub fn from_json(json: &[u8]) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Output {
+            journal: String,
+        }
+
+        let output: Output = serde_json::from_slice(json).context("not an --output json output")?;
+        let journal = LstDexStats::abi_decode(&hex::decode(output.journal)?, true)
+            .context("invalid journal")?;
+
+        PriorRun::try_from(&journal)
+    }
+
+    /// Fails unless the run was for `pool` and `lst` at `granularity_blocks`, the only one whose
+    /// yield is comparable with a run for them.
+    pub fn ensure_same_run(
+        &self,
+        pool: Address,
+        lst: Address,
+        granularity_blocks: u64,
+    ) -> Result<()> {
+        ensure!(
+            (self.pool, self.lst) == (pool, lst),
+            "the previous run is for pool {} and LST {}, not {pool} and {lst}",
+            self.pool,
+            self.lst
+        );
+        ensure!(
+            self.granularity_blocks == granularity_blocks,
+            "the previous run sampled every {} blocks, not every {granularity_blocks}",
+            self.granularity_blocks
+        );
+        Ok(())
+    }
+}
+
+impl TryFrom<&LstDexStats> for PriorRun {
+    type Error = anyhow::Error;
+
+    fn try_from(journal: &LstDexStats) -> Result<Self> {
+        let head = journal.commitment.blockNumber.to::<u64>();
+        let window_blocks = journal.windowBlocks;
+        let first_block = head.checked_sub(window_blocks).with_context(|| {
+            format!("the window of {window_blocks} blocks to block {head} starts before genesis")
+        })?;
+        Ok(PriorRun {
+            pool: journal.pool,
+            lst: journal.lst,
+            granularity_blocks: journal.granularityBlocks,
+            first_block,
+            head,
+            base_yield: wad_to_yield(journal.baseYield),
+        })
+    }
+}
+
+/// The yield of the overlap of two successive runs' windows, recomputed from the later run's
+/// samples, against the earlier run's.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Continuity {
+    pub overlap_from: u64,
+    pub overlap_to: u64,
+    pub prior_yield: f64,
+    pub recomputed_yield: f64,
+    pub tolerance: f64,
+}
+
+impl Continuity {
+    pub fn is_consistent(&self) -> bool {
+        (self.recomputed_yield - self.prior_yield).abs() <= self.tolerance
+    }
+}
+
+impl fmt::Display for Continuity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (from, to) = (self.overlap_from, self.overlap_to);
+        let (prior, recomputed) = (self.prior_yield * 100.0, self.recomputed_yield * 100.0);
+        if self.is_consistent() {
+            write!(
+                f,
+                "Continuity check passed: the yield over blocks {from} to {to} is \
+                 {recomputed:.4}%, the previous run's {prior:.4}%"
+            )
+        } else {
+            write!(
+                f,
+                "warning: discontinuity with the previous run, the yield over blocks {from} to \
+                 {to} is {recomputed:.4}% but the previous run's {prior:.4}%, more than {:.4}% \
+                 apart; the data source or the methodology may have changed",
+                self.tolerance * 100.0
+            )
+        }
+    }
+}
+
+/// Recomputes the yield of `inputs` over the part of their window that `prior`'s covers. Fails if
+/// the windows don't overlap by at least one interval.
+pub fn check_continuity(
+    prior: &PriorRun,
+    inputs: &[DexStatsInput],
+    params: &DexStatsParams,
+    tolerance: f64,
+) -> Result<Continuity> {
+    let (first, last) = match (inputs.first(), inputs.last()) {
+        (Some(first), Some(last)) => (first.block_number, last.block_number),
+        _ => bail!("no samples"),
+    };
+    let (overlap_from, overlap_to) = (first.max(prior.first_block), last.min(prior.head));
+    ensure!(
+        overlap_from < overlap_to,
+        "the window from block {first} to {last} does not overlap the previous run's from {} to {}",
+        prior.first_block,
+        prior.head
+    );
+
+    let start = inputs.partition_point(|input| input.block_number < overlap_from);
+    let end = inputs.partition_point(|input| input.block_number <= overlap_to);
+    let stats = try_calculate_dex_stats(&inputs[start..end], params)
+        .context("failed to recompute the yield of the overlap")?;
+
+    Ok(Continuity {
+        overlap_from,
+        overlap_to,
+        prior_yield: prior.base_yield,
+        recomputed_yield: stats.base_yield,
+        tolerance,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{Address, B256, U256};
+    use risc0_steel::BlockCommitment;
+    use tokemak::{
+        fixture::{self, daily_inputs, steady_backing},
+        yield_to_wad, PoolConfig, BLOCK_GRANULARITY,
+    };
+
+    /// Daily samples of a steady 3.65% yield from block 19_000_000.
+    fn inputs(days: std::ops::RangeInclusive<u64>) -> Vec<DexStatsInput> {
+        daily_inputs(days, steady_backing)
+    }
+
+    fn journal(head: u64, window_blocks: u64, base_yield: f64) -> LstDexStats {
+        LstDexStats {
+            commitment: BlockCommitment {
+                blockHash: B256::repeat_byte(0xab),
+                blockNumber: U256::from(head),
+            },
+            baseYield: yield_to_wad(base_yield),
+            windowBlocks: window_blocks,
+            ..fixture::journal()
+        }
+    }
+
+    #[test]
+    fn it_should_check_successive_runs_for_continuity() {
+        let params = DexStatsParams::default();
+        // yesterday's run over days 0 to 20, today's over days 1 to 21
+        let yesterday = inputs(0..=20);
+        let committed = try_calculate_dex_stats(&yesterday, &params).unwrap().base_yield;
+        let json = format!(
+            r#"{{"base_yield":{committed},"journal":"{}"}}"#,
+            hex::encode_prefixed(
+                journal(19_000_000 + 20 * BLOCK_GRANULARITY, 20 * BLOCK_GRANULARITY, committed)
+                    .abi_encode()
+            )
+        );
+        let prior = PriorRun::from_json(json.as_bytes()).unwrap();
+        assert_eq!(prior.first_block, 19_000_000);
+
+        let today = inputs(1..=21);
+        let continuity = check_continuity(&prior, &today, &params, 0.0005).unwrap();
+        assert_eq!(
+            (continuity.overlap_from, continuity.overlap_to),
+            (19_000_000 + BLOCK_GRANULARITY, 19_000_000 + 20 * BLOCK_GRANULARITY)
+        );
+        assert!(continuity.is_consistent(), "{continuity}");
+        assert!(continuity.to_string().starts_with("Continuity check passed"));
+
+        // today's run reads another source, whose backing grows twice as fast
+        let switched: Vec<_> = today
+            .iter()
+            .map(|input| DexStatsInput {
+                lst_backing: input.lst_backing * U256::from(2) - U256::from(10).pow(U256::from(18)),
+                ..input.clone()
+            })
+            .collect();
+        let continuity = check_continuity(&prior, &switched, &params, 0.0005).unwrap();
+        assert!(!continuity.is_consistent());
+        assert!(continuity.to_string().starts_with("warning: discontinuity"), "{continuity}");
+
+        // a run a month later shares nothing with yesterday's
+        let err = check_continuity(&prior, &inputs(40..=60), &params, 0.0005).unwrap_err();
+        assert!(err.to_string().contains("does not overlap"), "{err}");
+    }
+
+    #[test]
+    fn it_should_reject_a_prior_run_of_another_pool() {
+        let prior = PriorRun::try_from(&journal(19_000_000, BLOCK_GRANULARITY, 0.0365)).unwrap();
+        let PoolConfig { pool, lst, .. } = PoolConfig::CBETH_ETH;
+        prior.ensure_same_run(pool, lst, BLOCK_GRANULARITY).unwrap();
+
+        let err = prior.ensure_same_run(Address::repeat_byte(0x33), lst, BLOCK_GRANULARITY);
+        assert!(err.unwrap_err().to_string().starts_with("the previous run is for pool"));
+        let err = prior.ensure_same_run(pool, Address::repeat_byte(0x33), BLOCK_GRANULARITY);
+        assert!(err.unwrap_err().to_string().starts_with("the previous run is for pool"));
+        let err = prior.ensure_same_run(pool, lst, BLOCK_GRANULARITY / 2).unwrap_err();
+        assert_eq!(err.to_string(), "the previous run sampled every 7200 blocks, not every 3600");
+
+        // a window longer than the chain is no run at all
+        let err = PriorRun::try_from(&journal(1_000, BLOCK_GRANULARITY, 0.0365)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the window of 7200 blocks to block 1000 starts before genesis"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+//! Per-sample CSV export for analysis in pandas, Excel and the like.
+
+use alloy_primitives::{
+    utils::{format_units, parse_units},
+    U256,
+};
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufWriter, Write};
+use tokemak::{wad::mul_div, DexStatsInput};
+
+/// The values queried for a single sampled block.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SampleRow {
+    pub block_number: u64,
+    pub timestamp: u64,
+    /// cbETH `exchangeRate()`, 18 decimals.
+    pub exchange_rate: U256,
+    /// The backing the yield is computed from, 18 decimals, after any re-denomination.
+    pub backing: U256,
+    /// Curve pool `get_virtual_price()`, 18 decimals.
+    pub virtual_price: U256,
+}
+
+impl SampleRow {
+    /// The sample as a stats input.
+    pub fn input(&self) -> DexStatsInput {
+        DexStatsInput {
+            block_number: self.block_number,
+            timestamp: self.timestamp,
+            lst_backing: self.backing,
+            interpolated: false,
+            pool_tvl: None,
+        }
+    }
+}
+
+const HEADER: &str = "block_number,timestamp,exchange_rate,backing,virtual_price";
+const OVERRIDES_HEADER: &str = "block_number,exchange_rate";
+
+/// Writes one CSV row per sample as it is produced, so memory stays bounded however long the
+/// window is.
+pub struct DatasetWriter<W: Write> {
+    out: BufWriter<W>,
+    rows: usize,
+}
+
+impl<W: Write> DatasetWriter<W> {
+    pub fn new(out: W) -> Result<Self> {
+        let mut out = BufWriter::new(out);
+        writeln!(out, "{HEADER}")?;
+
+        Ok(DatasetWriter { out, rows: 0 })
+    }
+
+    pub fn write(&mut self, row: &SampleRow) -> Result<()> {
+        writeln!(
+            self.out,
+            "{},{},{},{},{}",
+            row.block_number,
+            row.timestamp,
+            format_units(row.exchange_rate, 18)?,
+            format_units(row.backing, 18)?,
+            format_units(row.virtual_price, 18)?
+        )?;
+        self.rows += 1;
+
+        Ok(())
+    }
+
+    /// Flushes the remaining buffered rows and returns how many were written.
+    pub fn finish(mut self) -> Result<usize> {
+        self.out.flush()?;
+
+        Ok(self.rows)
+    }
+}
+
+/// Reads a what-if series of exchange rates by block, from a CSV file with a `block_number` and
+/// an 18-decimal `exchange_rate` column.
+pub fn read_rate_overrides(input: impl BufRead) -> Result<BTreeMap<u64, U256>> {
+    let overrides = read_csv(input, "override", OVERRIDES_HEADER, |fields| {
+        ensure!(fields.len() == 2, "expected 2 fields, got {}", fields.len());
+
+        Ok((fields[0].parse()?, parse_units(fields[1], 18)?.into()))
+    })?;
+
+    Ok(overrides.into_iter().collect())
+}
+
+/// Replaces the exchange rate of every row with its override, keeping the real timestamps. The
+/// backing is scaled along, so that a re-denominated one keeps its price.
+pub fn apply_rate_overrides(rows: &mut [SampleRow], overrides: &BTreeMap<u64, U256>) -> Result<()> {
+    for row in rows {
+        let rate = *overrides
+            .get(&row.block_number)
+            .with_context(|| format!("no exchange rate override for block {}", row.block_number))?;
+        ensure!(!row.exchange_rate.is_zero(), "zero exchange rate at block {}", row.block_number);
+        row.backing = mul_div(row.backing, rate, row.exchange_rate);
+        row.exchange_rate = rate;
+    }
+
+    Ok(())
+}
+
+/// Reads a CSV file with the given header, parsing each row's fields with `parse`.
+fn read_csv<T>(
+    input: impl BufRead,
+    kind: &str,
+    expected_header: &str,
+    parse: impl Fn(&[&str]) -> Result<T>,
+) -> Result<Vec<T>> {
+    let mut lines = input.lines();
+    let header = lines.next().with_context(|| format!("empty {kind} file"))??;
+    ensure!(
+        header.trim() == expected_header,
+        "unexpected {kind} header '{header}', expected '{expected_header}'"
+    );
+
+    let mut rows = Vec::new();
+    for (index, line) in lines.enumerate() {
+        let line = line?;
+        let fields: Vec<_> = line.trim().split(',').collect();
+        // rows are numbered from the header on
+        rows.push(parse(&fields).with_context(|| format!("invalid {kind} row {}", index + 2))?);
+    }
+
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_write_header_and_rows() {
+        let mut buf = Vec::new();
+        let mut writer = DatasetWriter::new(&mut buf).unwrap();
+        for i in 0..3_u64 {
+            let rate = U256::from(1_050_000_000_000_000_000_u128 + i as u128);
+            writer
+                .write(&SampleRow {
+                    block_number: 19_000_000 + i * 7200,
+                    timestamp: 1_700_000_000 + i * 86400,
+                    exchange_rate: rate,
+                    backing: rate,
+                    virtual_price: U256::from(1_010_000_000_000_000_000_u128),
+                })
+                .unwrap();
+        }
+        assert_eq!(writer.finish().unwrap(), 3);
+
+        let csv = String::from_utf8(buf).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], HEADER);
+        assert_eq!(
+            lines[2],
+            "19007200,1700086400,1.050000000000000001,1.050000000000000001,1.010000000000000000"
+        );
+        assert!(lines[1..].iter().all(|line| line.split(',').count() == 5));
+    }
+}
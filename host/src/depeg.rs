@@ -0,0 +1,112 @@
+//! A check of the pool against a Chainlink feed for a depeg. The pool prices the LST in ETH by its
+//! balances, which a quote for a small swap of LST into ETH reads out; the feed quotes the LST's
+//! market price in ETH. The two normally agree closely, and a sample at which they diverge is
+//! taken during a depeg of the LST or a failure of the oracle, either of which makes the yield
+//! computed over it unreliable.
+//!
+//! The quote is net of the pool's swap fee, which is added back to it, so that a pool charging a
+//! fee above the threshold doesn't flag every sample. What remains is the slippage of the quoted
+//! amount, which is negligible next to any useful threshold.
+
+use alloy_primitives::{utils::format_units, U256};
+use core::fmt;
+
+/// The LST amount quoted, small enough that the slippage is negligible.
+pub const QUOTE_AMOUNT: U256 = U256::from_limbs([10_000_000_000_000_000, 0, 0, 0]);
+
+/// The scale of a Curve pool's `fee()`, 1e10 for a fee of the whole amount.
+pub const FEE_DENOMINATOR: U256 = U256::from_limbs([10_000_000_000, 0, 0, 0]);
+
+/// The prices of the LST in ETH at a sampled block, scaled by 1e18.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriceSample {
+    pub block_number: u64,
+    pub pool_price: U256,
+    pub oracle_price: U256,
+}
+
+impl PriceSample {
+    /// The sample of the ETH `quote` the pool gives for [`QUOTE_AMOUNT`] of the LST, net of its
+    /// `fee`, scaled by [`FEE_DENOMINATOR`].
+    pub fn from_quote(block_number: u64, quote: U256, fee: U256, oracle_price: U256) -> Self {
+        let gross = quote * FEE_DENOMINATOR / (FEE_DENOMINATOR - fee);
+        let pool_price = gross * U256::from(10).pow(U256::from(18)) / QUOTE_AMOUNT;
+
+        PriceSample { block_number, pool_price, oracle_price }
+    }
+
+    /// The relative deviation of the pool price from the oracle's.
+    pub fn divergence(&self) -> f64 {
+        (to_f64(self.pool_price) / to_f64(self.oracle_price) - 1.0).abs()
+    }
+}
+
+fn to_f64(value: U256) -> f64 {
+    format_units(value, 18).unwrap().parse().unwrap()
+}
+
+/// A sample at which the pool and the oracle diverge.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Depeg {
+    pub block_number: u64,
+    pub pool_price: f64,
+    pub oracle_price: f64,
+}
+
+impl fmt::Display for Depeg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "warning: at block {} the pool prices the LST at {:.6} ETH but the oracle at {:.6}, \
+             {:.2}% apart; a depeg or a failing oracle makes the yield over it unreliable",
+            self.block_number,
+            self.pool_price,
+            self.oracle_price,
+            (self.pool_price / self.oracle_price - 1.0).abs() * 100.0
+        )
+    }
+}
+
+/// The samples whose prices diverge by more than `max_divergence`, a fraction.
+pub fn flag_depegs(samples: &[PriceSample], max_divergence: f64) -> Vec<Depeg> {
+    samples
+        .iter()
+        .filter(|sample| sample.divergence() > max_divergence)
+        .map(|sample| Depeg {
+            block_number: sample.block_number,
+            pool_price: to_f64(sample.pool_price),
+            oracle_price: to_f64(sample.oracle_price),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wad(value: f64) -> U256 {
+        U256::from((value * 1e18) as u128)
+    }
+
+    #[test]
+    fn it_should_flag_a_diverging_oracle() {
+        // the pool quotes the oracle's price net of its 0.4% fee, until the LST depegs at block 300
+        let fee = U256::from(40_000_000);
+        let quote = |price: f64| wad(price * 0.996) * QUOTE_AMOUNT / wad(1.0);
+        let samples = [
+            PriceSample::from_quote(100, quote(1.0612), fee, wad(1.0615)),
+            PriceSample::from_quote(200, quote(1.0618), fee, wad(1.0620)),
+            PriceSample::from_quote(300, quote(1.0090), fee, wad(1.0622)),
+        ];
+
+        let flagged = flag_depegs(&samples, 0.005);
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].block_number, 300);
+        assert!(flagged[0]
+            .to_string()
+            .contains("at 1.009000 ETH but the oracle at 1.062200, 5.01%"));
+        assert!(flag_depegs(&samples, 0.06).is_empty());
+        // the fee alone is no divergence
+        assert!(flag_depegs(&samples[..2], 0.001).is_empty());
+    }
+}
@@ -0,0 +1,175 @@
+//! The journal as EIP-712 typed data, for `--output eip712`: the `LstDexStats` struct, field for
+//! field, under a domain a verifier contract checks signatures against, as `eth_signTypedData_v4`
+//! takes it.
+//!
+//! The domain separator is built from the name [`DOMAIN_NAME`], the version [`DOMAIN_VERSION`],
+//! the chain the samples were read on and the address of the verifier contract, and has no salt. A
+//! contract recomputes it from the same four values, so that a signature doesn't carry over to
+//! another chain, verifier or version of the message.
+
+use alloy_primitives::{Address, B256, U256};
+use alloy_sol_types::{Eip712Domain, SolStruct};
+use serde_json::{json, Map, Value};
+use std::borrow::Cow;
+use tokemak::LstDexStats;
+
+pub const DOMAIN_NAME: &str = "Tokemak LST DEX Stats";
+/// Bumped whenever the fields of `LstDexStats` change.
+pub const DOMAIN_VERSION: &str = "3";
+
+/// The domain of the stats read on `chain_id`, to be verified by `verifying_contract`.
+pub fn domain(chain_id: u64, verifying_contract: Address) -> Eip712Domain {
+    Eip712Domain::new(
+        Some(Cow::Borrowed(DOMAIN_NAME)),
+        Some(Cow::Borrowed(DOMAIN_VERSION)),
+        Some(U256::from(chain_id)),
+        Some(verifying_contract),
+        None,
+    )
+}
+
+/// The typed data of `stats` under `domain`, with the hash to sign.
+pub fn typed_data(stats: &LstDexStats, domain: &Eip712Domain) -> Value {
+    let mut types = struct_types(&LstDexStats::eip712_encode_type());
+    types.insert(
+        "EIP712Domain".into(),
+        json!([
+            { "name": "name", "type": "string" },
+            { "name": "version", "type": "string" },
+            { "name": "chainId", "type": "uint256" },
+            { "name": "verifyingContract", "type": "address" },
+        ]),
+    );
+
+    json!({
+        "types": types,
+        "primaryType": "LstDexStats",
+        "domain": {
+            "name": DOMAIN_NAME,
+            "version": DOMAIN_VERSION,
+            "chainId": domain.chain_id.map(|id| id.to::<u64>()),
+            "verifyingContract": domain.verifying_contract.map(|address| address.to_string()),
+        },
+        // 256-bit integers as decimal strings, which a JSON number can't hold exactly
+        "message": {
+            "commitment": {
+                "blockHash": stats.commitment.blockHash.to_string(),
+                "blockNumber": stats.commitment.blockNumber.to_string(),
+            },
+            "pool": stats.pool.to_string(),
+            "lst": stats.lst.to_string(),
+            "baseYield": stats.baseYield.to_string(),
+            "rewardPool": stats.rewardPool.to_string(),
+            "incentiveYield": stats.incentiveYield.to_string(),
+            "granularityBlocks": stats.granularityBlocks,
+            "windowBlocks": stats.windowBlocks,
+            "paramsDigest": stats.paramsDigest.to_string(),
+            "samplesDigest": stats.samplesDigest.to_string(),
+        },
+        "hash": signing_hash(stats, domain).to_string(),
+    })
+}
+
+/// The EIP-712 hash of `stats` under `domain`, the digest a signer signs.
+pub fn signing_hash(stats: &LstDexStats, domain: &Eip712Domain) -> B256 {
+    stats.eip712_signing_hash(domain)
+}
+
+/// The struct types of an EIP-712 type encoding, e.g. `A(B b,uint64 c)B(bytes32 d)`, as the
+/// `types` of typed data, so that they follow the `sol!` definition rather than a copy of it.
+fn struct_types(encode_type: &str) -> Map<String, Value> {
+    encode_type
+        .split_terminator(')')
+        .filter_map(|definition| {
+            let (name, fields) = definition.split_once('(')?;
+            let fields: Vec<_> = fields
+                .split(',')
+                .filter(|field| !field.is_empty())
+                .filter_map(|field| {
+                    let (ty, name) = field.split_once(' ')?;
+                    Some(json!({ "name": name, "type": ty }))
+                })
+                .collect();
+            Some((name.to_owned(), Value::Array(fields)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{b256, I256};
+    use risc0_steel::BlockCommitment;
+    use tokemak::fixture;
+
+    fn stats() -> LstDexStats {
+        LstDexStats {
+            commitment: BlockCommitment {
+                blockHash: B256::repeat_byte(0xab),
+                blockNumber: U256::from(19_000_000),
+            },
+            pool: Address::repeat_byte(0x11),
+            lst: Address::repeat_byte(0x22),
+            baseYield: I256::try_from(37_500_000_000_000_000_i64).unwrap(),
+            rewardPool: Address::repeat_byte(0x33),
+            incentiveYield: U256::from(12_500_000_000_000_000_u64),
+            windowBlocks: 180 * 7_200,
+            paramsDigest: B256::repeat_byte(0x55),
+            samplesDigest: B256::repeat_byte(0x66),
+            ..fixture::journal()
+        }
+    }
+
+    #[test]
+    fn it_should_hash_the_typed_data() {
+        let stats = stats();
+        let domain = domain(1, Address::repeat_byte(0x44));
+
+        assert_eq!(
+            LstDexStats::eip712_encode_type(),
+            "LstDexStats(BlockCommitment commitment,address pool,address lst,int256 baseYield,\
+             address rewardPool,uint256 incentiveYield,uint64 granularityBlocks,uint64 \
+             windowBlocks,bytes32 paramsDigest,bytes32 samplesDigest)BlockCommitment(bytes32 \
+             blockHash,uint256 blockNumber)"
+        );
+        assert_eq!(
+            domain.separator(),
+            b256!("960778eed1493f10f9699c02a8dc342a5a9ba3acf4ca4361b94ef3a99a41027a")
+        );
+        assert_eq!(
+            stats.eip712_hash_struct(),
+            b256!("999b8f754fd12aca02fbd55fa360c7d145897642d5e3c2eab006aa5a19b64f57")
+        );
+        assert_eq!(
+            signing_hash(&stats, &domain),
+            b256!("9cb49318047106090e78338f83113aafb1c5c92d5cac929726440c92cd88e052")
+        );
+    }
+
+    #[test]
+    fn it_should_render_the_typed_data() {
+        let stats = stats();
+        let typed = typed_data(&stats, &domain(1, Address::repeat_byte(0x44)));
+
+        assert_eq!(typed["primaryType"], "LstDexStats");
+        assert_eq!(
+            typed["types"]["BlockCommitment"],
+            json!([
+                { "name": "blockHash", "type": "bytes32" },
+                { "name": "blockNumber", "type": "uint256" },
+            ])
+        );
+        assert_eq!(typed["types"]["LstDexStats"].as_array().unwrap().len(), 10);
+        assert_eq!(
+            typed["types"]["LstDexStats"][3],
+            json!({ "name": "baseYield", "type": "int256" })
+        );
+        assert_eq!(typed["domain"]["chainId"], 1);
+        assert_eq!(typed["message"]["baseYield"], "37500000000000000");
+        assert_eq!(typed["message"]["windowBlocks"], 1_296_000);
+        assert_eq!(
+            typed["hash"],
+            "0x9cb49318047106090e78338f83113aafb1c5c92d5cac929726440c92cd88e052"
+        );
+    }
+}
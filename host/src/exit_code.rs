@@ -0,0 +1,107 @@
+//! The exit codes of a failed run, so that a wrapper script can branch on the class of a failure
+//! without parsing stderr:
+//!
+//! | code | class                 | meaning                                                   |
+//! |------|-----------------------|-----------------------------------------------------------|
+//! | 0    |                       | success                                                   |
+//! | 1    | [`HostError::Other`]  | anything not classified below                             |
+//! | 2    | [`HostError::Config`] | bad arguments or configuration; rerunning unchanged fails |
+//! | 3    | [`HostError::Rpc`]    | an RPC request failed; worth retrying later               |
+//! | 4    | [`HostError::Data`]   | the fetched samples were rejected by the stats' checks    |
+//! | 5    | [`HostError::Guest`]  | the guest failed, or its receipt does not verify          |
+//!
+//! Code 2 is also what clap exits with on a usage error. A failure is classified by the error
+//! types in its chain, or by a [`HostError`] attached to it as context at the site of the failure.
+
+use core::fmt;
+use risc0_steel::host::provider::Provider;
+use tokemak::DexStatsError;
+
+use crate::{provider::BudgetError, schedule::WindowError, RpcProvider};
+
+/// The error of a request through the RPC provider.
+type RpcError = <RpcProvider as Provider>::Error;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HostError {
+    Other,
+    Config,
+    Rpc,
+    Data,
+    Guest,
+}
+
+impl HostError {
+    /// The class of `err`: the outermost [`HostError`] attached to it, else that of the first error
+    /// of its chain with a known class.
+    pub fn classify(err: &anyhow::Error) -> HostError {
+        if let Some(class) = err.downcast_ref::<HostError>() {
+            return *class;
+        }
+
+        err.chain()
+            .find_map(|cause| {
+                if let Some(err) = cause.downcast_ref::<RpcError>() {
+                    return Some(match err {
+                        // raising the budget or going online is a change of configuration
+                        BudgetError::Exhausted { .. } | BudgetError::Offline => HostError::Config,
+                        BudgetError::Provider(_) => HostError::Rpc,
+                    });
+                }
+                if cause.is::<WindowError>() {
+                    return Some(HostError::Config);
+                }
+                cause.is::<DexStatsError>().then_some(HostError::Data)
+            })
+            .unwrap_or(HostError::Other)
+    }
+
+    pub fn exit_code(self) -> u8 {
+        match self {
+            HostError::Other => 1,
+            HostError::Config => 2,
+            HostError::Rpc => 3,
+            HostError::Data => 4,
+            HostError::Guest => 5,
+        }
+    }
+}
+
+impl fmt::Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HostError::Other => "the run failed",
+            HostError::Config => "invalid configuration",
+            HostError::Rpc => "an RPC request failed",
+            HostError::Data => "the samples were rejected",
+            HostError::Guest => "the guest failed",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::BreakerError;
+    use anyhow::anyhow;
+
+    #[test]
+    fn it_should_classify_each_failure_by_exit_code() {
+        let code = |err: anyhow::Error| HostError::classify(&err).exit_code();
+
+        assert_eq!(code(anyhow!("something else")), 1);
+        assert_eq!(code(anyhow!("--flag is required").context(HostError::Config)), 2);
+        assert_eq!(code(WindowError::EmptyWindow { from: 199, to: 199 }.into()), 2);
+        assert_eq!(code(RpcError::Exhausted { limit: 10 }.into()), 2);
+        assert_eq!(code(RpcError::Offline.into()), 2);
+        // also below the context the failing call adds
+        let err = anyhow::Error::new(RpcError::Provider(BreakerError::Open { failures: 5 }))
+            .context("could not retrieve block 100");
+        assert_eq!(code(err), 3);
+        assert_eq!(code(DexStatsError::Empty.into()), 4);
+        assert_eq!(code(anyhow!("out of cycles").context(HostError::Guest)), 5);
+        // an attached class wins over the chain
+        let err = anyhow::Error::new(DexStatsError::Empty).context(HostError::Guest);
+        assert_eq!(code(err), 5);
+    }
+}
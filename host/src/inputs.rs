@@ -0,0 +1,77 @@
+//! The input set of `--inputs-out`: the samples the stats of a run were computed from, once the
+//! guest accepted them, with the head its journal commits to. `replay` recomputes the stats from it
+//! with other parameters, without any RPC access.
+
+use anyhow::{Context, Result};
+use risc0_steel::BlockCommitment;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::dataset::SampleRow;
+use crate::manifest::BlockRef;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputSet {
+    /// The head the journal of the run commits to.
+    pub head: BlockRef,
+    /// Block distance between consecutive samples of the run.
+    pub granularity_blocks: u64,
+    /// The samples the stats were computed from, oldest first.
+    pub samples: Vec<SampleRow>,
+}
+
+impl InputSet {
+    pub fn new(head: &BlockCommitment, granularity_blocks: u64, samples: Vec<SampleRow>) -> Self {
+        InputSet {
+            head: BlockRef { number: head.blockNumber.to(), hash: head.blockHash },
+            granularity_blocks,
+            samples,
+        }
+    }
+
+    /// Writes the set to `path` as pretty-printed JSON.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+
+        Ok(serde_json::to_writer_pretty(file, self)?)
+    }
+
+    pub fn read(input: impl Read) -> Result<Self> {
+        serde_json::from_reader(input).context("invalid input set")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::{B256, U256};
+
+    #[test]
+    fn it_should_read_back_a_written_set() {
+        let head = BlockCommitment {
+            blockHash: B256::repeat_byte(0xab),
+            blockNumber: U256::from(19_007_200),
+        };
+        let row = SampleRow {
+            block_number: 19_000_000,
+            timestamp: 1_700_000_000,
+            exchange_rate: U256::from(1_050_000_000_000_000_000_u64),
+            backing: U256::from(3_150_123_456_789_012_345_678_u128),
+            virtual_price: U256::from(1_010_000_000_000_000_000_u64),
+        };
+        let set = InputSet::new(&head, 7_200, vec![row.clone()]);
+
+        let read = InputSet::read(serde_json::to_vec(&set).unwrap().as_slice()).unwrap();
+        assert_eq!(read.head, BlockRef { number: 19_007_200, hash: head.blockHash });
+        assert_eq!(read.granularity_blocks, 7_200);
+        assert_eq!(read.samples.len(), 1);
+        assert_eq!(read.samples[0].block_number, row.block_number);
+        assert_eq!(read.samples[0].backing, row.backing);
+
+        let err = InputSet::read(&b"block_number,timestamp"[..]).unwrap_err();
+        assert_eq!(err.to_string(), "invalid input set");
+    }
+}
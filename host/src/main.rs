@@ -12,122 +12,3283 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use alloy_sol_types::SolValue;
-use anyhow::{Context, Result};
-use clap::Parser;
-use methods::TOKEN_STATS_ELF;
+use alloy_primitives::{
+    hex,
+    utils::{format_units, parse_units},
+    Address, B256, U256,
+};
+use alloy_sol_types::{SolCall, SolValue};
+use anyhow::{anyhow, bail, ensure, Context, Result};
+use clap::{Parser, Subcommand, ValueEnum};
+use core::fmt;
+use methods::{TOKEN_STATS_ELF, TOKEN_STATS_ID};
 use risc0_steel::{
     config::ETH_MAINNET_CHAIN_SPEC,
     ethereum::{EthBlockHeader, EthViewCallEnv},
     host::{
-        provider::{CachedProvider, EthersProvider, Provider},
+        db::ProofDb,
+        provider::{EthersProvider, Provider},
         EthersClient,
     },
-    ViewCall, ViewCallInput,
+    ViewCall, ViewCallEnv, ViewCallInput,
 };
-use risc0_zkvm::{default_executor, ExecutorEnv};
-use std::path::PathBuf;
+use risc0_zkvm::{default_executor, ExecutorEnv, SessionInfo};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokemak::{BLOCKS_TO_QUERY, BLOCK_GRANULARITY, cbETHInterface, CBETH_ADDRESS, LstDexStats};
+use tokemak::{
+    aggregate::Aggregation,
+    backing::{BackingKind, ReferenceBalance, ViewCaller},
+    chain::{ChainHeader, HeadLink},
+    convex::ConvexRewards,
+    exclude_before, exclude_unfinalized,
+    fees::{FeeSchedule, NetYield},
+    oracle::PriceFeed,
+    portfolio::{AllocationSchedule, Portfolio},
+    query::PoolQuerySet,
+    reference::{ApyComparison, ReferenceApy},
+    try_calculate_dex_stats, wad_to_yield, ChainlinkInterface, ChangeMode, CurvePoolInterface,
+    DexStatsError, DexStatsInput, DexStatsOutput, DexStatsParams, GuestParams, LstDexStats,
+    PoolConfig, QueryMode, ReturnType, SampleAlignment, SkipRemainder, BLOCKS_TO_QUERY,
+    BLOCK_GRANULARITY, CBETH_ADDRESS, CBETH_CHAINLINK_ORACLE, GUEST_INPUT_VERSION,
+};
 use tracing_subscriber::EnvFilter;
 
+/// Prints a warning, always to stderr: it reaches the user whatever stdout is reserved for, and
+/// stays out of what a script reads from stdout either way. Defined ahead of the modules, so that
+/// every one of them can warn.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        eprintln!($($arg)*)
+    };
+}
+
+mod block_time;
+mod bonsai;
+mod cache;
+mod checkpoint;
+mod cli;
+mod continuity;
+mod dataset;
+mod depeg;
+mod eip712;
+mod exit_code;
+mod inputs;
+mod manifest;
+mod metrics;
+// only the smoke chain serves outside the tests
+#[cfg_attr(not(test), allow(dead_code))]
+mod mock;
+mod provider;
+mod report;
+mod schedule;
+mod self_test;
+mod stream;
+mod upgrades;
+mod verification;
+mod virtual_price;
+
+use block_time::{BeaconSchedule, BlockTimes, SLOTS_PER_EPOCH};
+use cache::{Cache, CacheBackend, CacheConfig, FsBackend};
+use checkpoint::SessionDir;
+use cli::{parse_fee_rate, parse_positive_amount, BlockSpec, DateTime, ImageId, PoolWeight};
+use continuity::{check_continuity, PriorRun};
+use dataset::{DatasetWriter, SampleRow};
+use exit_code::HostError;
+use inputs::InputSet;
+use provider::{BreakerProvider, BudgetedProvider, Endpoint, FallbackProvider, RequestBudget};
+use report::{Report, ScheduleSummary};
+use schedule::{
+    check_header_count, check_not_empty, check_sample_count, lookback_blocks, sample_blocks,
+    window_start, MidnightSampler,
+};
+use stream::{write_seq, GuestInput, Prefetch};
+use verification::Verification;
+use virtual_price::VirtualPriceSample;
+
+/// Set when stdout carries only the output, for piping it on; the report then goes to stderr.
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Prints a line of the run's report, to stdout unless it is reserved for the output.
+macro_rules! report {
+    ($($arg:tt)*) => {
+        if STDOUT_RESERVED.load(Ordering::Relaxed) {
+            eprintln!($($arg)*)
+        } else {
+            println!($($arg)*)
+        }
+    };
+}
+
+/// The provider all RPC requests go through.
+type RpcProvider =
+    BudgetedProvider<FallbackProvider<BreakerProvider<EthersProvider<EthersClient>>>>;
+
+/// Window of a smoke run: three samples, a hundred blocks apart.
+const SMOKE_WINDOW_BLOCKS: u64 = 200;
+const SMOKE_GRANULARITY_BLOCKS: u64 = 100;
+
+/// Default of `--breaker-threshold`.
+const DEFAULT_BREAKER_THRESHOLD: u32 = 5;
+
 // Simple program to show the use of Ethereum contract data inside the guest.
 #[derive(Parser, Debug)]
-#[command(about, long_about = None)]
+#[command(
+    about,
+    long_about = None,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
 struct Args {
-    /// URL of the RPC endpoint
-    #[arg(short, long, env = "RPC_URL")]
-    rpc_url: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// URL of the RPC endpoint; repeat the flag (or comma-separate) to fall back to further
+    /// endpoints when a request fails
+    #[arg(short, long, env = "RPC_URL", value_delimiter = ',', required = true)]
+    rpc_url: Vec<String>,
+    /// Directory to cache responses
+    #[arg(short, long, env = "CACHE_DIR", required = true)]
+    cache_dir: Option<String>,
+    /// Image ID, as hex, the guest must have; the run aborts before any work if the built guest
+    /// differs, e.g. from an audited build
+    #[arg(long, env = "EXPECTED_IMAGE_ID")]
+    expected_image_id: Option<ImageId>,
+    /// Store the response cache gzip-compressed; a cache is read either way
+    #[arg(long, env = "COMPRESS_CACHE")]
+    compress_cache: bool,
+    /// Block the window ends at, as a decimal or 0x-prefixed hex number, or `latest`
+    #[arg(short, long, env = "END_BLOCK_NUMBER", default_value = "latest")]
+    end_block_number: BlockSpec,
+    /// Hash of the window's last block, from a source trusted more than the RPC, e.g. a block
+    /// explorer; the run fails unless the fetched headers link up to it. Set the last block with
+    /// `--end-block-number`, since the head `latest` resolves to moves on
+    #[arg(long, env = "TRUSTED_HEAD_HASH")]
+    trusted_head_hash: Option<B256>,
+    /// End the window at the last block at or before this Unix timestamp instead, for the yield
+    /// as of that moment
+    #[arg(
+        long,
+        visible_alias = "as-of-timestamp",
+        env = "END_TIMESTAMP",
+        conflicts_with = "end_block_number"
+    )]
+    end_timestamp: Option<u64>,
+    /// Compute the yield over a calendar range instead, from the last block at or before this
+    /// ISO-8601 date or date and time, in UTC unless an offset is given, e.g. 2024-05-01
+    #[arg(long, env = "FROM_DATE", requires = "to_date")]
+    from_date: Option<DateTime>,
+    /// End of the calendar range, as for `--from-date`; the window ends at the last block at or
+    /// before it and starts a whole number of sampling intervals back, at or after the start
+    #[arg(
+        long,
+        env = "TO_DATE",
+        requires = "from_date",
+        conflicts_with_all = ["end_block_number", "end_timestamp", "epoch_aligned", "smoke"]
+    )]
+    to_date: Option<DateTime>,
+    /// Also compute the yield over each of these lookbacks, in days, e.g. 1,7,30, from the samples
+    /// of a window as long as the longest; only the longest is committed by the guest
+    #[arg(
+        long,
+        env = "WINDOWS",
+        value_delimiter = ',',
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["from_date", "smoke"]
+    )]
+    windows: Vec<u64>,
+    /// Also compare the yield of the last `--recent-days` with a baseline, the yield over this many
+    /// days before them, reporting how far the recent yield is above or below it
+    #[arg(
+        long,
+        env = "BASELINE_DAYS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = ["from_date", "smoke"]
+    )]
+    baseline_days: Option<u64>,
+    /// Length in days of the recent period `--baseline-days` compares
+    #[arg(
+        long,
+        default_value_t = 7,
+        value_parser = clap::value_parser!(u64).range(1..),
+        requires = "baseline_days"
+    )]
+    recent_days: u64,
+    /// Check the run for continuity with a previous one of the same pool and granularity, its
+    /// `--output json` output in this file: the windows have to overlap, and the yield recomputed
+    /// over the overlap has to agree with the previous one within `--continuity-tolerance`, else a
+    /// discontinuity is flagged
+    #[arg(long, value_name = "FILE", conflicts_with = "smoke")]
+    previous_run: Option<PathBuf>,
+    /// Largest absolute difference between the previous yield and the recomputed one that counts
+    /// as continuous; it has to allow for the yield moving over the days the windows don't share
+    #[arg(long, default_value_t = 0.001, requires = "previous_run")]
+    continuity_tolerance: f64,
+    /// Sample exactly these blocks, newline-separated in this file or on stdin for `-`, e.g. as
+    /// chosen by an external scheduler; the window ends at the last of them
+    #[arg(
+        long,
+        env = "BLOCKS",
+        conflicts_with_all = [
+            "end_block_number",
+            "end_timestamp",
+            "to_date",
+            "windows",
+            "epoch_aligned",
+            "align_to_midnight",
+            "max_interpolated",
+            "continue_on_error",
+            "smoke",
+        ]
+    )]
+    blocks: Option<PathBuf>,
+    /// Sample the closing block of each of the last DAYS UTC days, the last block before its
+    /// midnight, and of the day before them, for a daily series free of intraday noise; the
+    /// window ends at the last close at or before the end block
+    #[arg(
+        long,
+        env = "DAILY_CLOSE",
+        value_name = "DAYS",
+        value_parser = clap::value_parser!(u64).range(1..),
+        conflicts_with_all = [
+            "blocks",
+            "end_timestamp",
+            "from_date",
+            "windows",
+            "epoch_aligned",
+            "align_to_midnight",
+            "max_interpolated",
+            "continue_on_error",
+            "smoke",
+        ]
+    )]
+    daily_close: Option<u64>,
+    /// Bundle the view calls of each sampled block into a single Multicall3 call
+    #[arg(long, env = "MULTICALL")]
+    multicall: bool,
+    /// Chainlink feed to re-denominate the ETH backing with (e.g. ETH/USD for a USD yield)
+    #[arg(long, env = "ORACLE")]
+    oracle: Option<Address>,
+    /// Decimals of the oracle answers, instead of querying the feed's `decimals()`
+    #[arg(long, env = "ORACLE_DECIMALS", requires = "oracle")]
+    oracle_decimals: Option<u8>,
+    /// Chainlink feed quoting the reference asset in the quote asset of `--oracle` (e.g. BTC/USD
+    /// for a BTC yield). A yield in a volatile reference mixes its price returns into the yield.
+    #[arg(long, env = "DENOMINATION_ORACLE", requires = "oracle")]
+    denomination_oracle: Option<Address>,
+    /// Decimals of the denomination oracle answers, instead of querying its `decimals()`
+    #[arg(long, env = "DENOMINATION_ORACLE_DECIMALS", requires = "denomination_oracle")]
+    denomination_oracle_decimals: Option<u8>,
+    /// Convex base reward pool of the LP token, to report the CRV incentives alongside the pool
+    /// yield
+    #[arg(long, env = "CONVEX_REWARD_POOL", requires = "reward_oracle")]
+    convex_reward_pool: Option<Address>,
+    /// Chainlink feed quoting the reward token in ETH (e.g. CRV/ETH)
+    #[arg(long, env = "REWARD_ORACLE", requires = "convex_reward_pool")]
+    reward_oracle: Option<Address>,
+    /// Decimals of the reward oracle answers, instead of querying its `decimals()`
+    #[arg(long, env = "REWARD_ORACLE_DECIMALS", requires = "reward_oracle")]
+    reward_oracle_decimals: Option<u8>,
+    /// Decimals assumed for a feed whose `decimals()` reverts, as it does on some non-standard
+    /// tokens and on proxies early in their life; the run goes on with a warning
+    #[arg(long, env = "DEFAULT_DECIMALS", default_value_t = 18)]
+    default_decimals: u8,
+    /// Fail when the pool holds less than this much ETH value at any sample, as the yield of an
+    /// illiquid pool is unreliable
+    #[arg(long, env = "MIN_TVL_ETH")]
+    min_tvl_eth: Option<f64>,
+    /// Weight each interval's change by the pool's TVL at its start, for a capital-weighted
+    /// yield, rather than all equally
+    #[arg(long, env = "TVL_WEIGHTED")]
+    tvl_weighted: bool,
+    /// Fail when the exchange rate decreases between consecutive samples, which it never does for
+    /// LSTs quoting one like cbETH; rejected for other backing strategies
+    #[arg(long, env = "ENFORCE_MONOTONIC_RATE")]
+    enforce_monotonic_rate: bool,
+    /// Derive the LST backing with this strategy instead of the pool's own; `reference-balance`
+    /// tracks a rebasing LST through the balance of `--reference-holder`
+    #[arg(long, env = "BACKING", value_enum)]
+    backing: Option<BackingArg>,
+    /// Holder of `--backing reference-balance`, which has neither sent nor received the LST since
+    /// it held `--reference-principal` of it at a backing of one
+    #[arg(
+        long,
+        env = "REFERENCE_HOLDER",
+        requires = "reference_principal",
+        required_if_eq("backing", "reference-balance")
+    )]
+    reference_holder: Option<Address>,
+    /// Balance of `--reference-holder` at a backing of one, in the LST's smallest unit
+    #[arg(
+        long,
+        env = "REFERENCE_PRINCIPAL",
+        requires = "reference_holder",
+        value_parser = parse_positive_amount
+    )]
+    reference_principal: Option<U256>,
+    /// Maximum number of consecutive unavailable samples to interpolate over
+    #[arg(long, env = "MAX_INTERPOLATED", default_value_t = 0)]
+    max_interpolated: usize,
+    /// Largest block distance between consecutive samples to accept, however many missing samples
+    /// may be interpolated or left out; a longer one fails the run as a real hole in the data
+    #[arg(long, env = "MAX_BLOCK_GAP")]
+    max_block_gap: Option<u64>,
+    /// Time the samples by their block numbers, at 12 seconds per block, when any two consecutive
+    /// ones are less than this many seconds apart; for instant-mined local forks such as Anvil,
+    /// whose blocks share timestamps
+    #[arg(
+        long,
+        env = "MIN_TIME_DELTA",
+        value_name = "SECONDS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    min_time_delta: Option<u64>,
+    /// Merge a sampled interval shorter than this into the one after it rather than annualize
+    /// it, since a few seconds' rounding noise annualizes into a huge yield; trades how quickly a
+    /// real move shows for stability, so keep it well below the sampling interval
+    #[arg(long, env = "MIN_INTERVAL_SECONDS", value_name = "SECONDS")]
+    min_interval_seconds: Option<u64>,
+    /// Sample the block nearest to each UTC midnight instead of every granularity blocks back from
+    /// the head; the window then ends at the last midnight
+    #[arg(long, env = "ALIGN_TO_MIDNIGHT", conflicts_with_all = ["max_interpolated", "smoke"])]
+    align_to_midnight: bool,
+    /// End the window at the start of the latest finalized beacon chain epoch and start it at the
+    /// start of the epoch a window back, so that the yield covers whole epochs; assumes 12 second
+    /// slots
+    #[arg(long, env = "EPOCH_ALIGNED", conflicts_with_all = ["align_to_midnight", "smoke"])]
+    epoch_aligned: bool,
+    /// Leave out samples that fail to be queried, rather than failing the run, and summarize them
+    /// at the end. The yield is computed from the remaining samples alone, each change annualized
+    /// over the gap to the one before it rather than interpolated over, and the samples left out
+    /// count against the data quality. The head sample is still required.
+    #[arg(
+        long,
+        env = "CONTINUE_ON_ERROR",
+        conflicts_with_all = ["max_interpolated", "align_to_midnight"]
+    )]
+    continue_on_error: bool,
+    /// Leave samples that may not be finalized yet out of the yield, still committing to the head
+    #[arg(long, env = "EXCLUDE_UNFINALIZED")]
+    exclude_unfinalized: bool,
+    /// Number of blocks behind the head after which a sample is considered final
+    #[arg(long, env = "FINALITY_DEPTH", default_value_t = 64)]
+    finality_depth: u64,
+    /// Most RPC requests the run may make, counting neither cache hits nor retries; it fails with
+    /// the budget exhausted rather than making more
+    #[arg(long, env = "MAX_REQUESTS")]
+    max_requests: Option<u64>,
+    /// Serve every request from the cache and fail on the first miss rather than contacting the
+    /// RPC endpoints, for a reproducible run; the chain head is the one the cache last read, and
+    /// the reorg check is skipped
+    #[arg(long, env = "OFFLINE", conflicts_with = "max_requests")]
+    offline: bool,
+    /// Number of consecutive failed requests after which an RPC endpoint is taken for down and
+    /// no longer tried; the run fails fast once all endpoints are
+    #[arg(
+        long,
+        env = "BREAKER_THRESHOLD",
+        default_value_t = DEFAULT_BREAKER_THRESHOLD,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    breaker_threshold: u32,
+    /// Number of blocks behind the head, and behind the head of the last run, whose cached headers
+    /// are checked against the chain before the run; what is cached for those that changed in a
+    /// reorg is discarded. 0 disables the check
+    #[arg(long, env = "REORG_CHECK_DEPTH", default_value_t = 64)]
+    reorg_check_depth: u64,
+    /// Write Prometheus textfile-format metrics of the run to this path
+    #[arg(long, env = "METRICS_OUT")]
+    metrics_out: Option<PathBuf>,
+    /// Write the sampled data (block, timestamp, exchange rate, backing, virtual price) to this CSV
+    /// file
+    #[arg(long, env = "DATASET_OUT")]
+    dataset_out: Option<PathBuf>,
+    /// Write the samples the stats are computed from, once the guest accepted them, and the head
+    /// the journal commits to as JSON to this file, for `replay`
+    #[arg(long, env = "INPUTS_OUT")]
+    inputs_out: Option<PathBuf>,
+    /// Write the guest input, as it is assembled, to this file as the little-endian bytes of its
+    /// words, to run the guest on it again without any RPC access
+    #[arg(long, env = "GUEST_INPUT_OUT")]
+    guest_input_out: Option<PathBuf>,
+    /// Maximum number of fetched headers or preflighted samples to buffer ahead of writing them to
+    /// the guest input; lower it to reduce peak memory on large windows
+    #[arg(
+        long,
+        env = "BUFFER_SIZE",
+        default_value_t = 256,
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    buffer_size: u64,
+    /// Fail before fetching anything for the samples if the schedule holds more than this many,
+    /// to guard against an accidentally long window or fine granularity exhausting the executor
+    #[arg(long, env = "MAX_SAMPLES", default_value_t = 10_000)]
+    max_samples: usize,
+    /// Fail before fetching the window if it holds more blocks than this: the header of every
+    /// block in it is fetched to link the samples to the head, however few of them are sampled
+    #[arg(long, env = "MAX_HEADERS", default_value_t = 3_000_000)]
+    max_headers: u64,
+    /// Recompute the yield on the host and fail if it differs from the journal's, to catch the
+    /// guest and the host diverging
+    #[arg(long, env = "CROSS_CHECK")]
+    cross_check: bool,
+    /// Largest absolute difference between the guest and host yield the cross-check accepts
+    #[arg(long, env = "CROSS_CHECK_TOLERANCE", default_value_t = 1e-9, requires = "cross_check")]
+    cross_check_tolerance: f64,
+    /// Contract exposing an `apy()` view, as an 18-decimal fraction, to compare the computed yield
+    /// against at the head; methodology differences make some gap expected
+    #[arg(long, env = "REFERENCE_APY_CONTRACT")]
+    reference_apy_contract: Option<Address>,
+    /// Absolute difference from the reference APY above which the computed yield is flagged
+    #[arg(
+        long,
+        env = "REFERENCE_APY_TOLERANCE",
+        default_value_t = 0.005,
+        requires = "reference_apy_contract"
+    )]
+    reference_apy_tolerance: f64,
+    /// Compute the yield of a portfolio instead of the cbETH pool: a known pool (cbeth, reth) and
+    /// the share of the capital in it as `<name>:<weight>`, repeated per pool, the weights summing
+    /// to 1. Reports each pool's yield and the weighted one.
+    #[arg(
+        long = "pool",
+        conflicts_with_all = [
+            "blocks",
+            "convex_reward_pool",
+            "reference_apy_contract",
+            "dataset_out",
+            "inputs_out",
+            "metrics_out",
+            "output",
+        ]
+    )]
+    pool: Vec<PoolWeight>,
+    /// Compute the yield of a Tokemak autopool instead of the cbETH pool, from a CSV of its
+    /// allocations over time: a header of `block_number` and its destination pools, then a row
+    /// per reallocation of the block and the weights from it on. Reports each destination's yield
+    /// and the one blended by the weights averaged over the window.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "pool",
+            "blocks",
+            "convex_reward_pool",
+            "reference_apy_contract",
+            "dataset_out",
+            "inputs_out",
+            "metrics_out",
+            "output",
+        ]
+    )]
+    autopool_weights: Option<PathBuf>,
+    /// Also read the pool's virtual price at the blocks around each sample and warn about samples
+    /// deviating from both by more than this fraction, as possibly manipulated; see the
+    /// `virtual_price` module for the limits of this heuristic
+    #[arg(long, env = "VIRTUAL_PRICE_CHECK")]
+    virtual_price_check: Option<f64>,
+    /// Also report the pool's fee yield from its virtual price averaged over this many blocks
+    /// before and after each sample, damping a sample taken during an imbalance; it is computed by
+    /// the host and not proven
+    #[arg(long)]
+    virtual_price_window: Option<u64>,
+    /// Also compare the price of the LST the pool quotes with that of `--depeg-oracle` at each
+    /// sample and warn about samples where they diverge by more than this fraction, as taken
+    /// during a depeg or an oracle failure; see the `depeg` module for what the quote includes
+    #[arg(long, env = "DEPEG_CHECK")]
+    depeg_check: Option<f64>,
+    /// Chainlink feed quoting the LST in ETH for `--depeg-check`; required but for the cbETH pool,
+    /// which defaults to the cbETH/ETH feed
+    #[arg(long, env = "DEPEG_ORACLE")]
+    depeg_oracle: Option<Address>,
+    /// Run the whole pipeline with the executor over a minimal window as a quick check that it
+    /// works, and report pass/fail
+    #[arg(long)]
+    smoke: bool,
+    /// Before the run, run the guest over an in-memory chain and fail unless it commits the yield
+    /// the library computes from the same samples, to catch a guest built from another version of
+    /// the library
+    #[arg(long, env = "SELF_TEST")]
+    self_test: bool,
+    /// Prove the guest on the Bonsai proving service instead of only executing it locally, with
+    /// the credentials of `BONSAI_API_URL` and `BONSAI_API_KEY`; the receipt is verified before its
+    /// journal is used
+    #[arg(long, env = "BONSAI")]
+    bonsai: bool,
+    /// Prove the guest locally, saving the receipt of each segment to this directory as it is
+    /// proven; a rerun over the same input proves only the segments missing from it
+    #[arg(long, conflicts_with = "bonsai")]
+    resume: Option<PathBuf>,
+    /// What to print the result as: the report, the journal's ABI-encoded bytes as 0x hex for
+    /// passing as calldata to a verifier contract, JSON with the verification status, or the
+    /// journal as EIP-712 typed data for signing; the report goes to stderr for any but the first
+    #[arg(long, env = "OUTPUT", value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+    /// Contract verifying signatures over the EIP-712 typed data, part of its domain
+    #[arg(long, env = "EIP712_VERIFYING_CONTRACT", required_if_eq("output", "eip712"))]
+    eip712_verifying_contract: Option<Address>,
+    /// Finish with a report consolidating every metric of the run; with `--output json`, it is
+    /// printed as JSON instead of the usual object
+    #[arg(long)]
+    report: bool,
+    /// Write a JSON manifest of the run's provenance to this file: the sampled blocks and their
+    /// hashes, the guest's image ID and input hash, hashes of the RPC URLs and the host version,
+    /// for reproducing and auditing the run
+    #[arg(long)]
+    manifest_out: Option<PathBuf>,
+    /// Annual risk-free rate, as a fraction, the report's Sharpe-like ratio measures the yield's
+    /// excess over
+    #[arg(long, default_value_t = 0.0)]
+    risk_free_apr: f64,
+    /// Also report the base yield net of a vault's management fee, charged on the assets as this
+    /// annual rate, as a fraction from 0 to less than 1
+    #[arg(long, value_parser = parse_fee_rate)]
+    management_fee_apr: Option<f64>,
+    /// Also report the base yield net of a vault's performance fee, taking this share of a
+    /// positive yield, in basis points
+    #[arg(long, value_parser = clap::value_parser!(u32).range(..=10_000))]
+    performance_fee_bps: Option<u32>,
+}
+
+impl Args {
+    /// The `--management-fee-apr` and `--performance-fee-bps` fees, if either is given.
+    fn fees(&self) -> Option<FeeSchedule> {
+        (self.management_fee_apr.is_some() || self.performance_fee_bps.is_some()).then(|| {
+            FeeSchedule {
+                management_fee_apr: self.management_fee_apr.unwrap_or_default(),
+                performance_fee_bps: self.performance_fee_bps.unwrap_or_default(),
+            }
+        })
+    }
+
+    /// The `--backing` strategy, if given.
+    fn backing(&self) -> Option<BackingKind> {
+        Some(match self.backing? {
+            BackingArg::ExchangeRate => BackingKind::ExchangeRate,
+            BackingArg::Rebase => BackingKind::Rebase,
+            BackingArg::RedemptionRate => BackingKind::RedemptionRate,
+            // clap requires the holder and its principal with it
+            BackingArg::ReferenceBalance => BackingKind::ReferenceBalance(ReferenceBalance {
+                holder: self.reference_holder?,
+                principal: self.reference_principal?,
+            }),
+        })
+    }
+
+    /// Whether the run proves the guest, so that its journal comes with a receipt; a plain run
+    /// only executes it.
+    fn proves(&self) -> bool {
+        self.bonsai || self.resume.is_some()
+    }
+
+    /// How far the result of a run leaving out `failures` can be trusted: without a receipt,
+    /// nothing shows the journal was computed by the guest at all.
+    fn verification(&self, failures: &FailedSamples) -> Verification {
+        let mut verification = failures.verification();
+        if !self.proves() {
+            verification.unverified("executed only, no receipt proves the journal");
+        }
+
+        verification
+    }
+}
+
+/// The strategies of `--backing`, see [`BackingKind`].
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum BackingArg {
+    ExchangeRate,
+    Rebase,
+    RedemptionRate,
+    ReferenceBalance,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum OutputFormat {
+    Text,
+    AbiHex,
+    Json,
+    Eip712,
+}
+
+/// The result as printed by `--output json`, led by how far it can be trusted.
+#[derive(Serialize, Debug)]
+struct JsonOutput<'a> {
+    verification: &'a Verification,
+    base_yield: f64,
+    base_apr: f64,
+    base_apy: f64,
+    /// Not annualized, see [`DexStatsOutput::total_return`].
+    total_return: f64,
+    yield_volatility: f64,
+    sample_count: usize,
+    data_quality: f64,
+    block_number: u64,
+    block_hash: B256,
+    /// The journal's ABI encoding, as 0x hex.
+    journal: String,
+    /// The `--windows` yields, by lookback in days.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    yield_curve: Option<&'a BTreeMap<u64, f64>>,
+    /// The `--baseline-days` comparison.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    baseline: Option<&'a BaselineDelta>,
+    /// The base yield net of the `--management-fee-apr` and `--performance-fee-bps` fees.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    net_of_fees: Option<NetYield>,
+}
+
+impl<'a> JsonOutput<'a> {
+    fn new(verification: &'a Verification, journal: &LstDexStats, host: &DexStatsOutput) -> Self {
+        JsonOutput {
+            verification,
+            base_yield: wad_to_yield(journal.baseYield),
+            base_apr: host.base_apr,
+            base_apy: host.base_apy,
+            total_return: host.total_return,
+            yield_volatility: host.yield_volatility,
+            sample_count: host.sample_count,
+            data_quality: host.data_quality,
+            block_number: journal.commitment.blockNumber.to(),
+            block_hash: journal.commitment.blockHash,
+            journal: abi_hex(journal),
+            yield_curve: None,
+            baseline: None,
+            net_of_fees: None,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Recompute the stats from the JSON input set written by `--inputs-out`, without any RPC
+    /// access, e.g. to try other smoothing parameters against the same data
+    Replay(ReplayArgs),
+    /// Fetch the headers and view call responses of the cbETH pool's window into the cache and
+    /// exit, without running the guest, so that an `--offline` run over it needs no network
+    SeedCache(SeedCacheArgs),
+    /// Merge the response caches of several runs, e.g. fetched on other machines, into one; the
+    /// entries they share must match
+    MergeCache(MergeCacheArgs),
+    /// List the blocks whose headers differ between two response caches, e.g. snapshots taken at
+    /// different times, pointing at reorgs in between
+    DiffCache(DiffCacheArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct DiffCacheArgs {
+    /// The older cache
+    a: String,
+    /// The newer cache
+    b: String,
+    /// First block to compare
+    #[arg(long)]
+    from_block: u64,
+    /// Last block to compare
+    #[arg(long)]
+    to_block: u64,
+}
+
+#[derive(clap::Args, Debug)]
+struct MergeCacheArgs {
+    /// Cache to merge into, created if it doesn't exist
+    #[arg(long)]
+    into: String,
+    /// Caches to merge
+    #[arg(required = true)]
+    sources: Vec<String>,
+    /// Store the merged cache gzip-compressed; a cache is read either way
+    #[arg(long, env = "COMPRESS_CACHE")]
+    compress_cache: bool,
+}
+
+#[derive(clap::Args, Debug)]
+struct ReplayArgs {
+    /// JSON input set written by `--inputs-out`
+    #[arg(long)]
+    inputs: PathBuf,
+    /// Block distance between consecutive samples of the set, by default the one of its run
+    #[arg(long)]
+    granularity_blocks: Option<u64>,
+    /// Keep every n-th sample, counting back from the most recent one
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u64).range(1..))]
+    skip: u64,
+    /// Fail if the oldest samples don't fill a whole stride of `--skip`, rather than leaving them
+    /// out
+    #[arg(long)]
+    reject_skip_remainder: bool,
+    /// Smooth the changes over a rolling window of this many resampled points
+    #[arg(long)]
+    rolling_window: Option<usize>,
+    /// Maximum number of consecutive missing samples to interpolate over
+    #[arg(long, default_value_t = 0)]
+    max_interpolated: usize,
+    /// Average log returns instead of simple returns
+    #[arg(long)]
+    log_returns: bool,
+    /// CSV file of `block_number,exchange_rate` rows replacing the exchange rates of the
+    /// input set, for a what-if analysis; the result is not verifiable
+    #[arg(long)]
+    override_rates: Option<PathBuf>,
+}
+
+/// The options of a run that decide which responses it requests; a run over the seeded window
+/// with other query options, e.g. `--multicall` or an oracle, still has to fetch those.
+#[derive(clap::Args, Debug)]
+struct SeedCacheArgs {
+    /// URL of the RPC endpoint; repeat the flag (or comma-separate) to fall back to further
+    /// endpoints when a request fails
+    #[arg(short, long, env = "RPC_URL", value_delimiter = ',', required = true)]
+    rpc_url: Vec<String>,
     /// Directory to cache responses
     #[arg(short, long, env = "CACHE_DIR")]
     cache_dir: String,
-    /// Start Block Number
-    #[arg(short, long, env = "END_BLOCK_NUMBER")]
-    end_block_number: Option<String>,
+    /// Store the response cache gzip-compressed; a cache is read either way
+    #[arg(long, env = "COMPRESS_CACHE")]
+    compress_cache: bool,
+    /// Block the window ends at, as a decimal or 0x-prefixed hex number, or `latest`
+    #[arg(short, long, env = "END_BLOCK_NUMBER", default_value = "latest")]
+    end_block_number: BlockSpec,
+    /// Seed the window of a smoke run instead
+    #[arg(long)]
+    smoke: bool,
+}
+
+impl ReplayArgs {
+    fn params(&self, set: &InputSet) -> DexStatsParams {
+        DexStatsParams {
+            granularity_blocks: self.granularity_blocks.unwrap_or(set.granularity_blocks),
+            skip: self.skip as usize,
+            skip_remainder: if self.reject_skip_remainder {
+                SkipRemainder::Reject
+            } else {
+                SkipRemainder::Drop
+            },
+            mode: match self.rolling_window {
+                Some(window) => ChangeMode::Rolling { window },
+                None => ChangeMode::PointToPoint,
+            },
+            max_interpolated: self.max_interpolated,
+            return_type: if self.log_returns { ReturnType::Log } else { ReturnType::Simple },
+            ..Default::default()
+        }
+    }
+
+    /// A replay is computed outside the guest, so from data it never verified.
+    fn verification(&self) -> Verification {
+        let mut verification = Verification::default();
+        verification.unverified(format!("replayed from {}", self.inputs.display()));
+        if let Some(path) = &self.override_rates {
+            verification.unverified(format!("exchange rates overridden from {}", path.display()));
+        }
+
+        verification
+    }
+}
+
+/// Exits with the code of the class of a failure, see [`exit_code`].
+fn main() -> ExitCode {
+    match try_main() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            ExitCode::from(HostError::classify(&err).exit_code())
+        }
+    }
 }
 
-fn main() -> Result<()> {
+fn try_main() -> Result<()> {
     // Initialize tracing. In order to view logs, run `RUST_LOG=info cargo run`
     tracing_subscriber::fmt().with_env_filter(EnvFilter::from_default_env()).init();
     // parse the command line arguments
     let args = Args::parse();
+    STDOUT_RESERVED.store(args.output != OutputFormat::Text, Ordering::Relaxed);
+
+    if let Some(Command::Replay(replay_args)) = &args.command {
+        let open = |path: &Path| {
+            File::open(path)
+                .map(BufReader::new)
+                .with_context(|| format!("failed to open {}", path.display()))
+        };
+        let overrides = match &replay_args.override_rates {
+            Some(path) => Some(dataset::read_rate_overrides(open(path)?)?),
+            None => None,
+        };
+        let set = InputSet::read(open(&replay_args.inputs)?)
+            .with_context(|| format!("failed to read {}", replay_args.inputs.display()))?;
+        let params = replay_args.params(&set);
+        let stats = replay(set, overrides.as_ref(), &params)?;
+        println!("Verification: {}, no proof", replay_args.verification());
+        println!("{stats}");
+        if stats.dropped_samples > 0 {
+            warning!(
+                "note: the oldest {} samples don't fill a stride of {} and were left out",
+                stats.dropped_samples,
+                replay_args.skip
+            );
+        }
+        return Ok(());
+    }
+    if let Some(Command::SeedCache(seed_args)) = &args.command {
+        return run_seed_cache(seed_args);
+    }
+    if let Some(Command::MergeCache(merge_args)) = &args.command {
+        let config = |key: &String| CacheConfig {
+            backend: FsBackend,
+            key: key.clone(),
+            compress: merge_args.compress_cache,
+        };
+        let sources: Vec<_> = merge_args.sources.iter().map(config).collect();
+        config(&merge_args.into).merge(&sources)?;
+        println!("Merged {} caches into {}", sources.len(), merge_args.into);
+        return Ok(());
+    }
+    if let Some(Command::DiffCache(diff_args)) = &args.command {
+        return run_diff_cache(diff_args);
+    }
+    if !args.smoke {
+        return run(&args);
+    }
+    match run(&args) {
+        Ok(()) => {
+            println!("smoke test passed");
+            Ok(())
+        }
+        Err(err) => {
+            println!("smoke test failed: {err:#}");
+            std::process::exit(HostError::classify(&err).exit_code().into());
+        }
+    }
+}
+
+/// Computes the stats of the cbETH pool, of each pool of the `--pool` portfolio and their
+/// weighted yield, or of each destination of the `--autopool-weights` autopool and their blended
+/// yield.
+fn run(args: &Args) -> Result<()> {
+    // don't query any pool of a portfolio that doesn't add up
+    let portfolio = match args.pool.as_slice() {
+        [] => None,
+        pools => Some(Portfolio::new(pools.iter().map(|pool| pool.allocation).collect())?),
+    };
+    let autopool =
+        args.autopool_weights.as_deref().map(read_allocation_schedule_arg).transpose()?;
+    if let Some(expected) = &args.expected_image_id {
+        check_image_id(expected, TOKEN_STATS_ID).context(HostError::Config)?;
+    }
+    if args.bonsai {
+        bonsai::check_credentials(|name| std::env::var(name).ok()).context(HostError::Config)?;
+    }
+    if args.self_test {
+        report!("Self-test, a smoke run over an in-memory chain:");
+        let stats = run_self_test()?;
+        report!(
+            "Self-test passed: the guest committed the library's yield of {:.4}%",
+            wad_to_yield(stats.baseYield) * 100.0
+        );
+    }
+
+    if let Some((pools, schedule)) = autopool {
+        let mut base_yields = Vec::with_capacity(pools.len());
+        let mut window = None;
+        for (&name, &pool) in schedule.destinations().iter().zip(&pools) {
+            report!("Destination {name}:");
+            let stats = run_pool(args, pool, &Rpc::new(args))?;
+            // the destinations are only comparable over the same window, which a head resolved
+            // anew for each may have moved
+            let head: u64 = stats.commitment.blockNumber.to();
+            let from = head - stats.windowBlocks;
+            let (first_from, first_to) = *window.get_or_insert((from, head));
+            if (first_from, first_to) != (from, head) {
+                return Err(anyhow!(
+                    "destination {name} was sampled over blocks {from} to {head}, not over blocks \
+                     {first_from} to {first_to} as the first; set --end-block-number to pin the \
+                     window"
+                )
+                .context(HostError::Config));
+            }
+            base_yields.push(wad_to_yield(stats.baseYield));
+        }
+        let (from, to) = window.expect("a schedule has destinations");
+        report!(
+            "Autopool {}",
+            schedule.blended_yield(&base_yields, from, to).context(HostError::Config)?
+        );
+        return Ok(());
+    }
+    let Some(portfolio) = portfolio else {
+        run_pool(args, PoolConfig::CBETH_ETH, &Rpc::new(args))?;
+        return Ok(());
+    };
+    let mut base_yields = Vec::with_capacity(args.pool.len());
+    for pool in &args.pool {
+        report!("Pool {}:", pool.allocation.name);
+        base_yields.push(wad_to_yield(run_pool(args, pool.pool, &Rpc::new(args))?.baseYield));
+    }
+    report!("{}", portfolio.weighted_yield(&base_yields));
+
+    Ok(())
+}
+
+/// Runs a smoke run over [`mock::smoke_chain`], guest included, and checks its journal against the
+/// yield the library computes from the samples of its `--inputs-out`.
+fn run_self_test() -> Result<LstDexStats> {
+    let pool = PoolConfig::CBETH_ETH;
+    let dir = std::env::temp_dir().join(format!("host-self-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).with_context(|| format!("failed to create {}", dir.display()))?;
+    let (cache, inputs) = (dir.join("cache.json"), dir.join("inputs.json"));
+    let utf8 =
+        |path: &Path| path.to_str().context("the temp dir is not valid UTF-8").map(str::to_owned);
+    let args = Args::try_parse_from([
+        "host".to_owned(),
+        "--smoke".to_owned(),
+        "--rpc-url".to_owned(),
+        "http://self-test".to_owned(),
+        "--cache-dir".to_owned(),
+        utf8(&cache)?,
+        "--inputs-out".to_owned(),
+        utf8(&inputs)?,
+    ])?;
+
+    let checked =
+        run_pool(&args, pool, &mock::smoke_chain(self_test::HEAD, &pool)).and_then(|journal| {
+            let set = InputSet::read(File::open(&inputs)?)?;
+            let samples: Vec<_> = set.samples.iter().map(SampleRow::input).collect();
+            let params = DexStatsParams {
+                granularity_blocks: SMOKE_GRANULARITY_BLOCKS,
+                ..Default::default()
+            };
+            self_test::check(&journal, &samples, &params)?;
+            Ok(journal)
+        });
+    // a failed cleanup of the temp dir is no failed self-test
+    let _ = std::fs::remove_dir_all(&dir);
+
+    checked
+}
+
+/// Seeds the cache with the window `seed_args` selects, as a run with the default query options
+/// over it would request it.
+fn run_seed_cache(seed_args: &SeedCacheArgs) -> Result<()> {
+    let (window_blocks, granularity_blocks) = if seed_args.smoke {
+        (SMOKE_WINDOW_BLOCKS, SMOKE_GRANULARITY_BLOCKS)
+    } else {
+        (BLOCKS_TO_QUERY, BLOCK_GRANULARITY)
+    };
+    let cache = CacheConfig {
+        backend: FsBackend,
+        key: seed_args.cache_dir.clone(),
+        compress: seed_args.compress_cache,
+    };
+    let rpc = Rpc {
+        endpoints: Endpoint::all(&seed_args.rpc_url, DEFAULT_BREAKER_THRESHOLD),
+        budget: RequestBudget::new(None),
+    };
+
+    let params = GuestParams {
+        pool: PoolConfig::CBETH_ETH,
+        stats: DexStatsParams { granularity_blocks, ..Default::default() },
+        ..Default::default()
+    };
+    let samples = seed_cache(
+        &cache,
+        rpc.connect()?,
+        &params.pool,
+        seed_args.end_block_number,
+        window_blocks,
+        granularity_blocks,
+        |header| preflight_sample(&rpc, &cache, header, &params, false).map(|_| ()),
+    )?;
+    let (query_block_num, head_block_num) = (samples[0], *samples.last().unwrap());
+    println!(
+        "Seeded {} with the {} headers of blocks {query_block_num} to {head_block_num} and {} \
+         samples",
+        seed_args.cache_dir,
+        head_block_num - query_block_num + 1,
+        samples.len()
+    );
+
+    Ok(())
+}
+
+/// Prints the blocks whose headers differ between the two caches of `diff_args`.
+fn run_diff_cache(diff_args: &DiffCacheArgs) -> Result<()> {
+    ensure!(
+        diff_args.from_block <= diff_args.to_block,
+        "--from-block {} is after --to-block {}",
+        diff_args.from_block,
+        diff_args.to_block
+    );
+    // the caches are only read, in either form
+    let config =
+        |key: &String| CacheConfig { backend: FsBackend, key: key.clone(), compress: false };
+    let diff = config(&diff_args.a).diff_headers::<EthBlockHeader, _>(
+        &config(&diff_args.b),
+        diff_args.from_block..=diff_args.to_block,
+    )?;
+
+    if diff.differing.is_empty() {
+        println!("The caches agree on the {} headers both hold", diff.compared);
+    } else {
+        println!(
+            "{} of the {} headers both caches hold differ, possibly reorged: {}",
+            diff.differing.len(),
+            diff.compared,
+            diff.differing.iter().map(u64::to_string).collect::<Vec<_>>().join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+/// Computes the stats of `pool` from the chain `chain` reaches, returning the journal.
+fn run_pool<C: Connect>(args: &Args, pool: PoolConfig, chain: &C) -> Result<LstDexStats> {
+    let pool = PoolConfig { backing: args.backing().unwrap_or(pool.backing), ..pool };
+    let baseline_lookback = args
+        .baseline_days
+        .map(|days| days.checked_add(args.recent_days).context("--baseline-days overflows"))
+        .transpose()
+        .context(HostError::Config)?;
+    let lookback = args.windows.iter().copied().chain(baseline_lookback).max();
+    let (window_blocks, granularity_blocks) = match lookback {
+        _ if args.smoke => (SMOKE_WINDOW_BLOCKS, SMOKE_GRANULARITY_BLOCKS),
+        // one fetch covers every lookback
+        Some(days) => (lookback_blocks(days, BLOCK_GRANULARITY)?, BLOCK_GRANULARITY),
+        None => (BLOCKS_TO_QUERY, BLOCK_GRANULARITY),
+    };
+    let block_list =
+        args.blocks.as_deref().map(read_block_list_arg).transpose().context(HostError::Config)?;
+    if block_list.is_none() && args.daily_close.is_none() {
+        check_window(window_blocks, granularity_blocks)?;
+        // a window of a fixed length is too long before its head is even looked up
+        if args.from_date.is_none() {
+            check_header_count(window_blocks.saturating_add(1), args.max_headers)?;
+        }
+    }
+    if args.enforce_monotonic_rate && !pool.backing.is_monotonic() {
+        return Err(anyhow!(
+            "--enforce-monotonic-rate requires an exchange-rate LST, but the {:?} backing of pool \
+             {} may decrease",
+            pool.backing,
+            pool.pool
+        )
+        .context(HostError::Config));
+    }
+    // only the cbETH pool has a default feed, which would flag every sample of another LST
+    let depeg_oracle = match (args.depeg_check, args.depeg_oracle) {
+        (None, _) => None,
+        (Some(_), Some(oracle)) => Some(oracle),
+        (Some(_), None) if pool.lst == CBETH_ADDRESS => Some(CBETH_CHAINLINK_ORACLE),
+        (Some(_), None) => {
+            return Err(anyhow!(
+                "--depeg-check of pool {} requires --depeg-oracle, a feed quoting its LST in ETH",
+                pool.pool
+            )
+            .context(HostError::Config))
+        }
+    };
+    // a previous run for another pool or granularity fails here rather than after the proof
+    let prior = args
+        .previous_run
+        .as_deref()
+        .map(|path| read_prior_run(path, &pool, granularity_blocks))
+        .transpose()
+        .context(HostError::Config)?;
 
     // Create a view call environment from an RPC endpoint and a block number. If no block number is
     // provided, the latest block is used. The `with_chain_spec` method is used to specify the
     // chain configuration.
-    let cache_dir = PathBuf::from(args.cache_dir);
-    let client = EthersClient::new_client(&args.rpc_url, 3, 500)?;
-    let provider = EthersProvider::new(client);
-
-    let head_block_num = if let Some(end_block) = &args.end_block_number {
-        end_block.parse::<u64>().unwrap()
-    } else {
-        provider.get_block_number()?
+    let cache_dir = args.cache_dir.clone().context("--cache-dir is required");
+    let cache = CacheConfig {
+        backend: FsBackend,
+        key: cache_dir.context(HostError::Config)?,
+        compress: args.compress_cache,
     };
 
-    let cache_provider = CachedProvider::new(cache_dir.clone(), provider).unwrap();
+    // the head of the last run, whose unfinalized headers are checked for reorgs with the new ones
+    let previous_head = cache.head()?;
+    // Every read goes through the cache, so that a run can be repeated offline from what it
+    // cached. Each cache is open only as long as it is read from, since the last one closed is the
+    // one kept.
+    let (block_list, date_range, head_block_num) = {
+        let provider = chain.open(&cache)?;
+        let block_times = BlockTimes::new(&provider);
+        let date_range = match (args.from_date, args.to_date) {
+            (Some(from), Some(to)) => Some(resolve_date_range(
+                &block_times,
+                from,
+                to,
+                provider.get_block_number()?,
+                granularity_blocks,
+            )?),
+            _ => None,
+        };
+        // the closes are sampled like a block list
+        let block_list = match args.daily_close {
+            Some(days) => {
+                let end = args.end_block_number.resolve(|| provider.get_block_number())?;
+                Some(block_times.daily_closes(days, end)?)
+            }
+            None => block_list,
+        };
+        let mut head_block_num = match (&block_list, date_range, args.end_timestamp) {
+            (Some(blocks), _, _) => *blocks.last().unwrap(),
+            (None, Some((_, to)), _) => to,
+            (None, None, Some(timestamp)) => {
+                block_times.block_at_timestamp(timestamp, provider.get_block_number()?)?
+            }
+            (None, None, None) => args.end_block_number.resolve(|| provider.get_block_number())?,
+        };
+        if args.epoch_aligned {
+            let latest = provider.get_block_number()?;
+            head_block_num = block_times.finalized_epoch_start(
+                &BeaconSchedule::MAINNET,
+                head_block_num,
+                latest,
+            )?;
+        }
+        (block_list, date_range, head_block_num)
+    };
+    // offline, the chain isn't there to compare against
+    if args.reorg_check_depth > 0 && !args.offline {
+        let live = chain.connect()?;
+        let depth = args.reorg_check_depth;
+        let reorged = cache.invalidate_reorged(&live, previous_head, head_block_num, depth)?;
+        if !reorged.is_empty() {
+            warning!(
+                "Cached headers of blocks {reorged:?} changed in a reorg, discarded what was \
+                 cached for them"
+            );
+        }
+    }
+    // a wrong chain or a mistyped address shows as a contract without code
+    let contracts = [
+        ("LST", Some(pool.lst)),
+        ("pool", Some(pool.pool)),
+        ("oracle", args.oracle),
+        ("denomination oracle", args.denomination_oracle),
+        ("Convex reward pool", args.convex_reward_pool),
+        ("reward oracle", args.reward_oracle),
+        ("reference APY contract", args.reference_apy_contract),
+    ];
+    let contracts = contracts.iter().filter_map(|&(name, address)| Some((name, address?)));
+    let provider = chain.open(&cache)?;
+    check_contracts_deployed(&provider, contracts, head_block_num)?;
 
     // Take a block x behind head, to check hash linking to commitment
-    let query_block_num = head_block_num - BLOCKS_TO_QUERY;
+    let query_block_num = match (&block_list, date_range) {
+        (Some(blocks), _) => blocks[0],
+        (None, Some((from, _))) => from,
+        // missed slots make an epoch fewer blocks, so its start is looked up rather than counted
+        (None, None) if args.epoch_aligned => BlockTimes::new(&provider).epoch_window_start(
+            &BeaconSchedule::MAINNET,
+            head_block_num,
+            window_blocks / SLOTS_PER_EPOCH,
+        )?,
+        (None, None) => window_start(head_block_num, window_blocks)?,
+    };
+    drop(provider);
+    check_not_empty(query_block_num, head_block_num)?;
+    let window_blocks = head_block_num - query_block_num;
+    for warning in
+        upgrades::upgrade_warnings(upgrades::MAINNET_CHAIN_ID, query_block_num, head_block_num)
+    {
+        warning!("{warning}");
+    }
 
     // used for logging time for specific operations
     let current_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let mut stages = Vec::new();
+
+    // the feed decimals only need to be queried once, they are fixed for the feed's lifetime
+    let price_feed = args
+        .oracle
+        .map(|address| {
+            let decimals = args.oracle_decimals;
+            let default = args.default_decimals;
+            resolve_feed(chain, &cache, head_block_num, address, decimals, default)
+        })
+        .transpose()?;
+    let reference_feed = args
+        .denomination_oracle
+        .map(|address| {
+            resolve_feed(
+                chain,
+                &cache,
+                head_block_num,
+                address,
+                args.denomination_oracle_decimals,
+                args.default_decimals,
+            )
+        })
+        .transpose()?;
+    let convex = args
+        .convex_reward_pool
+        .zip(args.reward_oracle)
+        .map(|(reward_pool, address)| -> Result<_> {
+            let reward_feed = resolve_feed(
+                chain,
+                &cache,
+                head_block_num,
+                address,
+                args.reward_oracle_decimals,
+                args.default_decimals,
+            )?;
+            Ok(ConvexRewards { reward_pool, reward_feed })
+        })
+        .transpose()?;
+    let denominated_from = feeds_deployed_from(
+        &chain.open(&cache)?,
+        price_feed.iter().chain(&reference_feed),
+        query_block_num,
+        head_block_num,
+    )?;
+    if let Some(from) = denominated_from {
+        warning!(
+            "warning: the price feeds only exist from block {from} on; earlier samples are left \
+             out of the denominated yield"
+        );
+    }
+
+    let params = GuestParams {
+        pool,
+        query_mode: if args.multicall { QueryMode::Multicall } else { QueryMode::Individual },
+        price_feed,
+        reference_feed,
+        denominated_from,
+        finality_depth: args.exclude_unfinalized.then_some(args.finality_depth),
+        convex,
+        stats: DexStatsParams {
+            granularity_blocks,
+            max_interpolated: args.max_interpolated,
+            // the samples left out leave gaps the changes span rather than interpolate over
+            alignment: if args.align_to_midnight {
+                SampleAlignment::Midnight
+            } else if block_list.is_some() || args.continue_on_error {
+                SampleAlignment::Irregular
+            } else {
+                SampleAlignment::Blocks
+            },
+            max_block_gap: args.max_block_gap,
+            min_time_delta: args.min_time_delta,
+            min_interval_seconds: args.min_interval_seconds,
+            aggregation: if args.tvl_weighted {
+                Aggregation::TvlWeighted
+            } else {
+                Aggregation::default()
+            },
+            ..Default::default()
+        },
+    };
+
+    // The guest input is assembled as it is fetched: the headers and preflights are produced on a
+    // background thread at most `buffer_size` items ahead and released once written to the env.
+    let mut env = GuestInput::new();
+    if let Some(path) = &args.guest_input_out {
+        env.record_to(
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?,
+        );
+    }
+    env.write(&GUEST_INPUT_VERSION)?;
+    env.write(&params)?;
+
+    // headers used for historical header validation; only the sampled ones are kept around
+    let stride = match block_list {
+        Some(blocks) => blocks,
+        None => sample_blocks(query_block_num, head_block_num, granularity_blocks)?,
+    };
+    check_sample_count(stride.len(), args.max_samples)?;
+    check_header_count(head_block_num - query_block_num + 1, args.max_headers)?;
+    let mut midnights = args.align_to_midnight.then(MidnightSampler::default);
+    let headers = {
+        let (chain, cache) = (chain.clone(), cache.clone());
+        Prefetch::spawn(args.buffer_size as usize, move |sink| {
+            // the cached provider writes its data when it is dropped at the end of the thread
+            let provider = chain.open(&cache)?;
+            fetch_headers(&provider, query_block_num, head_block_num, |header| sink.push(header))
+        })
+    };
+    let mut sample_headers = Vec::with_capacity(stride.len());
+    let mut head_link = args.trusted_head_hash.map(HeadLink::new);
+    let headers = headers.map(|header| {
+        let header = header?;
+        if let Some(link) = &mut head_link {
+            link.push(&header)?;
+        }
+        match &mut midnights {
+            Some(sampler) => sample_headers.extend(sampler.push(header.clone())),
+            None if stride.binary_search(&header.number).is_ok() => {
+                sample_headers.push(header.clone())
+            }
+            None => {}
+        }
+        Ok(header)
+    });
+    write_seq(&mut env, (head_block_num - query_block_num + 1) as usize, headers)?;
+    if let Some(link) = head_link {
+        link.finish()?;
+        report!("The headers link up to the trusted head {}", args.trusted_head_hash.unwrap());
+    }
+    let samples: Vec<u64> = sample_headers.iter().map(|header| header.number).collect();
+    let current_time = log_time_delta("get_headers", current_time, &mut stages);
+
+    let mut dataset = match &args.dataset_out {
+        Some(path) => Some(DatasetWriter::new(
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?,
+        )?),
+        None => None,
+    };
+
+    let min_tvl: Option<U256> = args
+        .min_tvl_eth
+        .map(|eth| parse_units(&eth.to_string(), "ether").map(Into::into))
+        .transpose()
+        .context("invalid --min-tvl-eth")?;
 
     // TODO: parallelize
-    // headers used for historical header validation
-    let headers_from_query: Vec<_> = (query_block_num..=head_block_num)
-        .into_iter()
-        .map(|block_num| {
-            cache_provider
-                .get_block_header(block_num)
-                .with_context(|| format!("could not retrieve block {block_num}"))?
-                .with_context(|| format!("block at height {block_num} not found"))
+    let preflights = {
+        let (chain, cache, params, sample_headers) =
+            (chain.clone(), cache.clone(), params.clone(), sample_headers.clone());
+        let query_tvl = min_tvl.is_some() || args.tvl_weighted;
+        Prefetch::spawn(args.buffer_size as usize, move |sink| {
+            for header in sample_headers {
+                let preflight = preflight_sample(&chain, &cache, &header, &params, query_tvl);
+                if !sink.push(preflight) {
+                    break;
+                }
+            }
+            Ok(())
         })
-        .collect::<Result<_>>()?;
-    let current_time = log_time_delta("get_headers", current_time);
+    };
+    let mut dex_inputs: Vec<DexStatsInput> = Vec::new();
+    // the rows of the observed samples, for `--inputs-out`
+    let mut rows: Vec<SampleRow> = Vec::new();
+    // the last observed sample's exchange rate
+    let mut last_rate = None;
+    let mut failures = FailedSamples {
+        max_interpolated: args.max_interpolated,
+        continue_on_error: args.continue_on_error,
+        head: head_block_num,
+        ..Default::default()
+    };
+    // samples that couldn't be queried are passed as `None`
+    let inputs =
+        preflights.zip(samples.iter().enumerate()).map(|(preflight, (index, &block_num))| {
+            match preflight? {
+                Ok((input, row, tvl)) => {
+                    // an illiquid sample is no reason to interpolate, it fails the run
+                    if let (Some(min_tvl), Some(tvl)) = (min_tvl, tvl) {
+                        check_liquidity(block_num, tvl, min_tvl)?;
+                    }
+                    if args.enforce_monotonic_rate {
+                        check_monotonic_rate(last_rate, block_num, row.exchange_rate)?;
+                    }
+                    last_rate = Some((block_num, row.exchange_rate));
+                    // only the samples the stats are computed from, see `tokemak::resample`
+                    if let Some(dataset) = &mut dataset {
+                        if (samples.len() - 1 - index) % params.stats.skip == 0 {
+                            dataset.write(&row)?;
+                        }
+                    }
+                    dex_inputs.push(DexStatsInput {
+                        timestamp: row.timestamp,
+                        block_number: block_num,
+                        lst_backing: row.backing,
+                        interpolated: false,
+                        pool_tvl: tvl,
+                    });
+                    if args.inputs_out.is_some() {
+                        rows.push(row);
+                    }
+                    failures.observed();
+                    Ok(Some(input))
+                }
+                Err(err) => failures.leave_out(block_num, err).map(|()| None),
+            }
+        });
+    write_seq(&mut env, samples.len(), inputs)?;
+    if let Some(dataset) = dataset {
+        let rows = dataset.finish()?;
+        report!("Wrote {rows} samples to {}", args.dataset_out.as_ref().unwrap().display());
+    }
+    let current_time = log_time_delta("preflights", current_time, &mut stages);
+
+    // don't spend executor time on a schedule the guest rejects; the midnight samples are picked
+    // from the headers as they came in, the others were laid out ahead
+    let schedule = if args.align_to_midnight { &samples } else { &stride };
+    let checked =
+        schedule::validate_schedule(&dex_inputs, schedule, &sample_headers, &params.stats);
+    checked.map_err(|issues| {
+        let issues: Vec<_> = issues.iter().map(ToString::to_string).collect();
+        anyhow!("invalid sample schedule:\n  {}", issues.join("\n  "))
+    })?;
+
+    // the journal only carries the yield, so the remaining stats are recomputed from the same
+    // samples the guest sees
+    let dex_inputs = match params.denominated_from {
+        Some(from) => exclude_before(&dex_inputs, from),
+        None => &dex_inputs,
+    };
+    let dex_inputs = match params.finality_depth {
+        Some(depth) => exclude_unfinalized(dex_inputs, head_block_num, depth),
+        None => dex_inputs,
+    };
+
+    report!("Running the guest with the constructed input:");
+    let input_hash = env.digest();
+    let env = env.build().context("Failed to build exec env")?;
+    let journal = if args.bonsai {
+        bonsai::prove(&bonsai::SdkClient::default(), env, TOKEN_STATS_ELF, TOKEN_STATS_ID)
+    } else if let Some(path) = &args.resume {
+        SessionDir::open(path)
+            .and_then(|dir| checkpoint::prove(env, TOKEN_STATS_ELF, TOKEN_STATS_ID, &dir))
+            .map(|proof| {
+                report!(
+                    "Proven locally, {} of {} segments resumed from {}",
+                    proof.resumed,
+                    proof.segments,
+                    path.display()
+                );
+                proof.journal
+            })
+    } else {
+        execute(env).map(|session_info| session_info.journal)
+    };
+    let journal = match journal {
+        Ok(journal) => journal,
+        Err(err) => {
+            // all that was fetched still gives a yield, if not a proven one
+            let window = (query_block_num, head_block_num);
+            warning!(
+                "{}",
+                UnprovenResult::new(&err, dex_inputs, &params.stats, window, samples.len())
+            );
+            return Err(err.context(HostError::Guest));
+        }
+    };
+    let current_time = log_time_delta("executor", current_time, &mut stages);
+    if args.bonsai {
+        report!(
+            "Proven on Bonsai, the receipt verifies against image ID {}",
+            ImageId::from(TOKEN_STATS_ID)
+        );
+    }
+
+    let stats = LstDexStats::abi_decode(&journal.bytes, true)?;
+    if let Some(path) = &args.manifest_out {
+        let manifest = manifest::Manifest::new(
+            TOKEN_STATS_ID,
+            &args.rpc_url,
+            input_hash,
+            pool.pool,
+            granularity_blocks,
+            &stats.commitment,
+            &sample_headers,
+        );
+        manifest.write(path)?;
+        report!("Wrote the provenance manifest to {}", path.display());
+    }
+    if let Some(path) = &args.inputs_out {
+        // the samples left out of the stats are left out of the set
+        let first = dex_inputs.first().map_or(u64::MAX, |input| input.block_number);
+        let last = dex_inputs.last().map_or(0, |input| input.block_number);
+        rows.retain(|row| (first..=last).contains(&row.block_number));
+        let set = InputSet::new(&stats.commitment, granularity_blocks, rows);
+        set.write(path)?;
+        report!("Wrote {} samples to {}", set.samples.len(), path.display());
+    }
+    match args.output {
+        OutputFormat::Text => report!("{}", stats),
+        OutputFormat::AbiHex => println!("{}", abi_hex(&stats)),
+        OutputFormat::Eip712 => {
+            let verifying_contract = args.eip712_verifying_contract.expect("required for eip712");
+            let domain = eip712::domain(upgrades::MAINNET_CHAIN_ID, verifying_contract);
+            println!("{}", serde_json::to_string_pretty(&eip712::typed_data(&stats, &domain))?);
+        }
+        // printed with the host stats below
+        OutputFormat::Json => {}
+    }
+    log_time_delta("end", current_time, &mut stages);
+
+    let mut host_stats = try_calculate_dex_stats(dex_inputs, &params.stats)?;
+    host_stats.data_quality *= failures.kept_share(samples.len());
+    report!("{}", host_stats);
+    if !failures.skipped.is_empty() {
+        report!(
+            "Skipped {} of {} samples, data quality {:.0}%:",
+            failures.skipped.len(),
+            samples.len(),
+            host_stats.data_quality * 100.0
+        );
+        for (block_num, err) in &failures.skipped {
+            report!("  block {block_num}: {err}");
+        }
+    }
+    let verification = args.verification(&failures);
+    report!("Verification: {verification}");
+    let curve = match args.windows.as_slice() {
+        [] => None,
+        windows => Some(yield_curve(dex_inputs, windows, &params.stats)?),
+    };
+    if let Some(curve) = &curve {
+        let yields: Vec<_> = curve
+            .iter()
+            .map(|(days, base_yield)| format!("{days}d {:.2}%", base_yield * 100.0))
+            .collect();
+        report!("Yield curve: {}", yields.join(", "));
+    }
+    let baseline = args
+        .baseline_days
+        .map(|days| yield_vs_baseline(dex_inputs, args.recent_days, days, &params.stats))
+        .transpose()?;
+    if let Some(baseline) = &baseline {
+        report!("{baseline}");
+    }
+    if let Some(prior) = &prior {
+        let continuity =
+            check_continuity(prior, dex_inputs, &params.stats, args.continuity_tolerance)?;
+        if continuity.is_consistent() {
+            report!("{continuity}");
+        } else {
+            warning!("{continuity}");
+        }
+    }
+    let net_of_fees = args.fees().map(|fees| fees.apply(wad_to_yield(stats.baseYield)));
+    if let Some(net) = &net_of_fees {
+        report!("Base yield {net}");
+    }
+    if args.cross_check {
+        cross_check(&stats, &host_stats, args.cross_check_tolerance)?;
+        report!("Cross-check passed: the guest and host yields agree");
+    }
+    if let Some(contract) = args.reference_apy_contract {
+        let cp = chain.open(&cache)?;
+        let mut env = EthViewCallEnv::from_provider(cp, head_block_num)?
+            .with_chain_spec(&ETH_MAINNET_CHAIN_SPEC);
+        let reference = ReferenceApy { contract }.query(&mut Preflight(&mut env))?;
+        // the headline yield is a simple rate by default; compare like with like
+        let comparison = ApyComparison { computed: host_stats.base_apy, reference };
+        report!("Reference APY: {comparison}");
+        if comparison.diverges(args.reference_apy_tolerance) {
+            warning!(
+                "warning: the computed yield diverges from the reference by more than {} bps",
+                args.reference_apy_tolerance * 10_000.0
+            );
+        }
+    }
+
+    if let Some(max_deviation) = args.virtual_price_check {
+        let latest = chain.open(&cache)?.get_block_number()?;
+        let query = |block_num| query_virtual_price(chain, &cache, pool.pool, block_num);
+        let samples = samples
+            .iter()
+            .map(|&block_num| {
+                Ok(VirtualPriceSample {
+                    block_number: block_num,
+                    before: block_num.checked_sub(1).map(&query).transpose()?,
+                    at: query(block_num)?,
+                    after: (block_num < latest).then(|| query(block_num + 1)).transpose()?,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let flagged = virtual_price::flag_manipulated(&samples, max_deviation);
+        for suspicious in &flagged {
+            warning!("{suspicious}");
+        }
+        if flagged.is_empty() {
+            report!("Virtual price check passed for {} samples", samples.len());
+        }
+    }
+
+    if let (Some(max_divergence), Some(oracle)) = (args.depeg_check, depeg_oracle) {
+        let default = args.default_decimals;
+        let feed = resolve_feed(chain, &cache, head_block_num, oracle, None, default)?;
+        let samples = samples
+            .iter()
+            .map(|&block_num| query_depeg_prices(chain, &cache, pool.pool, feed, block_num))
+            .collect::<Result<Vec<_>>>()?;
+        let flagged = depeg::flag_depegs(&samples, max_divergence);
+        for depeg in &flagged {
+            warning!("{depeg}");
+        }
+        if flagged.is_empty() {
+            report!("Depeg check passed for {} samples", samples.len());
+        }
+    }
+
+    if let Some(radius) = args.virtual_price_window {
+        let latest = chain.open(&cache)?.get_block_number()?;
+        let query = |block_num| query_virtual_price(chain, &cache, pool.pool, block_num);
+        let inputs = dex_inputs
+            .iter()
+            .map(|input| {
+                let lst_backing =
+                    virtual_price::averaged(query, input.block_number, radius, latest)?;
+                Ok(DexStatsInput { lst_backing, ..input.clone() })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let fee_stats = try_calculate_dex_stats(&inputs, &params.stats)?;
+        report!(
+            "Fee yield, UNPROVEN, from the virtual price averaged over ±{radius} blocks: {:.4}%",
+            fee_stats.base_yield * 100.0
+        );
+    }
+
+    if args.report {
+        let schedule = ScheduleSummary {
+            first_block: query_block_num,
+            last_block: head_block_num,
+            granularity_blocks,
+            scheduled: samples.len(),
+            used: host_stats.sample_count,
+            interpolated: failures.interpolated.len(),
+            skipped: failures.skipped.len(),
+            dropped: host_stats.dropped_samples,
+        };
+        let report = Report {
+            yield_curve: curve.as_ref(),
+            ..Report::new(&verification, &stats, &host_stats, schedule)
+        }
+        .with_risk_free_apr(args.risk_free_apr, &host_stats);
+        let report = match args.fees() {
+            Some(fees) => report.with_fees(fees),
+            None => report,
+        };
+        match args.output {
+            OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+            _ => report!("{report}"),
+        }
+    } else if args.output == OutputFormat::Json {
+        let output = JsonOutput {
+            yield_curve: curve.as_ref(),
+            baseline: baseline.as_ref(),
+            net_of_fees,
+            ..JsonOutput::new(&verification, &stats, &host_stats)
+        };
+        println!("{}", serde_json::to_string_pretty(&output)?);
+    }
+
+    if let Some(path) = &args.metrics_out {
+        metrics::Metrics::new(&host_stats, stages).write(path)?;
+    }
+    Ok(stats)
+}
+
+/// Runs the guest over `env`.
+fn execute(env: ExecutorEnv) -> Result<SessionInfo> {
+    default_executor().execute(env, TOKEN_STATS_ELF).context("failed to run executor")
+}
+
+/// The yield the host computes from the guest's inputs when the executor fails on them, so that a
+/// run isn't lost after all the fetching. Whether the host computes a yield tells apart a failure
+/// of the guest from one of the data.
+struct UnprovenResult {
+    error: String,
+    first_block: u64,
+    last_block: u64,
+    scheduled: usize,
+    observed: usize,
+    host_stats: Result<DexStatsOutput, DexStatsError>,
+}
 
-    // manually drop the cached provider to ensure it writes its data
-    drop(cache_provider);
+impl UnprovenResult {
+    fn new(
+        error: &anyhow::Error,
+        inputs: &[DexStatsInput],
+        params: &DexStatsParams,
+        (first_block, last_block): (u64, u64),
+        scheduled: usize,
+    ) -> Self {
+        UnprovenResult {
+            error: format!("{error:#}"),
+            first_block,
+            last_block,
+            scheduled,
+            observed: inputs.len(),
+            host_stats: try_calculate_dex_stats(inputs, params),
+        }
+    }
+}
+
+impl fmt::Display for UnprovenResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "The guest failed: {}", self.error)?;
+        writeln!(
+            f,
+            "Its input: {} of {} scheduled samples from block {} to {}",
+            self.observed, self.scheduled, self.first_block, self.last_block
+        )?;
+        match &self.host_stats {
+            Ok(stats) => write!(
+                f,
+                "UNPROVEN, computed by the host from the same samples: {stats}\nthe data gives a \
+                 yield, so the guest is likely at fault"
+            ),
+            Err(err) => write!(
+                f,
+                "the host fails on the same samples as well: {err}\nthe data is likely at fault"
+            ),
+        }
+    }
+}
+
+/// Decides whether a sample that failed to be queried fails the run or is left out of the guest
+/// input.
+#[derive(Debug, Default)]
+struct FailedSamples {
+    max_interpolated: usize,
+    continue_on_error: bool,
+    head: u64,
+    /// Whether a sample was observed yet.
+    any_observed: bool,
+    /// Consecutive samples left out since the last observed one.
+    missing: usize,
+    /// The samples left out under `continue_on_error`, with why they failed.
+    skipped: Vec<(u64, String)>,
+    /// The samples the guest interpolates over.
+    interpolated: Vec<u64>,
+}
+
+impl FailedSamples {
+    fn observed(&mut self) {
+        self.any_observed = true;
+        self.missing = 0;
+    }
+
+    /// Returns `err` unless the sample at `block_num` may be left out.
+    fn leave_out(&mut self, block_num: u64, err: anyhow::Error) -> Result<()> {
+        // the window ends at the committed head
+        if block_num == self.head {
+            return Err(err);
+        }
+        if self.continue_on_error {
+            warning!("warning: sample at block {block_num} failed, skipping: {err:#}");
+            self.skipped.push((block_num, format!("{err:#}")));
+            return Ok(());
+        }
+        // the guest interpolates over the gap; it needs observed samples on both sides of it
+        if self.missing < self.max_interpolated && self.any_observed {
+            warning!("warning: sample at block {block_num} unavailable, interpolating: {err:#}");
+            self.missing += 1;
+            self.interpolated.push(block_num);
+            return Ok(());
+        }
+
+        Err(err)
+    }
+
+    /// The share of the `scheduled` samples not left out under `continue_on_error`.
+    fn kept_share(&self, scheduled: usize) -> f64 {
+        (scheduled - self.skipped.len()) as f64 / scheduled as f64
+    }
+
+    /// A run leaving samples out computes the yield from verified data, but not all of it.
+    fn verification(&self) -> Verification {
+        let mut verification = Verification::default();
+        if !self.skipped.is_empty() {
+            verification.partially(format!(
+                "{} samples left out by --continue-on-error",
+                self.skipped.len()
+            ));
+        }
+        if !self.interpolated.is_empty() {
+            verification.partially(format!(
+                "{} samples interpolated over by --max-interpolated",
+                self.interpolated.len()
+            ));
+        }
+
+        verification
+    }
+}
+
+/// The base yield over each lookback of `windows` days, computed from the samples of `inputs` up to
+/// that many sampling intervals before the last one.
+fn yield_curve(
+    inputs: &[DexStatsInput],
+    windows: &[u64],
+    params: &DexStatsParams,
+) -> Result<BTreeMap<u64, f64>> {
+    let last = inputs.last().context("no samples")?.block_number;
+    windows
+        .iter()
+        .map(|&days| {
+            let from = last.saturating_sub(lookback_blocks(days, params.granularity_blocks)?);
+            let start = inputs.partition_point(|input| input.block_number < from);
+            let stats = try_calculate_dex_stats(&inputs[start..], params)
+                .with_context(|| format!("failed to compute the {days} day yield"))?;
+            Ok((days, stats.base_yield))
+        })
+        .collect()
+}
+
+/// The yield of a recent period against that of the baseline period before it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+struct BaselineDelta {
+    recent_days: u64,
+    baseline_days: u64,
+    recent_yield: f64,
+    baseline_yield: f64,
+    /// `recent_yield - baseline_yield`; positive when the yield accelerated.
+    delta: f64,
+}
+
+impl fmt::Display for BaselineDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = if self.delta < 0.0 { "below" } else { "above" };
+        write!(
+            f,
+            "Yield over the last {} days: {:.2}%, {:.2}% {direction} the {:.2}% of the {} days \
+             before",
+            self.recent_days,
+            self.recent_yield * 100.0,
+            self.delta.abs() * 100.0,
+            self.baseline_yield * 100.0,
+            self.baseline_days
+        )
+    }
+}
+
+/// Compares the base yield over the last `recent_days` of `inputs` with the baseline over the
+/// `baseline_days` before them. The baseline ends at the sample the recent period starts at, so
+/// that the two cover consecutive intervals; it fails if the samples don't reach back that far.
+fn yield_vs_baseline(
+    inputs: &[DexStatsInput],
+    recent_days: u64,
+    baseline_days: u64,
+    params: &DexStatsParams,
+) -> Result<BaselineDelta> {
+    let (first, last) = match inputs {
+        [first, .., last] => (first.block_number, last.block_number),
+        _ => bail!("too few samples for a baseline"),
+    };
+    let granularity = params.granularity_blocks;
+    let recent_from = last.saturating_sub(lookback_blocks(recent_days, granularity)?);
+    let baseline_from = recent_from.saturating_sub(lookback_blocks(baseline_days, granularity)?);
+    ensure!(
+        first <= baseline_from,
+        "the samples start at block {first}, after the {baseline_days} day baseline starting at \
+         block {baseline_from}"
+    );
+    let recent_start = inputs.partition_point(|input| input.block_number < recent_from);
+    let baseline_start = inputs.partition_point(|input| input.block_number < baseline_from);
 
+    let recent_yield = try_calculate_dex_stats(&inputs[recent_start..], params)
+        .context("failed to compute the recent yield")?
+        .base_yield;
+    let baseline_yield = try_calculate_dex_stats(&inputs[baseline_start..=recent_start], params)
+        .context("failed to compute the baseline yield")?
+        .base_yield;
+
+    Ok(BaselineDelta {
+        recent_days,
+        baseline_days,
+        recent_yield,
+        baseline_yield,
+        delta: recent_yield - baseline_yield,
+    })
+}
+
+/// Recomputes the stats from the samples of an input set written by `--inputs-out`, with the
+/// exchange rates replaced by `overrides` if given.
+fn replay(
+    mut set: InputSet,
+    overrides: Option<&BTreeMap<u64, U256>>,
+    params: &DexStatsParams,
+) -> Result<DexStatsOutput> {
+    if let Some(overrides) = overrides {
+        dataset::apply_rate_overrides(&mut set.samples, overrides)?;
+    }
+    let samples: Vec<_> = set.samples.iter().map(SampleRow::input).collect();
+
+    Ok(try_calculate_dex_stats(&samples, params)?)
+}
+
+/// The ABI encoding of the journal as a 0x-prefixed hex string.
+fn abi_hex(stats: &LstDexStats) -> String {
+    hex::encode_prefixed(stats.abi_encode())
+}
+
+/// Fails if the yield committed by the guest differs from the one the host computed over the same
+/// samples by more than `tolerance`.
+fn cross_check(journal: &LstDexStats, host: &DexStatsOutput, tolerance: f64) -> Result<()> {
+    let guest_yield = wad_to_yield(journal.baseYield);
+    let delta = (guest_yield - host.base_yield).abs();
+    ensure!(
+        delta <= tolerance,
+        "guest yield {guest_yield} differs from the host yield {} by {delta}, more than \
+         {tolerance}",
+        host.base_yield
+    );
+
+    Ok(())
+}
+
+/// Fetches the contiguous range of headers from `from` to `to`, inclusive, handing each to `push`
+/// as it arrives. Stops early once `push` returns false.
+fn fetch_headers<P>(
+    provider: &P,
+    from: u64,
+    to: u64,
+    mut push: impl FnMut(P::Header) -> bool,
+) -> Result<()>
+where
+    P: Provider,
+    P::Header: ChainHeader,
+{
     // TODO: parallelize
-    let mut inputs: Vec<ViewCallInput<EthBlockHeader>> = Vec::new();
-    for block_num in (query_block_num..=head_block_num).step_by(BLOCK_GRANULARITY as usize) {
-        let c = EthersClient::new_client(&args.rpc_url, 3, 500)?;
-        let p = EthersProvider::new(c);
-        let cp = CachedProvider::new(cache_dir.clone(), p)?;
+    for block_num in from..=to {
+        let header = provider
+            .get_block_header(block_num)
+            .with_context(|| format!("could not retrieve block {block_num}"))?
+            .with_context(|| format!("block at height {block_num} not found"))?;
+        // don't let a faulty provider slip a different block into the chain
+        ensure!(
+            header.number() == block_num,
+            "provider returned block {} for height {block_num}",
+            header.number()
+        );
+        if !push(header) {
+            break;
+        }
+    }
 
-        let mut env =
-            EthViewCallEnv::from_provider(cp, block_num)?.with_chain_spec(&ETH_MAINNET_CHAIN_SPEC);
+    Ok(())
+}
 
-        env.preflight(ViewCall::new(cbETHInterface::exchangeRateCall {}, CBETH_ADDRESS))?._0;
+/// Fetches what a run over `pool` with the window of `window_blocks` up to `end` reads before its
+/// preflights into `cache` through `provider`: the head, the code of the pool's contracts at it and
+/// the headers of the window. Then hands each sampled header to `preflight` to fetch its view
+/// calls. The headers are written back before the first preflight, which opens the cache anew.
+/// Returns the sampled blocks, from the window's first block to its head.
+fn seed_cache<P, B>(
+    cache: &CacheConfig<B>,
+    provider: P,
+    pool: &PoolConfig,
+    end: BlockSpec,
+    window_blocks: u64,
+    granularity_blocks: u64,
+    mut preflight: impl FnMut(&EthBlockHeader) -> Result<()>,
+) -> Result<Vec<u64>>
+where
+    P: Provider,
+    B: CacheBackend + Clone,
+    Cache<P, B>: Provider<Header = EthBlockHeader>,
+{
+    let mut sample_headers = Vec::new();
+    let samples = {
+        let provider = cache.open(provider)?;
+        let to = end.resolve(|| provider.get_block_number())?;
+        let from = window_start(to, window_blocks)?;
+        let samples = sample_blocks(from, to, granularity_blocks)?;
+        check_contracts_deployed(&provider, [("LST", pool.lst), ("pool", pool.pool)], to)?;
+        fetch_headers(&provider, from, to, |header| {
+            if samples.binary_search(&header.number).is_ok() {
+                sample_headers.push(header);
+            }
+            true
+        })?;
+        samples
+    };
+    for header in &sample_headers {
+        preflight(header).with_context(|| format!("failed to seed block {}", header.number))?;
+    }
 
-        let input = env.into_zkvm_input()?;
-        inputs.push(input);
+    Ok(samples)
+}
+
+/// Reads the `--blocks` list from `path`, or from stdin for `-`.
+fn read_block_list_arg(path: &Path) -> Result<Vec<u64>> {
+    if path == Path::new("-") {
+        return cli::read_block_list(io::stdin().lock());
     }
-    let current_time = log_time_delta("preflights", current_time);
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
 
-    println!("Running the guest with the constructed input:");
-    let session_info = {
-        let env = ExecutorEnv::builder()
-            .write(&inputs)?
-            .write(&headers_from_query)?
-            .build()
-            .context("Failed to build exec env")?;
-        let exec = default_executor();
-        exec.execute(env, TOKEN_STATS_ELF).context("failed to run executor")?
+    cli::read_block_list(BufReader::new(file))
+}
+
+/// Reads the `--autopool-weights` schedule from `path`.
+fn read_allocation_schedule_arg(path: &Path) -> Result<(Vec<PoolConfig>, AllocationSchedule)> {
+    let file = File::open(path).with_context(|| format!("failed to open {}", path.display()))?;
+
+    cli::read_allocation_schedule(BufReader::new(file))
+        .with_context(|| format!("invalid allocation schedule {}", path.display()))
+        .context(HostError::Config)
+}
+
+/// Resolves the calendar range from `from` to `to` into the window's first and last block: the last
+/// block at or before `to`, and a whole number of `granularity_blocks` intervals back the earliest
+/// block at or after the last one at or before `from`.
+fn resolve_date_range<P>(
+    times: &BlockTimes<P>,
+    from: DateTime,
+    to: DateTime,
+    latest: u64,
+    granularity_blocks: u64,
+) -> Result<(u64, u64)>
+where
+    P: Provider,
+    P::Header: ChainHeader,
+{
+    ensure!(
+        from.timestamp < to.timestamp,
+        "--from-date ({}) is not before --to-date ({})",
+        from.timestamp,
+        to.timestamp
+    );
+    ensure!(
+        to.timestamp <= times.timestamp_at_block(latest)?,
+        "--to-date ({}) is past the latest block {latest}",
+        to.timestamp
+    );
+
+    let first = times.block_at_timestamp(from.timestamp, latest)?;
+    let last = times.block_at_timestamp(to.timestamp, latest)?;
+    let intervals = (last - first) / granularity_blocks;
+    ensure!(
+        intervals > 0,
+        "the range from block {first} to {last} is shorter than a sampling interval of \
+         {granularity_blocks} blocks"
+    );
+
+    Ok((last - intervals * granularity_blocks, last))
+}
+
+/// The run of `--previous-run`, if it is for the same pool and granularity as this one.
+fn read_prior_run(path: &Path, pool: &PoolConfig, granularity_blocks: u64) -> Result<PriorRun> {
+    let json = std::fs::read(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let prior = PriorRun::from_json(&json)
+        .with_context(|| format!("invalid previous run {}", path.display()))?;
+    prior.ensure_same_run(pool.pool, pool.lst, granularity_blocks)?;
+
+    Ok(prior)
+}
+
+/// Checks that the window is a whole number of sampling intervals, so that counting back from the
+/// head the oldest sample falls on the window's first block rather than past it.
+fn check_window(window_blocks: u64, granularity_blocks: u64) -> Result<()> {
+    ensure!(granularity_blocks > 0, "the block granularity must be positive");
+    ensure!(
+        window_blocks % granularity_blocks == 0,
+        "a window of {window_blocks} blocks is not a whole number of {granularity_blocks} block \
+         intervals"
+    );
+
+    Ok(())
+}
+
+/// Checks that the guest `image_id` is the `expected` one.
+fn check_image_id(expected: &ImageId, image_id: [u32; 8]) -> Result<()> {
+    let actual = ImageId::from(image_id);
+    ensure!(
+        actual == *expected,
+        "guest image ID {actual} does not match the expected {expected}, refusing to run an \
+         unexpected guest build"
+    );
+
+    Ok(())
+}
+
+/// How a run reaches the chain: through its RPC endpoints, or in tests a mock chain. Each use
+/// connects anew, from whichever thread it runs on.
+trait Connect: Clone + Send + 'static {
+    type Provider: Provider<Header = EthBlockHeader>;
+    type Cached: Provider<Header = EthBlockHeader>;
+
+    fn connect(&self) -> Result<Self::Provider>;
+
+    /// A provider behind `cache`, written back when it is dropped.
+    fn open(&self, cache: &CacheConfig) -> Result<Self::Cached>;
+}
+
+/// The RPC endpoints of a run, under its request budget.
+#[derive(Clone)]
+struct Rpc {
+    endpoints: Vec<Endpoint>,
+    budget: RequestBudget,
+}
+
+impl Rpc {
+    fn new(args: &Args) -> Self {
+        let budget = if args.offline {
+            RequestBudget::offline()
+        } else {
+            RequestBudget::new(args.max_requests)
+        };
+
+        Rpc { endpoints: Endpoint::all(&args.rpc_url, args.breaker_threshold), budget }
+    }
+}
+
+impl Connect for Rpc {
+    type Provider = RpcProvider;
+    type Cached = Cache<RpcProvider>;
+
+    fn connect(&self) -> Result<RpcProvider> {
+        new_provider(&self.endpoints, &self.budget)
+    }
+
+    fn open(&self, cache: &CacheConfig) -> Result<Cache<RpcProvider>> {
+        cache.open(self.connect()?)
+    }
+}
+
+fn new_provider(endpoints: &[Endpoint], budget: &RequestBudget) -> Result<RpcProvider> {
+    let providers = endpoints
+        .iter()
+        .map(|endpoint| {
+            let client = EthersClient::new_client(&endpoint.url, 3, 500)?;
+            Ok(BreakerProvider::new(EthersProvider::new(client), endpoint.breaker.clone()))
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(BudgetedProvider::new(FallbackProvider::new(providers), budget.clone()))
+}
+
+/// Preflights all view calls of a single sampled block and returns the resulting guest input
+/// together with the queried values, and the pool's TVL if `query_tvl` is set.
+fn preflight_sample(
+    chain: &impl Connect,
+    cache: &CacheConfig,
+    header: &EthBlockHeader,
+    params: &GuestParams,
+    query_tvl: bool,
+) -> Result<(ViewCallInput<EthBlockHeader>, SampleRow, Option<U256>)> {
+    let block_num = header.number;
+    let cp = chain.open(cache)?;
+
+    let mut env =
+        EthViewCallEnv::from_provider(cp, block_num)?.with_chain_spec(&ETH_MAINNET_CHAIN_SPEC);
+
+    // the same calls the guest makes over the fetched state; the host reports the incentives from
+    // the journal
+    let query =
+        PoolQuerySet::new(params).with_tvl(query_tvl).query(block_num, &mut Preflight(&mut env))?;
+    let (exchange_rate, backing) = (query.exchange_rate, query.backing);
+    // outside the guest's calls, for the dataset only
+    let virtual_price = query_virtual_price(chain, cache, params.pool.pool, block_num)?;
+
+    let row = SampleRow {
+        block_number: block_num,
+        timestamp: header.timestamp,
+        exchange_rate,
+        backing,
+        virtual_price,
+    };
+
+    Ok((env.into_zkvm_input()?, row, query.tvl))
+}
+
+/// The first block from `from` to `to` at which `address` has code, found by bisection as a
+/// contract that is deployed stays so; `None` if it has none at `to`.
+fn first_block_with_code<P: Provider>(
+    provider: &P,
+    address: Address,
+    from: u64,
+    to: u64,
+) -> Result<Option<u64>> {
+    let has_code = |block: u64| -> Result<bool> {
+        let code = provider.get_code(address, block).with_context(|| {
+            format!("could not retrieve the code of {address} at block {block}")
+        })?;
+        Ok(!code.is_empty())
     };
-    let current_time = log_time_delta("executor", current_time);
+    if !has_code(to)? {
+        return Ok(None);
+    }
+    let (mut low, mut high) = (from, to);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if has_code(mid)? {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+
+    Ok(Some(low))
+}
+
+/// The block from which all of the price `feeds` exist, if they were deployed early in the window
+/// from `from` to `head` rather than before it. Fails with [`schedule::WindowError::EmptyWindow`]
+/// if none of the window's intervals is left to denominate.
+fn feeds_deployed_from<'a, P: Provider>(
+    provider: &P,
+    feeds: impl IntoIterator<Item = &'a PriceFeed>,
+    from: u64,
+    head: u64,
+) -> Result<Option<u64>> {
+    let mut deployed_from = None;
+    for feed in feeds {
+        let deployed = first_block_with_code(provider, feed.address, from, head)?
+            .with_context(|| format!("price feed {} has no code at the head", feed.address))?;
+        if deployed > from {
+            deployed_from = deployed_from.max(Some(deployed));
+        }
+    }
+    if let Some(deployed) = deployed_from {
+        check_not_empty(deployed, head)?;
+    }
+
+    Ok(deployed_from)
+}
+
+/// Fails unless each of the named `contracts` has code at block `head`.
+fn check_contracts_deployed<'a, P: Provider>(
+    provider: &P,
+    contracts: impl IntoIterator<Item = (&'a str, Address)>,
+    head: u64,
+) -> Result<()> {
+    for (name, address) in contracts {
+        let code = provider
+            .get_code(address, head)
+            .with_context(|| format!("could not retrieve the code of {address} at block {head}"))?;
+        ensure!(
+            !code.is_empty(),
+            "no contract code at address {address} on this chain: the {name} is not deployed at \
+             block {head}"
+        );
+    }
 
-    let stats = LstDexStats::abi_decode(&session_info.journal.bytes, true)?;
-    println!("{}", stats);
-    log_time_delta("end", current_time);
     Ok(())
 }
 
-fn log_time_delta(name: &str, start: Duration) -> Duration {
+/// Fails if the pool's TVL at `block_num` is below `min_tvl`, both in wei.
+fn check_liquidity(block_num: u64, tvl: U256, min_tvl: U256) -> Result<()> {
+    ensure!(
+        tvl >= min_tvl,
+        "pool TVL of {} ETH at block {block_num} is below the minimum of {} ETH",
+        format_units(tvl, "ether")?,
+        format_units(min_tvl, "ether")?
+    );
+
+    Ok(())
+}
+
+/// Fails if the exchange rate at `block_num` is below the one of the `previous` sample, a block
+/// and rate.
+fn check_monotonic_rate(previous: Option<(u64, U256)>, block_num: u64, rate: U256) -> Result<()> {
+    if let Some((previous_block, previous_rate)) = previous {
+        ensure!(
+            rate >= previous_rate,
+            "exchange rate decreased from {} at block {previous_block} to {} at block \
+             {block_num}, bad data or a wrong contract",
+            format_units(previous_rate, 18)?,
+            format_units(rate, 18)?
+        );
+    }
+
+    Ok(())
+}
+
+/// Preflights the view calls of a backing strategy.
+struct Preflight<'a, P: Provider>(&'a mut ViewCallEnv<ProofDb<P>, P::Header>);
+
+impl<P: Provider> ViewCaller for Preflight<'_, P> {
+    type Error = anyhow::Error;
+
+    fn call<C: SolCall>(&mut self, target: Address, call: C) -> Result<C::Return> {
+        self.0.preflight(ViewCall::new(call, target))
+    }
+}
+
+/// Queries the virtual price of the Curve pool at `block_num`, outside the guest.
+fn query_virtual_price(
+    chain: &impl Connect,
+    cache: &CacheConfig,
+    pool: Address,
+    block_num: u64,
+) -> Result<U256> {
+    let cp = chain.open(cache)?;
+    let mut env =
+        EthViewCallEnv::from_provider(cp, block_num)?.with_chain_spec(&ETH_MAINNET_CHAIN_SPEC);
+
+    Ok(env.preflight(ViewCall::new(CurvePoolInterface::get_virtual_priceCall {}, pool))?._0)
+}
+
+/// Queries the price of the LST the Curve pool quotes and that of `feed` at `block_num`, outside
+/// the guest.
+fn query_depeg_prices(
+    chain: &impl Connect,
+    cache: &CacheConfig,
+    pool: Address,
+    feed: PriceFeed,
+    block_num: u64,
+) -> Result<depeg::PriceSample> {
+    let cp = chain.open(cache)?;
+    let mut env =
+        EthViewCallEnv::from_provider(cp, block_num)?.with_chain_spec(&ETH_MAINNET_CHAIN_SPEC);
+    // coin 1 is the LST, coin 0 ETH
+    let dy =
+        CurvePoolInterface::get_dyCall { i: U256::from(1), j: U256::ZERO, dx: depeg::QUOTE_AMOUNT };
+    let quote = env.preflight(ViewCall::new(dy, pool))?._0;
+    let fee = env.preflight(ViewCall::new(CurvePoolInterface::feeCall {}, pool))?._0;
+    let answer =
+        env.preflight(ViewCall::new(ChainlinkInterface::latestRoundDataCall {}, feed.address))?._1;
+
+    Ok(depeg::PriceSample::from_quote(block_num, quote, fee, feed.scale_answer(answer)))
+}
+
+/// Configures the price feed at `address`, querying its decimals at `block_num` unless given, or
+/// taking `default_decimals` if the query reverts.
+fn resolve_feed(
+    chain: &impl Connect,
+    cache: &CacheConfig,
+    block_num: u64,
+    address: Address,
+    decimals: Option<u8>,
+    default_decimals: u8,
+) -> Result<PriceFeed> {
+    let decimals = match decimals {
+        Some(decimals) => decimals,
+        None => {
+            let cp = chain.open(cache)?;
+            let (decimals, warning) = query_decimals(cp, block_num, address, default_decimals)?;
+            if let Some(warning) = warning {
+                warning!("{warning}");
+            }
+            decimals
+        }
+    };
+    report!("Using feed {address} ({decimals} decimals)");
+
+    Ok(PriceFeed { address, decimals })
+}
+
+/// Queries the `decimals()` of `address` at `block_num`, falling back to `default` if the call
+/// reverts, with the warning to log. An address without code, e.g. a mistyped one, a call failing
+/// otherwise and a failed RPC request still fail.
+fn query_decimals<P: Provider<Header = EthBlockHeader>>(
+    provider: P,
+    block_num: u64,
+    address: Address,
+    default: u8,
+) -> Result<(u8, Option<String>)> {
+    if provider.get_code(address, block_num)?.is_empty() {
+        let err = anyhow!("feed {address} has no code at block {block_num}");
+        return Err(err.context(HostError::Config));
+    }
+    let mut env = EthViewCallEnv::from_provider(provider, block_num)?
+        .with_chain_spec(&ETH_MAINNET_CHAIN_SPEC);
+    match env.preflight(ViewCall::new(ChainlinkInterface::decimalsCall {}, address)) {
+        Ok(ret) => Ok((ret._0, None)),
+        Err(err) if is_revert(&err) => {
+            let warning =
+                format!("warning: decimals() of {address} reverted, assuming {default}: {err:#}");
+            Ok((default, Some(warning)))
+        }
+        Err(err) => Err(err),
+    }
+}
+
+/// Whether a preflight failed because the call reverted, rather than on a halt, on return data
+/// that doesn't decode or on the provider. Steel reports a failed call by the `Debug` of the
+/// `ExecutionResult` only, so the revert is told by its variant name.
+fn is_revert(err: &anyhow::Error) -> bool {
+    HostError::classify(err) == HostError::Other && format!("{err:#}").contains("Revert {")
+}
+
+fn log_time_delta(
+    name: &'static str,
+    start: Duration,
+    stages: &mut Vec<(&'static str, f64)>,
+) -> Duration {
     let now_time = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
-    let delta = (now_time - start).as_secs();
-    println!("{} took {} seconds", name, delta);
+    let delta = now_time - start;
+    report!("{} took {} seconds", name, delta.as_secs());
+    stages.push((name, delta.as_secs_f64()));
 
     now_time
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        mock::{smoke_chain, MemoryBackend, MockProvider},
+        schedule::WindowError,
+    };
+    use alloy_primitives::{Bytes, B256, I256};
+    use risc0_steel::BlockCommitment;
+    use tokemak::{
+        chain::HeaderChain,
+        fixture::{self, accelerating_backing, daily_inputs},
+        pool_tvl, yield_to_wad,
+    };
+
+    fn collect_headers<P>(provider: &P, from: u64, to: u64) -> Result<Vec<EthBlockHeader>>
+    where
+        P: Provider<Header = EthBlockHeader>,
+    {
+        let mut headers = Vec::new();
+        fetch_headers(provider, from, to, |header| {
+            headers.push(header);
+            true
+        })?;
+
+        Ok(headers)
+    }
+
+    #[test]
+    fn it_should_assemble_a_smoke_window() {
+        let head = 19_000_000;
+        let provider = MockProvider::with_chain(head - 1000, 1001);
+        let from = head - SMOKE_WINDOW_BLOCKS;
+
+        let headers = collect_headers(&provider, from, head).unwrap();
+        assert_eq!(headers.len() as u64, SMOKE_WINDOW_BLOCKS + 1);
+        assert_eq!(HeaderChain::link(&headers).head_number(), head);
+
+        // the schedule the preflights run over is the minimal one
+        let samples = sample_blocks(from, head, SMOKE_GRANULARITY_BLOCKS).unwrap();
+        assert_eq!(samples, vec![from, from + 100, head]);
+        assert_eq!(provider.request_count(), headers.len());
+    }
+
+    /// Six samples one day apart, of backings changing unevenly enough that a mistake in any of
+    /// them shows.
+    fn canned_inputs() -> Vec<DexStatsInput> {
+        let backings = [1.05, 1.0502, 1.0501, 1.0507, 1.0511, 1.0512];
+        daily_inputs(0..=5, |day| backings[day as usize])
+    }
+
+    /// The journal the guest commits for a guest input recorded with `--guest-input-out`.
+    fn execute_recorded(input: &[u8]) -> Vec<u8> {
+        let words: Vec<u32> = input
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .collect();
+        let env = ExecutorEnv::builder().write_slice(&words).build().unwrap();
+        default_executor().execute(env, TOKEN_STATS_ELF).unwrap().journal.bytes
+    }
+
+    #[test]
+    fn it_should_pass_a_smoke_test_on_the_mock_chain() {
+        let head = 19_000_000;
+        let pool = PoolConfig::CBETH_ETH;
+        let chain = smoke_chain(head, &pool);
+        let dir = std::env::temp_dir().join(format!("host-smoke-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = dir.join("cache.json");
+        let input = dir.join("guest.input");
+        let args = Args::parse_from([
+            "host",
+            "--smoke",
+            "--rpc-url",
+            "http://mock",
+            "--cache-dir",
+            cache.to_str().unwrap(),
+            "--guest-input-out",
+            input.to_str().unwrap(),
+        ]);
+
+        // headers, preflights and the guest, over the mock chain's exchange rate rising by the
+        // block
+        let stats = run_pool(&args, pool, &chain).unwrap();
+        assert_eq!(stats.commitment.blockNumber, U256::from(head));
+        assert_eq!(stats.commitment.blockHash, chain.header(head).unwrap().hash());
+        assert_eq!((stats.pool, stats.lst), (pool.pool, pool.lst));
+        assert_eq!(stats.granularityBlocks, SMOKE_GRANULARITY_BLOCKS);
+        assert_eq!(stats.windowBlocks, SMOKE_WINDOW_BLOCKS);
+        assert_eq!(stats.samplesDigest, tokemak::samples_digest(&[head - 200, head - 100, head]));
+        assert!(stats.baseYield > I256::ZERO, "{}", stats.baseYield);
+        // the recorded input is all the guest needs to commit the journal again
+        let recorded = std::fs::read(&input).unwrap();
+        assert_eq!(recorded.len() % 4, 0);
+        assert_eq!(execute_recorded(&recorded), stats.abi_encode());
+
+        // a second run is served from the cache
+        chain.set_failing(true);
+        let offline = Args::parse_from([
+            "host",
+            "--smoke",
+            "--offline",
+            "--rpc-url",
+            "http://mock",
+            "--cache-dir",
+            cache.to_str().unwrap(),
+        ]);
+        assert_eq!(abi_hex(&run_pool(&offline, pool, &chain).unwrap()), abi_hex(&stats));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn it_should_reject_an_oversized_window_before_any_fetch() {
+        let chain = MockProvider::with_chain(19_000_000 - 1000, 1001);
+        let args = Args::parse_from([
+            "host",
+            "--rpc-url",
+            "http://mock",
+            "--cache-dir",
+            "/nonexistent/cache.json",
+            "--windows",
+            "180",
+            "--max-headers",
+            "1000000",
+        ]);
+
+        // a window of 180 days is 1_296_001 headers, however few of them are sampled
+        let err = run_pool(&args, PoolConfig::CBETH_ETH, &chain).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<WindowError>(),
+            Some(&WindowError::TooManyHeaders { headers: 1_296_001, max_headers: 1_000_000 })
+        );
+        assert_eq!(chain.request_count(), 0);
+    }
+
+    #[test]
+    fn it_should_classify_a_misconfigured_pool_before_any_fetch() {
+        let chain = MockProvider::with_chain(19_000_000 - 1000, 1001);
+        let args = Args::parse_from([
+            "host",
+            "--rpc-url",
+            "http://mock",
+            "--cache-dir",
+            "/nonexistent/cache.json",
+            "--enforce-monotonic-rate",
+            "--backing",
+            "rebase",
+        ]);
+
+        let err = run_pool(&args, PoolConfig::CBETH_ETH, &chain).unwrap_err();
+        assert_eq!(HostError::classify(&err), HostError::Config);
+        assert!(err.root_cause().to_string().starts_with("--enforce-monotonic-rate"), "{err:#}");
+        assert_eq!(chain.request_count(), 0);
+    }
+
+    #[test]
+    fn it_should_pass_the_self_test() {
+        let stats = run_self_test().unwrap();
+        assert_eq!(stats.commitment.blockNumber, U256::from(self_test::HEAD));
+        assert!(stats.baseYield > I256::ZERO, "{}", stats.baseYield);
+    }
+
+    #[test]
+    fn it_should_assemble_a_window_through_an_in_memory_cache() {
+        let head = 19_000_000;
+        let chain = MockProvider::with_chain(head - 1000, 1001);
+        let backend = MemoryBackend::default();
+        let cache = CacheConfig { backend: backend.clone(), key: "cache".into(), compress: true };
+        let from = head - SMOKE_WINDOW_BLOCKS;
+
+        let fetched = collect_headers(&cache.open(chain.clone()).unwrap(), from, head).unwrap();
+        assert!(backend.get("cache").unwrap().is_some());
+        assert_eq!(chain.request_count(), fetched.len());
+
+        // a second run is served from the backend alone
+        let cached = collect_headers(&cache.open(MockProvider::failing()).unwrap(), from, head);
+        assert_eq!(cached.unwrap(), fetched);
+        assert_eq!(chain.request_count(), fetched.len());
+    }
+
+    #[test]
+    fn it_should_seed_the_cache_for_an_offline_run() {
+        let head = 19_000_000;
+        let chain = MockProvider::with_chain(head - 1000, 1001);
+        let pool = PoolConfig::CBETH_ETH;
+        chain.deploy_code(pool.lst, 0, Bytes::from_static(&[0x60, 0x80]));
+        chain.deploy_code(pool.pool, 0, Bytes::from_static(&[0x60, 0x80]));
+        let backend = MemoryBackend::default();
+        let cache = CacheConfig { backend: backend.clone(), key: "cache".into(), compress: false };
+        let from = head - SMOKE_WINDOW_BLOCKS;
+
+        // the preflights only record their block; the smoke test runs them
+        let mut preflighted = Vec::new();
+        let samples = seed_cache(
+            &cache,
+            chain.clone(),
+            &pool,
+            BlockSpec::Latest,
+            SMOKE_WINDOW_BLOCKS,
+            SMOKE_GRANULARITY_BLOCKS,
+            |header| {
+                preflighted.push(header.number);
+                Ok(())
+            },
+        )
+        .unwrap();
+        assert_eq!(samples, vec![from, from + 100, head]);
+        assert_eq!(preflighted, samples);
+        assert_eq!(backend.keys(), vec!["cache".to_owned(), "cache.head".to_owned()]);
+        let requests = chain.request_count();
+
+        // an offline smoke run reads what `run_pool` reads before its preflights from the seed
+        // alone, in the same order, and nothing beyond the window
+        let offline = cache.open(MockProvider::failing()).unwrap();
+        let end = BlockSpec::Latest.resolve(|| offline.get_block_number()).unwrap();
+        assert_eq!(end, head);
+        let contracts = [("LST", pool.lst), ("pool", pool.pool)];
+        check_contracts_deployed(&offline, contracts, end).unwrap();
+        let from = window_start(end, SMOKE_WINDOW_BLOCKS).unwrap();
+        let headers = collect_headers(&offline, from, end).unwrap();
+        assert_eq!(headers.len(), 201);
+        let sampled: Vec<_> = headers
+            .iter()
+            .map(|header| header.number)
+            .filter(|number| samples.binary_search(number).is_ok())
+            .collect();
+        assert_eq!(sampled, samples);
+        assert!(collect_headers(&offline, from - 1, from - 1).is_err());
+        assert!(collect_headers(&offline, end + 1, end + 1).is_err());
+        assert_eq!(chain.request_count(), requests);
+    }
+
+    #[test]
+    fn it_should_fail_on_missing_headers() {
+        let provider = MockProvider::with_chain(100, 10);
+
+        let err = collect_headers(&provider, 105, 112).unwrap_err();
+        assert_eq!(err.to_string(), "block at height 110 not found");
+    }
+
+    #[test]
+    fn it_should_compute_the_yield_on_the_host_when_the_guest_fails() {
+        // without any input, the guest fails reading the input version
+        let err = execute(ExecutorEnv::builder().build().unwrap()).unwrap_err();
+        let (inputs, params) = (canned_inputs(), DexStatsParams::default());
+        let window = (inputs[0].block_number, inputs[5].block_number);
+
+        let unproven = UnprovenResult::new(&err, &inputs, &params, window, 6);
+        let expected = try_calculate_dex_stats(&inputs, &params).unwrap();
+        assert_eq!(unproven.host_stats.as_ref().unwrap().base_yield, expected.base_yield);
+        let text = unproven.to_string();
+        assert!(text.starts_with("The guest failed: failed to run executor"), "{text}");
+        assert!(
+            text.contains("Its input: 6 of 6 scheduled samples from block 19000000 to 19036000")
+        );
+        assert!(text.contains("UNPROVEN, computed by the host"), "{text}");
+        assert!(text.ends_with("the guest is likely at fault"), "{text}");
+
+        // samples the host can't compute a yield from either
+        let unproven = UnprovenResult::new(&err, &inputs[..1], &params, window, 6);
+        assert!(unproven.host_stats.is_err());
+        assert!(unproven.to_string().ends_with("the data is likely at fault"));
+    }
+
+    #[test]
+    fn it_should_reject_an_input_of_another_version() {
+        let env = ExecutorEnv::builder()
+            .write(&(GUEST_INPUT_VERSION + 1))
+            .unwrap()
+            .write(&GuestParams::default())
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let err = execute(env).unwrap_err();
+        let message = format!("{err:#}");
+        let expected = format!(
+            "unsupported guest input version {}, this guest reads version {GUEST_INPUT_VERSION}",
+            GUEST_INPUT_VERSION + 1
+        );
+        assert!(message.contains(&expected), "{message}");
+    }
+
+    fn journal(base_yield: I256) -> LstDexStats {
+        LstDexStats { baseYield: base_yield, ..fixture::journal() }
+    }
+
+    #[test]
+    fn it_should_print_the_journal_as_abi_hex() {
+        let stats = journal(yield_to_wad(0.0321));
+        let hex = abi_hex(&stats);
+
+        assert!(hex.starts_with("0x"));
+        let bytes = hex::decode(&hex).unwrap();
+        // the commitment takes two words, every other field one
+        assert_eq!(bytes.len(), 11 * 32);
+        let decoded = LstDexStats::abi_decode(&bytes, true).unwrap();
+        assert_eq!(decoded.baseYield, stats.baseYield);
+        assert_eq!(decoded.lst, stats.lst);
+        assert_eq!(decoded.abi_encode(), stats.abi_encode());
+    }
+
+    #[test]
+    fn it_should_cross_check_the_journal_yield() {
+        let inputs: Vec<_> = [100.0, 100.01, 100.10, 100.15, 100.25]
+            .iter()
+            .enumerate()
+            .map(|(i, backing)| DexStatsInput {
+                timestamp: 1716129570 + i as u64 * 86_400,
+                block_number: i as u64 * BLOCK_GRANULARITY,
+                lst_backing: parse_units(&backing.to_string(), 18).unwrap().into(),
+                interpolated: false,
+                pool_tvl: None,
+            })
+            .collect();
+        let host = try_calculate_dex_stats(&inputs, &DexStatsParams::default()).unwrap();
+
+        // the guest commits the yield the same way
+        let committed = yield_to_wad(host.base_yield);
+        cross_check(&journal(committed), &host, 1e-9).unwrap();
+
+        // a guest off by a basis point doesn't pass
+        let perturbed = committed + I256::from_raw(U256::from(100_000_000_000_000_u64));
+        let err = cross_check(&journal(perturbed), &host, 1e-9).unwrap_err();
+        assert!(err.to_string().starts_with("guest yield "), "{err}");
+    }
+
+    #[test]
+    fn it_should_reject_illiquid_samples() {
+        let wad = U256::from(10).pow(U256::from(18));
+        let min_tvl = U256::from(100) * wad;
+        let rate = U256::from(1_050_000_000_000_000_000_u64);
+
+        // 60 ETH and 40 cbETH at 1.05 make 102 ETH
+        let liquid = pool_tvl(U256::from(60) * wad, U256::from(40) * wad, rate);
+        check_liquidity(19_000_000, liquid, min_tvl).unwrap();
+
+        let drained = pool_tvl(U256::from(30) * wad, U256::from(20) * wad, rate);
+        let err = check_liquidity(19_000_000, drained, min_tvl).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "pool TVL of 51.000000000000000000 ETH at block 19000000 is below the minimum of \
+             100.000000000000000000 ETH"
+        );
+    }
+
+    #[test]
+    fn it_should_reject_a_decreasing_exchange_rate() {
+        let series = ["1.05", "1.0502", "1.0502", "1.0499", "1.0505"];
+        let mut previous = None;
+        let results: Vec<_> = series
+            .iter()
+            .enumerate()
+            .map(|(i, rate)| {
+                let block_num = 19_000_000 + i as u64 * 7_200;
+                let rate = parse_units(rate, 18).unwrap().into();
+                let result = check_monotonic_rate(previous, block_num, rate);
+                previous = Some((block_num, rate));
+                result
+            })
+            .collect();
+
+        // an unchanged rate passes, the drop fails, and the recovery after it passes again
+        assert!(results[..3].iter().all(Result::is_ok));
+        assert_eq!(
+            results[3].as_ref().unwrap_err().to_string(),
+            "exchange rate decreased from 1.050200000000000000 at block 19014400 to \
+             1.049900000000000000 at block 19021600, bad data or a wrong contract"
+        );
+        assert!(results[4].is_ok());
+        assert!(BackingKind::ExchangeRate.is_monotonic());
+        assert!(!BackingKind::Rebase.is_monotonic());
+    }
+
+    #[test]
+    fn it_should_select_the_backing_strategy() {
+        let parse = |extra: &[&str]| {
+            let args = ["host", "--rpc-url", "http://mock", "--cache-dir", "cache"];
+            Args::try_parse_from(args.iter().chain(extra))
+        };
+        assert_eq!(parse(&[]).unwrap().backing(), None);
+        assert_eq!(parse(&["--backing", "rebase"]).unwrap().backing(), Some(BackingKind::Rebase));
+
+        let holder = Address::repeat_byte(0x22);
+        let args = parse(&[
+            "--backing",
+            "reference-balance",
+            "--reference-holder",
+            &holder.to_string(),
+            "--reference-principal",
+            "1000000000000000000000",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.backing(),
+            Some(BackingKind::ReferenceBalance(ReferenceBalance {
+                holder,
+                principal: U256::from(1_000_000_000_000_000_000_000_u128),
+            }))
+        );
+        // the holder and its principal go together, and with the strategy
+        assert!(parse(&["--backing", "reference-balance"]).is_err());
+        let holder = holder.to_string();
+        assert!(parse(&["--backing", "reference-balance", "--reference-holder", &holder]).is_err());
+        let zero = [
+            "--backing",
+            "reference-balance",
+            "--reference-holder",
+            &holder,
+            "--reference-principal",
+            "0",
+        ];
+        assert!(parse(&zero).is_err());
+    }
+
+    /// An input set of `samples`, as a run writes it and a replay reads it.
+    fn input_set(samples: Vec<SampleRow>) -> InputSet {
+        let head = BlockCommitment {
+            blockHash: B256::repeat_byte(0xab),
+            blockNumber: U256::from(samples.last().unwrap().block_number),
+        };
+        let json = serde_json::to_vec(&InputSet::new(&head, BLOCK_GRANULARITY, samples)).unwrap();
+
+        InputSet::read(json.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn it_should_replay_an_input_set_with_other_params() {
+        let mut samples = Vec::new();
+        for (i, backing) in [100.0, 100.5, 101.0, 101.25, 101.3].iter().enumerate() {
+            let backing = parse_units(&backing.to_string(), 18).unwrap().into();
+            samples.push(SampleRow {
+                block_number: 19_000_000 + i as u64 * BLOCK_GRANULARITY,
+                timestamp: 1716129570 + i as u64 * 86_400,
+                exchange_rate: backing,
+                backing,
+                virtual_price: backing,
+            });
+        }
+        let set = input_set(samples);
+
+        let replay_with = |args: &[&str]| {
+            let args = Args::parse_from(
+                [&["host", "replay", "--inputs", "inputs.json"][..], args].concat(),
+            );
+            let Some(Command::Replay(replay_args)) = args.command else { panic!("not a replay") };
+            replay(set.clone(), None, &replay_args.params(&set)).unwrap()
+        };
+
+        let point_to_point = replay_with(&[]);
+        let rolling = replay_with(&["--rolling-window", "3"]);
+        let log = replay_with(&["--log-returns"]);
+        assert_eq!(point_to_point.sample_count, 5);
+        assert!((point_to_point.base_yield - rolling.base_yield).abs() > 0.01);
+        assert!((point_to_point.base_yield - log.base_yield).abs() > 0.01);
+
+        // the same as computing from the samples directly
+        let samples: Vec<_> = set.samples.iter().map(SampleRow::input).collect();
+        let params =
+            DexStatsParams { mode: ChangeMode::Rolling { window: 3 }, ..Default::default() };
+        assert_eq!(
+            rolling.base_yield,
+            try_calculate_dex_stats(&samples, &params).unwrap().base_yield
+        );
+        assert_eq!(replay_with(&["--skip", "2"]).sample_count, 3);
+        assert_eq!(replay_with(&["--skip", "4", "--reject-skip-remainder"]).sample_count, 2);
+    }
+
+    #[test]
+    fn it_should_replay_with_overridden_exchange_rates() {
+        // a year of daily samples whose on-chain rate is flat, with a re-denominated backing
+        let mut samples = Vec::new();
+        let mut overrides = String::from("block_number,exchange_rate\n");
+        let rate = U256::from(10_u64.pow(18));
+        for i in 0..366_u64 {
+            let block_number = 19_000_000 + i * BLOCK_GRANULARITY;
+            samples.push(SampleRow {
+                block_number,
+                timestamp: 1716129570 + i * 86_400,
+                exchange_rate: rate,
+                backing: rate * U256::from(2),
+                virtual_price: rate,
+            });
+            // compounding by 1 bp a day instead
+            overrides.push_str(&format!("{block_number},{}\n", 1.0001_f64.powi(i as i32)));
+        }
+        let set = input_set(samples);
+        let overrides = dataset::read_rate_overrides(overrides.as_bytes()).unwrap();
+
+        let replayed = replay(set.clone(), None, &Default::default()).unwrap();
+        assert_eq!(replayed.base_yield, 0.0);
+        let what_if = replay(set.clone(), Some(&overrides), &Default::default()).unwrap();
+        assert!((what_if.base_yield - 0.0365).abs() < 1e-9, "{}", what_if.base_yield);
+
+        // every sample needs an override
+        let mut partial = overrides.clone();
+        partial.remove(&(19_000_000 + 100 * BLOCK_GRANULARITY));
+        let err = replay(set, Some(&partial), &Default::default()).unwrap_err();
+        assert_eq!(err.to_string(), "no exchange rate override for block 19720000");
+    }
+
+    #[test]
+    fn it_should_compute_a_yield_curve_from_one_window() {
+        // 30 days, the backing growing faster lately
+        let inputs = daily_inputs(0..=30, accelerating_backing);
+        let params = DexStatsParams::default();
+
+        let curve = yield_curve(&inputs, &[1, 7, 30], &params).unwrap();
+        assert_eq!(curve.keys().copied().collect::<Vec<_>>(), vec![1, 7, 30]);
+        // each lookback is the stats over its own tail of the samples
+        for (days, base_yield) in &curve {
+            let subset = &inputs[30 - *days as usize..];
+            assert_eq!(subset.len() as u64, days + 1);
+            assert_eq!(*base_yield, try_calculate_dex_stats(subset, &params).unwrap().base_yield);
+        }
+        // the last week accelerated
+        assert!((curve[&1] - 0.073).abs() < 1e-4, "{}", curve[&1]);
+        assert!((curve[&7] - curve[&1]).abs() < 1e-4);
+        assert!(curve[&30] < curve[&7] - 0.02);
+
+        // a lookback longer than the window uses all of it
+        assert_eq!(yield_curve(&inputs, &[90], &params).unwrap()[&90], curve[&30]);
+        let err = yield_curve(&inputs, &[u64::MAX], &params).unwrap_err();
+        assert!(err.to_string().contains("overflows"), "{err}");
+    }
+
+    #[test]
+    fn it_should_compare_the_recent_yield_with_a_baseline() {
+        // 30 days, the backing growing twice as fast in the last week
+        let inputs = daily_inputs(0..=30, accelerating_backing);
+        let params = DexStatsParams::default();
+
+        let baseline = yield_vs_baseline(&inputs, 7, 14, &params).unwrap();
+        assert!(baseline.delta > 0.03, "{baseline:?}");
+        assert_eq!(baseline.delta, baseline.recent_yield - baseline.baseline_yield);
+        assert_eq!(
+            baseline.recent_yield,
+            try_calculate_dex_stats(&inputs[23..], &params).unwrap().base_yield
+        );
+        assert_eq!(
+            baseline.baseline_yield,
+            try_calculate_dex_stats(&inputs[9..=23], &params).unwrap().base_yield
+        );
+        assert_eq!(
+            baseline.to_string(),
+            "Yield over the last 7 days: 7.30%, 3.65% above the 3.65% of the 14 days before"
+        );
+
+        // a baseline reaching back before the samples fails
+        let err = yield_vs_baseline(&inputs, 7, 30, &params).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "the samples start at block 19000000, after the 30 day baseline starting at block \
+             18949600"
+        );
+    }
+
+    #[test]
+    fn it_should_leave_out_failed_samples() {
+        let head = 19_000_000 + 7 * BLOCK_GRANULARITY;
+        let mut failures = FailedSamples { continue_on_error: true, head, ..Default::default() };
+        let backings = [100.0, 100.01, 100.02, 100.03, 100.04, 100.05, 100.06, 100.07];
+
+        // the first sample, and two in the middle, are unreachable
+        let mut inputs = Vec::new();
+        for (i, backing) in backings.iter().enumerate() {
+            let block_number = 19_000_000 + i as u64 * BLOCK_GRANULARITY;
+            if [0, 3, 4].contains(&i) {
+                failures.leave_out(block_number, anyhow!("execution reverted")).unwrap();
+                continue;
+            }
+            failures.observed();
+            inputs.push(DexStatsInput {
+                timestamp: 1716129570 + i as u64 * 86_400,
+                block_number,
+                lst_backing: parse_units(&backing.to_string(), 18).unwrap().into(),
+                interpolated: false,
+                pool_tvl: None,
+            });
+        }
+        assert_eq!(failures.skipped.len(), 3);
+        assert_eq!(failures.skipped[0], (19_000_000, "execution reverted".to_string()));
+        // but not the head
+        assert!(failures.leave_out(head, anyhow!("execution reverted")).is_err());
+
+        // the yield comes from the remaining samples alone, nothing is interpolated into the gap
+        let params = DexStatsParams { alignment: SampleAlignment::Irregular, ..Default::default() };
+        let stats = try_calculate_dex_stats(&inputs, &params).unwrap();
+        assert_eq!(stats.sample_count, 5);
+        assert_eq!(stats.data_quality, 1.0);
+        assert!((stats.base_yield - 0.0365).abs() < 0.001, "{}", stats.base_yield);
+        // but the samples left out count against the data quality
+        assert_eq!(failures.kept_share(backings.len()), 5.0 / 8.0);
+    }
+
+    #[test]
+    fn it_should_only_interpolate_between_observed_samples() {
+        let mut failures = FailedSamples { max_interpolated: 1, head: 400, ..Default::default() };
+        assert!(failures.leave_out(100, anyhow!("timeout")).is_err());
+        failures.observed();
+        failures.leave_out(200, anyhow!("timeout")).unwrap();
+        assert!(failures.leave_out(300, anyhow!("timeout")).is_err());
+        assert!(failures.skipped.is_empty());
+    }
+
+    #[test]
+    fn it_should_downgrade_the_verification_of_runs_leaving_samples_out() {
+        let failures = FailedSamples { max_interpolated: 1, head: 400, ..Default::default() };
+        assert_eq!(failures.verification(), Verification::Verified);
+
+        let mut interpolating = FailedSamples { any_observed: true, ..failures };
+        interpolating.leave_out(200, anyhow!("timeout")).unwrap();
+        assert_eq!(
+            interpolating.verification(),
+            Verification::PartiallyVerified {
+                reasons: vec!["1 samples interpolated over by --max-interpolated".into()]
+            }
+        );
+
+        let mut skipping =
+            FailedSamples { continue_on_error: true, head: 400, ..Default::default() };
+        skipping.leave_out(100, anyhow!("timeout")).unwrap();
+        skipping.leave_out(200, anyhow!("timeout")).unwrap();
+        assert_eq!(
+            skipping.verification(),
+            Verification::PartiallyVerified {
+                reasons: vec!["2 samples left out by --continue-on-error".into()]
+            }
+        );
+    }
+
+    #[test]
+    fn it_should_mark_runs_without_a_receipt_unverified() {
+        let failures = FailedSamples::default();
+        let executed = Args::parse_from(["host"]);
+        assert!(!executed.proves());
+        assert_eq!(
+            executed.verification(&failures),
+            Verification::Unverified {
+                reasons: vec!["executed only, no receipt proves the journal".into()]
+            }
+        );
+
+        assert_eq!(
+            Args::parse_from(["host", "--bonsai"]).verification(&failures),
+            Verification::Verified
+        );
+        let resumed = Args::parse_from(["host", "--resume", "session"]);
+        assert_eq!(resumed.verification(&failures), Verification::Verified);
+    }
+
+    #[test]
+    fn it_should_mark_replays_unverified() {
+        let verification = |args: &[&str]| {
+            let args = Args::parse_from(
+                [&["host", "replay", "--inputs", "inputs.json"][..], args].concat(),
+            );
+            let Some(Command::Replay(replay_args)) = args.command else { panic!("not a replay") };
+            replay_args.verification()
+        };
+
+        assert_eq!(
+            verification(&[]),
+            Verification::Unverified { reasons: vec!["replayed from inputs.json".into()] }
+        );
+        assert_eq!(
+            verification(&["--override-rates", "rates.csv"]),
+            Verification::Unverified {
+                reasons: vec![
+                    "replayed from inputs.json".into(),
+                    "exchange rates overridden from rates.csv".into(),
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn it_should_lead_the_json_output_with_the_verification() {
+        let journal = journal(yield_to_wad(0.0321));
+        let host = DexStatsOutput {
+            base_yield: 0.0321,
+            base_apr: 0.0321,
+            base_apy: 0.0326,
+            total_return: 0.0,
+            changes: vec![0.0321],
+            yield_volatility: 0.0,
+            yield_std_error: 0.0,
+            sample_count: 2,
+            data_quality: 0.5,
+            dropped_samples: 0,
+        };
+        let verification = Verification::PartiallyVerified {
+            reasons: vec!["1 samples interpolated over by --max-interpolated".into()],
+        };
+
+        let json = serde_json::to_string(&JsonOutput::new(&verification, &journal, &host)).unwrap();
+        assert!(json.starts_with(
+            r#"{"verification":{"status":"partially_verified","reasons":["1 samples interpolated"#
+        ));
+        assert!(json.contains(r#""base_yield":0.0321,"#));
+        assert!(json.contains(&format!(r#""journal":"{}""#, abi_hex(&journal))));
+    }
+
+    #[test]
+    fn it_should_anchor_the_window_as_of_a_timestamp() {
+        // 12 second blocks from 1_700_000_000, so block 500 is at 1_700_006_000
+        let provider = MockProvider::with_chain(0, 1_000);
+        let times = BlockTimes::new(&provider);
+        let args = Args::parse_from(["host", "--as-of-timestamp", "1700006007"]);
+        assert_eq!(args.end_timestamp, Some(1_700_006_007));
+
+        // between blocks 500 and 501, the window ends at the earlier one
+        assert_eq!(times.block_at_timestamp(args.end_timestamp.unwrap(), 999).unwrap(), 500);
+
+        // read through the cache, the search is repeated offline, up to the same head
+        let config =
+            CacheConfig { backend: MemoryBackend::default(), key: "cache".into(), compress: false };
+        let as_of = |provider| -> Result<u64> {
+            let cache = config.open(provider)?;
+            BlockTimes::new(&cache).block_at_timestamp(1_700_006_007, cache.get_block_number()?)
+        };
+        assert_eq!(as_of(provider).unwrap(), 500);
+        assert_eq!(as_of(MockProvider::failing()).unwrap(), 500);
+    }
+
+    #[test]
+    fn it_should_resolve_a_date_range_to_blocks() {
+        // 12 second blocks from 2023-11-14T22:13:20Z
+        let provider = MockProvider::with_chain(0, 30_000);
+        let times = BlockTimes::new(&provider);
+        let date = |s: &str| s.parse::<DateTime>().unwrap();
+
+        // the last blocks at or before the two midnights are three days apart
+        let range =
+            |from, to| resolve_date_range(&times, date(from), date(to), 29_999, BLOCK_GRANULARITY);
+        assert_eq!(range("2023-11-15", "2023-11-18").unwrap(), (533, 533 + 3 * BLOCK_GRANULARITY));
+        // a partial interval at the start is left out
+        assert_eq!(
+            range("2023-11-15T00:00:12Z", "2023-11-18").unwrap(),
+            (533 + BLOCK_GRANULARITY, 533 + 3 * BLOCK_GRANULARITY)
+        );
+
+        let err = range("2023-11-18", "2023-11-15").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--from-date (1700265600) is not before --to-date (1700006400)"
+        );
+        assert!(range("2023-11-15", "2023-11-15T12:00Z").is_err());
+        assert!(range("2023-11-15", "2023-12-01").is_err());
+    }
+
+    #[test]
+    fn it_should_reject_a_window_clamped_to_the_feed_deployment_at_the_head() {
+        let (early, late) = (Address::repeat_byte(0xcc), Address::repeat_byte(0xdd));
+        let provider = MockProvider::with_chain(100, 100);
+        provider.deploy_code(early, 150, Bytes::from_static(&[0x60, 0x80]));
+        provider.deploy_code(late, 199, Bytes::from_static(&[0x60, 0x80]));
+        let feed = |address| PriceFeed { address, decimals: 8 };
+
+        let feeds = [feed(early)];
+        assert_eq!(feeds_deployed_from(&provider, &feeds, 100, 199).unwrap(), Some(150));
+        // deployed at the head, it leaves the denominated window a single block
+        let feeds = [feed(early), feed(late)];
+        let err = feeds_deployed_from(&provider, &feeds, 100, 199).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<WindowError>(),
+            Some(&WindowError::EmptyWindow { from: 199, to: 199 })
+        );
+        assert_eq!(
+            err.to_string(),
+            "the window from block 199 to block 199 is empty, no yield can be computed over it"
+        );
+    }
+
+    #[test]
+    fn it_should_reject_contracts_without_code() {
+        let pool = PoolConfig::CBETH_ETH;
+        let provider = MockProvider::with_chain(100, 100);
+        provider.set_code(pool.lst, Bytes::from_static(&[0x60, 0x80]));
+
+        check_contracts_deployed(&provider, [("LST", pool.lst)], 199).unwrap();
+        let err =
+            check_contracts_deployed(&provider, [("LST", pool.lst), ("pool", pool.pool)], 199)
+                .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "no contract code at address {} on this chain: the pool is not deployed at block \
+                 199",
+                pool.pool
+            )
+        );
+    }
+
+    #[test]
+    fn it_should_find_when_an_oracle_was_deployed() {
+        let oracle = Address::repeat_byte(0xcc);
+        let provider = MockProvider::with_chain(100, 100);
+        provider.deploy_code(oracle, 150, Bytes::from_static(&[0x60, 0x80]));
+
+        assert_eq!(first_block_with_code(&provider, oracle, 100, 199).unwrap(), Some(150));
+        assert_eq!(first_block_with_code(&provider, oracle, 150, 199).unwrap(), Some(150));
+        assert_eq!(first_block_with_code(&provider, oracle, 160, 199).unwrap(), Some(160));
+        assert_eq!(first_block_with_code(&provider, oracle, 100, 149).unwrap(), None);
+        assert_eq!(first_block_with_code(&provider, Address::ZERO, 100, 199).unwrap(), None);
+
+        // the samples before it keep their ETH backing and stay out of the yield
+        let samples: Vec<_> = (100..200)
+            .step_by(20)
+            .map(|block| DexStatsInput {
+                timestamp: 1_700_000_000 + block * 12,
+                block_number: block,
+                lst_backing: U256::from(block),
+                interpolated: false,
+                pool_tvl: None,
+            })
+            .collect();
+        let denominated = exclude_before(&samples, 150);
+        assert_eq!(denominated[0].block_number, 160);
+        assert_eq!(denominated.len(), 2);
+    }
+
+    #[test]
+    fn it_should_fall_back_on_decimals_reverting() {
+        let provider = MockProvider::with_chain(100, 100);
+        // one token returns 8 from any call, the other reverts on any
+        let standard = Address::repeat_byte(0xd8);
+        provider.deploy_code(
+            standard,
+            100,
+            Bytes::from_static(&[0x60, 0x08, 0x60, 0x00, 0x52, 0x60, 0x20, 0x60, 0x00, 0xf3]),
+        );
+        let reverting = Address::repeat_byte(0xdd);
+        provider.deploy_code(reverting, 100, Bytes::from_static(&[0x60, 0x00, 0x60, 0x00, 0xfd]));
+
+        assert_eq!(query_decimals(provider.clone(), 150, standard, 18).unwrap(), (8, None));
+        let (decimals, warning) = query_decimals(provider.clone(), 150, reverting, 18).unwrap();
+        assert_eq!(decimals, 18);
+        let warning = warning.unwrap();
+        assert!(
+            warning
+                .starts_with(&format!("warning: decimals() of {reverting} reverted, assuming 18")),
+            "{warning}"
+        );
+
+        // a mistyped address has no code, and a call returning nothing doesn't revert
+        let err =
+            query_decimals(provider.clone(), 150, Address::repeat_byte(0xee), 18).unwrap_err();
+        assert_eq!(HostError::classify(&err), HostError::Config);
+        assert!(err.root_cause().to_string().contains("has no code at block 150"), "{err:#}");
+        let empty = Address::repeat_byte(0xde);
+        provider.deploy_code(empty, 100, Bytes::from_static(&[0x00]));
+        assert!(query_decimals(provider, 150, empty, 18).is_err());
+    }
+
+    #[test]
+    fn it_should_check_the_image_id() {
+        let pinned: ImageId = ImageId::from(TOKEN_STATS_ID).to_string().parse().unwrap();
+        check_image_id(&pinned, TOKEN_STATS_ID).unwrap();
+
+        let mut other = pinned;
+        other.0[31] ^= 1;
+        let err = check_image_id(&other, TOKEN_STATS_ID).unwrap_err();
+        assert!(err.to_string().contains("does not match the expected"), "{err}");
+    }
+
+    #[test]
+    fn it_should_reject_a_window_of_partial_intervals() {
+        check_window(BLOCKS_TO_QUERY, BLOCK_GRANULARITY).unwrap();
+        check_window(SMOKE_WINDOW_BLOCKS, SMOKE_GRANULARITY_BLOCKS).unwrap();
+
+        let err = check_window(350, 100).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "a window of 350 blocks is not a whole number of 100 block intervals"
+        );
+        assert!(check_window(300, 0).is_err());
+    }
+
+    #[test]
+    fn it_should_reject_headers_for_the_wrong_height() {
+        let provider = MockProvider::with_chain(100, 10);
+        provider.insert_header_at(105, provider.header(107).unwrap());
+
+        let err = collect_headers(&provider, 103, 109).unwrap_err();
+        assert_eq!(err.to_string(), "provider returned block 107 for height 105");
+    }
+}
@@ -0,0 +1,126 @@
+//! The provenance manifest of `--manifest-out`: what a run sampled and with which guest, enough to
+//! reproduce it independently and to audit it later. The RPC URLs are recorded as hashes only,
+//! since they often embed an API key; an auditor holding the URL can still check it was the one.
+
+use alloy_primitives::{keccak256, Address, B256};
+use anyhow::{Context, Result};
+use risc0_steel::BlockCommitment;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::Path;
+use tokemak::chain::ChainHeader;
+
+use crate::cli::ImageId;
+
+/// A block by number and hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockRef {
+    pub number: u64,
+    pub hash: B256,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Manifest {
+    /// Version of the host that made the run.
+    pub tool_version: &'static str,
+    /// Image ID of the guest, as hex.
+    pub image_id: String,
+    /// keccak256 of every RPC URL, in the order they were tried.
+    pub rpc_url_hashes: Vec<B256>,
+    /// See [`GuestInput::digest`](crate::stream::GuestInput::digest).
+    pub guest_input_hash: B256,
+    pub pool: Address,
+    pub granularity_blocks: u64,
+    /// The head the journal commits to.
+    pub head: BlockRef,
+    /// The sampled blocks, oldest first.
+    pub samples: Vec<BlockRef>,
+}
+
+impl Manifest {
+    pub fn new(
+        image_id: [u32; 8],
+        rpc_urls: &[String],
+        guest_input_hash: B256,
+        pool: Address,
+        granularity_blocks: u64,
+        head: &BlockCommitment,
+        samples: &[impl ChainHeader],
+    ) -> Self {
+        Manifest {
+            tool_version: env!("CARGO_PKG_VERSION"),
+            image_id: ImageId::from(image_id).to_string(),
+            rpc_url_hashes: rpc_urls.iter().map(keccak256).collect(),
+            guest_input_hash,
+            pool,
+            granularity_blocks,
+            head: BlockRef { number: head.blockNumber.to(), hash: head.blockHash },
+            samples: samples
+                .iter()
+                .map(|header| BlockRef { number: header.number(), hash: header.hash() })
+                .collect(),
+        }
+    }
+
+    /// Writes the manifest to `path` as pretty-printed JSON.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file =
+            File::create(path).with_context(|| format!("failed to create {}", path.display()))?;
+
+        Ok(serde_json::to_writer_pretty(file, self)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockProvider;
+    use alloy_primitives::U256;
+    use serde_json::Value;
+
+    #[test]
+    fn it_should_record_the_provenance_of_a_run() {
+        let chain = MockProvider::with_chain(100, 21);
+        let samples: Vec<_> =
+            [100, 110, 120].iter().map(|&number| chain.header(number).unwrap()).collect();
+        let head = BlockCommitment { blockHash: samples[2].hash(), blockNumber: U256::from(120) };
+        let urls = ["https://rpc.example/v1/secret-key".to_owned()];
+        let manifest = Manifest::new(
+            [1, 2, 3, 4, 5, 6, 7, 8],
+            &urls,
+            B256::repeat_byte(0x42),
+            Address::repeat_byte(0x11),
+            10,
+            &head,
+            &samples,
+        );
+
+        let path =
+            std::env::temp_dir().join(format!("host-manifest-test-{}.json", std::process::id()));
+        manifest.write(&path).unwrap();
+        let json: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        for key in [
+            "tool_version",
+            "image_id",
+            "rpc_url_hashes",
+            "guest_input_hash",
+            "pool",
+            "granularity_blocks",
+            "head",
+            "samples",
+        ] {
+            assert!(json.get(key).is_some(), "no {key} in {json}");
+        }
+        assert_eq!(json["tool_version"], env!("CARGO_PKG_VERSION"));
+        assert_eq!(json["image_id"], ImageId::from([1, 2, 3, 4, 5, 6, 7, 8]).to_string());
+        assert_eq!(json["samples"].as_array().unwrap().len(), 3);
+        assert_eq!(json["samples"][1]["number"], 110);
+        assert_eq!(json["samples"][1]["hash"], samples[1].hash().to_string());
+        assert_eq!(json["head"]["number"], 120);
+        // the URL itself, with its key, is not recorded
+        assert_eq!(json["rpc_url_hashes"][0], keccak256(&urls[0]).to_string());
+        assert!(!json.to_string().contains("secret-key"));
+    }
+}
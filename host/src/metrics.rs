@@ -0,0 +1,148 @@
+//! Prometheus textfile-format metrics, for pickup by node_exporter's textfile collector.
+
+use anyhow::{Context, Result};
+use std::{fmt::Write, fs, path::Path};
+use tokemak::DexStatsOutput;
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    pub base_yield: f64,
+    pub yield_volatility: f64,
+    pub sample_count: usize,
+    pub data_quality: f64,
+    /// Wall-clock duration of each named stage of the run, in the order they ran.
+    pub stages: Vec<(&'static str, f64)>,
+}
+
+impl Metrics {
+    pub fn new(stats: &DexStatsOutput, stages: Vec<(&'static str, f64)>) -> Self {
+        Metrics {
+            base_yield: stats.base_yield,
+            yield_volatility: stats.yield_volatility,
+            sample_count: stats.sample_count,
+            data_quality: stats.data_quality,
+            stages,
+        }
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let gauges = [
+            ("lst_base_yield", "Annualized base yield of the LST backing.", self.base_yield),
+            (
+                "lst_yield_volatility",
+                "Standard deviation of the annualized per-interval changes.",
+                self.yield_volatility,
+            ),
+            ("lst_sample_count", "Number of samples used.", self.sample_count as f64),
+            ("lst_data_quality", "Fraction of samples that were observed.", self.data_quality),
+        ];
+        for (name, help, value) in gauges {
+            writeln!(out, "# HELP {name} {help}").unwrap();
+            writeln!(out, "# TYPE {name} gauge").unwrap();
+            writeln!(out, "{name} {value}").unwrap();
+        }
+
+        writeln!(out, "# HELP lst_run_duration_seconds Duration of each stage of the run.")
+            .unwrap();
+        writeln!(out, "# TYPE lst_run_duration_seconds gauge").unwrap();
+        for (stage, seconds) in &self.stages {
+            writeln!(out, "lst_run_duration_seconds{{stage=\"{stage}\"}} {seconds}").unwrap();
+        }
+
+        out
+    }
+
+    /// Writes the metrics to `path`. The file is written next to its destination and renamed into
+    /// place, so the collector never scrapes a partially written file.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let tmp_path = path.with_extension("prom.tmp");
+        fs::write(&tmp_path, self.render())
+            .with_context(|| format!("failed to write {}", tmp_path.display()))?;
+        fs::rename(&tmp_path, path)
+            .with_context(|| format!("failed to move metrics to {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Checks the subset of the exposition format the metrics use: every sample belongs to a
+    /// metric with a preceding `TYPE` line, has well-formed labels and a float value.
+    fn validate_exposition(text: &str) -> Result<usize, String> {
+        let mut typed = HashSet::new();
+        let mut samples = 0;
+        for line in text.lines() {
+            if let Some(comment) = line.strip_prefix("# ") {
+                let mut parts = comment.splitn(3, ' ');
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some("TYPE"), Some(name), Some("gauge")) => {
+                        typed.insert(name.to_string());
+                    }
+                    (Some("HELP"), Some(_), Some(_)) => {}
+                    _ => return Err(format!("invalid comment: {line}")),
+                }
+                continue;
+            }
+
+            let (series, value) = line.rsplit_once(' ').ok_or(format!("no value: {line}"))?;
+            value.parse::<f64>().map_err(|_| format!("invalid value: {line}"))?;
+            let name = match series.split_once('{') {
+                Some((name, labels)) => {
+                    let labels = labels.strip_suffix('}').ok_or(format!("unclosed: {line}"))?;
+                    for label in labels.split(',') {
+                        let (key, value) = label.split_once('=').ok_or(format!("label: {line}"))?;
+                        let valid_key = key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                        if !valid_key || !value.starts_with('"') || !value.ends_with('"') {
+                            return Err(format!("invalid label: {line}"));
+                        }
+                    }
+                    name
+                }
+                None => series,
+            };
+            if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':') {
+                return Err(format!("invalid metric name: {line}"));
+            }
+            if !typed.contains(name) {
+                return Err(format!("sample without TYPE: {line}"));
+            }
+            samples += 1;
+        }
+
+        Ok(samples)
+    }
+
+    #[test]
+    fn it_should_emit_valid_exposition_format() {
+        let metrics = Metrics {
+            base_yield: 0.0312,
+            yield_volatility: 0.004,
+            sample_count: 4,
+            data_quality: 0.75,
+            stages: vec![("get_headers", 12.5), ("preflights", 30.0), ("executor", 9.0)],
+        };
+
+        let text = metrics.render();
+        assert_eq!(validate_exposition(&text), Ok(7));
+        assert!(text.contains("lst_base_yield 0.0312\n"));
+        assert!(text.contains("lst_run_duration_seconds{stage=\"preflights\"} 30\n"));
+    }
+
+    #[test]
+    fn it_should_write_the_file_atomically() {
+        let dir = std::env::temp_dir().join(format!("lst-metrics-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("lst.prom");
+
+        let metrics = Metrics { sample_count: 2, ..Default::default() };
+        metrics.write(&path).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), metrics.render());
+        assert!(!path.with_extension("prom.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
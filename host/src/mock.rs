@@ -0,0 +1,416 @@
+//! An in-memory provider and cache backend for tests, and the chain `--self-test` runs on.
+
+use std::{
+    collections::BTreeMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use crate::{
+    cache::{Cache, CacheBackend, CacheConfig},
+    Connect, SMOKE_WINDOW_BLOCKS,
+};
+use alloy_primitives::{
+    keccak256, Address, Bytes, Sealable, StorageKey, StorageValue, TxNumber, B256, U256,
+};
+use risc0_steel::{
+    ethereum::EthBlockHeader,
+    host::provider::{EIP1186Proof, Provider},
+};
+use tokemak::PoolConfig;
+
+/// A contract answering any call with an exchange rate growing from 1 by 1e-9 per block:
+/// `1e18 + 1e9 * block.number` as a 32-byte word. It reads no storage.
+pub const RATE_CODE: [u8; 25] = [
+    0x63, 0x3b, 0x9a, 0xca, 0x00, // PUSH4 1e9
+    0x43, 0x02, // NUMBER MUL
+    0x67, 0x0d, 0xe0, 0xb6, 0xb3, 0xa7, 0x64, 0x00, 0x00, // PUSH8 1e18
+    0x01, // ADD
+    0x60, 0x00, 0x52, // PUSH1 0 MSTORE
+    0x60, 0x20, 0x60, 0x00, 0xf3, // PUSH1 32 PUSH1 0 RETURN
+];
+
+/// A chain of the smoke window up to `head`, on which the pool and the LST of `pool` answer any
+/// call with the exchange rate of [`RATE_CODE`].
+pub fn smoke_chain(head: u64, pool: &PoolConfig) -> MockProvider {
+    let chain = MockProvider::with_chain(head - SMOKE_WINDOW_BLOCKS, SMOKE_WINDOW_BLOCKS + 1);
+    chain.deploy_code(pool.lst, 0, Bytes::from_static(&RATE_CODE));
+    chain.deploy_code(pool.pool, 0, Bytes::from_static(&RATE_CODE));
+    chain.seal_state();
+    chain
+}
+
+#[derive(Default)]
+struct State {
+    headers: Mutex<BTreeMap<u64, EthBlockHeader>>,
+    /// The code of each contract, and the block it was deployed at.
+    code: Mutex<BTreeMap<Address, (u64, Bytes)>>,
+    failing: AtomicBool,
+    requests: AtomicUsize,
+}
+
+/// Serves a fixed set of headers and contract code, and proofs of the accounts of the contracts.
+/// Clones share their state, so a test can keep a handle to a provider it moved into a wrapper, or
+/// into another thread, and inspect or modify it afterwards.
+#[derive(Clone, Default)]
+pub struct MockProvider {
+    state: Arc<State>,
+}
+
+impl MockProvider {
+    /// A provider whose every request fails.
+    pub fn failing() -> Self {
+        let provider = MockProvider::default();
+        provider.set_failing(true);
+        provider
+    }
+
+    /// A provider serving a linked chain of `len` headers starting at block `start`, 12 seconds
+    /// apart.
+    pub fn with_chain(start: u64, len: u64) -> Self {
+        let provider = MockProvider::default();
+        let mut parent_hash = B256::ZERO;
+        for number in start..start + len {
+            let header = EthBlockHeader {
+                number,
+                parent_hash,
+                timestamp: 1_700_000_000 + number * 12,
+                ..Default::default()
+            };
+            parent_hash = header.hash_slow();
+            provider.insert_header(header);
+        }
+        provider
+    }
+
+    pub fn insert_header(&self, header: EthBlockHeader) {
+        self.state.headers.lock().unwrap().insert(header.number, header);
+    }
+
+    /// Serves `header` for `number` regardless of its own block number, like a faulty provider.
+    pub fn insert_header_at(&self, number: u64, header: EthBlockHeader) {
+        self.state.headers.lock().unwrap().insert(number, header);
+    }
+
+    pub fn header(&self, number: u64) -> Option<EthBlockHeader> {
+        self.state.headers.lock().unwrap().get(&number).cloned()
+    }
+
+    /// Makes the headers fit for view calls: each commits to the state root of the contracts
+    /// deployed by its block, and carries the gas limit and blob gas fields a Cancun block needs,
+    /// the mock chain's timestamps being past the fork. The headers are linked anew.
+    pub fn seal_state(&self) {
+        let mut headers = self.state.headers.lock().unwrap();
+        let mut parent: Option<EthBlockHeader> = None;
+        for header in headers.values_mut() {
+            header.state_root = self.state_root(header.number);
+            header.gas_limit = 30_000_000;
+            header.blob_gas_used = Some(0);
+            header.excess_blob_gas = Some(0);
+            if let Some(parent) = parent.filter(|parent| parent.number + 1 == header.number) {
+                header.parent_hash = parent.hash_slow();
+            }
+            parent = Some(header.clone());
+        }
+    }
+
+    pub fn set_code(&self, address: Address, code: Bytes) {
+        self.deploy_code(address, 0, code);
+    }
+
+    /// Serves `code` for `address` from block `block` on, and no code before.
+    pub fn deploy_code(&self, address: Address, block: u64, code: Bytes) {
+        self.state.code.lock().unwrap().insert(address, (block, code));
+    }
+
+    pub fn set_failing(&self, failing: bool) {
+        self.state.failing.store(failing, Ordering::Relaxed);
+    }
+
+    /// Number of requests made against the provider, including failed ones.
+    pub fn request_count(&self) -> usize {
+        self.state.requests.load(Ordering::Relaxed)
+    }
+
+    fn request(&self) -> io::Result<()> {
+        self.state.requests.fetch_add(1, Ordering::Relaxed);
+        if self.state.failing.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "mock RPC unavailable"));
+        }
+        Ok(())
+    }
+
+    fn code_at(&self, address: Address, block: u64) -> Bytes {
+        match self.state.code.lock().unwrap().get(&address) {
+            Some((deployed, code)) if block >= *deployed => code.clone(),
+            _ => Bytes::new(),
+        }
+    }
+
+    /// The state trie at `block`: an account without balance, nonce or storage for each contract
+    /// deployed by then.
+    fn state_trie(&self, block: u64) -> Vec<(Vec<u8>, Vec<u8>)> {
+        let code = self.state.code.lock().unwrap();
+        let mut leaves: Vec<_> = code
+            .iter()
+            .filter(|(_, (deployed, _))| block >= *deployed)
+            .map(|(address, (_, code))| {
+                let account = rlp::list(&[
+                    rlp::uint(0),
+                    rlp::uint(0),
+                    rlp::bytes(rlp::empty_root().as_slice()),
+                    rlp::bytes(keccak256(code).as_slice()),
+                ]);
+                (rlp::nibbles(keccak256(address).as_slice()), account)
+            })
+            .collect();
+        leaves.sort();
+        leaves
+    }
+
+    fn state_root(&self, block: u64) -> B256 {
+        keccak256(rlp::trie_node(&self.state_trie(block), 0))
+    }
+}
+
+impl Provider for MockProvider {
+    type Error = io::Error;
+    type Header = EthBlockHeader;
+
+    fn get_block_number(&self) -> Result<u64, Self::Error> {
+        self.request()?;
+        Ok(self.state.headers.borrow().keys().next_back().copied().unwrap_or_default())
+    }
+
+    fn get_block_header(&self, block: u64) -> Result<Option<Self::Header>, Self::Error> {
+        self.request()?;
+        Ok(self.header(block))
+    }
+
+    fn get_transaction_count(&self, _: Address, _: u64) -> Result<TxNumber, Self::Error> {
+        self.request()?;
+        Ok(0)
+    }
+
+    fn get_balance(&self, _: Address, _: u64) -> Result<U256, Self::Error> {
+        self.request()?;
+        Ok(U256::ZERO)
+    }
+
+    fn get_code(&self, address: Address, block: u64) -> Result<Bytes, Self::Error> {
+        self.request()?;
+        Ok(self.code_at(address, block))
+    }
+
+    fn get_storage_at(
+        &self,
+        _: Address,
+        _: StorageKey,
+        _: u64,
+    ) -> Result<StorageValue, Self::Error> {
+        self.request()?;
+        Ok(StorageValue::ZERO)
+    }
+
+    fn get_proof(
+        &self,
+        address: Address,
+        _: Vec<StorageKey>,
+        block: u64,
+    ) -> Result<EIP1186Proof, Self::Error> {
+        self.request()?;
+        let code = self.code_at(address, block);
+        let key = rlp::nibbles(keccak256(address).as_slice());
+        let mut account_proof = Vec::new();
+        rlp::trie_proof(&self.state_trie(block), 0, &key, true, &mut account_proof);
+
+        // the contracts have no storage, and none is read, so no storage proofs are needed
+        Ok(EIP1186Proof {
+            address,
+            balance: U256::ZERO,
+            code_hash: keccak256(&code),
+            nonce: 0,
+            storage_hash: if code.is_empty() { B256::ZERO } else { rlp::empty_root() },
+            account_proof: account_proof.into_iter().map(Bytes::from).collect(),
+            storage_proof: Vec::new(),
+        })
+    }
+}
+
+impl Connect for MockProvider {
+    type Provider = MockProvider;
+    type Cached = Cache<MockProvider>;
+
+    fn connect(&self) -> anyhow::Result<MockProvider> {
+        Ok(self.clone())
+    }
+
+    fn open(&self, cache: &CacheConfig) -> anyhow::Result<Cache<MockProvider>> {
+        cache.open(self.clone())
+    }
+}
+
+/// Just enough RLP and Merkle Patricia trie to prove the mock state: leaves are keyed by the
+/// nibbles of their hashed key, and a node is referenced by its hash unless its encoding is shorter
+/// than one.
+mod rlp {
+    use alloy_primitives::{keccak256, B256};
+
+    pub fn bytes(bytes: &[u8]) -> Vec<u8> {
+        match bytes {
+            [byte] if *byte < 0x80 => vec![*byte],
+            _ => prefixed(0x80, bytes),
+        }
+    }
+
+    pub fn uint(value: u64) -> Vec<u8> {
+        let be = value.to_be_bytes();
+        let first = be.iter().position(|&byte| byte != 0).unwrap_or(be.len());
+        bytes(&be[first..])
+    }
+
+    pub fn list(items: &[Vec<u8>]) -> Vec<u8> {
+        prefixed(0xc0, &items.concat())
+    }
+
+    fn prefixed(offset: u8, payload: &[u8]) -> Vec<u8> {
+        let mut out = if payload.len() < 56 {
+            vec![offset + payload.len() as u8]
+        } else {
+            let len = payload.len().to_be_bytes();
+            let first = len.iter().position(|&byte| byte != 0).unwrap();
+            let mut out = vec![offset + 55 + (len.len() - first) as u8];
+            out.extend_from_slice(&len[first..]);
+            out
+        };
+        out.extend_from_slice(payload);
+        out
+    }
+
+    /// The root hash of the empty trie.
+    pub fn empty_root() -> B256 {
+        keccak256(bytes(&[]))
+    }
+
+    pub fn nibbles(key: &[u8]) -> Vec<u8> {
+        key.iter().flat_map(|byte| [byte >> 4, byte & 0x0f]).collect()
+    }
+
+    /// The hex-prefix encoding of the `path` of a leaf or an extension.
+    fn hex_prefix(path: &[u8], leaf: bool) -> Vec<u8> {
+        let flag = if leaf { 2 } else { 0 } + (path.len() % 2) as u8;
+        let mut nibbles = vec![flag];
+        if path.len() % 2 == 0 {
+            nibbles.push(0);
+        }
+        nibbles.extend_from_slice(path);
+        nibbles.chunks(2).map(|pair| pair[0] << 4 | pair[1]).collect()
+    }
+
+    /// The length of the path the sorted `leaves` share from nibble `depth` on.
+    fn shared(leaves: &[(Vec<u8>, Vec<u8>)], depth: usize) -> usize {
+        let (first, last) = (&leaves[0].0, &leaves[leaves.len() - 1].0);
+        first[depth..].iter().zip(&last[depth..]).take_while(|(a, b)| a == b).count()
+    }
+
+    fn children(leaves: &[(Vec<u8>, Vec<u8>)], depth: usize, nibble: u8) -> &[(Vec<u8>, Vec<u8>)] {
+        let start = leaves.partition_point(|(key, _)| key[depth] < nibble);
+        let end = leaves.partition_point(|(key, _)| key[depth] <= nibble);
+        &leaves[start..end]
+    }
+
+    fn reference(node: Vec<u8>) -> Vec<u8> {
+        if node.len() < 32 {
+            node
+        } else {
+            bytes(keccak256(&node).as_slice())
+        }
+    }
+
+    /// The encoding of the node holding the sorted `leaves`, which agree on their first `depth`
+    /// nibbles.
+    pub fn trie_node(leaves: &[(Vec<u8>, Vec<u8>)], depth: usize) -> Vec<u8> {
+        match leaves {
+            [] => bytes(&[]),
+            [(key, value)] => list(&[bytes(&hex_prefix(&key[depth..], true)), bytes(value)]),
+            _ => match shared(leaves, depth) {
+                0 => {
+                    let mut items: Vec<_> = (0..16)
+                        .map(|nibble| match children(leaves, depth, nibble) {
+                            [] => bytes(&[]),
+                            children => reference(trie_node(children, depth + 1)),
+                        })
+                        .collect();
+                    items.push(bytes(&[]));
+                    list(&items)
+                }
+                shared => {
+                    let path = hex_prefix(&leaves[0].0[depth..depth + shared], false);
+                    list(&[bytes(&path), reference(trie_node(leaves, depth + shared))])
+                }
+            },
+        }
+    }
+
+    /// Appends the nodes on the path to `key` that aren't inlined in their parent, the root first,
+    /// to `proof`: those of the leaf holding it, or up to where its path leaves the trie.
+    pub fn trie_proof(
+        leaves: &[(Vec<u8>, Vec<u8>)],
+        depth: usize,
+        key: &[u8],
+        root: bool,
+        proof: &mut Vec<Vec<u8>>,
+    ) {
+        let node = trie_node(leaves, depth);
+        if root || node.len() >= 32 {
+            proof.push(node);
+        }
+        if leaves.len() < 2 {
+            return;
+        }
+        match shared(leaves, depth) {
+            0 => {
+                let children = children(leaves, depth, key[depth]);
+                if !children.is_empty() {
+                    trie_proof(children, depth + 1, key, false, proof);
+                }
+            }
+            shared if key[depth..depth + shared] == leaves[0].0[depth..depth + shared] => {
+                trie_proof(leaves, depth + shared, key, false, proof)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// A cache backend holding its entries in memory. Clones share their entries, like the provider.
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    entries: Arc<Mutex<BTreeMap<String, Vec<u8>>>>,
+}
+
+impl MemoryBackend {
+    /// The keys of the stored entries, in order.
+    pub fn keys(&self) -> Vec<String> {
+        self.entries.lock().unwrap().keys().cloned().collect()
+    }
+}
+
+impl CacheBackend for MemoryBackend {
+    fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.entries.lock().unwrap().get(key).cloned())
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        self.entries.lock().unwrap().insert(key.to_owned(), bytes.to_vec());
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> anyhow::Result<()> {
+        self.entries.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
@@ -0,0 +1,511 @@
+//! Providers layered between the RPC clients and the response cache.
+
+use alloy_primitives::{Address, Bytes, StorageKey, StorageValue, TxNumber, U256};
+use risc0_steel::host::provider::{EIP1186Proof, Provider};
+use std::error::Error;
+use std::fmt;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Tries a list of providers in order, falling through to the next one when a request fails, so
+/// that a transient outage of one endpoint doesn't abort the whole run.
+///
+/// Wrapped in a `CachedProvider`, responses are cached independently of the endpoint that served
+/// them.
+pub struct FallbackProvider<P> {
+    providers: Vec<P>,
+}
+
+impl<P: Provider> FallbackProvider<P> {
+    pub fn new(providers: Vec<P>) -> Self {
+        assert!(!providers.is_empty(), "no providers");
+
+        FallbackProvider { providers }
+    }
+
+    fn try_each<T>(&self, request: impl Fn(&P) -> Result<T, P::Error>) -> Result<T, P::Error> {
+        let mut last_err = None;
+        for (index, provider) in self.providers.iter().enumerate() {
+            match request(provider) {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    if index + 1 < self.providers.len() {
+                        warning!("RPC endpoint #{index} failed, trying the next one: {err}");
+                    }
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err.unwrap())
+    }
+}
+
+impl<P: Provider> Provider for FallbackProvider<P> {
+    type Error = P::Error;
+    type Header = P::Header;
+
+    fn get_block_number(&self) -> Result<u64, Self::Error> {
+        self.try_each(|p| p.get_block_number())
+    }
+
+    fn get_block_header(&self, block: u64) -> Result<Option<Self::Header>, Self::Error> {
+        self.try_each(|p| p.get_block_header(block))
+    }
+
+    fn get_transaction_count(&self, address: Address, block: u64) -> Result<TxNumber, Self::Error> {
+        self.try_each(|p| p.get_transaction_count(address, block))
+    }
+
+    fn get_balance(&self, address: Address, block: u64) -> Result<U256, Self::Error> {
+        self.try_each(|p| p.get_balance(address, block))
+    }
+
+    fn get_code(&self, address: Address, block: u64) -> Result<Bytes, Self::Error> {
+        self.try_each(|p| p.get_code(address, block))
+    }
+
+    fn get_storage_at(
+        &self,
+        address: Address,
+        key: StorageKey,
+        block: u64,
+    ) -> Result<StorageValue, Self::Error> {
+        self.try_each(|p| p.get_storage_at(address, key, block))
+    }
+
+    fn get_proof(
+        &self,
+        address: Address,
+        storage_keys: Vec<StorageKey>,
+        block: u64,
+    ) -> Result<EIP1186Proof, Self::Error> {
+        self.try_each(|p| p.get_proof(address, storage_keys.clone(), block))
+    }
+}
+
+/// A ceiling on the requests made through all [`BudgetedProvider`]s sharing it, as a guard against
+/// exhausting a metered RPC plan. Clones share the count.
+#[derive(Debug, Clone, Default)]
+pub struct RequestBudget {
+    limit: Option<u64>,
+    /// Fail every request as [`BudgetError::Offline`].
+    offline: bool,
+    used: Arc<AtomicU64>,
+}
+
+impl RequestBudget {
+    /// A budget of `limit` requests; `None` for no limit.
+    pub fn new(limit: Option<u64>) -> Self {
+        RequestBudget { limit, offline: false, used: Arc::default() }
+    }
+
+    /// A budget allowing no requests at all. Beneath a `CachedProvider`, only cache misses reach
+    /// the budget, so a run on it is served from the cache alone and fails on the first miss.
+    pub fn offline() -> Self {
+        RequestBudget { limit: Some(0), offline: true, used: Arc::default() }
+    }
+
+    /// Number of requests made so far.
+    pub fn used(&self) -> u64 {
+        self.used.load(Ordering::Relaxed)
+    }
+
+    fn spend<E>(&self) -> Result<(), BudgetError<E>> {
+        if self.offline {
+            return Err(BudgetError::Offline);
+        }
+        match self.limit {
+            Some(limit) if self.used.fetch_add(1, Ordering::Relaxed) >= limit => {
+                Err(BudgetError::Exhausted { limit })
+            }
+            Some(_) => Ok(()),
+            None => {
+                self.used.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The error of a [`BudgetedProvider`].
+#[derive(Debug)]
+pub enum BudgetError<E> {
+    /// The request was not made, as the budget was used up.
+    Exhausted {
+        limit: u64,
+    },
+    /// The request was not made, as the budget is [`RequestBudget::offline`].
+    Offline,
+    Provider(E),
+}
+
+impl<E: fmt::Display> fmt::Display for BudgetError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BudgetError::Exhausted { limit } => {
+                write!(f, "request budget exhausted: all {limit} requests used")
+            }
+            BudgetError::Offline => write!(f, "not cached, and no requests are made offline"),
+            BudgetError::Provider(err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for BudgetError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BudgetError::Exhausted { .. } | BudgetError::Offline => None,
+            BudgetError::Provider(err) => Some(err),
+        }
+    }
+}
+
+/// Counts the requests made through `P` against a [`RequestBudget`], and fails those beyond it
+/// without making them.
+pub struct BudgetedProvider<P> {
+    inner: P,
+    budget: RequestBudget,
+}
+
+impl<P: Provider> BudgetedProvider<P> {
+    pub fn new(inner: P, budget: RequestBudget) -> Self {
+        BudgetedProvider { inner, budget }
+    }
+
+    fn spend<T>(
+        &self,
+        request: impl FnOnce(&P) -> Result<T, P::Error>,
+    ) -> Result<T, BudgetError<P::Error>> {
+        self.budget.spend()?;
+        request(&self.inner).map_err(BudgetError::Provider)
+    }
+}
+
+impl<P: Provider> Provider for BudgetedProvider<P>
+where
+    BudgetError<P::Error>: Error + Send + Sync + 'static,
+{
+    type Error = BudgetError<P::Error>;
+    type Header = P::Header;
+
+    fn get_block_number(&self) -> Result<u64, Self::Error> {
+        self.spend(|p| p.get_block_number())
+    }
+
+    fn get_block_header(&self, block: u64) -> Result<Option<Self::Header>, Self::Error> {
+        self.spend(|p| p.get_block_header(block))
+    }
+
+    fn get_transaction_count(&self, address: Address, block: u64) -> Result<TxNumber, Self::Error> {
+        self.spend(|p| p.get_transaction_count(address, block))
+    }
+
+    fn get_balance(&self, address: Address, block: u64) -> Result<U256, Self::Error> {
+        self.spend(|p| p.get_balance(address, block))
+    }
+
+    fn get_code(&self, address: Address, block: u64) -> Result<Bytes, Self::Error> {
+        self.spend(|p| p.get_code(address, block))
+    }
+
+    fn get_storage_at(
+        &self,
+        address: Address,
+        key: StorageKey,
+        block: u64,
+    ) -> Result<StorageValue, Self::Error> {
+        self.spend(|p| p.get_storage_at(address, key, block))
+    }
+
+    fn get_proof(
+        &self,
+        address: Address,
+        storage_keys: Vec<StorageKey>,
+        block: u64,
+    ) -> Result<EIP1186Proof, Self::Error> {
+        self.spend(|p| p.get_proof(address, storage_keys, block))
+    }
+}
+
+/// Counts the consecutive failed requests to an RPC endpoint and trips once there are `threshold`
+/// of them, as the endpoint then appears down: every further request fails at once rather than
+/// grinding through the retries of the client for each. A successful request resets the count.
+/// Clones share the state, so every provider connecting to the endpoint sees it tripped.
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    threshold: u32,
+    failures: Arc<AtomicU32>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32) -> Self {
+        assert!(threshold > 0, "breaker threshold must be positive");
+
+        CircuitBreaker { threshold, failures: Arc::default() }
+    }
+
+    pub fn is_tripped(&self) -> bool {
+        self.failures.load(Ordering::Relaxed) >= self.threshold
+    }
+
+    fn record<T, E>(&self, result: &Result<T, E>) {
+        match result {
+            Ok(_) => self.failures.store(0, Ordering::Relaxed),
+            Err(_) => {
+                self.failures.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// An RPC endpoint and the breaker guarding it.
+#[derive(Debug, Clone)]
+pub struct Endpoint {
+    pub url: String,
+    pub breaker: CircuitBreaker,
+}
+
+impl Endpoint {
+    /// The endpoints at `urls`, each with a breaker tripping after `threshold` failures.
+    pub fn all(urls: &[String], threshold: u32) -> Vec<Endpoint> {
+        urls.iter()
+            .map(|url| Endpoint { url: url.clone(), breaker: CircuitBreaker::new(threshold) })
+            .collect()
+    }
+}
+
+/// The error of a [`BreakerProvider`].
+#[derive(Debug)]
+pub enum BreakerError<E> {
+    /// The request was not made, as the breaker is tripped.
+    Open {
+        failures: u32,
+    },
+    Provider(E),
+}
+
+impl<E: fmt::Display> fmt::Display for BreakerError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakerError::Open { failures } => {
+                write!(f, "RPC endpoint appears unavailable: its last {failures} requests failed")
+            }
+            BreakerError::Provider(err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for BreakerError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BreakerError::Open { .. } => None,
+            BreakerError::Provider(err) => Some(err),
+        }
+    }
+}
+
+/// Makes the requests through `P` unless its [`CircuitBreaker`] is tripped. Beneath a
+/// [`FallbackProvider`], a tripped endpoint is skipped at once, and the run fails fast once all
+/// of them are.
+pub struct BreakerProvider<P> {
+    inner: P,
+    breaker: CircuitBreaker,
+}
+
+impl<P: Provider> BreakerProvider<P> {
+    pub fn new(inner: P, breaker: CircuitBreaker) -> Self {
+        BreakerProvider { inner, breaker }
+    }
+
+    fn guard<T>(
+        &self,
+        request: impl FnOnce(&P) -> Result<T, P::Error>,
+    ) -> Result<T, BreakerError<P::Error>> {
+        if self.breaker.is_tripped() {
+            return Err(BreakerError::Open { failures: self.breaker.threshold });
+        }
+        let result = request(&self.inner);
+        self.breaker.record(&result);
+
+        result.map_err(BreakerError::Provider)
+    }
+}
+
+impl<P: Provider> Provider for BreakerProvider<P>
+where
+    BreakerError<P::Error>: Error + Send + Sync + 'static,
+{
+    type Error = BreakerError<P::Error>;
+    type Header = P::Header;
+
+    fn get_block_number(&self) -> Result<u64, Self::Error> {
+        self.guard(|p| p.get_block_number())
+    }
+
+    fn get_block_header(&self, block: u64) -> Result<Option<Self::Header>, Self::Error> {
+        self.guard(|p| p.get_block_header(block))
+    }
+
+    fn get_transaction_count(&self, address: Address, block: u64) -> Result<TxNumber, Self::Error> {
+        self.guard(|p| p.get_transaction_count(address, block))
+    }
+
+    fn get_balance(&self, address: Address, block: u64) -> Result<U256, Self::Error> {
+        self.guard(|p| p.get_balance(address, block))
+    }
+
+    fn get_code(&self, address: Address, block: u64) -> Result<Bytes, Self::Error> {
+        self.guard(|p| p.get_code(address, block))
+    }
+
+    fn get_storage_at(
+        &self,
+        address: Address,
+        key: StorageKey,
+        block: u64,
+    ) -> Result<StorageValue, Self::Error> {
+        self.guard(|p| p.get_storage_at(address, key, block))
+    }
+
+    fn get_proof(
+        &self,
+        address: Address,
+        storage_keys: Vec<StorageKey>,
+        block: u64,
+    ) -> Result<EIP1186Proof, Self::Error> {
+        self.guard(|p| p.get_proof(address, storage_keys, block))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        cache::{CacheConfig, FsBackend},
+        mock::MockProvider,
+    };
+
+    #[test]
+    fn it_should_fall_back_to_the_next_provider() {
+        let failing = MockProvider::failing();
+        let serving = MockProvider::with_chain(100, 10);
+        let provider = FallbackProvider::new(vec![failing.clone(), serving.clone()]);
+
+        let header = provider.get_block_header(105).unwrap().unwrap();
+        assert_eq!(header.number, 105);
+        assert_eq!(provider.get_block_number().unwrap(), 109);
+
+        assert_eq!(failing.request_count(), 2);
+        assert_eq!(serving.request_count(), 2);
+    }
+
+    #[test]
+    fn it_should_prefer_the_first_provider() {
+        let first = MockProvider::with_chain(100, 10);
+        let second = MockProvider::with_chain(100, 10);
+        let provider = FallbackProvider::new(vec![first.clone(), second.clone()]);
+
+        provider.get_block_header(101).unwrap();
+        assert_eq!(first.request_count(), 1);
+        assert_eq!(second.request_count(), 0);
+    }
+
+    #[test]
+    fn it_should_fail_when_all_providers_fail() {
+        let provider =
+            FallbackProvider::new(vec![MockProvider::failing(), MockProvider::failing()]);
+
+        assert!(provider.get_block_header(101).is_err());
+    }
+
+    #[test]
+    fn it_should_stop_once_the_budget_is_exhausted() {
+        let chain = MockProvider::with_chain(100, 10);
+        let budget = RequestBudget::new(Some(3));
+        let provider = BudgetedProvider::new(chain.clone(), budget.clone());
+
+        let err = crate::fetch_headers(&provider, 100, 109, |_| true).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("request budget exhausted: all 3 requests used"),
+            "{err:#}"
+        );
+        // the request past the budget was never made
+        assert_eq!(chain.request_count(), 3);
+
+        // a budget is shared by all providers it is handed to
+        let other = BudgetedProvider::new(chain.clone(), budget);
+        assert!(matches!(other.get_block_number(), Err(BudgetError::Exhausted { limit: 3 })));
+        assert_eq!(chain.request_count(), 3);
+    }
+
+    #[test]
+    fn it_should_trip_the_breaker_of_a_dead_endpoint() {
+        let dead = MockProvider::failing();
+        let breaker = CircuitBreaker::new(3);
+        let provider = BreakerProvider::new(dead.clone(), breaker.clone());
+
+        for _ in 0..3 {
+            assert!(matches!(provider.get_block_number(), Err(BreakerError::Provider(_))));
+        }
+        assert!(breaker.is_tripped());
+        let err = crate::fetch_headers(&provider, 100, 109, |_| true).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("RPC endpoint appears unavailable: its last 3 requests"),
+            "{err:#}"
+        );
+        // the tripped breaker made no further requests, and another provider sharing it neither
+        let other = BreakerProvider::new(dead.clone(), breaker);
+        assert!(matches!(other.get_block_number(), Err(BreakerError::Open { failures: 3 })));
+        assert_eq!(dead.request_count(), 3);
+    }
+
+    #[test]
+    fn it_should_fall_back_past_a_tripped_endpoint() {
+        let (dead, serving) = (MockProvider::failing(), MockProvider::with_chain(100, 10));
+        let breakers = [CircuitBreaker::new(2), CircuitBreaker::new(2)];
+        let provider = FallbackProvider::new(vec![
+            BreakerProvider::new(dead.clone(), breakers[0].clone()),
+            BreakerProvider::new(serving.clone(), breakers[1].clone()),
+        ]);
+
+        let headers = crate::fetch_headers(&provider, 100, 109, |_| true);
+        assert!(headers.is_ok());
+        // the dead endpoint is only tried until it trips
+        assert_eq!((dead.request_count(), serving.request_count()), (2, 10));
+        assert!(breakers[0].is_tripped() && !breakers[1].is_tripped());
+
+        // and a success resets the count
+        serving.set_failing(true);
+        assert!(provider.get_block_number().is_err());
+        serving.set_failing(false);
+        provider.get_block_number().unwrap();
+        serving.set_failing(true);
+        assert!(provider.get_block_number().is_err());
+        assert!(!breakers[1].is_tripped());
+    }
+
+    #[test]
+    fn it_should_fail_a_cache_miss_offline() {
+        let dir = std::env::temp_dir().join(format!("host-offline-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = CacheConfig {
+            backend: FsBackend,
+            key: dir.join("cache.json").to_string_lossy().into_owned(),
+            compress: false,
+        };
+        let chain = MockProvider::with_chain(100, 10);
+        cache.open(chain.clone()).unwrap().get_block_header(105).unwrap().unwrap();
+
+        let offline = cache.open(BudgetedProvider::new(chain.clone(), RequestBudget::offline()));
+        let offline = offline.unwrap();
+        assert_eq!(offline.get_block_header(105).unwrap().unwrap().number, 105);
+        let err = offline.get_block_header(106).unwrap_err();
+        assert!(format!("{err:#}").contains("not cached, and no requests are made offline"));
+        // the miss never reached the chain
+        assert_eq!(chain.request_count(), 1);
+        drop(offline);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,279 @@
+//! The consolidated report of `--report`: every metric of a run in one place, as text for an
+//! analyst or as JSON, rather than scattered over the log.
+
+use alloy_primitives::{Address, B256};
+use core::fmt;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use tokemak::{
+    attribution::YieldAttribution,
+    fees::{FeeSchedule, NetYield},
+    wad_to_yield, DexStatsOutput, LstDexStats,
+};
+
+use crate::verification::Verification;
+
+/// Which samples the run scheduled and what became of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ScheduleSummary {
+    pub first_block: u64,
+    pub last_block: u64,
+    pub granularity_blocks: u64,
+    /// Number of samples scheduled.
+    pub scheduled: usize,
+    /// Number of resampled points the stats are computed from.
+    pub used: usize,
+    pub interpolated: usize,
+    pub skipped: usize,
+    /// Number of the oldest samples the resample left out.
+    pub dropped: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Report<'a> {
+    pub verification: &'a Verification,
+    pub pool: Address,
+    pub block_number: u64,
+    pub block_hash: B256,
+    pub base_yield: f64,
+    pub incentive_yield: f64,
+    pub base_apr: f64,
+    pub base_apy: f64,
+    pub yield_volatility: f64,
+    pub yield_std_error: f64,
+    /// The rate [`sharpe_ratio`](Self::sharpe_ratio) is taken over.
+    pub risk_free_apr: f64,
+    /// See [`DexStatsOutput::sharpe_ratio`]; `None` without any volatility.
+    pub sharpe_ratio: Option<f64>,
+    /// The base yield net of the `--management-fee-apr` and `--performance-fee-bps` fees.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub net_of_fees: Option<NetYield>,
+    pub data_quality: f64,
+    pub schedule: ScheduleSummary,
+    /// The base yield as staking and the incentive yield; the trading fees aren't measured.
+    pub attribution: YieldAttribution,
+    /// The `--windows` yields, by lookback in days.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub yield_curve: Option<&'a BTreeMap<u64, f64>>,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(
+        verification: &'a Verification,
+        journal: &LstDexStats,
+        host: &DexStatsOutput,
+        schedule: ScheduleSummary,
+    ) -> Self {
+        let (base_yield, incentive_yield) =
+            (wad_to_yield(journal.baseYield), journal.incentive_yield());
+        Report {
+            verification,
+            pool: journal.pool,
+            block_number: journal.commitment.blockNumber.to(),
+            block_hash: journal.commitment.blockHash,
+            base_yield,
+            incentive_yield,
+            base_apr: host.base_apr,
+            base_apy: host.base_apy,
+            yield_volatility: host.yield_volatility,
+            yield_std_error: host.yield_std_error,
+            risk_free_apr: 0.0,
+            sharpe_ratio: host.sharpe_ratio(0.0),
+            net_of_fees: None,
+            data_quality: host.data_quality,
+            schedule,
+            attribution: YieldAttribution::new(base_yield, 0.0, incentive_yield),
+            yield_curve: None,
+        }
+    }
+
+    /// The report with the Sharpe-like ratio of `host` taken over `risk_free_apr` instead of zero.
+    pub fn with_risk_free_apr(self, risk_free_apr: f64, host: &DexStatsOutput) -> Self {
+        Report { risk_free_apr, sharpe_ratio: host.sharpe_ratio(risk_free_apr), ..self }
+    }
+
+    /// The report with the base yield net of `fees`.
+    pub fn with_fees(self, fees: FeeSchedule) -> Self {
+        Report { net_of_fees: Some(fees.apply(self.base_yield)), ..self }
+    }
+}
+
+impl fmt::Display for Report<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |value: f64| format!("{:.2}%", value * 100.0);
+        let schedule = &self.schedule;
+
+        writeln!(
+            f,
+            "Yield report for pool {} at block {} ({})",
+            self.pool, self.block_number, self.block_hash
+        )?;
+        writeln!(f, "  Verification:  {}", self.verification)?;
+        writeln!(
+            f,
+            "  Base yield:    {} (APR {}, APY {})",
+            percent(self.base_yield),
+            percent(self.base_apr),
+            percent(self.base_apy)
+        )?;
+        writeln!(
+            f,
+            "  Incentives:    {}, combined {}",
+            percent(self.incentive_yield),
+            percent(self.base_yield + self.incentive_yield)
+        )?;
+        writeln!(
+            f,
+            "  Volatility:    {} (std error {})",
+            percent(self.yield_volatility),
+            percent(self.yield_std_error)
+        )?;
+        match self.sharpe_ratio {
+            Some(ratio) => writeln!(
+                f,
+                "  Sharpe-like:   {ratio:.2} over a risk-free {}",
+                percent(self.risk_free_apr)
+            )?,
+            None => writeln!(f, "  Sharpe-like:   undefined, without any volatility")?,
+        }
+        if let Some(net) = &self.net_of_fees {
+            writeln!(f, "  Net of fees:   {net}")?;
+        }
+        writeln!(f, "  Data quality:  {:.0}%", self.data_quality * 100.0)?;
+        writeln!(
+            f,
+            "  Schedule:      {} samples from block {} to {}, every {} blocks; {} used, {} \
+             interpolated, {} skipped, {} dropped",
+            schedule.scheduled,
+            schedule.first_block,
+            schedule.last_block,
+            schedule.granularity_blocks,
+            schedule.used,
+            schedule.interpolated,
+            schedule.skipped,
+            schedule.dropped
+        )?;
+        write!(f, "  Attribution:   {} (fees not measured)", self.attribution)?;
+        if let Some(curve) = self.yield_curve {
+            let yields: Vec<_> = curve
+                .iter()
+                .map(|(days, base_yield)| format!("{days}d {}", percent(*base_yield)))
+                .collect();
+            write!(f, "\n  Yield curve:   {}", yields.join(", "))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::U256;
+    use risc0_steel::BlockCommitment;
+    use tokemak::{fixture, yield_to_wad};
+
+    #[test]
+    fn it_should_render_every_metric() {
+        let verification = Verification::default();
+        let journal = LstDexStats {
+            commitment: BlockCommitment {
+                blockHash: B256::repeat_byte(0xab),
+                blockNumber: U256::from(19_000_000),
+            },
+            pool: Address::repeat_byte(0x11),
+            lst: Address::repeat_byte(0x22),
+            baseYield: yield_to_wad(0.0375),
+            rewardPool: Address::repeat_byte(0x33),
+            incentiveYield: U256::from(12_500_000_000_000_000_u64),
+            windowBlocks: 180 * 7_200,
+            paramsDigest: B256::repeat_byte(0x55),
+            samplesDigest: B256::repeat_byte(0x66),
+            ..fixture::journal()
+        };
+        let host = DexStatsOutput {
+            base_yield: 0.0375,
+            base_apr: 0.0368,
+            base_apy: 0.0375,
+            total_return: 0.0,
+            changes: Vec::new(),
+            yield_volatility: 0.0052,
+            yield_std_error: 0.0004,
+            sample_count: 180,
+            data_quality: 0.99,
+            dropped_samples: 0,
+        };
+        let schedule = ScheduleSummary {
+            first_block: 17_704_000,
+            last_block: 19_000_000,
+            granularity_blocks: 7_200,
+            scheduled: 181,
+            used: 180,
+            interpolated: 1,
+            skipped: 0,
+            dropped: 0,
+        };
+        let curve = BTreeMap::from([(7, 0.035), (30, 0.0365)]);
+        let report = Report {
+            yield_curve: Some(&curve),
+            ..Report::new(&verification, &journal, &host, schedule)
+        }
+        .with_risk_free_apr(0.0115, &host)
+        .with_fees(FeeSchedule { management_fee_apr: 0.005, performance_fee_bps: 2_000 });
+
+        assert_eq!(
+            report.to_string(),
+            format!(
+                "Yield report for pool {} at block 19000000 ({})\n  \
+                 Verification:  verified\n  \
+                 Base yield:    3.75% (APR 3.68%, APY 3.75%)\n  \
+                 Incentives:    1.25%, combined 5.00%\n  \
+                 Volatility:    0.52% (std error 0.04%)\n  \
+                 Sharpe-like:   5.00 over a risk-free 1.15%\n  \
+                 Net of fees:   2.50% net of a 0.50% management and 20.00% performance fee, \
+                 3.75% gross\n  \
+                 Data quality:  99%\n  \
+                 Schedule:      181 samples from block 17704000 to 19000000, every 7200 blocks; \
+                 180 used, 1 interpolated, 0 skipped, 0 dropped\n  \
+                 Attribution:   staking 75%, fees 0%, incentives 25% (fees not measured)\n  \
+                 Yield curve:   7d 3.50%, 30d 3.65%",
+                journal.pool, journal.commitment.blockHash
+            )
+        );
+
+        // the same fields in the same order, led by the verification
+        let json = serde_json::to_string(&report).unwrap();
+        assert!(json.starts_with(r#"{"verification":{"status":"verified"},"#), "{json}");
+        let positions: Vec<_> = [
+            "verification",
+            "pool",
+            "block_number",
+            "block_hash",
+            "base_yield",
+            "incentive_yield",
+            "base_apr",
+            "base_apy",
+            "yield_volatility",
+            "yield_std_error",
+            "risk_free_apr",
+            "sharpe_ratio",
+            "net_of_fees",
+            "data_quality",
+            "schedule",
+            "attribution",
+            "yield_curve",
+        ]
+        .iter()
+        .map(|key| json.find(&format!(r#""{key}":"#)).unwrap_or_else(|| panic!("no {key}")))
+        .collect();
+        assert!(positions.windows(2).all(|pair| pair[0] < pair[1]), "{json}");
+        assert!(json.contains(r#""interpolated":1,"#));
+        assert!((report.sharpe_ratio.unwrap() - 5.0).abs() < 1e-9);
+
+        let steady = DexStatsOutput { yield_volatility: 0.0, ..host };
+        let report = Report::new(&verification, &journal, &steady, schedule);
+        assert_eq!(report.sharpe_ratio, None);
+        assert!(report.to_string().contains("Sharpe-like:   undefined, without any volatility"));
+        assert!(json.contains(r#""yield_curve":{"7":0.035,"30":0.0365}"#));
+    }
+}
@@ -0,0 +1,536 @@
+//! Checks of the assembled sample schedule, run once before the executor is started, so that a
+//! broken schedule fails fast and with every problem listed rather than one per run.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+use tokemak::{
+    chain::ChainHeader, midnight_day, pseudo_timed, DexStatsInput, DexStatsParams, SampleAlignment,
+    DAY_IN_SECONDS,
+};
+
+/// A problem with the sample schedule; the guest would reject the input because of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScheduleIssue {
+    /// A sample is not at a higher block than the one before it.
+    NotIncreasing { block: u64, prior_block: u64 },
+    /// A sample is not at a later timestamp than the one before it.
+    TimestampNotIncreasing { block: u64, timestamp: u64, prior_timestamp: u64 },
+    /// A sample is not a whole number of intervals after the one before it, or more intervals than
+    /// can be interpolated over.
+    Granularity { block: u64, prior_block: u64, granularity: u64, max_interpolated: usize },
+    /// A sample is further after the one before it than the largest block gap accepted.
+    BlockGap { block: u64, prior_block: u64, max_block_gap: u64 },
+    /// A sample aligned to midnight is not near one.
+    NotAligned { block: u64, timestamp: u64 },
+    /// A sample aligned to midnight is not at the midnight after the one before it.
+    DayGap { block: u64, prior_block: u64 },
+    /// A sample's block is not one the run resolved to sample.
+    NotScheduled { block: u64 },
+    /// A scheduled block is not among the headers linked to the head.
+    NotCovered { block: u64 },
+    /// A sample's timestamp differs from the one of its header.
+    TimestampMismatch { block: u64, timestamp: u64, header_timestamp: u64 },
+    /// A sample has no backing, as at a block before the LST was deployed or minted.
+    NotLive { block: u64 },
+}
+
+impl fmt::Display for ScheduleIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScheduleIssue::NotIncreasing { block, prior_block } => {
+                write!(f, "block {block} does not follow block {prior_block}")
+            }
+            ScheduleIssue::TimestampNotIncreasing { block, timestamp, prior_timestamp } => write!(
+                f,
+                "block {block} has timestamp {timestamp}, not after the prior {prior_timestamp}"
+            ),
+            ScheduleIssue::Granularity { block, prior_block, granularity, max_interpolated } => {
+                write!(
+                    f,
+                    "block {block} is {} blocks after block {prior_block}, not {granularity} or a \
+                     gap of at most {max_interpolated} samples",
+                    block.saturating_sub(*prior_block)
+                )
+            }
+            ScheduleIssue::BlockGap { block, prior_block, max_block_gap } => write!(
+                f,
+                "block {block} is {} blocks after block {prior_block}, more than the \
+                 {max_block_gap} accepted",
+                block - prior_block
+            ),
+            ScheduleIssue::NotAligned { block, timestamp } => {
+                write!(f, "block {block} at timestamp {timestamp} is not near a UTC midnight")
+            }
+            ScheduleIssue::DayGap { block, prior_block } => write!(
+                f,
+                "block {block} is not at the midnight after the one of block {prior_block}"
+            ),
+            ScheduleIssue::NotScheduled { block } => {
+                write!(f, "block {block} is not in the resolved schedule")
+            }
+            ScheduleIssue::NotCovered { block } => {
+                write!(f, "block {block} is not covered by the header chain")
+            }
+            ScheduleIssue::TimestampMismatch { block, timestamp, header_timestamp } => write!(
+                f,
+                "block {block} has timestamp {timestamp}, but its header {header_timestamp}"
+            ),
+            ScheduleIssue::NotLive { block } => {
+                write!(f, "block {block} has no backing, the LST is not live yet")
+            }
+        }
+    }
+}
+
+/// A window that can't be laid out, as its blocks would under- or overflow a block number or too
+/// few of them are left to compute a yield over.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowError {
+    /// The window reaches back past the genesis block.
+    BeforeGenesis { head: u64, window_blocks: u64 },
+    /// A lookback is more blocks than a block number holds.
+    LookbackOverflow { days: u64, granularity: u64 },
+    /// Samples zero blocks apart.
+    ZeroGranularity,
+    /// The window from `from` to `to` holds fewer than the two blocks a change is taken between,
+    /// e.g. after its start was clamped to a contract's deployment.
+    EmptyWindow { from: u64, to: u64 },
+    /// More samples scheduled than `--max-samples` allows.
+    TooManySamples { samples: usize, max_samples: usize },
+    /// More headers in the window than `--max-headers` allows.
+    TooManyHeaders { headers: u64, max_headers: u64 },
+}
+
+impl fmt::Display for WindowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WindowError::BeforeGenesis { head, window_blocks } => write!(
+                f,
+                "a window of {window_blocks} blocks back from block {head} starts before the \
+                 genesis block"
+            ),
+            WindowError::LookbackOverflow { days, granularity } => write!(
+                f,
+                "a lookback of {days} days of {granularity} blocks overflows a block number"
+            ),
+            WindowError::ZeroGranularity => write!(f, "the block granularity must be positive"),
+            WindowError::EmptyWindow { from, to } => write!(
+                f,
+                "the window from block {from} to block {to} is empty, no yield can be computed \
+                 over it"
+            ),
+            WindowError::TooManySamples { samples, max_samples } => write!(
+                f,
+                "{samples} samples scheduled, more than the {max_samples} of --max-samples; sample \
+                 at a coarser granularity or over a shorter window, or raise the cap"
+            ),
+            WindowError::TooManyHeaders { headers, max_headers } => write!(
+                f,
+                "{headers} headers in the window, more than the {max_headers} of --max-headers; \
+                 the header of every block in it is fetched however few are sampled, so sample \
+                 over a shorter window, or raise the cap"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WindowError {}
+
+/// The first block of the window of `window_blocks` blocks ending at `head`.
+pub fn window_start(head: u64, window_blocks: u64) -> Result<u64, WindowError> {
+    head.checked_sub(window_blocks).ok_or(WindowError::BeforeGenesis { head, window_blocks })
+}
+
+/// Fails unless the window from `from` to `to` spans at least one block interval.
+pub fn check_not_empty(from: u64, to: u64) -> Result<(), WindowError> {
+    if from >= to {
+        return Err(WindowError::EmptyWindow { from, to });
+    }
+
+    Ok(())
+}
+
+/// Fails if a schedule of `samples` samples exceeds `max_samples`, before any of it is fetched.
+pub fn check_sample_count(samples: usize, max_samples: usize) -> Result<(), WindowError> {
+    if samples > max_samples {
+        return Err(WindowError::TooManySamples { samples, max_samples });
+    }
+
+    Ok(())
+}
+
+/// Fails if a window of `headers` blocks exceeds `max_headers`, before any of them is fetched: the
+/// samples are linked to the head through the header of every block in between.
+pub fn check_header_count(headers: u64, max_headers: u64) -> Result<(), WindowError> {
+    if headers > max_headers {
+        return Err(WindowError::TooManyHeaders { headers, max_headers });
+    }
+
+    Ok(())
+}
+
+/// The blocks in a lookback of `days` days, `granularity` blocks each.
+pub fn lookback_blocks(days: u64, granularity: u64) -> Result<u64, WindowError> {
+    days.checked_mul(granularity).ok_or(WindowError::LookbackOverflow { days, granularity })
+}
+
+/// The blocks to sample between `from` and `to`, every `granularity` blocks counting back from
+/// `to`, so that the last sample is the block the output commits to.
+pub fn sample_blocks(from: u64, to: u64, granularity: u64) -> Result<Vec<u64>, WindowError> {
+    if granularity == 0 {
+        return Err(WindowError::ZeroGranularity);
+    }
+    // a stride beyond the address space steps past `from` anyway
+    let step = usize::try_from(granularity).unwrap_or(usize::MAX);
+    let mut samples: Vec<u64> = (from..=to).rev().step_by(step).collect();
+    samples.reverse();
+
+    Ok(samples)
+}
+
+/// Checks the observed samples against each other, against `schedule`, the blocks the run resolved
+/// to sample, and against `headers`, the headers of the chain at the scheduled blocks, and returns
+/// every issue found.
+pub fn validate_schedule<H: ChainHeader>(
+    inputs: &[DexStatsInput],
+    schedule: &[u64],
+    headers: &[H],
+    params: &DexStatsParams,
+) -> Result<(), Vec<ScheduleIssue>> {
+    let scheduled: BTreeSet<u64> = schedule.iter().copied().collect();
+    let timestamps: HashMap<u64, u64> =
+        headers.iter().map(|header| (header.number(), header.timestamp())).collect();
+    let granularity = params.granularity_blocks;
+    let max_interpolated = params.max_interpolated;
+    // the guest orders the samples by the time it annualizes over, the headers' or pseudo-time
+    let retimed =
+        params.min_time_delta.and_then(|min_time_delta| pseudo_timed(inputs, min_time_delta));
+    let times: Vec<u64> =
+        retimed.as_deref().unwrap_or(inputs).iter().map(|input| input.timestamp).collect();
+
+    let mut issues = Vec::new();
+    for (index, input) in inputs.iter().enumerate() {
+        let block = input.block_number;
+        if let Some(prior) = index.checked_sub(1).map(|prior| &inputs[prior]) {
+            if block <= prior.block_number {
+                issues
+                    .push(ScheduleIssue::NotIncreasing { block, prior_block: prior.block_number });
+            } else if params
+                .max_block_gap
+                .is_some_and(|max_block_gap| block - prior.block_number > max_block_gap)
+            {
+                issues.push(ScheduleIssue::BlockGap {
+                    block,
+                    prior_block: prior.block_number,
+                    max_block_gap: params.max_block_gap.unwrap(),
+                });
+            } else if params.alignment == SampleAlignment::Midnight {
+                // a sample off midnight is reported as not aligned
+                if let (Some(prior_day), Some(day)) =
+                    (midnight_day(prior.timestamp), midnight_day(input.timestamp))
+                {
+                    if day != prior_day + 1 {
+                        issues
+                            .push(ScheduleIssue::DayGap { block, prior_block: prior.block_number });
+                    }
+                }
+            } else if params.alignment == SampleAlignment::Blocks {
+                let delta = block - prior.block_number;
+                let on_schedule = granularity > 0
+                    && delta % granularity == 0
+                    && delta / granularity - 1 <= max_interpolated as u64;
+                if !on_schedule {
+                    issues.push(ScheduleIssue::Granularity {
+                        block,
+                        prior_block: prior.block_number,
+                        granularity,
+                        max_interpolated,
+                    });
+                }
+            }
+            if times[index] <= times[index - 1] {
+                issues.push(ScheduleIssue::TimestampNotIncreasing {
+                    block,
+                    timestamp: times[index],
+                    prior_timestamp: times[index - 1],
+                });
+            }
+        }
+
+        if params.alignment == SampleAlignment::Midnight && midnight_day(input.timestamp).is_none()
+        {
+            issues.push(ScheduleIssue::NotAligned { block, timestamp: input.timestamp });
+        }
+        match timestamps.get(&block) {
+            _ if !scheduled.contains(&block) => issues.push(ScheduleIssue::NotScheduled { block }),
+            None => issues.push(ScheduleIssue::NotCovered { block }),
+            Some(&header_timestamp) if header_timestamp != input.timestamp => {
+                issues.push(ScheduleIssue::TimestampMismatch {
+                    block,
+                    timestamp: input.timestamp,
+                    header_timestamp,
+                });
+            }
+            Some(_) => {}
+        }
+        if input.lst_backing.is_zero() {
+            issues.push(ScheduleIssue::NotLive { block });
+        }
+    }
+    // the scheduled blocks without a sample, e.g. a failed one, must be covered all the same
+    let sampled: BTreeSet<u64> = inputs.iter().map(|input| input.block_number).collect();
+    for &block in scheduled.difference(&sampled) {
+        if !timestamps.contains_key(&block) {
+            issues.push(ScheduleIssue::NotCovered { block });
+        }
+    }
+
+    if issues.is_empty() {
+        Ok(())
+    } else {
+        Err(issues)
+    }
+}
+
+/// Picks the header nearest to each UTC midnight out of a contiguous run of headers, for
+/// [`SampleAlignment::Midnight`]. The header of a midnight is known once the first header after it
+/// is pushed.
+pub struct MidnightSampler<H> {
+    prior: Option<H>,
+}
+
+impl<H> Default for MidnightSampler<H> {
+    fn default() -> Self {
+        MidnightSampler { prior: None }
+    }
+}
+
+impl<H: ChainHeader + Clone> MidnightSampler<H> {
+    /// Takes the next header of the run and returns the header nearest to the midnight between it
+    /// and the previous one, if there is one.
+    pub fn push(&mut self, header: H) -> Option<H> {
+        let prior = self.prior.replace(header.clone())?;
+        let midnight = header.timestamp() / DAY_IN_SECONDS * DAY_IN_SECONDS;
+        if prior.timestamp() >= midnight {
+            return None;
+        }
+
+        if header.timestamp() - midnight <= midnight - prior.timestamp() {
+            Some(header)
+        } else {
+            Some(prior)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockProvider;
+    use alloy_primitives::U256;
+    use risc0_steel::ethereum::EthBlockHeader;
+
+    const GRANULARITY: u64 = 100;
+
+    fn headers(provider: &MockProvider, blocks: &[u64]) -> Vec<EthBlockHeader> {
+        blocks.iter().map(|&block| provider.header(block).unwrap()).collect()
+    }
+
+    fn input(header: &EthBlockHeader) -> DexStatsInput {
+        DexStatsInput {
+            timestamp: header.timestamp,
+            block_number: header.number,
+            lst_backing: U256::from(1_050_000_000_000_000_000_u64),
+            interpolated: false,
+            pool_tvl: None,
+        }
+    }
+
+    #[test]
+    fn it_should_anchor_the_samples_at_the_head() {
+        assert_eq!(sample_blocks(100, 400, 100).unwrap(), vec![100, 200, 300, 400]);
+        // a window that isn't a whole number of intervals drops the oldest partial interval, where
+        // stepping forward from the start would have missed the head
+        let naive: Vec<u64> = (50..=400).step_by(100).collect();
+        assert_eq!(naive.last(), Some(&350));
+        assert_eq!(sample_blocks(50, 400, 100).unwrap(), vec![100, 200, 300, 400]);
+        // the head is kept however coarse the granularity
+        assert_eq!(sample_blocks(50, 400, 1000).unwrap(), vec![400]);
+        assert_eq!(sample_blocks(400, 400, 100).unwrap(), vec![400]);
+    }
+
+    #[test]
+    fn it_should_lay_out_windows_at_the_ends_of_the_block_range() {
+        assert_eq!(window_start(u64::MAX, 21_600), Ok(u64::MAX - 21_600));
+        assert_eq!(window_start(21_600, 21_600), Ok(0));
+        let err = window_start(21_599, 21_600).unwrap_err();
+        assert_eq!(err, WindowError::BeforeGenesis { head: 21_599, window_blocks: 21_600 });
+        assert_eq!(
+            err.to_string(),
+            "a window of 21600 blocks back from block 21599 starts before the genesis block"
+        );
+
+        assert_eq!(lookback_blocks(u64::MAX / 7200, 7200), Ok(u64::MAX / 7200 * 7200));
+        assert_eq!(
+            lookback_blocks(u64::MAX / 7200 + 1, 7200),
+            Err(WindowError::LookbackOverflow { days: u64::MAX / 7200 + 1, granularity: 7200 })
+        );
+
+        assert_eq!(
+            sample_blocks(u64::MAX - 250, u64::MAX, 100).unwrap(),
+            vec![u64::MAX - 200, u64::MAX - 100, u64::MAX]
+        );
+        assert_eq!(sample_blocks(0, u64::MAX, u64::MAX).unwrap(), vec![0, u64::MAX]);
+        assert_eq!(sample_blocks(0, 400, 0), Err(WindowError::ZeroGranularity));
+
+        assert_eq!(check_not_empty(u64::MAX - 1, u64::MAX), Ok(()));
+        assert_eq!(
+            check_not_empty(u64::MAX, u64::MAX),
+            Err(WindowError::EmptyWindow { from: u64::MAX, to: u64::MAX })
+        );
+    }
+
+    #[test]
+    fn it_should_cap_the_number_of_samples() {
+        // a year sampled every block rather than every day
+        let samples = sample_blocks(19_000_000 - 365 * 7200, 19_000_000, 1).unwrap();
+        let err = check_sample_count(samples.len(), 10_000).unwrap_err();
+        assert_eq!(err, WindowError::TooManySamples { samples: 2_628_001, max_samples: 10_000 });
+        assert!(err.to_string().contains("coarser granularity or over a shorter window"));
+
+        let samples = sample_blocks(19_000_000 - 365 * 7200, 19_000_000, 7200).unwrap();
+        assert_eq!(check_sample_count(samples.len(), 10_000), Ok(()));
+        assert_eq!(check_sample_count(10_000, 10_000), Ok(()));
+
+        // sampled daily, two years are still a header per block
+        let err = check_header_count(2 * 365 * 7200 + 1, 3_000_000).unwrap_err();
+        assert_eq!(err, WindowError::TooManyHeaders { headers: 5_256_001, max_headers: 3_000_000 });
+        assert!(err.to_string().contains("however few are sampled"));
+        assert_eq!(check_header_count(365 * 7200 + 1, 3_000_000), Ok(()));
+    }
+
+    fn params(max_interpolated: usize) -> DexStatsParams {
+        DexStatsParams { granularity_blocks: GRANULARITY, max_interpolated, ..Default::default() }
+    }
+
+    #[test]
+    fn it_should_accept_a_consistent_schedule() {
+        let provider = MockProvider::with_chain(1000, 501);
+        let schedule = [1000, 1100, 1200, 1300, 1400, 1500];
+        let headers = headers(&provider, &schedule);
+        let inputs: Vec<_> =
+            headers.iter().filter(|header| header.number != 1300).map(input).collect();
+
+        // the gap at 1300 is interpolated over, unless it is too long regardless
+        validate_schedule(&inputs, &schedule, &headers, &params(1)).unwrap();
+        let capped = DexStatsParams { max_block_gap: Some(GRANULARITY), ..params(1) };
+        assert_eq!(
+            validate_schedule(&inputs, &schedule, &headers, &capped).unwrap_err(),
+            vec![ScheduleIssue::BlockGap { block: 1400, prior_block: 1200, max_block_gap: 100 }]
+        );
+        assert_eq!(
+            validate_schedule(&inputs, &schedule, &headers, &params(0)).unwrap_err(),
+            vec![ScheduleIssue::Granularity {
+                block: 1400,
+                prior_block: 1200,
+                granularity: GRANULARITY,
+                max_interpolated: 0
+            }]
+        );
+    }
+
+    #[test]
+    fn it_should_report_all_issues_at_once() {
+        let provider = MockProvider::with_chain(1000, 501);
+        let headers = headers(&provider, &[1000, 1100, 1200, 1300, 1400]);
+        // a scheduled block past the headers fetched
+        let schedule = [1000, 1100, 1200, 1300, 1400, 1500];
+        let mut inputs: Vec<_> = headers.iter().map(input).collect();
+        // before the LST was live
+        inputs[0].lst_backing = U256::ZERO;
+        // off schedule
+        inputs[1].block_number = 1150;
+        // stale timestamp
+        inputs[3].timestamp = inputs[2].timestamp;
+        // out of order
+        inputs.swap(3, 4);
+
+        let issues = validate_schedule(&inputs, &schedule, &headers, &params(0)).unwrap_err();
+        assert_eq!(
+            issues,
+            vec![
+                ScheduleIssue::NotLive { block: 1000 },
+                ScheduleIssue::Granularity {
+                    block: 1150,
+                    prior_block: 1000,
+                    granularity: GRANULARITY,
+                    max_interpolated: 0
+                },
+                ScheduleIssue::NotScheduled { block: 1150 },
+                ScheduleIssue::Granularity {
+                    block: 1200,
+                    prior_block: 1150,
+                    granularity: GRANULARITY,
+                    max_interpolated: 0
+                },
+                ScheduleIssue::Granularity {
+                    block: 1400,
+                    prior_block: 1200,
+                    granularity: GRANULARITY,
+                    max_interpolated: 0
+                },
+                ScheduleIssue::NotIncreasing { block: 1300, prior_block: 1400 },
+                ScheduleIssue::TimestampNotIncreasing {
+                    block: 1300,
+                    timestamp: headers[2].timestamp,
+                    prior_timestamp: headers[4].timestamp
+                },
+                ScheduleIssue::TimestampMismatch {
+                    block: 1300,
+                    timestamp: headers[2].timestamp,
+                    header_timestamp: headers[3].timestamp
+                },
+                ScheduleIssue::NotCovered { block: 1500 },
+            ]
+        );
+        assert_eq!(issues[2].to_string(), "block 1150 is not in the resolved schedule");
+        assert_eq!(issues[8].to_string(), "block 1500 is not covered by the header chain");
+    }
+
+    #[test]
+    fn it_should_sample_the_blocks_nearest_to_midnight() {
+        // three and a bit days of 12 second blocks
+        let provider = MockProvider::with_chain(0, 3 * 7200 + 600);
+        let mut sampler = MidnightSampler::default();
+        let samples: Vec<_> =
+            (0..3 * 7200 + 600).filter_map(|n| sampler.push(provider.header(n).unwrap())).collect();
+
+        assert_eq!(samples.len(), 4);
+        for (i, sample) in samples.iter().enumerate() {
+            let midnight =
+                (sample.timestamp + DAY_IN_SECONDS / 2) / DAY_IN_SECONDS * DAY_IN_SECONDS;
+            // nearer than half a block
+            assert!(sample.timestamp.abs_diff(midnight) <= 6, "{} is off midnight", sample.number);
+            assert_eq!(
+                midnight_day(sample.timestamp),
+                midnight_day(samples[0].timestamp).map(|day| day + i as u64)
+            );
+        }
+
+        let inputs: Vec<_> = samples.iter().map(input).collect();
+        let schedule: Vec<_> = samples.iter().map(|sample| sample.number).collect();
+        let params = DexStatsParams { alignment: SampleAlignment::Midnight, ..Default::default() };
+        validate_schedule(&inputs, &schedule, &samples, &params).unwrap();
+        // the stride check doesn't apply, but the alignment one does
+        let mut shifted = inputs.clone();
+        shifted[1] = input(&provider.header(samples[1].number + 600).unwrap());
+        assert_eq!(
+            validate_schedule(&shifted, &schedule, &samples, &params).unwrap_err()[0],
+            ScheduleIssue::NotAligned {
+                block: samples[1].number + 600,
+                timestamp: shifted[1].timestamp
+            }
+        );
+    }
+}
@@ -0,0 +1,54 @@
+//! `--self-test`: a check that the guest, as built, computes the yield the library does. A smoke
+//! run over the in-memory chain of [`crate::mock::smoke_chain`], which needs no RPC, runs the guest
+//! on the samples the chain's canned contracts answer with, and its journal has to commit exactly
+//! the yield the library computes from the same samples. A different one points at a guest built
+//! from another version of the library, e.g. a stale build.
+
+use anyhow::{ensure, Result};
+use tokemak::{
+    try_calculate_dex_stats, wad_to_yield, yield_to_wad, DexStatsInput, DexStatsParams, LstDexStats,
+};
+
+/// The head block of the self-test's chain.
+pub const HEAD: u64 = 19_000_000;
+
+/// Fails unless the guest's `journal` commits the yield the library computes from `inputs` under
+/// `params`.
+pub fn check(
+    journal: &LstDexStats,
+    inputs: &[DexStatsInput],
+    params: &DexStatsParams,
+) -> Result<()> {
+    let expected = yield_to_wad(try_calculate_dex_stats(inputs, params)?.base_yield);
+    ensure!(
+        journal.baseYield == expected,
+        "self-test failed: the guest computed a yield of {} but the library {}; the guest may be \
+         built from another version of the library",
+        wad_to_yield(journal.baseYield),
+        wad_to_yield(expected)
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::I256;
+    use tokemak::fixture::{self, daily_inputs, steady_backing};
+
+    #[test]
+    fn it_should_fail_on_a_yield_off_the_library() {
+        let inputs = daily_inputs(0..=5, steady_backing);
+        let params = DexStatsParams::default();
+        let committed = yield_to_wad(try_calculate_dex_stats(&inputs, &params).unwrap().base_yield);
+        let journal = LstDexStats { baseYield: committed, ..fixture::journal() };
+        check(&journal, &inputs, &params).unwrap();
+
+        // a journal off in the last digit of the yield fails it
+        let one = I256::try_from(1).unwrap();
+        let off = LstDexStats { baseYield: committed + one, ..fixture::journal() };
+        let err = check(&off, &inputs, &params).unwrap_err();
+        assert!(err.to_string().starts_with("self-test failed: the guest computed"), "{err}");
+    }
+}
@@ -0,0 +1,309 @@
+//! Streaming assembly of the guest input. Items are produced on a background thread that runs at
+//! most a bounded number of items ahead of the consumer, which writes them to the executor env as
+//! they arrive, so a large window is never held in memory all at once.
+
+use alloy_primitives::{Keccak256, B256};
+use anyhow::{ensure, Result};
+use risc0_zkvm::{serde::to_vec, ExecutorEnv, ExecutorEnvBuilder};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::panic;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+/// Items produced on a background thread and buffered up to a fixed capacity; the producer blocks
+/// while the buffer is full.
+pub struct Prefetch<T> {
+    queue: Arc<Queue<T>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl<T: Send + 'static> Prefetch<T> {
+    /// Runs `produce` on a new thread, buffering at most `capacity` of the items it pushes. An
+    /// error returned by `produce` is yielded after the items pushed before it.
+    pub fn spawn<F>(capacity: usize, produce: F) -> Self
+    where
+        F: FnOnce(&Sink<T>) -> Result<()> + Send + 'static,
+    {
+        assert!(capacity > 0, "buffer size must be positive");
+        let queue = Arc::new(Queue {
+            capacity,
+            state: Mutex::new(QueueState { items: VecDeque::new(), closed: false, peak: 0 }),
+            changed: Condvar::new(),
+        });
+        let sink = Sink { queue: queue.clone() };
+        let handle = thread::spawn(move || {
+            if let Err(err) = produce(&sink) {
+                sink.send(Err(err));
+            }
+        });
+
+        Prefetch { queue, handle: Some(handle) }
+    }
+}
+
+impl<T> Prefetch<T> {
+    /// The largest number of items that were buffered at once.
+    pub fn peak(&self) -> usize {
+        self.queue.state.lock().unwrap().peak
+    }
+}
+
+impl<T> Iterator for Prefetch<T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.queue.pop();
+        if item.is_none() {
+            // surface a panic of the producer rather than ending the stream early
+            if let Some(Err(payload)) = self.handle.take().map(JoinHandle::join) {
+                panic::resume_unwind(payload);
+            }
+        }
+
+        item
+    }
+}
+
+impl<T> Drop for Prefetch<T> {
+    fn drop(&mut self) {
+        // unblock the producer; it stops at its next push
+        self.queue.close();
+    }
+}
+
+/// The producer's end of a [`Prefetch`].
+pub struct Sink<T> {
+    queue: Arc<Queue<T>>,
+}
+
+impl<T> Sink<T> {
+    /// Hands `item` to the consumer, blocking while the buffer is full. Returns false once the
+    /// consumer is gone, in which case the producer should stop.
+    pub fn push(&self, item: T) -> bool {
+        self.send(Ok(item))
+    }
+
+    fn send(&self, item: Result<T>) -> bool {
+        let mut state = self.queue.state.lock().unwrap();
+        while state.items.len() >= self.queue.capacity && !state.closed {
+            state = self.queue.changed.wait(state).unwrap();
+        }
+        if state.closed {
+            return false;
+        }
+        state.items.push_back(item);
+        state.peak = state.peak.max(state.items.len());
+        self.queue.changed.notify_all();
+
+        true
+    }
+}
+
+impl<T> Drop for Sink<T> {
+    fn drop(&mut self) {
+        // also runs when the producer panics, so the consumer never waits forever
+        self.queue.close();
+    }
+}
+
+struct Queue<T> {
+    capacity: usize,
+    state: Mutex<QueueState<T>>,
+    changed: Condvar,
+}
+
+struct QueueState<T> {
+    items: VecDeque<Result<T>>,
+    /// Set once either end is gone.
+    closed: bool,
+    peak: usize,
+}
+
+impl<T> Queue<T> {
+    /// Takes the next item, waiting for the producer; `None` once it is done and all are taken.
+    fn pop(&self) -> Option<Result<T>> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.changed.notify_all();
+                return Some(item);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.changed.wait(state).unwrap();
+        }
+    }
+
+    fn close(&self) {
+        // a poisoned lock means the other end panicked; there is nobody left to notify
+        if let Ok(mut state) = self.state.lock() {
+            state.closed = true;
+            self.changed.notify_all();
+        }
+    }
+}
+
+/// The guest input as it is written to the executor env, hashed along the way, so that a run can
+/// record which input it proved without holding on to it.
+pub struct GuestInput<'a> {
+    env: ExecutorEnvBuilder<'a>,
+    hasher: Keccak256,
+    /// A copy of the input, as it is written, if it is recorded.
+    record: Option<BufWriter<File>>,
+}
+
+impl<'a> GuestInput<'a> {
+    pub fn new() -> Self {
+        GuestInput { env: ExecutorEnv::builder(), hasher: Keccak256::new(), record: None }
+    }
+
+    /// Also writes the input from here on to `file`, as the little-endian bytes of its words, which
+    /// `ExecutorEnvBuilder::write_slice` takes back to run the guest on it again.
+    pub fn record_to(&mut self, file: File) {
+        self.record = Some(BufWriter::new(file));
+    }
+
+    /// Writes `value` in the zkVM serde encoding, as `ExecutorEnvBuilder::write` does.
+    pub fn write<T: Serialize>(&mut self, value: &T) -> Result<()> {
+        let words = to_vec(value)?;
+        for word in &words {
+            self.hasher.update(word.to_le_bytes());
+            if let Some(record) = &mut self.record {
+                record.write_all(&word.to_le_bytes())?;
+            }
+        }
+        self.env.write_slice(&words);
+
+        Ok(())
+    }
+
+    /// The keccak256 hash of the input written so far, as the little-endian bytes of its words.
+    pub fn digest(&self) -> B256 {
+        self.hasher.clone().finalize()
+    }
+
+    pub fn build(&mut self) -> Result<ExecutorEnv<'a>> {
+        if let Some(record) = &mut self.record {
+            record.flush()?;
+        }
+        self.env.build()
+    }
+}
+
+impl Default for GuestInput<'_> {
+    fn default() -> Self {
+        GuestInput::new()
+    }
+}
+
+/// Writes `items` to the guest input in the encoding of a `Vec` of `len` items, without collecting
+/// them first. Fails on the first error among the items, or if they don't number exactly `len`.
+pub fn write_seq<T: Serialize>(
+    env: &mut GuestInput<'_>,
+    len: usize,
+    items: impl IntoIterator<Item = Result<T>>,
+) -> Result<()> {
+    // the zkVM serde encodes a sequence as its length, as a u32, followed by its elements
+    env.write(&u32::try_from(len)?)?;
+    let mut written = 0;
+    for item in items {
+        ensure!(written < len, "more than the expected {len} items");
+        env.write(&item?)?;
+        written += 1;
+    }
+    ensure!(written == len, "expected {len} items, got {written}");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::time::Duration;
+
+    #[test]
+    fn it_should_bound_the_buffered_items() {
+        let mut prefetch = Prefetch::spawn(4, |sink| {
+            for i in 0..100 {
+                if !sink.push(i) {
+                    break;
+                }
+            }
+            Ok(())
+        });
+
+        let mut received = Vec::new();
+        for item in prefetch.by_ref() {
+            // a slow consumer, so the producer runs into the bound
+            thread::sleep(Duration::from_millis(1));
+            received.push(item.unwrap());
+        }
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+        assert!(prefetch.peak() <= 4, "peak of {} items", prefetch.peak());
+    }
+
+    #[test]
+    fn it_should_forward_producer_errors() {
+        let prefetch = Prefetch::spawn(2, |sink| {
+            sink.push(1);
+            Err(anyhow!("connection refused"))
+        });
+
+        let err = prefetch.collect::<Result<Vec<_>>>().unwrap_err();
+        assert_eq!(err.to_string(), "connection refused");
+    }
+
+    #[test]
+    fn it_should_stop_the_producer_when_dropped() {
+        let prefetch = Prefetch::spawn(1, |sink| {
+            while sink.push(0) {}
+            Ok(())
+        });
+
+        assert_eq!(prefetch.take(3).count(), 3);
+    }
+
+    #[test]
+    fn it_should_encode_sequences_like_vectors() {
+        let items = vec![Some(1_u64), None, Some(3)];
+        let mut streamed = to_vec(&(items.len() as u32)).unwrap();
+        for item in &items {
+            streamed.extend(to_vec(item).unwrap());
+        }
+
+        assert_eq!(streamed, to_vec(&items).unwrap());
+
+        // and hash the same either way
+        let mut collected = GuestInput::new();
+        collected.write(&items).unwrap();
+        let mut input = GuestInput::new();
+        write_seq(&mut input, items.len(), items.iter().map(Ok)).unwrap();
+        assert_eq!(input.digest(), collected.digest());
+        assert_ne!(input.digest(), GuestInput::new().digest());
+    }
+
+    #[test]
+    fn it_should_record_the_written_words() {
+        let path = std::env::temp_dir().join(format!("guest-input-{}", std::process::id()));
+        let mut input = GuestInput::new();
+        input.record_to(File::create(&path).unwrap());
+        input.write(&(7_u32, vec![1_u64, 2])).unwrap();
+        input.build().unwrap();
+
+        let recorded = std::fs::read(&path).unwrap();
+        let words: Vec<u8> = to_vec(&(7_u32, vec![1_u64, 2]))
+            .unwrap()
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect();
+        assert_eq!(recorded, words);
+        assert_eq!(input.digest(), alloy_primitives::keccak256(&recorded));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
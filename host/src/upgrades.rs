@@ -0,0 +1,69 @@
+//! Known network upgrades. An upgrade can change the block time, and with it the number of blocks
+//! per day the block granularity assumes, so a window crossing one deserves a second look.
+
+/// A hard fork and the block it activated at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetworkUpgrade {
+    pub name: &'static str,
+    pub block: u64,
+}
+
+pub const MAINNET_CHAIN_ID: u64 = 1;
+
+const MAINNET_UPGRADES: &[NetworkUpgrade] = &[
+    NetworkUpgrade { name: "London", block: 12_965_000 },
+    NetworkUpgrade { name: "Arrow Glacier", block: 13_773_000 },
+    NetworkUpgrade { name: "Gray Glacier", block: 15_050_000 },
+    NetworkUpgrade { name: "Paris", block: 15_537_394 },
+    NetworkUpgrade { name: "Shanghai", block: 17_034_870 },
+    NetworkUpgrade { name: "Cancun", block: 19_426_587 },
+    NetworkUpgrade { name: "Prague", block: 22_431_084 },
+];
+
+/// The upgrades of the chain, ordered by block; none for an unknown chain.
+pub fn known_upgrades(chain_id: u64) -> &'static [NetworkUpgrade] {
+    match chain_id {
+        MAINNET_CHAIN_ID => MAINNET_UPGRADES,
+        _ => &[],
+    }
+}
+
+/// A warning for every known upgrade that activated within the window from `from` to `to`, past
+/// its first block.
+pub fn upgrade_warnings(chain_id: u64, from: u64, to: u64) -> Vec<String> {
+    known_upgrades(chain_id)
+        .iter()
+        .filter(|upgrade| from < upgrade.block && upgrade.block <= to)
+        .map(|upgrade| {
+            format!(
+                "warning: the window from block {from} to {to} crosses the {} upgrade at block {}, \
+                 which may have changed block times; interpret the yield with care or sample with \
+                 --align-to-midnight",
+                upgrade.name, upgrade.block
+            )
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_warn_about_a_window_straddling_an_upgrade() {
+        let warnings = upgrade_warnings(MAINNET_CHAIN_ID, 19_420_000, 19_441_600);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("crosses the Cancun upgrade at block 19426587"));
+
+        // starting at the activation block, the whole window is past the upgrade
+        assert!(upgrade_warnings(MAINNET_CHAIN_ID, 19_426_587, 19_448_187).is_empty());
+        assert!(upgrade_warnings(MAINNET_CHAIN_ID, 19_900_000, 19_921_600).is_empty());
+        assert!(upgrade_warnings(8453, 19_420_000, 19_441_600).is_empty());
+    }
+
+    #[test]
+    fn it_should_order_the_upgrades_by_block() {
+        let upgrades = known_upgrades(MAINNET_CHAIN_ID);
+        assert!(upgrades.windows(2).all(|pair| pair[0].block < pair[1].block));
+    }
+}
@@ -0,0 +1,92 @@
+//! How far a result can be trusted. Some options trade soundness for convenience, e.g. leaving out
+//! samples that fail or replaying a dataset off-chain; their results carry a [`Verification`] that
+//! says so, and why, so that they are not mistaken for a verified one downstream.
+
+use core::fmt;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Verification {
+    /// The yield is computed by the guest from chain data it verified itself, and a receipt proves
+    /// it was.
+    #[default]
+    Verified,
+    /// The yield is computed by the guest from verified chain data, but not from all of the
+    /// samples of the window.
+    PartiallyVerified { reasons: Vec<String> },
+    /// The yield is computed from data the guest did not verify.
+    Unverified { reasons: Vec<String> },
+}
+
+impl Verification {
+    /// Downgrades to at most partially verified, for `reason`.
+    pub fn partially(&mut self, reason: impl Into<String>) {
+        match self {
+            Verification::Verified => {
+                *self = Verification::PartiallyVerified { reasons: vec![reason.into()] }
+            }
+            Verification::PartiallyVerified { reasons } | Verification::Unverified { reasons } => {
+                reasons.push(reason.into())
+            }
+        }
+    }
+
+    /// Downgrades to unverified, for `reason`.
+    pub fn unverified(&mut self, reason: impl Into<String>) {
+        let mut reasons = match self {
+            Verification::Verified => Vec::new(),
+            Verification::PartiallyVerified { reasons } | Verification::Unverified { reasons } => {
+                std::mem::take(reasons)
+            }
+        };
+        reasons.push(reason.into());
+        *self = Verification::Unverified { reasons };
+    }
+
+    pub fn is_verified(&self) -> bool {
+        *self == Verification::Verified
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (status, reasons) = match self {
+            Verification::Verified => return write!(f, "verified"),
+            Verification::PartiallyVerified { reasons } => ("PARTIALLY VERIFIED", reasons),
+            Verification::Unverified { reasons } => ("UNVERIFIED", reasons),
+        };
+        write!(f, "{status}: {}", reasons.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_keep_the_weakest_status_and_every_reason() {
+        let mut verification = Verification::default();
+        assert!(verification.is_verified());
+        assert_eq!(verification.to_string(), "verified");
+
+        verification.partially("2 samples left out");
+        verification.unverified("exchange rates overridden");
+        // an unverified result stays so
+        verification.partially("1 sample interpolated");
+        assert_eq!(
+            verification,
+            Verification::Unverified {
+                reasons: vec![
+                    "2 samples left out".into(),
+                    "exchange rates overridden".into(),
+                    "1 sample interpolated".into(),
+                ]
+            }
+        );
+        assert_eq!(
+            verification.to_string(),
+            "UNVERIFIED: 2 samples left out; exchange rates overridden; 1 sample interpolated"
+        );
+    }
+}
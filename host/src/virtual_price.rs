@@ -0,0 +1,176 @@
+//! A heuristic for spotting a manipulated Curve virtual price at a sampled block. The virtual price
+//! enters the incentive yield, and an imbalanced pool, e.g. one pushed around with a flash loan,
+//! can move it for as long as the imbalance lasts. A sample whose virtual price stands apart from
+//! both the block before and the block after it looks like such a spike rather than the slow
+//! growth of the price from fees.
+//!
+//! Its limits: the state is read at the end of each block, so an imbalance opened and closed
+//! within a block is invisible anyway, while one lasting three blocks or more moves the neighbours
+//! along and goes unnoticed. A legitimate jump, such as a large fee accrual, differs from only one
+//! neighbour and is not flagged; a legitimate one-block swing in a thin pool is, as a false
+//! positive. The head sample has no block after it yet and is compared to the one before only.
+//!
+//! Rather than flagging such a sample, [`averaged`] damps it: the mean virtual price of the blocks
+//! around a sample, each standing for the same slot time, is a time-weighted average a one-block
+//! imbalance moves only by its share of the window. The yield of the averaged series, the pool's
+//! fee yield, is computed by the host alone and not proven.
+
+use alloy_primitives::{utils::format_units, U256};
+use anyhow::Result;
+use core::fmt;
+
+/// The virtual price of a sampled block and of its neighbours, where they exist.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VirtualPriceSample {
+    pub block_number: u64,
+    pub before: Option<U256>,
+    pub at: U256,
+    pub after: Option<U256>,
+}
+
+impl VirtualPriceSample {
+    /// The smallest relative deviation of the sample from its neighbours; `None` without any.
+    pub fn deviation(&self) -> Option<f64> {
+        let at = to_f64(self.at);
+        [self.before, self.after]
+            .into_iter()
+            .flatten()
+            .map(|neighbour| (at / to_f64(neighbour) - 1.0).abs())
+            .reduce(f64::min)
+    }
+}
+
+/// An 18-decimal virtual price as a float.
+fn to_f64(value: U256) -> f64 {
+    format_units(value, 18).unwrap().parse().unwrap()
+}
+
+/// The mean virtual price, as `query` reads it, of the blocks from `radius` before `block_number`
+/// to `radius` after it, those after `latest` left out.
+pub fn averaged(
+    mut query: impl FnMut(u64) -> Result<U256>,
+    block_number: u64,
+    radius: u64,
+    latest: u64,
+) -> Result<U256> {
+    let blocks = block_number.saturating_sub(radius)..=(block_number + radius).min(latest);
+    let count = U256::from(blocks.clone().count());
+    let mut sum = U256::ZERO;
+    for block in blocks {
+        sum += query(block)?;
+    }
+
+    Ok(sum / count)
+}
+
+/// A sample flagged as possibly manipulated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Suspicious {
+    pub block_number: u64,
+    pub deviation: f64,
+}
+
+impl fmt::Display for Suspicious {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "warning: the virtual price at block {} deviates {:.2}% from its neighbouring blocks, \
+             possibly manipulated",
+            self.block_number,
+            self.deviation * 100.0
+        )
+    }
+}
+
+/// The samples deviating from their neighbours by more than `max_deviation`, a fraction.
+pub fn flag_manipulated(samples: &[VirtualPriceSample], max_deviation: f64) -> Vec<Suspicious> {
+    samples
+        .iter()
+        .filter_map(|sample| {
+            let deviation = sample.deviation()?;
+            (deviation > max_deviation)
+                .then_some(Suspicious { block_number: sample.block_number, deviation })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokemak::{try_calculate_dex_stats, DexStatsInput, DexStatsParams, BLOCK_GRANULARITY};
+
+    fn price(value: f64) -> U256 {
+        U256::from((value * 1e18) as u128)
+    }
+
+    fn sample(block_number: u64, before: f64, at: f64, after: Option<f64>) -> VirtualPriceSample {
+        VirtualPriceSample {
+            block_number,
+            before: Some(price(before)),
+            at: price(at),
+            after: after.map(price),
+        }
+    }
+
+    #[test]
+    fn it_should_flag_a_manipulated_sample() {
+        let samples = [
+            sample(100, 1.0400, 1.0400, Some(1.0400)),
+            // pushed up 3% for the one block
+            sample(200, 1.0402, 1.0714, Some(1.0402)),
+            // a fee accrual stepping the price up for good
+            sample(300, 1.0403, 1.0450, Some(1.0450)),
+            // the head, without a block after it
+            sample(400, 1.0450, 1.0451, None),
+        ];
+
+        let flagged = flag_manipulated(&samples, 0.001);
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].block_number, 200);
+        assert!((flagged[0].deviation - 0.03).abs() < 1e-4, "{}", flagged[0].deviation);
+        assert!(flagged[0].to_string().contains("block 200 deviates 3.00%"));
+        // a looser threshold lets it pass
+        assert!(flag_manipulated(&samples, 0.05).is_empty());
+    }
+
+    #[test]
+    fn it_should_damp_noise_by_averaging_around_each_sample() {
+        // a steadily growing price with a pseudo-random imbalance of up to ±0.2% at every block
+        let noise = |block: u64| ((block * 2_654_435_761) % 1_000) as f64 / 1_000.0 - 0.5;
+        let trend = |block: u64| 1.04 + block as f64 * 1e-9;
+        let query = |block: u64| Ok(price(trend(block) + noise(block) * 0.004));
+
+        let stats = |value: &dyn Fn(u64) -> U256| {
+            let inputs: Vec<_> = (0..=30)
+                .map(|day| {
+                    let block_number = 19_000_000 + day * BLOCK_GRANULARITY;
+                    DexStatsInput {
+                        timestamp: 1_716_129_570 + day * 86_400,
+                        block_number,
+                        lst_backing: value(block_number),
+                        interpolated: false,
+                        pool_tvl: None,
+                    }
+                })
+                .collect();
+            try_calculate_dex_stats(&inputs, &DexStatsParams::default()).unwrap()
+        };
+        let latest = 20_000_000;
+        let single = stats(&|block| averaged(query, block, 0, latest).unwrap());
+        let windowed = stats(&|block| averaged(query, block, 3, latest).unwrap());
+        let exact = stats(&|block| price(trend(block)));
+
+        assert_eq!(averaged(query, 100, 0, latest).unwrap(), query(100).unwrap());
+        assert!(windowed.yield_volatility < single.yield_volatility / 2.0);
+        assert!(
+            (windowed.base_yield - exact.base_yield).abs()
+                < (single.base_yield - exact.base_yield).abs()
+        );
+        // the window is cut at the head
+        assert_eq!(averaged(query, latest, 3, latest).unwrap(), {
+            let sum =
+                (latest - 3..=latest).fold(U256::ZERO, |sum, block| sum + query(block).unwrap());
+            sum / U256::from(4)
+        });
+    }
+}
@@ -12,77 +12,142 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use alloy_primitives::{FixedBytes, Sealable, U256, utils::parse_units};
-use alloy_sol_types::SolValue;
+use std::convert::Infallible;
+
+use alloy_primitives::{utils::parse_units, Address, U256};
+use alloy_sol_types::{SolCall, SolValue};
 use risc0_steel::{
     config::ETH_MAINNET_CHAIN_SPEC,
     ethereum::{EthBlockHeader, EthViewCallInput},
-    BlockCommitment, ViewCall,
+    StateDB, ViewCall, ViewCallEnv,
 };
 use risc0_zkvm::guest::env::{self};
-use std::collections::HashMap;
-use tokemak::{calculate_dex_stats, DexStatsInput, cbETHInterface, CBETH_ADDRESS, LstDexStats};
-
-// TODO remove inline block, just used for profiling
-#[inline(never)]
-fn hash_headers_upwards(
-    headers: Vec<EthBlockHeader>,
-) -> (FixedBytes<32>, u64, HashMap<FixedBytes<32>, (u64, u64)>) {
-    let mut current_hash: FixedBytes<32> = headers[0].hash_slow();
-    let mut current_height: u64 = headers[0].number;
-    let mut validated_hashes: HashMap<FixedBytes<32>, (u64, u64)> = HashMap::new();
+use tokemak::{
+    backing::ViewCaller,
+    calculate_dex_stats_with,
+    chain::{check_input_shape, HeaderChain},
+    check_input_version,
+    convex::{incentive_yield, RewardSample},
+    exclude_before, exclude_unfinalized,
+    query::PoolQuerySet,
+    samples_digest, verify_midnight_window_end, verify_window_end, yield_to_wad, DexStatsInput,
+    GuestParams, LstDexStats, SampleAlignment,
+};
 
-    validated_hashes.insert(current_hash, (headers[0].timestamp, current_height));
+/// Executes the view calls of the query set against the state of a sampled block.
+struct StateCaller<'a>(&'a mut ViewCallEnv<StateDB, EthBlockHeader>);
 
-    for header in &headers[1..] {
-        assert_eq!(current_hash, header.parent_hash);
-        current_hash = header.hash_slow();
-        current_height = header.number;
+impl ViewCaller for StateCaller<'_> {
+    type Error = Infallible;
 
-        validated_hashes.insert(current_hash, (header.timestamp, current_height));
+    fn call<C: SolCall>(&mut self, target: Address, call: C) -> Result<C::Return, Infallible> {
+        Ok(self.0.execute(ViewCall::new(call, target)))
     }
-
-    (current_hash, current_height, validated_hashes)
 }
 
 fn main() {
-    // TODO: ensure that we're getting blocks stepping back from the end block, not from the start block
-    // we want our last value to correspond to the end_commitment
+    // a host writing another layout is rejected before anything is deserialized into it
+    let version: u32 = env::read();
+    if let Err(err) = check_input_version(version) {
+        panic!("{err}");
+    }
+    // Read the input from the guest environment. Samples the host couldn't query are `None`; the
+    // stats interpolate over them, or span the gap they leave if the samples are irregular.
+    let (params, block_headers, inputs): (
+        GuestParams,
+        Vec<EthBlockHeader>,
+        Vec<Option<EthViewCallInput>>,
+    ) = env::read();
 
-    // Read the input from the guest environment.
-    let (inputs, block_headers): (Vec<EthViewCallInput>, Vec<EthBlockHeader>) = env::read();
+    // reject a truncated or mismatched input with a diagnostic rather than an index panic below
+    let samples = inputs.iter().flatten().count();
+    let range = match check_input_shape(&block_headers, samples) {
+        Ok(range) => range,
+        Err(err) => panic!("invalid guest input: {err}"),
+    };
 
     // Prove the hash link from the block queried upwards.
-    let (block_hash, block_num, validated_hashes) = hash_headers_upwards(block_headers);
-    let end_commitment =
-        BlockCommitment { blockHash: block_hash, blockNumber: U256::from(block_num) };
+    let chain = HeaderChain::link(&block_headers);
+    let end_commitment = chain.head_commitment();
 
+    let query_set = PoolQuerySet::new(&params);
     let mut dex_inputs = Vec::<DexStatsInput>::new();
-    for input in inputs {
+    let mut reward_samples = Vec::<RewardSample>::new();
+    for input in inputs.into_iter().flatten() {
         let mut view_call_env = input.into_env().with_chain_spec(&ETH_MAINNET_CHAIN_SPEC);
         let commitment = view_call_env.block_commitment();
+        if let Err(err) = range.check_sample(commitment.blockNumber.to()) {
+            panic!("invalid guest input: {err}");
+        }
 
-        // confirm that the hash is in the validated set and get the associated timestamp
-        let (timestamp, block_number) = validated_hashes.get(&commitment.blockHash).unwrap();
+        // confirm that the block links up to the head and get the associated timestamp
+        let (timestamp, block_number) = chain.verify(&commitment);
 
-        // Execute the view call; it returns the result in the type generated by the `sol!` macro.
-        let backing = view_call_env
-            .execute(ViewCall::new(cbETHInterface::exchangeRateCall {}, CBETH_ADDRESS))
-            ._0;
+        // Execute the view calls the same way the host preflighted them; the calls return the
+        // results in the types generated by the `sol!` macro. Every call targets a contract the
+        // journal names or commits to through its params digest.
+        let query = query_set.query(block_number, &mut StateCaller(&mut view_call_env)).unwrap();
+        if let Some(rewards) = query.rewards {
+            reward_samples.push(rewards.at(timestamp));
+        }
 
         dex_inputs.push(DexStatsInput {
-            timestamp: *timestamp,
-            block_number: *block_number,
-            lst_backing: backing,
+            timestamp,
+            block_number,
+            lst_backing: query.backing,
+            interpolated: false,
+            pool_tvl: query.tvl,
         });
     }
 
-    let res = calculate_dex_stats(&dex_inputs, 1);
-    let base_yield: U256 = parse_units(&res.base_yield.to_string(), "ether").unwrap().into();
+    // The yield is denominated throughout, so it starts with the first sample the feeds exist at.
+    let all_inputs = dex_inputs.len();
+    let dex_inputs = match params.denominated_from {
+        Some(from) => exclude_before(&dex_inputs, from),
+        None => &dex_inputs,
+    };
+    let undenominated = all_inputs - dex_inputs.len();
+
+    // The committed head state anchors the yield: the window ends at the head sample, or at the
+    // last midnight up to it for samples aligned to midnight. The unfinalized samples are only
+    // left out after, so that the window still ends at the last sample at or below the finality
+    // depth rather than wherever the host stopped sampling.
+    match params.stats.alignment {
+        SampleAlignment::Midnight => verify_midnight_window_end(dex_inputs, chain.head_timestamp()),
+        _ => verify_window_end(dex_inputs, chain.head_number()),
+    }
+    let dex_inputs = match params.finality_depth {
+        Some(depth) => exclude_unfinalized(dex_inputs, chain.head_number(), depth),
+        None => dex_inputs,
+    };
+    let res = calculate_dex_stats_with(dex_inputs, &params.stats);
+    let base_yield = yield_to_wad(res.base_yield);
+    // the incentives over the same samples the base yield was computed from
+    let incentive: U256 = match params.convex {
+        Some(_) => {
+            let rewards = &reward_samples[undenominated..undenominated + dex_inputs.len()];
+            let apr = incentive_yield(rewards, params.stats.day_count);
+            parse_units(&apr.to_string(), "ether").unwrap().into()
+        }
+        None => U256::ZERO,
+    };
+    // make the methodology self-describing: the sampling granularity and the block range the
+    // yield was computed over
+    let window_blocks = dex_inputs.last().unwrap().block_number - dex_inputs[0].block_number;
+    // and the blocks it was computed from, which the host may choose freely
+    let sampled_blocks: Vec<u64> = dex_inputs.iter().map(|input| input.block_number).collect();
     let output = LstDexStats {
         commitment: end_commitment,
+        pool: params.pool.pool,
+        lst: params.pool.lst,
         baseYield: base_yield,
+        rewardPool: params.convex.map(|convex| convex.reward_pool).unwrap_or_default(),
+        incentiveYield: incentive,
+        granularityBlocks: params.stats.granularity_blocks,
+        windowBlocks: window_blocks,
+        paramsDigest: params.digest(),
+        samplesDigest: samples_digest(&sampled_blocks),
     };
 
     env::commit_slice(&output.abi_encode());
-}
\ No newline at end of file
+}
@@ -0,0 +1,205 @@
+//! How the annualized per-interval changes are combined into the yield. The built-in aggregations
+//! are selected through [`Aggregation`], which the guest receives as part of its parameters; other
+//! aggregators can be passed to [`crate::try_calculate_dex_stats_with_aggregator`] directly.
+
+use serde::{Deserialize, Serialize};
+
+use crate::DexStatsError;
+
+/// An annualized change in backing over one interval of the resampled series.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IntervalChange {
+    pub start_timestamp: u64,
+    pub end_timestamp: u64,
+    /// The annualized change, simple or log depending on the [`crate::ReturnType`].
+    pub annualized: f64,
+    /// The pool's TVL in ETH at the start of the interval, if queried.
+    pub start_tvl: Option<f64>,
+}
+
+/// Combines the interval changes into a single annualized rate.
+pub trait Aggregator {
+    /// Aggregates `changes`, ordered by time; never called with an empty slice, nor with one
+    /// [`Aggregator::check`] rejected.
+    fn aggregate(&self, changes: &[IntervalChange]) -> f64;
+
+    /// Rejects a number of changes too small to aggregate, rather than panicking on it.
+    fn check(&self, _changes: usize) -> Result<(), DexStatsError> {
+        Ok(())
+    }
+}
+
+/// The arithmetic mean.
+#[derive(Debug, Clone, Copy)]
+pub struct Mean;
+
+impl Aggregator for Mean {
+    fn aggregate(&self, changes: &[IntervalChange]) -> f64 {
+        changes.iter().map(|change| change.annualized).sum::<f64>() / changes.len() as f64
+    }
+}
+
+/// The median, which a single outlier interval doesn't move.
+#[derive(Debug, Clone, Copy)]
+pub struct Median;
+
+impl Aggregator for Median {
+    fn aggregate(&self, changes: &[IntervalChange]) -> f64 {
+        let mut sorted: Vec<f64> = changes.iter().map(|change| change.annualized).collect();
+        sorted.sort_by(f64::total_cmp);
+
+        let mid = sorted.len() / 2;
+        if sorted.len() % 2 == 0 {
+            (sorted[mid - 1] + sorted[mid]) / 2.0
+        } else {
+            sorted[mid]
+        }
+    }
+}
+
+/// An exponentially weighted mean: the weight of a change halves for every `half_life_seconds` its
+/// interval ended before the last one.
+#[derive(Debug, Clone, Copy)]
+pub struct Ema {
+    pub half_life_seconds: u64,
+}
+
+impl Aggregator for Ema {
+    fn aggregate(&self, changes: &[IntervalChange]) -> f64 {
+        assert!(self.half_life_seconds > 0, "half-life must be positive");
+        let last = changes.last().unwrap().end_timestamp;
+
+        let (weighted, total) = changes.iter().fold((0.0, 0.0), |(weighted, total), change| {
+            let age = (last - change.end_timestamp) as f64 / self.half_life_seconds as f64;
+            let weight = 0.5_f64.powf(age);
+            (weighted + weight * change.annualized, total + weight)
+        });
+
+        weighted / total
+    }
+}
+
+/// The mean weighted by the pool's TVL at the start of each interval, i.e. by the capital that
+/// earned the change. The equal-weighted [`Mean`] is the yield of a position held throughout the
+/// window, however the pool around it grew or shrank; weighting by TVL gives the intervals in
+/// which the pool was large more say, closer to the return its liquidity as a whole realized. In
+/// a pool that shrank over the window, the early intervals weigh more.
+///
+/// Falls back to equal weights if the pool held nothing throughout, and panics if a change lacks
+/// its TVL.
+#[derive(Debug, Clone, Copy)]
+pub struct TvlWeighted;
+
+impl Aggregator for TvlWeighted {
+    fn aggregate(&self, changes: &[IntervalChange]) -> f64 {
+        let tvl = |change: &IntervalChange| change.start_tvl.expect("change without a pool TVL");
+        let total: f64 = changes.iter().map(tvl).sum();
+        if total == 0.0 {
+            return Mean.aggregate(changes);
+        }
+
+        changes.iter().map(|change| tvl(change) * change.annualized).sum::<f64>() / total
+    }
+}
+
+/// Aggregates with `inner` after leaving out the `excluded` changes of the largest magnitude,
+/// positive or negative, to show how much the aggregate rests on its biggest moves. The changes
+/// kept stay in time order. Leaving out every change is rejected as
+/// [`DexStatsError::ExcludesAll`].
+#[derive(Debug, Clone, Copy)]
+pub struct ExcludingLargest<A> {
+    pub inner: A,
+    pub excluded: usize,
+}
+
+impl<A: Aggregator> Aggregator for ExcludingLargest<A> {
+    fn aggregate(&self, changes: &[IntervalChange]) -> f64 {
+        assert!(self.excluded < changes.len(), "no change left to aggregate");
+        let mut by_magnitude: Vec<usize> = (0..changes.len()).collect();
+        by_magnitude
+            .sort_by(|&a, &b| changes[b].annualized.abs().total_cmp(&changes[a].annualized.abs()));
+        let excluded = &by_magnitude[..self.excluded];
+        let kept: Vec<IntervalChange> = changes
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| !excluded.contains(index))
+            .map(|(_, &change)| change)
+            .collect();
+
+        self.inner.aggregate(&kept)
+    }
+
+    fn check(&self, changes: usize) -> Result<(), DexStatsError> {
+        if self.excluded >= changes {
+            return Err(DexStatsError::ExcludesAll { excluded: self.excluded, changes });
+        }
+
+        self.inner.check(changes - self.excluded)
+    }
+}
+
+/// The built-in aggregation applied to the changes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Aggregation {
+    /// See [`Mean`].
+    #[default]
+    Mean,
+    /// See [`Median`].
+    Median,
+    /// See [`Ema`].
+    Ema { half_life_seconds: u64 },
+    /// See [`TvlWeighted`]; needs the [`crate::DexStatsInput::pool_tvl`] of every sample.
+    TvlWeighted,
+}
+
+impl Aggregator for Aggregation {
+    fn aggregate(&self, changes: &[IntervalChange]) -> f64 {
+        match *self {
+            Aggregation::Mean => Mean.aggregate(changes),
+            Aggregation::Median => Median.aggregate(changes),
+            Aggregation::Ema { half_life_seconds } => Ema { half_life_seconds }.aggregate(changes),
+            Aggregation::TvlWeighted => TvlWeighted.aggregate(changes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::DAY_IN_SECONDS;
+
+    fn daily(values: &[f64]) -> Vec<IntervalChange> {
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &annualized)| IntervalChange {
+                start_timestamp: i as u64 * DAY_IN_SECONDS,
+                end_timestamp: (i as u64 + 1) * DAY_IN_SECONDS,
+                annualized,
+                start_tvl: None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn it_should_aggregate_by_mean_and_median() {
+        let changes = daily(&[0.03, 0.04, 0.5, 0.035]);
+
+        assert!((Aggregation::Mean.aggregate(&changes) - 0.15125).abs() < 1e-12);
+        // the outlier doesn't pull the median
+        assert!((Aggregation::Median.aggregate(&changes) - 0.0375).abs() < 1e-12);
+        assert_eq!(Median.aggregate(&changes[..3]), 0.04);
+    }
+
+    #[test]
+    fn it_should_weight_recent_changes_in_an_ema() {
+        let changes = daily(&[0.02, 0.04]);
+
+        // the older change is a half-life back, so it weighs half as much
+        let ema = Aggregation::Ema { half_life_seconds: DAY_IN_SECONDS };
+        assert!((ema.aggregate(&changes) - (0.5 * 0.02 + 0.04) / 1.5).abs() < 1e-12);
+        // with a long half-life it approaches the mean
+        let slow = Ema { half_life_seconds: 1000 * DAY_IN_SECONDS };
+        assert!((slow.aggregate(&changes) - 0.03).abs() < 1e-4);
+    }
+}
@@ -0,0 +1,100 @@
+//! Attribution of a total yield to its sources, for reporting how much of it each contributes.
+
+use core::fmt;
+
+use serde::Serialize;
+
+/// Below this, in absolute terms, a total yield counts as zero and the shares are undefined.
+const ZERO_TOTAL: f64 = 1e-12;
+
+/// One source of yield.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Contribution {
+    /// The annualized yield from this source.
+    pub value: f64,
+    /// `value` as a fraction of the total yield; `None` if the total is about zero.
+    pub share: Option<f64>,
+}
+
+/// A yield broken down by source.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct YieldAttribution {
+    /// Growth of the LST backing.
+    pub staking: Contribution,
+    /// Trading fees earned by the pool.
+    pub fees: Contribution,
+    /// Incentives paid on top, e.g. by Convex.
+    pub incentives: Contribution,
+}
+
+impl YieldAttribution {
+    pub fn new(staking: f64, fees: f64, incentives: f64) -> Self {
+        let total = staking + fees + incentives;
+        let contribution = |value: f64| Contribution {
+            value,
+            share: (total.abs() >= ZERO_TOTAL).then(|| value / total),
+        };
+
+        YieldAttribution {
+            staking: contribution(staking),
+            fees: contribution(fees),
+            incentives: contribution(incentives),
+        }
+    }
+
+    pub fn total(&self) -> f64 {
+        self.staking.value + self.fees.value + self.incentives.value
+    }
+}
+
+impl fmt::Display for YieldAttribution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sources =
+            [("staking", self.staking), ("fees", self.fees), ("incentives", self.incentives)];
+        for (i, (name, contribution)) in sources.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            match contribution.share {
+                Some(share) => write!(f, "{name} {:.0}%", share * 100.0)?,
+                // without a total to share in, show what each source yields
+                None => write!(f, "{name} {:.2}% yield", contribution.value * 100.0)?,
+            }
+        }
+        if self.staking.share.is_none() {
+            write!(f, " (total about zero, no shares)")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_attribute_shares_of_the_total() {
+        let attribution = YieldAttribution::new(0.028, 0.01, 0.002);
+
+        assert!((attribution.total() - 0.04).abs() < 1e-12);
+        assert!((attribution.staking.share.unwrap() - 0.7).abs() < 1e-12);
+        assert!((attribution.fees.share.unwrap() - 0.25).abs() < 1e-12);
+        assert_eq!(attribution.incentives.value, 0.002);
+        assert_eq!(attribution.to_string(), "staking 70%, fees 25%, incentives 5%");
+    }
+
+    #[test]
+    fn it_should_leave_shares_undefined_for_a_zero_total() {
+        // a loss wiping out the incentives
+        let attribution = YieldAttribution::new(-0.01, 0.0, 0.01);
+
+        assert_eq!(attribution.staking.share, None);
+        assert_eq!(attribution.incentives.share, None);
+        assert_eq!(
+            attribution.to_string(),
+            "staking -1.00% yield, fees 0.00% yield, incentives 1.00% yield (total about zero, no \
+             shares)"
+        );
+    }
+}
@@ -0,0 +1,259 @@
+//! How the backing of an LST, the ETH one LST is worth scaled by 1e18, is derived from its
+//! contracts. LSTs expose it differently: cbETH quotes an exchange rate, stETH rebases and its
+//! backing follows from pooled ether per share, and rETH quotes a redemption rate. A rebasing
+//! token without a share interface is tracked through the balance of a holder that never moves
+//! its tokens.
+//!
+//! Strategies query through a [`ViewCaller`], so the same strategy runs in the host preflight, in
+//! the guest and bundled into a multicall.
+
+use alloy_primitives::{Address, U256};
+use alloy_sol_types::{sol, SolCall};
+use serde::{Deserialize, Serialize};
+
+use crate::{cbETHInterface, wad::div_wad, CommittedBacking};
+
+sol! {
+    /// Lido stETH.
+    interface IRebasingLst {
+        function getTotalPooledEther() external view returns (uint256);
+        function getTotalShares() external view returns (uint256);
+    }
+
+    /// Any ERC-20, for the balance of a holder.
+    interface IErc20 {
+        function balanceOf(address account) external view returns (uint256);
+    }
+
+    /// Rocket Pool rETH.
+    interface IRedeemableLst {
+        function getExchangeRate() external view returns (uint256);
+    }
+}
+
+/// Executes view calls against the state of a sampled block.
+pub trait ViewCaller {
+    type Error;
+
+    fn call<C: SolCall>(&mut self, target: Address, call: C) -> Result<C::Return, Self::Error>;
+}
+
+/// Maps the values queried from an LST's contracts into its backing.
+pub trait BackingStrategy {
+    /// Queries `lst` through `caller` and derives the backing. The calls made must not depend on
+    /// the values returned, so that they can be bundled into a single multicall.
+    fn backing<V: ViewCaller>(&self, lst: Address, caller: &mut V) -> Result<U256, V::Error>;
+}
+
+/// LSTs quoting the ETH per token directly, like cbETH's `exchangeRate()`.
+#[derive(Debug, Clone, Copy)]
+pub struct ExchangeRate;
+
+impl BackingStrategy for ExchangeRate {
+    fn backing<V: ViewCaller>(&self, lst: Address, caller: &mut V) -> Result<U256, V::Error> {
+        Ok(caller.call(lst, cbETHInterface::exchangeRateCall {})?._0)
+    }
+}
+
+/// Rebasing LSTs like stETH, whose balances track the pooled ether; one share is backed by the
+/// pooled ether divided by the total shares.
+#[derive(Debug, Clone, Copy)]
+pub struct Rebase;
+
+impl BackingStrategy for Rebase {
+    fn backing<V: ViewCaller>(&self, lst: Address, caller: &mut V) -> Result<U256, V::Error> {
+        let pooled = caller.call(lst, IRebasingLst::getTotalPooledEtherCall {})?._0;
+        let shares = caller.call(lst, IRebasingLst::getTotalSharesCall {})?._0;
+        if shares.is_zero() {
+            // nothing minted yet, so nothing is backed
+            return Ok(U256::ZERO);
+        }
+
+        Ok(div_wad(pooled, shares))
+    }
+}
+
+/// LSTs quoting the ETH a token redeems for, like rETH's `getExchangeRate()`.
+#[derive(Debug, Clone, Copy)]
+pub struct RedemptionRate;
+
+impl BackingStrategy for RedemptionRate {
+    fn backing<V: ViewCaller>(&self, lst: Address, caller: &mut V) -> Result<U256, V::Error> {
+        Ok(caller.call(lst, IRedeemableLst::getExchangeRateCall {})?._0)
+    }
+}
+
+/// Rebasing LSTs without a share interface, tracked through the balance of `holder`, which held
+/// `principal` tokens at a backing of one and has neither sent nor received any since: every
+/// rebase scales its balance with the backing. Only sound for a holder that never moves its
+/// tokens, such as a locked contract; a transfer to or from it reads as a change of the backing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReferenceBalance {
+    pub holder: Address,
+    pub principal: U256,
+}
+
+impl BackingStrategy for ReferenceBalance {
+    fn backing<V: ViewCaller>(&self, lst: Address, caller: &mut V) -> Result<U256, V::Error> {
+        let balance = caller.call(lst, IErc20::balanceOfCall { account: self.holder })?._0;
+        if self.principal.is_zero() {
+            return Ok(U256::ZERO);
+        }
+
+        Ok(div_wad(balance, self.principal))
+    }
+}
+
+/// The backing strategy of a pool's LST.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackingKind {
+    /// See [`ExchangeRate`].
+    #[default]
+    ExchangeRate,
+    /// See [`Rebase`].
+    Rebase,
+    /// See [`RedemptionRate`].
+    RedemptionRate,
+    /// See [`ReferenceBalance`].
+    ReferenceBalance(ReferenceBalance),
+}
+
+impl BackingKind {
+    /// Whether the backing never decreases. cbETH's exchange rate only grows by protocol design,
+    /// so a decrease between samples points at bad data or a wrong contract. Lido and Rocket Pool
+    /// pass slashing and penalties on to stETH and rETH holders, whose backing may legitimately
+    /// fall, as does the balance of a reference holder.
+    pub fn is_monotonic(&self) -> bool {
+        matches!(self, BackingKind::ExchangeRate)
+    }
+
+    /// The strategy as the journal commits to it through [`crate::GuestParams::digest`]: the
+    /// holder and its principal scale the backing just as the calls of the other strategies do.
+    pub fn committed(&self) -> CommittedBacking {
+        let (kind, reference) = match self {
+            BackingKind::ExchangeRate => (0, None),
+            BackingKind::Rebase => (1, None),
+            BackingKind::RedemptionRate => (2, None),
+            BackingKind::ReferenceBalance(reference) => (3, Some(reference)),
+        };
+
+        CommittedBacking {
+            kind,
+            holder: reference.map(|reference| reference.holder).unwrap_or_default(),
+            principal: reference.map(|reference| reference.principal).unwrap_or_default(),
+        }
+    }
+}
+
+impl BackingStrategy for BackingKind {
+    fn backing<V: ViewCaller>(&self, lst: Address, caller: &mut V) -> Result<U256, V::Error> {
+        match self {
+            BackingKind::ExchangeRate => ExchangeRate.backing(lst, caller),
+            BackingKind::Rebase => Rebase.backing(lst, caller),
+            BackingKind::RedemptionRate => RedemptionRate.backing(lst, caller),
+            BackingKind::ReferenceBalance(reference) => reference.backing(lst, caller),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+
+    /// Answers view calls from a table of values by target and function selector.
+    #[derive(Default)]
+    struct MockCaller {
+        values: HashMap<(Address, [u8; 4]), U256>,
+        calls: Vec<(Address, [u8; 4])>,
+    }
+
+    impl MockCaller {
+        fn with<C: SolCall>(mut self, target: Address, value: U256) -> Self {
+            self.values.insert((target, C::SELECTOR), value);
+            self
+        }
+    }
+
+    impl ViewCaller for MockCaller {
+        type Error = Infallible;
+
+        fn call<C: SolCall>(&mut self, target: Address, _: C) -> Result<C::Return, Infallible> {
+            self.calls.push((target, C::SELECTOR));
+            let value = self.values[&(target, C::SELECTOR)];
+            Ok(C::abi_decode_returns(&value.to_be_bytes::<32>(), true).unwrap())
+        }
+    }
+
+    const LST: Address = Address::repeat_byte(0x11);
+
+    #[test]
+    fn it_should_read_an_exchange_rate() {
+        let rate = U256::from(1_067_123_456_789_012_345_u64);
+        let mut caller = MockCaller::default().with::<cbETHInterface::exchangeRateCall>(LST, rate);
+
+        assert_eq!(BackingKind::ExchangeRate.backing(LST, &mut caller).unwrap(), rate);
+    }
+
+    #[test]
+    fn it_should_derive_a_rebasing_backing_from_shares() {
+        let wad = U256::from(10).pow(U256::from(18));
+        let mut caller = MockCaller::default()
+            .with::<IRebasingLst::getTotalPooledEtherCall>(LST, U256::from(9_500_000) * wad)
+            .with::<IRebasingLst::getTotalSharesCall>(LST, U256::from(8_100_000) * wad);
+
+        assert_eq!(
+            BackingKind::Rebase.backing(LST, &mut caller).unwrap(),
+            U256::from(1_172_839_506_172_839_506_u64)
+        );
+    }
+
+    #[test]
+    fn it_should_not_back_an_empty_rebasing_lst() {
+        let mut caller = MockCaller::default()
+            .with::<IRebasingLst::getTotalPooledEtherCall>(LST, U256::ZERO)
+            .with::<IRebasingLst::getTotalSharesCall>(LST, U256::ZERO);
+
+        assert_eq!(Rebase.backing(LST, &mut caller).unwrap(), U256::ZERO);
+    }
+
+    #[test]
+    fn it_should_read_a_redemption_rate() {
+        let rate = U256::from(1_100_472_345_678_901_234_u64);
+        let mut caller =
+            MockCaller::default().with::<IRedeemableLst::getExchangeRateCall>(LST, rate);
+
+        assert_eq!(BackingKind::RedemptionRate.backing(LST, &mut caller).unwrap(), rate);
+        assert_eq!(caller.calls, vec![(LST, IRedeemableLst::getExchangeRateCall::SELECTOR)]);
+    }
+
+    #[test]
+    fn it_should_track_a_rebasing_balance() {
+        let wad = U256::from(10).pow(U256::from(18));
+        let holder = Address::repeat_byte(0x22);
+        let kind = BackingKind::ReferenceBalance(ReferenceBalance {
+            holder,
+            principal: U256::from(1_000) * wad,
+        });
+
+        // daily rebases of a 3.65% yearly yield, and a slashing on the third day
+        let balances = [1_000.0, 1_000.1, 1_000.2, 999.9, 1_000.0];
+        let backings: Vec<_> = balances
+            .iter()
+            .map(|balance| {
+                let balance =
+                    U256::from((balance * 1e6) as u64) * U256::from(10).pow(U256::from(12));
+                let mut caller = MockCaller::default().with::<IErc20::balanceOfCall>(LST, balance);
+                let backing = kind.backing(LST, &mut caller).unwrap();
+                assert_eq!(caller.calls, vec![(LST, IErc20::balanceOfCall::SELECTOR)]);
+                backing
+            })
+            .collect();
+
+        assert_eq!(backings[0], wad);
+        assert_eq!(backings[1], U256::from(1_000_100_000_000_000_000_u64));
+        assert!(backings[3] < backings[2] && backings[4] > backings[3]);
+        assert!(!kind.is_monotonic());
+    }
+}
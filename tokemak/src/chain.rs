@@ -0,0 +1,372 @@
+//! Linkage of the sampled blocks to a single trusted head through a contiguous header chain.
+//!
+//! Every view call input carries its own block commitment, but the output only commits to the head
+//! of the chain. The samples are therefore only sound if each of their blocks is part of the header
+//! chain that hashes up to that head.
+
+use std::collections::HashMap;
+
+use alloy_primitives::{Sealable, B256, U256};
+use risc0_steel::{ethereum::EthBlockHeader, BlockCommitment};
+
+/// The parts of a block header the linkage checks need.
+pub trait ChainHeader {
+    fn number(&self) -> u64;
+    fn timestamp(&self) -> u64;
+    fn parent_hash(&self) -> B256;
+    fn hash(&self) -> B256;
+}
+
+impl ChainHeader for EthBlockHeader {
+    fn number(&self) -> u64 {
+        self.number
+    }
+
+    fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    fn parent_hash(&self) -> B256 {
+        self.parent_hash
+    }
+
+    fn hash(&self) -> B256 {
+        self.hash_slow()
+    }
+}
+
+/// A contiguous, hash-linked range of headers and the head it links up to.
+#[derive(Debug)]
+pub struct HeaderChain {
+    head_hash: B256,
+    head_number: u64,
+    head_timestamp: u64,
+    /// Timestamp and number of every block in the chain, by hash.
+    blocks: HashMap<B256, (u64, u64)>,
+}
+
+impl HeaderChain {
+    /// Hashes the headers upwards from the oldest, asserting that each one is the parent of the
+    /// next.
+    pub fn link<H: ChainHeader>(headers: &[H]) -> Self {
+        assert!(!headers.is_empty(), "no headers provided");
+
+        let mut blocks = HashMap::with_capacity(headers.len());
+        let mut head_hash = headers[0].hash();
+        let mut head_number = headers[0].number();
+        let mut head_timestamp = headers[0].timestamp();
+        blocks.insert(head_hash, (head_timestamp, head_number));
+
+        for header in &headers[1..] {
+            assert_eq!(head_hash, header.parent_hash(), "header chain is not linked");
+            head_hash = header.hash();
+            head_number = header.number();
+            head_timestamp = header.timestamp();
+            blocks.insert(head_hash, (head_timestamp, head_number));
+        }
+
+        HeaderChain { head_hash, head_number, head_timestamp, blocks }
+    }
+
+    /// The commitment to the head all verified blocks link up to.
+    pub fn head_commitment(&self) -> BlockCommitment {
+        BlockCommitment { blockHash: self.head_hash, blockNumber: U256::from(self.head_number) }
+    }
+
+    pub fn head_number(&self) -> u64 {
+        self.head_number
+    }
+
+    pub fn head_timestamp(&self) -> u64 {
+        self.head_timestamp
+    }
+
+    /// Asserts that the committed block is covered by the chain and returns its timestamp and
+    /// number.
+    pub fn verify(&self, commitment: &BlockCommitment) -> (u64, u64) {
+        let Some(&(timestamp, number)) = self.blocks.get(&commitment.blockHash) else {
+            panic!(
+                "block {} ({}) not covered by the provided headers",
+                commitment.blockNumber, commitment.blockHash
+            );
+        };
+        assert_eq!(commitment.blockNumber, U256::from(number), "commitment block number mismatch");
+
+        (timestamp, number)
+    }
+}
+
+/// Why the guest input is inconsistent, see [`check_input_shape`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum InputError {
+    #[error("no headers provided")]
+    NoHeaders,
+    #[error("no samples provided")]
+    NoSamples,
+    #[error("header range is not contiguous: block {number} follows block {previous}")]
+    NotContiguous { number: u64, previous: u64 },
+    #[error("{samples} samples for a range of only {headers} headers")]
+    TooManySamples { samples: usize, headers: usize },
+    #[error("sampled block {number} is outside the header range {first} to {last}")]
+    SampleOutOfRange { number: u64, first: u64, last: u64 },
+}
+
+/// The block numbers a range of headers covers, from `first` to `last` inclusive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeaderRange {
+    pub first: u64,
+    pub last: u64,
+}
+
+impl HeaderRange {
+    /// Checks that the sampled block `number` lies within the range.
+    pub fn check_sample(&self, number: u64) -> Result<(), InputError> {
+        if !(self.first..=self.last).contains(&number) {
+            return Err(InputError::SampleOutOfRange {
+                number,
+                first: self.first,
+                last: self.last,
+            });
+        }
+
+        Ok(())
+    }
+}
+
+/// Checks the shape of the guest input before anything indexes into it: the dense `headers` number
+/// every block of their range, and the `samples` queried out of it fit into it. The guest checks
+/// each sample against the returned range as it reads it, and the linkage checks prove the rest;
+/// this only turns a truncated or mismatched input into a diagnostic rather than an index panic.
+pub fn check_input_shape<H: ChainHeader>(
+    headers: &[H],
+    samples: usize,
+) -> Result<HeaderRange, InputError> {
+    let (Some(first), Some(last)) = (headers.first(), headers.last()) else {
+        return Err(InputError::NoHeaders);
+    };
+    for pair in headers.windows(2) {
+        let (previous, number) = (pair[0].number(), pair[1].number());
+        if previous.checked_add(1) != Some(number) {
+            return Err(InputError::NotContiguous { number, previous });
+        }
+    }
+    if samples == 0 {
+        return Err(InputError::NoSamples);
+    }
+    if samples > headers.len() {
+        return Err(InputError::TooManySamples { samples, headers: headers.len() });
+    }
+
+    Ok(HeaderRange { first: first.number(), last: last.number() })
+}
+
+/// Why a range of headers doesn't link to a trusted head, see [`HeadLink`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum LinkError {
+    #[error("no headers provided")]
+    Empty,
+    #[error("header chain is not linked: block {number} is not the child of the block before it")]
+    NotLinked { number: u64 },
+    #[error(
+        "head block {number} hashes to {hash}, not to the trusted {trusted}; the RPC serves \
+         another chain"
+    )]
+    HeadMismatch { number: u64, hash: B256, trusted: B256 },
+}
+
+/// Checks a range of headers, one at a time as they arrive, for being hash-linked up to a trusted
+/// head hash, e.g. one taken from a block explorer, rather than trusting the head the RPC reports.
+#[derive(Debug)]
+pub struct HeadLink {
+    trusted: B256,
+    /// Number and hash of the last header pushed.
+    last: Option<(u64, B256)>,
+}
+
+impl HeadLink {
+    pub fn new(trusted: B256) -> Self {
+        HeadLink { trusted, last: None }
+    }
+
+    /// Checks that `header` is the child of the header pushed before it.
+    pub fn push<H: ChainHeader>(&mut self, header: &H) -> Result<(), LinkError> {
+        if let Some((_, hash)) = self.last {
+            if header.parent_hash() != hash {
+                return Err(LinkError::NotLinked { number: header.number() });
+            }
+        }
+        self.last = Some((header.number(), header.hash()));
+
+        Ok(())
+    }
+
+    /// Checks that the last header pushed is the trusted head.
+    pub fn finish(self) -> Result<(), LinkError> {
+        match self.last {
+            None => Err(LinkError::Empty),
+            Some((number, hash)) if hash != self.trusted => {
+                Err(LinkError::HeadMismatch { number, hash, trusted: self.trusted })
+            }
+            Some(_) => Ok(()),
+        }
+    }
+}
+
+/// Checks that `headers`, oldest first, are hash-linked up to the head hash `trusted`.
+pub fn verify_headers_link_to<H: ChainHeader>(
+    headers: &[H],
+    trusted: B256,
+) -> Result<(), LinkError> {
+    let mut link = HeadLink::new(trusted);
+    for header in headers {
+        link.push(header)?;
+    }
+
+    link.finish()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use alloy_primitives::keccak256;
+
+    #[derive(Debug, Clone)]
+    pub(crate) struct TestHeader {
+        pub number: u64,
+        pub timestamp: u64,
+        pub parent_hash: B256,
+    }
+
+    impl ChainHeader for TestHeader {
+        fn number(&self) -> u64 {
+            self.number
+        }
+
+        fn timestamp(&self) -> u64 {
+            self.timestamp
+        }
+
+        fn parent_hash(&self) -> B256 {
+            self.parent_hash
+        }
+
+        fn hash(&self) -> B256 {
+            let mut preimage = self.parent_hash.to_vec();
+            preimage.extend_from_slice(&self.number.to_be_bytes());
+            preimage.extend_from_slice(&self.timestamp.to_be_bytes());
+            keccak256(preimage)
+        }
+    }
+
+    /// Builds a linked chain of `len` headers starting at block `start`, 12 seconds apart.
+    pub(crate) fn test_chain(start: u64, len: u64) -> Vec<TestHeader> {
+        let mut headers: Vec<TestHeader> = Vec::new();
+        for number in start..start + len {
+            let parent_hash = headers.last().map(|h| h.hash()).unwrap_or_default();
+            headers.push(TestHeader {
+                number,
+                timestamp: 1_700_000_000 + number * 12,
+                parent_hash,
+            });
+        }
+        headers
+    }
+
+    fn commitment(header: &TestHeader) -> BlockCommitment {
+        BlockCommitment { blockHash: header.hash(), blockNumber: U256::from(header.number) }
+    }
+
+    #[test]
+    fn it_should_link_headers_to_the_head() {
+        let headers = test_chain(100, 10);
+        let chain = HeaderChain::link(&headers);
+
+        let head = chain.head_commitment();
+        assert_eq!(head.blockHash, headers[9].hash());
+        assert_eq!(head.blockNumber, U256::from(109));
+        assert_eq!(chain.verify(&commitment(&headers[3])), (headers[3].timestamp, 103));
+    }
+
+    #[test]
+    #[should_panic(expected = "header chain is not linked")]
+    fn it_should_reject_unlinked_headers() {
+        let mut headers = test_chain(100, 10);
+        headers.remove(5);
+
+        HeaderChain::link(&headers);
+    }
+
+    #[test]
+    #[should_panic(expected = "not covered by the provided headers")]
+    fn it_should_reject_blocks_outside_the_header_range() {
+        let headers = test_chain(100, 20);
+        let chain = HeaderChain::link(&headers[10..]);
+
+        // the sampled block is part of the same chain, but older than the provided headers
+        chain.verify(&commitment(&headers[5]));
+    }
+
+    #[test]
+    #[should_panic(expected = "commitment block number mismatch")]
+    fn it_should_reject_mismatched_block_numbers() {
+        let headers = test_chain(100, 10);
+        let chain = HeaderChain::link(&headers);
+
+        let mut forged = commitment(&headers[3]);
+        forged.blockNumber = U256::from(104);
+        chain.verify(&forged);
+    }
+
+    #[test]
+    fn it_should_link_headers_to_a_trusted_head() {
+        let headers = test_chain(100, 10);
+        assert_eq!(verify_headers_link_to(&headers, headers[9].hash()), Ok(()));
+
+        // a head the RPC made up, or one of another chain
+        let other = test_chain(200, 10);
+        assert_eq!(
+            verify_headers_link_to(&headers, other[9].hash()),
+            Err(LinkError::HeadMismatch {
+                number: 109,
+                hash: headers[9].hash(),
+                trusted: other[9].hash()
+            })
+        );
+        // the trusted hash of an older block is no anchor for the range above it
+        assert!(verify_headers_link_to(&headers, headers[8].hash()).is_err());
+
+        let mut unlinked = headers.clone();
+        unlinked.remove(5);
+        assert_eq!(
+            verify_headers_link_to(&unlinked, headers[9].hash()),
+            Err(LinkError::NotLinked { number: 106 })
+        );
+        assert_eq!(verify_headers_link_to::<TestHeader>(&[], B256::ZERO), Err(LinkError::Empty));
+    }
+
+    #[test]
+    fn it_should_reject_mismatched_input_shapes() {
+        let headers = test_chain(100, 10);
+        let range = check_input_shape(&headers, 3).unwrap();
+        assert_eq!(range, HeaderRange { first: 100, last: 109 });
+        assert_eq!(range.check_sample(109), Ok(()));
+        assert_eq!(
+            range.check_sample(110),
+            Err(InputError::SampleOutOfRange { number: 110, first: 100, last: 109 })
+        );
+
+        // a truncated header stream, or a sample stream longer than the headers it was taken from
+        assert_eq!(check_input_shape::<TestHeader>(&[], 3), Err(InputError::NoHeaders));
+        assert_eq!(
+            check_input_shape(&headers[..2], 3),
+            Err(InputError::TooManySamples { samples: 3, headers: 2 })
+        );
+        assert_eq!(check_input_shape(&headers, 0), Err(InputError::NoSamples));
+        let mut gapped = headers.clone();
+        gapped.remove(4);
+        assert_eq!(
+            check_input_shape(&gapped, 3),
+            Err(InputError::NotContiguous { number: 105, previous: 103 })
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Convex incentives on top of the pool yield. Convex stakes the Curve LP tokens and streams CRV to
+//! the stakers through a reward pool; the incentive yield is the value of that stream relative to
+//! the value of the staked LP tokens. It is reported separately from the pool's base yield.
+//!
+//! The valuation assumes:
+//! - only the CRV stream of the base reward pool counts; the CVX minted alongside it and any extra
+//!   reward contracts are left out, so the incentive yield is a lower bound
+//! - the reward token is priced at the sampled block by a Chainlink feed quoting it in ETH, e.g.
+//!   CRV/ETH, rather than by what a staker could actually sell it for
+//! - an LP token is worth its virtual price in ETH, which holds for an ETH-paired pool close to
+//!   balance
+
+use alloy_primitives::{Address, U256};
+use alloy_sol_types::sol;
+use serde::{Deserialize, Serialize};
+
+use crate::{oracle::PriceFeed, u256_to_f64, DayCount};
+
+sol! {
+    interface IConvexRewardPool {
+        function rewardRate() external view returns (uint256);
+        function totalSupply() external view returns (uint256);
+        function periodFinish() external view returns (uint256);
+    }
+}
+
+/// Where the incentives of a pool are paid and how the reward token is priced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConvexRewards {
+    /// The Convex base reward pool of the Curve LP token.
+    pub reward_pool: Address,
+    /// Feed quoting the reward token in ETH.
+    pub reward_feed: PriceFeed,
+}
+
+/// The reward pool state at a sampled block.
+#[derive(Debug, Clone, Copy)]
+pub struct RewardSample {
+    pub timestamp: u64,
+    /// Reward tokens paid out per second, in wei.
+    pub reward_rate: U256,
+    /// Staked LP tokens, in wei.
+    pub total_supply: U256,
+    /// Timestamp at which the current reward period ends.
+    pub period_finish: U256,
+    /// ETH per LP token, scaled by 1e18.
+    pub virtual_price: U256,
+    /// ETH per reward token, scaled by 1e18.
+    pub reward_price: U256,
+}
+
+impl RewardSample {
+    /// The annualized incentive rate at this sample: zero once the reward period has ended or while
+    /// nothing is staked.
+    pub fn incentive_apr(&self, day_count: DayCount) -> f64 {
+        if U256::from(self.timestamp) >= self.period_finish
+            || self.total_supply.is_zero()
+            || self.virtual_price.is_zero()
+        {
+            return 0.0;
+        }
+
+        let rewards_per_year = u256_to_f64(self.reward_rate, 18)
+            * day_count.seconds_per_year()
+            * u256_to_f64(self.reward_price, 18);
+        let staked = u256_to_f64(self.total_supply, 18) * u256_to_f64(self.virtual_price, 18);
+
+        rewards_per_year / staked
+    }
+}
+
+/// The mean incentive rate over the samples.
+///
+/// Panics if `samples` is empty.
+pub fn incentive_yield(samples: &[RewardSample], day_count: DayCount) -> f64 {
+    assert!(!samples.is_empty(), "no reward samples");
+
+    samples.iter().map(|sample| sample.incentive_apr(day_count)).sum::<f64>() / samples.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_sol_types::SolCall;
+
+    const WAD: u64 = 1_000_000_000_000_000_000;
+
+    /// The state of a reward pool paying a CRV a second, decoded from the return data a reward
+    /// contract would produce.
+    fn mock_reward_pool(timestamp: u64, period_finish: u64) -> RewardSample {
+        let returns = |value: U256| value.to_be_bytes::<32>().to_vec();
+        let reward_rate =
+            IConvexRewardPool::rewardRateCall::abi_decode_returns(&returns(U256::from(WAD)), true)
+                .unwrap()
+                ._0;
+        let total_supply = IConvexRewardPool::totalSupplyCall::abi_decode_returns(
+            &returns(U256::from(100_000) * U256::from(WAD)),
+            true,
+        )
+        .unwrap()
+        ._0;
+        let period_finish = IConvexRewardPool::periodFinishCall::abi_decode_returns(
+            &returns(U256::from(period_finish)),
+            true,
+        )
+        .unwrap()
+        ._0;
+
+        RewardSample {
+            timestamp,
+            reward_rate,
+            total_supply,
+            period_finish,
+            // 1 LP = 1.02 ETH, 1 CRV = 0.0002 ETH
+            virtual_price: U256::from(1_020_000_000_000_000_000_u64),
+            reward_price: U256::from(200_000_000_000_000_u64),
+        }
+    }
+
+    #[test]
+    fn it_should_price_the_rewards_of_a_mock_reward_pool() {
+        let sample = mock_reward_pool(1_700_000_000, 1_700_100_000);
+
+        // 1 CRV/s * 31536000 s * 0.0002 ETH / (100_000 LP * 1.02 ETH), about 6.18%
+        let expected = 31_536_000.0 * 0.0002 / 102_000.0;
+        let apr = sample.incentive_apr(DayCount::Actual365);
+        assert!((apr - expected).abs() <= 0.00000001);
+
+        let samples = [sample, mock_reward_pool(1_700_000_000, 1_700_100_000)];
+        assert!((incentive_yield(&samples, DayCount::Actual365) - expected).abs() <= 0.00000001);
+    }
+
+    #[test]
+    fn it_should_stop_counting_rewards_after_the_period() {
+        let active = mock_reward_pool(1_700_000_000, 1_700_100_000);
+        let finished = mock_reward_pool(1_700_100_000, 1_700_100_000);
+        assert_eq!(finished.incentive_apr(DayCount::Actual365), 0.0);
+
+        let mean = incentive_yield(&[active, finished], DayCount::Actual365);
+        assert!((mean - active.incentive_apr(DayCount::Actual365) / 2.0).abs() <= 0.00000001);
+    }
+
+    #[test]
+    fn it_should_ignore_an_empty_reward_pool() {
+        let sample = RewardSample { total_supply: U256::ZERO, ..mock_reward_pool(1, 2) };
+        assert_eq!(sample.incentive_apr(DayCount::Actual365), 0.0);
+    }
+}
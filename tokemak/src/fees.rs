@@ -0,0 +1,74 @@
+//! The yield net of a vault's fees, as vault products report it: a management fee charged on the
+//! assets every year, whatever they earn, and a performance fee taking a share of what they earn.
+
+use core::fmt;
+
+use serde::Serialize;
+
+/// Basis points in one.
+const BPS: f64 = 10_000.0;
+
+/// The fees a vault charges its depositors.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct FeeSchedule {
+    /// Charged on the assets, as an annual rate.
+    pub management_fee_apr: f64,
+    /// Share of a positive gross yield taken, in basis points.
+    pub performance_fee_bps: u32,
+}
+
+impl FeeSchedule {
+    /// The yield net of the fees out of `gross`. The performance fee only applies to a positive
+    /// yield and the management fee always does; the net yield is floored at the negative
+    /// management fee, what the fees cost a depositor when the vault earns nothing.
+    pub fn apply(&self, gross: f64) -> NetYield {
+        let performance_fee = gross.max(0.0) * f64::from(self.performance_fee_bps) / BPS;
+        let net = (gross - performance_fee - self.management_fee_apr).max(-self.management_fee_apr);
+
+        NetYield { gross, net, fees: *self }
+    }
+}
+
+/// A yield before and after the fees of a [`FeeSchedule`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct NetYield {
+    pub gross: f64,
+    pub net: f64,
+    pub fees: FeeSchedule,
+}
+
+impl fmt::Display for NetYield {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.2}% net of a {:.2}% management and {:.2}% performance fee, {:.2}% gross",
+            self.net * 100.0,
+            self.fees.management_fee_apr * 100.0,
+            f64::from(self.fees.performance_fee_bps) / 100.0,
+            self.gross * 100.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_should_net_the_yield_of_the_fees() {
+        // a vault charging 2 and 20 on a 4% yield
+        let fees = FeeSchedule { management_fee_apr: 0.02, performance_fee_bps: 2_000 };
+        let net = fees.apply(0.04);
+        assert!((net.net - 0.012).abs() < 1e-12, "{}", net.net);
+        assert_eq!(net.gross, 0.04);
+        assert_eq!(
+            net.to_string(),
+            "1.20% net of a 2.00% management and 20.00% performance fee, 4.00% gross"
+        );
+
+        // fees exceeding the yield cost the depositors the management fee at most
+        assert!((fees.apply(0.015).net - -0.008).abs() < 1e-12);
+        assert_eq!(fees.apply(-0.01).net, -0.02);
+        assert_eq!(FeeSchedule::default().apply(0.04).net, 0.04);
+    }
+}
@@ -0,0 +1,59 @@
+//! Sample series and journals shared by the tests of this crate and, through the `test-utils`
+//! feature, of the host.
+
+use alloy_primitives::{utils::parse_units, Address, B256, U256};
+use core::ops::RangeInclusive;
+use risc0_steel::BlockCommitment;
+
+use crate::{
+    samples_digest, yield_to_wad, DexStatsInput, GuestParams, LstDexStats, BLOCKS_TO_QUERY,
+    BLOCK_GRANULARITY, CBETH_ADDRESS, CURVE_POOL_ADDRESS, DAY_IN_SECONDS,
+};
+
+/// The timestamp of day 0 of [`daily_inputs`], at block 19_000_000.
+pub const DAY_0_TIMESTAMP: u64 = 1_716_129_570;
+
+/// Observed samples one day and [`BLOCK_GRANULARITY`] blocks apart over `days`, day 0 at block
+/// 19_000_000, each of the backing `backing` gives for its day.
+pub fn daily_inputs(days: RangeInclusive<u64>, backing: impl Fn(u64) -> f64) -> Vec<DexStatsInput> {
+    days.map(|day| DexStatsInput {
+        timestamp: DAY_0_TIMESTAMP + day * DAY_IN_SECONDS,
+        block_number: 19_000_000 + day * BLOCK_GRANULARITY,
+        lst_backing: parse_units(&format!("{:.18}", backing(day)), 18).unwrap().into(),
+        interpolated: false,
+        pool_tvl: None,
+    })
+    .collect()
+}
+
+/// A backing of 1 on day 0 growing by 0.0001 a day, a steady yield of 3.65% a year.
+pub fn steady_backing(day: u64) -> f64 {
+    1.0 + 0.0001 * day as f64
+}
+
+/// A backing of 100 on day 0 compounding 0.01% a day, and twice that from day 24 on: over a 30
+/// day window, the last week grows twice as fast as the weeks before.
+pub fn accelerating_backing(day: u64) -> f64 {
+    let (slow, fast) = (day.min(23), day.saturating_sub(23));
+    100.0 * 1.0001_f64.powi(slow as i32) * 1.0002_f64.powi(fast as i32)
+}
+
+/// The journal of a default run of the cbETH pool to block 19_900_000, of a 3.1% yield and no
+/// incentives; a test overrides the fields it checks.
+pub fn journal() -> LstDexStats {
+    LstDexStats {
+        commitment: BlockCommitment {
+            blockHash: B256::repeat_byte(0xab),
+            blockNumber: U256::from(19_900_000),
+        },
+        pool: CURVE_POOL_ADDRESS,
+        lst: CBETH_ADDRESS,
+        baseYield: yield_to_wad(0.031),
+        rewardPool: Address::ZERO,
+        incentiveYield: U256::ZERO,
+        granularityBlocks: BLOCK_GRANULARITY,
+        windowBlocks: BLOCKS_TO_QUERY,
+        paramsDigest: GuestParams::default().digest(),
+        samplesDigest: samples_digest(&[19_878_400, 19_885_600, 19_892_800, 19_900_000]),
+    }
+}
@@ -1,8 +1,32 @@
-use core::fmt;
+use core::{fmt, ops::Range};
 
-use alloy_primitives::{address, utils::format_units, Address, U256};
-use alloy_sol_types::sol;
+use aggregate::{Aggregation, Aggregator, ExcludingLargest, IntervalChange};
+use alloy_primitives::{
+    address, keccak256,
+    utils::{format_units, parse_units},
+    Address, B256, I256, U256,
+};
+use alloy_sol_types::{sol, SolValue};
+use backing::BackingKind;
+use convex::ConvexRewards;
+use oracle::PriceFeed;
 use risc0_steel::BlockCommitment;
+use serde::{Deserialize, Serialize};
+
+pub mod aggregate;
+pub mod attribution;
+pub mod backing;
+pub mod chain;
+pub mod convex;
+pub mod fees;
+#[cfg(any(test, feature = "test-utils"))]
+pub mod fixture;
+pub mod multicall;
+pub mod oracle;
+pub mod portfolio;
+pub mod query;
+pub mod reference;
+pub mod wad;
 
 // Curve/Convex cbETH/ETH pool
 pub const CURVE_POOL_ADDRESS: Address = address!("06325440D014E39736583C165C2963BA99FAF14E");
@@ -10,15 +34,28 @@ pub const CURVE_LP_ADDRESS: Address = address!("5b6C539b224014A09B3388e51CaAA8e3
 pub const CBETH_ADDRESS: Address = address!("Be9895146f7AF43049ca1c1AE358B0541Ea49704");
 pub const CBETH_CHAINLINK_ORACLE: Address = address!("F017fcB346A1885194689bA23Eff2fE6fA5C483b");
 
+// Curve rETH/ETH pool
+pub const RETH_CURVE_POOL_ADDRESS: Address = address!("0f3159811670c117c372428D4E69AC32325e4D0F");
+pub const RETH_CURVE_LP_ADDRESS: Address = address!("6c38cE8984a890F5e46e6dF6117C26b3F1EcfC9C");
+pub const RETH_ADDRESS: Address = address!("ae78736Cd615f374D3085123A210448E74Fc6393");
+
 pub const DAY_IN_SECONDS: u64 = 24 * 60 * 60;
 pub const BLOCK_GRANULARITY: u64 = DAY_IN_SECONDS / 12;
 pub const BLOCKS_TO_QUERY: u64 = (3 * DAY_IN_SECONDS) / 12;
+/// How far from midnight a sample aligned to [`SampleAlignment::Midnight`] may be: the nearest
+/// block is normally within a slot of it, this leaves room for a few missed slots.
+pub const MIDNIGHT_TOLERANCE_SECONDS: u64 = 60;
+/// Seconds per block of the pseudo-time [`DexStatsParams::min_time_delta`] falls back to, that of
+/// mainnet since the merge.
+pub const PSEUDO_SECONDS_PER_BLOCK: u64 = 12;
 
 sol! {
     interface CurvePoolInterface {
         function get_virtual_price() public view returns (uint256);
         function coins(uint256) public view returns (address);
         function balances(uint256) public view returns (uint256);
+        function get_dy(uint256 i, uint256 j, uint256 dx) public view returns (uint256);
+        function fee() public view returns (uint256);
     }
 
     interface ERC20Interface {
@@ -28,6 +65,7 @@ sol! {
 
     interface ChainlinkInterface {
         function latestRoundData() public view returns (uint80,int256,uint256,uint256,uint80);
+        function decimals() public view returns (uint8);
     }
 
     interface cbETHInterface {
@@ -39,81 +77,1262 @@ sol! {
     #[derive(Debug)]
     struct LstDexStats {
         BlockCommitment commitment;
-        uint256 baseYield;
+        address pool;
+        address lst;
+        // signed, as the backing can fall over the window, e.g. during a depeg or a slashing
+        int256 baseYield;
+        address rewardPool;
+        uint256 incentiveYield;
+        uint64 granularityBlocks;
+        uint64 windowBlocks;
+        // keccak256 of the ABI-encoded CommittedParams the yields were computed with
+        bytes32 paramsDigest;
+        // keccak256 of the ABI-encoded uint64[] of the blocks the yields were computed from
+        bytes32 samplesDigest;
+    }
+}
+
+sol! {
+    /// A price feed as the journal commits to it; the zero address with zero decimals when none is
+    /// configured.
+    #[derive(Debug, PartialEq, Eq)]
+    struct CommittedFeed {
+        address feed;
+        uint8 decimals;
+    }
+
+    /// The guest params that shape the committed yields beyond the pool, the LST and the window,
+    /// see [`GuestParams::committed`].
+    #[derive(Debug, PartialEq, Eq)]
+    struct CommittedParams {
+        CommittedFeed priceFeed;
+        CommittedFeed referenceFeed;
+        CommittedFeed rewardFeed;
+        // the first block the yield is denominated from, zero when it is throughout the window
+        uint64 denominatedFrom;
+        // the depth below the head samples are left out of the yield within, zero when none are
+        uint64 finalityDepth;
+        CommittedBacking backing;
+        CommittedStats stats;
+    }
+
+    /// How the backing of the LST is derived, by the index of its [`BackingKind`] variant, with
+    /// the holder and principal of [`BackingKind::ReferenceBalance`], zero for the others.
+    #[derive(Debug, PartialEq, Eq)]
+    struct CommittedBacking {
+        uint8 kind;
+        address holder;
+        uint256 principal;
+    }
+
+    /// The [`DexStatsParams`] beyond the journaled granularity, see [`DexStatsParams::committed`].
+    /// Enums are committed by the index of their variant, and a bound that is `None` as zero.
+    #[derive(Debug, PartialEq, Eq)]
+    struct CommittedStats {
+        uint64 skip;
+        uint8 skipRemainder;
+        // the window of ChangeMode::Rolling, zero for point-to-point changes
+        uint64 rollingWindow;
+        uint64 maxInterpolated;
+        uint8 dayCount;
+        uint8 returnType;
+        uint8 alignment;
+        uint8 aggregation;
+        // the half-life of Aggregation::Ema, zero for the others
+        uint64 emaHalfLifeSeconds;
+        uint64 maxBlockGap;
+        uint64 minTimeDelta;
+        uint64 minIntervalSeconds;
+    }
+}
+
+/// What a consumer of a journal expects it to have been computed for, to reject a valid journal
+/// for other parameters before trusting its yield.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpectedParams {
+    pub pool: PoolConfig,
+    /// The block the journal commits to.
+    pub head_block: u64,
+    /// Block distance from the first to the last sample the yield is computed from.
+    pub window_blocks: u64,
+    pub granularity_blocks: u64,
+    /// The [`GuestParams::digest`] of the params the journal is expected to be computed with.
+    pub params_digest: B256,
+    /// The [`samples_digest`] of the blocks the yield is expected to be computed from.
+    pub samples_digest: B256,
+}
+
+/// How a journal differs from the [`ExpectedParams`].
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParamsMismatch {
+    #[error("journal is for pool {committed}, expected {expected}")]
+    Pool { expected: Address, committed: Address },
+    #[error("journal is for LST {committed}, expected {expected}")]
+    Lst { expected: Address, committed: Address },
+    #[error("journal commits to block {committed}, expected {expected}")]
+    HeadBlock { expected: u64, committed: U256 },
+    #[error("journal covers a window of {committed} blocks, expected {expected}")]
+    Window { expected: u64, committed: u64 },
+    #[error("journal samples every {committed} blocks, expected {expected}")]
+    Granularity { expected: u64, committed: u64 },
+    #[error("journal was computed with params of digest {committed}, expected {expected}")]
+    Params { expected: B256, committed: B256 },
+    #[error("journal was computed from samples of digest {committed}, expected {expected}")]
+    Samples { expected: B256, committed: B256 },
+}
+
+impl LstDexStats {
+    /// Checks the committed pool, head block, window, granularity, params and samples against
+    /// `expected`, reporting the first that differs.
+    pub fn verify_parameters(&self, expected: &ExpectedParams) -> Result<(), ParamsMismatch> {
+        if self.pool != expected.pool.pool {
+            return Err(ParamsMismatch::Pool {
+                expected: expected.pool.pool,
+                committed: self.pool,
+            });
+        }
+        if self.lst != expected.pool.lst {
+            return Err(ParamsMismatch::Lst { expected: expected.pool.lst, committed: self.lst });
+        }
+        if self.commitment.blockNumber != U256::from(expected.head_block) {
+            return Err(ParamsMismatch::HeadBlock {
+                expected: expected.head_block,
+                committed: self.commitment.blockNumber,
+            });
+        }
+        if self.windowBlocks != expected.window_blocks {
+            return Err(ParamsMismatch::Window {
+                expected: expected.window_blocks,
+                committed: self.windowBlocks,
+            });
+        }
+        if self.granularityBlocks != expected.granularity_blocks {
+            return Err(ParamsMismatch::Granularity {
+                expected: expected.granularity_blocks,
+                committed: self.granularityBlocks,
+            });
+        }
+        if self.paramsDigest != expected.params_digest {
+            return Err(ParamsMismatch::Params {
+                expected: expected.params_digest,
+                committed: self.paramsDigest,
+            });
+        }
+        if self.samplesDigest != expected.samples_digest {
+            return Err(ParamsMismatch::Samples {
+                expected: expected.samples_digest,
+                committed: self.samplesDigest,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The pool yield plus the Convex incentive yield, if any; `None` if the sum doesn't fit a
+    /// yield, as a journal can carry any values.
+    pub fn combined_yield(&self) -> Option<I256> {
+        let incentive = I256::try_from(self.incentiveYield).ok()?;
+
+        self.baseYield.checked_add(incentive)
+    }
+
+    /// The Convex incentive yield, as a fraction; zero without a reward pool.
+    pub fn incentive_yield(&self) -> f64 {
+        u256_to_f64(self.incentiveYield, 18)
     }
 }
 
 impl fmt::Display for LstDexStats {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let base_yield = u256_to_f64(self.baseYield, 18) * 100.0;
+        let base_yield = wad_to_yield(self.baseYield) * 100.0;
+        write!(f, "LstDexStats: baseYield={:.2}%", base_yield)?;
+        let incentives = !self.rewardPool.is_zero();
+        if incentives {
+            write!(f, ", incentiveYield={:.2}%", self.incentive_yield() * 100.0)?;
+            // the components still show a sum that doesn't fit
+            if let Some(combined) = self.combined_yield() {
+                write!(f, ", combinedYield={:.2}%", wad_to_yield(combined) * 100.0)?;
+            }
+        }
+        write!(f, " (pool={}, ", self.pool)?;
+        if incentives {
+            write!(f, "rewardPool={}, ", self.rewardPool)?;
+        }
         write!(
             f,
-            "LstDexStats: baseYield={:.2}% (blockNumber={}, blockHash={})",
-            base_yield, self.commitment.blockNumber, self.commitment.blockHash
+            "lst={}, blockNumber={}, blockHash={}, granularityBlocks={}, windowBlocks={}, \
+             paramsDigest={}, samplesDigest={})",
+            self.lst,
+            self.commitment.blockNumber,
+            self.commitment.blockHash,
+            self.granularityBlocks,
+            self.windowBlocks,
+            self.paramsDigest,
+            self.samplesDigest
         )
     }
 }
 
-#[derive(Debug)]
+/// The contracts a yield is computed from. The host chooses it and the guest commits to the pool
+/// and the LST, so a verifier can tell which pool a yield is for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PoolConfig {
+    /// The Curve pool.
+    pub pool: Address,
+    /// The pool's LP token, which isn't committed to, so no view call may target it.
+    pub lp_token: Address,
+    /// The LST whose backing is queried.
+    pub lst: Address,
+    /// How the backing is derived from the LST's contracts.
+    pub backing: BackingKind,
+}
+
+impl PoolConfig {
+    /// The Curve/Convex cbETH/ETH pool.
+    pub const CBETH_ETH: PoolConfig = PoolConfig {
+        pool: CURVE_POOL_ADDRESS,
+        lp_token: CURVE_LP_ADDRESS,
+        lst: CBETH_ADDRESS,
+        backing: BackingKind::ExchangeRate,
+    };
+
+    /// The Curve rETH/ETH pool.
+    pub const RETH_ETH: PoolConfig = PoolConfig {
+        pool: RETH_CURVE_POOL_ADDRESS,
+        lp_token: RETH_CURVE_LP_ADDRESS,
+        lst: RETH_ADDRESS,
+        backing: BackingKind::RedemptionRate,
+    };
+
+    /// The pools known by name, e.g. for selecting them on the command line.
+    pub const KNOWN: &'static [(&'static str, PoolConfig)] =
+        &[("cbeth", PoolConfig::CBETH_ETH), ("reth", PoolConfig::RETH_ETH)];
+
+    pub fn by_name(name: &str) -> Option<(&'static str, PoolConfig)> {
+        PoolConfig::KNOWN.iter().copied().find(|(known, _)| known.eq_ignore_ascii_case(name))
+    }
+
+    /// Asserts that a view call targets the pool or the LST, the contracts the journal names.
+    pub fn verify_target(&self, target: Address) {
+        assert!(
+            target == self.pool || target == self.lst,
+            "view call target {target} is not part of the pool config"
+        );
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig::CBETH_ETH
+    }
+}
+
+/// How the host queried each sampled block, so the guest can replay the same calls.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum QueryMode {
+    /// One view call per queried value.
+    #[default]
+    Individual,
+    /// All values of a block bundled into a single Multicall3 call.
+    Multicall,
+}
+
+/// Version of the guest input layout, which the host writes ahead of the [`GuestParams`]. Bump it
+/// whenever the params or the order of what follows them change, so that a guest built against
+/// another layout rejects the input by name rather than failing on a garbled deserialization.
+pub const GUEST_INPUT_VERSION: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+#[error(
+    "unsupported guest input version {found}, this guest reads version {expected}; the host and \
+     the guest are likely built from different sources"
+)]
+pub struct InputVersionError {
+    pub found: u32,
+    pub expected: u32,
+}
+
+/// Checks that the guest input is of the [`GUEST_INPUT_VERSION`] layout.
+pub fn check_input_version(found: u32) -> Result<(), InputVersionError> {
+    if found != GUEST_INPUT_VERSION {
+        return Err(InputVersionError { found, expected: GUEST_INPUT_VERSION });
+    }
+
+    Ok(())
+}
+
+/// Parameters the host passes to the guest ahead of the view call inputs.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GuestParams {
+    pub pool: PoolConfig,
+    pub query_mode: QueryMode,
+    pub stats: DexStatsParams,
+    /// Feed to re-denominate the ETH backing with, e.g. ETH/USD for a USD yield. Note that a yield
+    /// in a volatile reference asset mixes its price returns into the staking returns.
+    pub price_feed: Option<PriceFeed>,
+    /// Feed quoting the reference asset in the quote asset of `price_feed`, e.g. BTC/USD for a BTC
+    /// yield, to convert the re-denominated backing into the reference asset.
+    pub reference_feed: Option<PriceFeed>,
+    /// First block all the price feeds exist at, when that is within the window. Earlier samples
+    /// keep the plain ETH backing and are left out of the yield, so that it is denominated
+    /// throughout. The guest can't tell where the feeds begin, so the journal commits to the block
+    /// for a verifier to check against their deployments.
+    pub denominated_from: Option<u64>,
+    /// When set, samples within this many blocks of the head are left out of the yield, as a reorg
+    /// could still change them. The output still commits to the head.
+    pub finality_depth: Option<u64>,
+    /// When set, the Convex incentives of the pool are committed alongside its base yield.
+    pub convex: Option<ConvexRewards>,
+}
+
+impl GuestParams {
+    /// The params the journal commits to through [`GuestParams::digest`]: the guest trusts the
+    /// host with them, and each of them changes the yields, so a verifier has to be able to tell
+    /// which the yields were computed with. A host swapping a price feed for another contract still
+    /// gets a valid proof, but not of these params.
+    pub fn committed(&self) -> CommittedParams {
+        CommittedParams {
+            priceFeed: committed_feed(self.price_feed),
+            referenceFeed: committed_feed(self.reference_feed),
+            rewardFeed: committed_feed(self.convex.map(|convex| convex.reward_feed)),
+            denominatedFrom: self.denominated_from.unwrap_or_default(),
+            finalityDepth: self.finality_depth.unwrap_or_default(),
+            backing: self.pool.backing.committed(),
+            stats: self.stats.committed(),
+        }
+    }
+
+    /// The keccak256 digest of the ABI-encoded [`GuestParams::committed`], as the journal carries
+    /// it in `paramsDigest`.
+    pub fn digest(&self) -> B256 {
+        keccak256(self.committed().abi_encode())
+    }
+
+    /// Asserts that a view call targets a contract the journal commits to: the pool or the LST,
+    /// which it names, the Convex reward pool, which it names when configured, or one of the price
+    /// feeds, which it commits to through [`GuestParams::digest`]. The state of any other contract
+    /// could change the yields without the journal showing it.
+    pub fn verify_target(&self, target: Address) {
+        let feeds = [self.price_feed, self.reference_feed, self.convex.map(|c| c.reward_feed)];
+        let committed = target == self.pool.pool
+            || target == self.pool.lst
+            || self.convex.is_some_and(|convex| convex.reward_pool == target)
+            || feeds.iter().flatten().any(|feed| feed.address == target);
+        assert!(committed, "view call target {target} is not committed to by the journal");
+    }
+}
+
+/// The keccak256 digest of the ABI-encoded `blocks`, as the journal carries the sampled blocks in
+/// `samplesDigest`; a contract computes it as `keccak256(abi.encode(blocks))`. The host may sample
+/// any blocks, e.g. off a `--blocks` list, so a verifier has to be able to tell which it sampled.
+pub fn samples_digest(blocks: &[u64]) -> B256 {
+    keccak256(blocks.to_vec().abi_encode())
+}
+
+fn committed_feed(feed: Option<PriceFeed>) -> CommittedFeed {
+    match feed {
+        Some(feed) => CommittedFeed { feed: feed.address, decimals: feed.decimals },
+        None => CommittedFeed { feed: Address::ZERO, decimals: 0 },
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DexStatsInput {
     pub timestamp: u64,
     pub block_number: u64,
     pub lst_backing: U256,
+    /// Whether the sample was interpolated from its neighbours rather than observed.
+    pub interpolated: bool,
+    /// The pool's TVL in ETH at the sample, see [`pool_tvl`], if queried; the weight of the
+    /// interval the sample starts under [`Aggregation::TvlWeighted`].
+    pub pool_tvl: Option<U256>,
+}
+
+/// Why a set of [`DexStatsInput`]s was rejected. The variants carry the offending samples, so the
+/// message shows what is wrong with the data.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DexStatsError {
+    #[error("input data not long enough")]
+    Empty,
+    #[error("list not sorted: {sample} does not follow {prior}")]
+    NotSorted { prior: SampleContext, sample: SampleContext },
+    #[error("timestamps not increasing: {sample} is not after {prior}")]
+    TimestampNotIncreasing { prior: SampleContext, sample: SampleContext },
+    #[error(
+        "provided data not at correct granularity: {prior} to {sample} is {} blocks, expected \
+         {granularity}",
+        .sample.block_number - .prior.block_number
+    )]
+    Granularity { prior: SampleContext, sample: SampleContext, granularity: u64 },
+    #[error(
+        "too many consecutive missing samples: {missing} between {prior} and {sample}, at most \
+         {max_interpolated} can be interpolated"
+    )]
+    TooManyMissing {
+        prior: SampleContext,
+        sample: SampleContext,
+        missing: u64,
+        max_interpolated: usize,
+    },
+    #[error(
+        "block gap too large: {prior} to {sample} is {} blocks, at most {max_block_gap} allowed",
+        .sample.block_number - .prior.block_number
+    )]
+    BlockGap { prior: SampleContext, sample: SampleContext, max_block_gap: u64 },
+    #[error("samples not at consecutive UTC midnights: {prior} then {sample}")]
+    NotAligned { prior: SampleContext, sample: SampleContext },
+    #[error("rolling window must cover at least two samples, got {window}")]
+    Window { window: usize },
+    #[error("resampled data insufficient: {resampled} samples, need more than {span}")]
+    Insufficient { resampled: usize, span: usize },
+    #[error(
+        "{len} samples are not a whole number of strides of {skip}: the oldest {dropped} would be \
+         dropped"
+    )]
+    SkipRemainder { len: usize, skip: usize, dropped: usize },
+    #[error("excluding the {excluded} largest changes leaves none of the {changes}")]
+    ExcludesAll { excluded: usize, changes: usize },
+    #[error("no pool TVL for {sample}, which the TVL-weighted aggregation needs")]
+    MissingTvl { sample: SampleContext },
+}
+
+/// A sample as reported in a [`DexStatsError`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SampleContext {
+    /// Position of the sample in the input.
+    pub index: usize,
+    pub block_number: u64,
+    pub timestamp: u64,
+    pub lst_backing: U256,
+}
+
+impl SampleContext {
+    fn new(index: usize, input: &DexStatsInput) -> Self {
+        SampleContext {
+            index,
+            block_number: input.block_number,
+            timestamp: input.timestamp,
+            lst_backing: input.lst_backing,
+        }
+    }
+}
+
+impl fmt::Display for SampleContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "sample {} (block {}, timestamp {}, backing {})",
+            self.index,
+            self.block_number,
+            self.timestamp,
+            format_units(self.lst_backing, 18).unwrap()
+        )
+    }
+}
+
+/// Checks that blocks and timestamps strictly increase from `prior` to `item`, at `index`.
+fn check_order(
+    index: usize,
+    prior: &DexStatsInput,
+    item: &DexStatsInput,
+) -> Result<(), DexStatsError> {
+    let context = || (SampleContext::new(index - 1, prior), SampleContext::new(index, item));
+
+    if item.block_number <= prior.block_number {
+        let (prior, sample) = context();
+        return Err(DexStatsError::NotSorted { prior, sample });
+    }
+    if item.timestamp <= prior.timestamp {
+        let (prior, sample) = context();
+        return Err(DexStatsError::TimestampNotIncreasing { prior, sample });
+    }
+
+    Ok(())
+}
+
+/// Checks that `item`, at `index`, may follow `prior`: blocks and timestamps strictly increase and
+/// are `granularity` blocks apart, or a whole number of intervals with at most `max_interpolated`
+/// samples missing in between.
+fn check_successor(
+    index: usize,
+    prior: &DexStatsInput,
+    item: &DexStatsInput,
+    granularity: u64,
+    max_interpolated: usize,
+) -> Result<(), DexStatsError> {
+    check_order(index, prior, item)?;
+    let context = || (SampleContext::new(index - 1, prior), SampleContext::new(index, item));
+
+    // verify that the list is approximately daily
+    // note: we may need to do this differently to account for chain pausing
+    let block_delta = item.block_number - prior.block_number;
+    if block_delta == granularity {
+        return Ok(());
+    }
+    let (prior, sample) = context();
+    if max_interpolated == 0 || granularity == 0 || block_delta % granularity != 0 {
+        return Err(DexStatsError::Granularity { prior, sample, granularity });
+    }
+    let missing = block_delta / granularity - 1;
+    if missing > max_interpolated as u64 {
+        return Err(DexStatsError::TooManyMissing { prior, sample, missing, max_interpolated });
+    }
+
+    Ok(())
+}
+
+/// Checks that `item`, at `index`, is at most `max_block_gap` blocks after `prior`, whatever gaps
+/// interpolation would fill.
+fn check_block_gap(
+    index: usize,
+    prior: &DexStatsInput,
+    item: &DexStatsInput,
+    max_block_gap: Option<u64>,
+) -> Result<(), DexStatsError> {
+    match max_block_gap {
+        Some(max_block_gap)
+            if item.block_number.saturating_sub(prior.block_number) > max_block_gap =>
+        {
+            Err(DexStatsError::BlockGap {
+                prior: SampleContext::new(index - 1, prior),
+                sample: SampleContext::new(index, item),
+                max_block_gap,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Checks that `item`, at `index`, may follow `prior` under [`SampleAlignment::Midnight`]: both are
+/// at a midnight, and `item` at the one after `prior`'s.
+fn check_midnight_successor(
+    index: usize,
+    prior: &DexStatsInput,
+    item: &DexStatsInput,
+) -> Result<(), DexStatsError> {
+    check_order(index, prior, item)?;
+
+    match (midnight_day(prior.timestamp), midnight_day(item.timestamp)) {
+        (Some(prior_day), Some(day)) if day == prior_day + 1 => Ok(()),
+        _ => Err(DexStatsError::NotAligned {
+            prior: SampleContext::new(index - 1, prior),
+            sample: SampleContext::new(index, item),
+        }),
+    }
+}
+
+/// The day, counted from the Unix epoch, at whose UTC midnight `timestamp` is within
+/// [`MIDNIGHT_TOLERANCE_SECONDS`]; `None` if it isn't near a midnight.
+pub fn midnight_day(timestamp: u64) -> Option<u64> {
+    let day = (timestamp + DAY_IN_SECONDS / 2) / DAY_IN_SECONDS;
+
+    (timestamp.abs_diff(day * DAY_IN_SECONDS) <= MIDNIGHT_TOLERANCE_SECONDS).then_some(day)
+}
+
+/// Assembles a series of observed [`DexStatsInput`]s, checking as each sample is pushed that blocks
+/// and timestamps strictly increase and that blocks are `granularity_blocks` apart. The first
+/// violation is reported by [`DexStatsInputBuilder::build`].
+#[derive(Debug, Clone)]
+pub struct DexStatsInputBuilder {
+    granularity_blocks: u64,
+    samples: Vec<DexStatsInput>,
+    error: Option<DexStatsError>,
+}
+
+impl DexStatsInputBuilder {
+    pub fn new(granularity_blocks: u64) -> Self {
+        DexStatsInputBuilder { granularity_blocks, samples: Vec::new(), error: None }
+    }
+
+    /// Appends an observed sample. Samples pushed after a violation are ignored.
+    pub fn push(&mut self, timestamp: u64, block_number: u64, lst_backing: U256) -> &mut Self {
+        if self.error.is_some() {
+            return self;
+        }
+
+        let sample = DexStatsInput {
+            timestamp,
+            block_number,
+            lst_backing,
+            interpolated: false,
+            pool_tvl: None,
+        };
+        if let Some(prior) = self.samples.last() {
+            let index = self.samples.len();
+            if let Err(err) = check_successor(index, prior, &sample, self.granularity_blocks, 0) {
+                self.error = Some(err);
+                return self;
+            }
+        }
+
+        self.samples.push(sample);
+        self
+    }
+
+    pub fn build(self) -> Result<Vec<DexStatsInput>, DexStatsError> {
+        match self.error {
+            Some(err) => Err(err),
+            None if self.samples.is_empty() => Err(DexStatsError::Empty),
+            None => Ok(self.samples),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct DexStatsOutput {
+    /// The headline yield, as committed to the journal.
     pub base_yield: f64,
+    /// Simple annualized rate: the aggregate, by default the mean, of the annualized per-interval
+    /// changes. Under [`ReturnType::Log`] this is the continuously compounded rate.
+    pub base_apr: f64,
+    /// `base_apr` compounded once per sampling interval, i.e. daily at the default granularity.
+    pub base_apy: f64,
+    /// The return over the whole resampled series, `last / first - 1` of the backing. Unlike the
+    /// figures above it is not annualized: it is a holding-period return over the window, which
+    /// for a window of `t` years grows like `(1 + base_apy)^t - 1`, not a yearly rate.
+    pub total_return: f64,
+    /// The annualized per-interval changes that were aggregated into `base_yield`.
+    pub changes: Vec<f64>,
+    /// Sample standard deviation of `changes`; zero when there are fewer than two.
+    pub yield_volatility: f64,
+    /// Standard error of the mean of `changes`, `yield_volatility / sqrt(n)`, for a confidence
+    /// interval around `base_yield`. It treats the interval changes as independent, which
+    /// overlapping rolling windows or autocorrelated returns are not, so it is on the optimistic
+    /// side; zero when there are fewer than two changes.
+    pub yield_std_error: f64,
+    /// Number of resampled points the changes were computed from.
+    pub sample_count: usize,
+    /// Fraction of the sample schedule that was observed rather than interpolated.
+    pub data_quality: f64,
+    /// Number of the oldest samples that fall before the first resampled one and so are left out
+    /// entirely, those at the indices [`dropped_indices`] returns.
+    pub dropped_samples: usize,
+}
+
+/// What the resample does with the oldest samples when `skip` doesn't divide the number of
+/// intervals, so that they come before the first full stride counting back from the most recent
+/// sample.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SkipRemainder {
+    /// Leave them out, reporting how many in [`DexStatsOutput::dropped_samples`].
+    #[default]
+    Drop,
+    /// Fail with [`DexStatsError::SkipRemainder`].
+    Reject,
+}
+
+/// How the per-interval changes that get averaged into the yield are derived from the resampled
+/// series.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChangeMode {
+    /// Annualized change between each pair of consecutive resampled points.
+    PointToPoint,
+    /// Annualized change over a trailing window of `window` resampled points, evaluated at every
+    /// point that has a full window behind it. This produces a smoothed rolling series; a window of
+    /// 2 is equivalent to [`ChangeMode::PointToPoint`].
+    Rolling { window: usize },
+}
+
+/// Day-count convention used to annualize a change observed over a number of seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DayCount {
+    /// 365-day years.
+    #[default]
+    Actual365,
+    /// 360-day years, as used by money markets.
+    Actual360,
+    /// 365.25-day years, averaging over leap years.
+    Actual36525,
+}
+
+/// How a per-interval change in backing is expressed before it is averaged.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReturnType {
+    /// Simple returns, `current / prior - 1`, averaged and annualized linearly.
+    #[default]
+    Simple,
+    /// Log returns, `ln(current / prior)`, which add up across intervals. Their annualized mean is
+    /// a continuously compounded rate, reported back as an effective yield of `exp(rate) - 1`. This
+    /// only differs noticeably from [`ReturnType::Simple`] when the per-interval moves are large,
+    /// e.g. around a depeg or with a sparse sampling schedule: simple returns then overstate the
+    /// yield of a series that moves up and down by the same amount.
+    Log,
+}
+
+/// Where in the window the samples are taken.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SampleAlignment {
+    /// Every `granularity_blocks` blocks, counting back from the head.
+    #[default]
+    Blocks,
+    /// At the block nearest to each UTC midnight, so that the samples line up with daily yields
+    /// reported elsewhere. Missing days can't be interpolated over, and the window ends at the last
+    /// midnight rather than at the head.
+    Midnight,
+    /// At blocks chosen by the caller, e.g. by an external scheduler: the samples only need to be
+    /// in order, and each change is annualized over its own time span. Missing samples can't be
+    /// interpolated over.
+    Irregular,
+}
+
+impl DayCount {
+    pub fn seconds_per_year(&self) -> f64 {
+        let days = match self {
+            DayCount::Actual365 => 365.0,
+            DayCount::Actual360 => 360.0,
+            DayCount::Actual36525 => 365.25,
+        };
+        days * DAY_IN_SECONDS as f64
+    }
+}
+
+/// Annualizes the relative change from `prior` to `current` observed over `time_delta_seconds`,
+/// without compounding: a 1% change over a 365th of a year annualizes to 3.65 under
+/// [`DayCount::Actual365`].
+///
+/// Panics if `time_delta_seconds` is zero.
+pub fn annualized_change(
+    prior: f64,
+    current: f64,
+    time_delta_seconds: u64,
+    day_count: DayCount,
+) -> f64 {
+    assert!(time_delta_seconds > 0, "zero time delta");
+    let annualizer = day_count.seconds_per_year() / time_delta_seconds as f64;
+
+    (current / prior - 1.0) * annualizer
+}
+
+/// Annualizes the log return from `prior` to `current` observed over `time_delta_seconds`, giving
+/// a continuously compounded annual rate.
+///
+/// Panics if `time_delta_seconds` is zero.
+pub fn annualized_log_change(
+    prior: f64,
+    current: f64,
+    time_delta_seconds: u64,
+    day_count: DayCount,
+) -> f64 {
+    assert!(time_delta_seconds > 0, "zero time delta");
+    let annualizer = day_count.seconds_per_year() / time_delta_seconds as f64;
+
+    (current / prior).ln() * annualizer
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DexStatsParams {
+    /// Block distance between consecutive samples.
+    pub granularity_blocks: u64,
+    /// Keep every `skip`-th input, anchored at the most recent one.
+    pub skip: usize,
+    /// What to do with the oldest inputs left over by `skip`.
+    pub skip_remainder: SkipRemainder,
+    pub mode: ChangeMode,
+    /// Maximum number of consecutive missing samples to fill by linear interpolation; longer gaps
+    /// are rejected. Zero disables interpolation.
+    pub max_interpolated: usize,
+    pub day_count: DayCount,
+    pub return_type: ReturnType,
+    pub alignment: SampleAlignment,
+    pub aggregation: Aggregation,
+    /// Largest block distance between consecutive samples accepted at all, bounding how much of
+    /// the window interpolation may make up for; `None` for no bound.
+    pub max_block_gap: Option<u64>,
+    /// Time the series by its block numbers, see [`pseudo_timed`], when any two consecutive
+    /// samples are less than this many seconds apart; `None` to fail on timestamps that don't
+    /// increase.
+    pub min_time_delta: Option<u64>,
+    /// Merge a resampled interval shorter than this many seconds into the one after it, see
+    /// [`merge_short_intervals`]; `None` to annualize every interval however short.
+    pub min_interval_seconds: Option<u64>,
+}
+
+impl Default for DexStatsParams {
+    fn default() -> Self {
+        DexStatsParams {
+            granularity_blocks: BLOCK_GRANULARITY,
+            skip: 1,
+            skip_remainder: SkipRemainder::default(),
+            mode: ChangeMode::PointToPoint,
+            max_interpolated: 0,
+            day_count: DayCount::default(),
+            return_type: ReturnType::default(),
+            alignment: SampleAlignment::default(),
+            aggregation: Aggregation::default(),
+            max_block_gap: None,
+            min_time_delta: None,
+            min_interval_seconds: None,
+        }
+    }
+}
+
+impl DexStatsParams {
+    /// The params as the journal commits to them through [`GuestParams::digest`]: each of them
+    /// changes the yield, so a verifier has to be able to tell which methodology produced it.
+    pub fn committed(&self) -> CommittedStats {
+        let (aggregation, ema_half_life_seconds) = match self.aggregation {
+            Aggregation::Mean => (0, 0),
+            Aggregation::Median => (1, 0),
+            Aggregation::Ema { half_life_seconds } => (2, half_life_seconds),
+            Aggregation::TvlWeighted => (3, 0),
+        };
+
+        CommittedStats {
+            skip: self.skip as u64,
+            skipRemainder: self.skip_remainder as u8,
+            rollingWindow: match self.mode {
+                ChangeMode::PointToPoint => 0,
+                ChangeMode::Rolling { window } => window as u64,
+            },
+            maxInterpolated: self.max_interpolated as u64,
+            dayCount: self.day_count as u8,
+            returnType: self.return_type as u8,
+            alignment: self.alignment as u8,
+            aggregation,
+            emaHalfLifeSeconds: ema_half_life_seconds,
+            maxBlockGap: self.max_block_gap.unwrap_or_default(),
+            minTimeDelta: self.min_time_delta.unwrap_or_default(),
+            minIntervalSeconds: self.min_interval_seconds.unwrap_or_default(),
+        }
+    }
 }
 
 pub fn calculate_dex_stats(input: &[DexStatsInput], skip: usize) -> DexStatsOutput {
+    calculate_dex_stats_with(input, &DexStatsParams { skip, ..Default::default() })
+}
+
+/// Like [`try_calculate_dex_stats`], but panics on invalid input.
+pub fn calculate_dex_stats_with(
+    input: &[DexStatsInput],
+    params: &DexStatsParams,
+) -> DexStatsOutput {
+    try_calculate_dex_stats(input, params).unwrap_or_else(|err| panic!("{err}"))
+}
+
+pub fn try_calculate_dex_stats(
+    input: &[DexStatsInput],
+    params: &DexStatsParams,
+) -> Result<DexStatsOutput, DexStatsError> {
+    // the interpolated samples take their TVL from the observed ones around them
+    if params.aggregation == Aggregation::TvlWeighted {
+        if let Some(index) = input.iter().position(|item| item.pool_tvl.is_none()) {
+            return Err(DexStatsError::MissingTvl {
+                sample: SampleContext::new(index, &input[index]),
+            });
+        }
+    }
+
+    try_calculate_dex_stats_with_aggregator(input, params, &params.aggregation)
+}
+
+/// Like [`try_calculate_dex_stats`], but combines the changes with `aggregator` instead of
+/// `params.aggregation`.
+pub fn try_calculate_dex_stats_with_aggregator(
+    input: &[DexStatsInput],
+    params: &DexStatsParams,
+    aggregator: &impl Aggregator,
+) -> Result<DexStatsOutput, DexStatsError> {
     // unchecked: verify that the provided history is as long as it can be
     // we want X days of data, but that may not exist. If it doesn't exist we need to check contract creation
     // assumptions: provided data has already been verified in the guest program
 
-    assert!(input.len() > 0, "input data not long enough");
+    if input.is_empty() {
+        return Err(DexStatsError::Empty);
+    }
+    let retimed =
+        params.min_time_delta.and_then(|min_time_delta| pseudo_timed(input, min_time_delta));
+    let input = retimed.as_deref().unwrap_or(input);
+    for (index, (prior, item)) in input.iter().zip(&input[1..]).enumerate() {
+        check_block_gap(index + 1, prior, item, params.max_block_gap)?;
+        match params.alignment {
+            SampleAlignment::Blocks => check_successor(
+                index + 1,
+                prior,
+                item,
+                params.granularity_blocks,
+                params.max_interpolated,
+            )?,
+            SampleAlignment::Midnight => check_midnight_successor(index + 1, prior, item)?,
+            SampleAlignment::Irregular => check_order(index + 1, prior, item)?,
+        }
+    }
+
+    // only a block schedule has a stride to fill gaps at
+    let input = &match params.alignment {
+        SampleAlignment::Blocks => fill_gaps(input, params.granularity_blocks),
+        SampleAlignment::Midnight | SampleAlignment::Irregular => input.to_vec(),
+    };
+
+    let dropped_samples = dropped_indices(input.len(), params.skip).len();
+    if dropped_samples > 0 && params.skip_remainder == SkipRemainder::Reject {
+        return Err(DexStatsError::SkipRemainder {
+            len: input.len(),
+            skip: params.skip,
+            dropped: dropped_samples,
+        });
+    }
+    let resampled = resample(input, params.skip);
+    let resampled = match params.min_interval_seconds {
+        Some(min_seconds) => merge_short_intervals(resampled, min_seconds),
+        None => resampled,
+    };
+
+    // the span, in resampled points, over which each change is measured
+    let span = match params.mode {
+        ChangeMode::PointToPoint => 1,
+        ChangeMode::Rolling { window } if window < 2 => {
+            return Err(DexStatsError::Window { window });
+        }
+        ChangeMode::Rolling { window } => window - 1,
+    };
+    if resampled.len() <= span {
+        return Err(DexStatsError::Insufficient { resampled: resampled.len(), span });
+    }
+
+    // a genuinely flat series has exactly zero yield; don't let the float conversions leave a
+    // residual of rounding noise
+    let flat = resampled.windows(2).all(|pair| pair[0].lst_backing == pair[1].lst_backing);
+
+    let mut interval_changes = Vec::with_capacity(resampled.len() - span);
+    for (prior, item) in resampled.iter().zip(resampled[span..].iter()) {
+        let annualized = if flat {
+            0.0
+        } else {
+            let time_delta_seconds = item.timestamp - prior.timestamp;
+            let prior_backing = u256_to_f64(prior.lst_backing, 18);
+            let current = u256_to_f64(item.lst_backing, 18);
+            let annualize = match params.return_type {
+                ReturnType::Simple => annualized_change,
+                ReturnType::Log => annualized_log_change,
+            };
+            annualize(prior_backing, current, time_delta_seconds, params.day_count)
+        };
+        interval_changes.push(IntervalChange {
+            start_timestamp: prior.timestamp,
+            end_timestamp: item.timestamp,
+            annualized,
+            start_tvl: prior.pool_tvl.map(|tvl| u256_to_f64(tvl, 18)),
+        });
+    }
+    let changes: Vec<f64> = interval_changes.iter().map(|change| change.annualized).collect();
+
+    aggregator.check(interval_changes.len())?;
+    let rate = aggregator.aggregate(&interval_changes);
+    let (base_yield, base_apr, base_apy) = match params.return_type {
+        ReturnType::Simple => {
+            // compound at the sampling frequency: the number of average resampled intervals per
+            // year
+            let first = resampled.first().unwrap();
+            let last = resampled.last().unwrap();
+            let interval_seconds =
+                (last.timestamp - first.timestamp) as f64 / (resampled.len() - 1) as f64;
+            let periods_per_year = params.day_count.seconds_per_year() / interval_seconds;
+            (rate, rate, apr_to_apy(rate, periods_per_year))
+        }
+        ReturnType::Log => {
+            // the aggregate is a continuously compounded rate; exponentiate back to an effective
+            // yield
+            let effective = rate.exp_m1();
+            (effective, rate, effective)
+        }
+    };
+
+    let total_return = if flat {
+        0.0
+    } else {
+        let first = resampled.first().unwrap();
+        let last = resampled.last().unwrap();
+        u256_to_f64(last.lst_backing, 18) / u256_to_f64(first.lst_backing, 18) - 1.0
+    };
+
+    let yield_volatility = if changes.len() > 1 {
+        let mean_change = changes.iter().sum::<f64>() / changes.len() as f64;
+        let sum_sq = changes.iter().map(|change| (change - mean_change).powi(2)).sum::<f64>();
+        (sum_sq / (changes.len() - 1) as f64).sqrt()
+    } else {
+        0.0
+    };
+    let yield_std_error = yield_volatility / (changes.len() as f64).sqrt();
+
+    let observed = input.iter().filter(|item| !item.interpolated).count();
+    let data_quality = observed as f64 / input.len() as f64;
+
+    Ok(DexStatsOutput {
+        base_yield,
+        base_apr,
+        base_apy,
+        total_return,
+        changes,
+        yield_volatility,
+        yield_std_error,
+        sample_count: resampled.len(),
+        data_quality,
+        dropped_samples,
+    })
+}
+
+/// The base yield recomputed without the `excluded` per-interval changes of the largest magnitude,
+/// for reporting how sensitive the yield is to its biggest moves, e.g. "3.5%, or 3.2% excluding
+/// the two largest". See [`ExcludingLargest`].
+pub fn yield_excluding_largest(
+    input: &[DexStatsInput],
+    params: &DexStatsParams,
+    excluded: usize,
+) -> Result<f64, DexStatsError> {
+    let aggregator = ExcludingLargest { inner: params.aggregation, excluded };
+
+    Ok(try_calculate_dex_stats_with_aggregator(input, params, &aggregator)?.base_yield)
+}
+
+/// Fixed-stride resample: keeps every `skip`-th item, counting back from the most recent one, in
+/// ascending order. See [`resample_indices`] for which items are kept.
+pub fn resample(input: &[DexStatsInput], skip: usize) -> Vec<&DexStatsInput> {
+    resample_indices(input.len(), skip).map(|index| &input[index]).collect()
+}
+
+/// Merges each interval of `resampled` shorter than `min_seconds` into the one after it, by leaving
+/// out the sample between them; a short last interval is merged into the one before it instead, so
+/// the window still ends at the head sample. Annualizing scales a change by the inverse of its
+/// interval, so that the backing's rounding noise over a few seconds comes out as a yield large
+/// enough to dominate the mean of all the others.
+///
+/// The floor trades responsiveness for stability: a merged interval's change is measured over
+/// longer, and a real move within it is spread over the whole. Keep it well below the sampling
+/// interval, so that only the outliers of an irregular series are merged.
+pub fn merge_short_intervals(
+    resampled: Vec<&DexStatsInput>,
+    min_seconds: u64,
+) -> Vec<&DexStatsInput> {
+    let Some((&last, rest)) = resampled.split_last() else {
+        return resampled;
+    };
+    let mut merged: Vec<&DexStatsInput> = Vec::with_capacity(resampled.len());
+    for &item in rest {
+        match merged.last() {
+            Some(prior) if item.timestamp - prior.timestamp < min_seconds => {}
+            _ => merged.push(item),
+        }
+    }
+    // the head sample stays, at the expense of a sample too close before it
+    if merged.len() > 1 && last.timestamp - merged[merged.len() - 1].timestamp < min_seconds {
+        merged.pop();
+    }
+    merged.push(last);
+
+    merged
+}
+
+/// The ascending indices [`resample`] keeps out of `len` items: the last index `len - 1` and every
+/// `skip`-th index before it, down to the first one that is non-negative, `(len - 1) % skip`.
+///
+/// Panics if `skip` is zero.
+pub fn resample_indices(len: usize, skip: usize) -> impl Iterator<Item = usize> {
+    assert!(skip > 0, "skip must be positive");
+    let first = match len.checked_sub(1) {
+        Some(last) => last % skip,
+        None => 0,
+    };
+
+    (first..len).step_by(skip)
+}
+
+/// The indices of the oldest of `len` items, before the first one [`resample_indices`] keeps,
+/// which the resample leaves out entirely rather than stepping over. Empty when `skip` divides
+/// the `len - 1` intervals.
+///
+/// Panics if `skip` is zero.
+pub fn dropped_indices(len: usize, skip: usize) -> Range<usize> {
+    0..resample_indices(len, skip).next().unwrap_or(0)
+}
+
+/// Compounds a simple annual rate `periods_per_year` times a year.
+pub fn apr_to_apy(apr: f64, periods_per_year: f64) -> f64 {
+    (1.0 + apr / periods_per_year).powf(periods_per_year) - 1.0
+}
 
-    for (index, item) in input[1..].iter().enumerate() {
-        let prior_block_number = input[index].block_number;
+impl fmt::Display for DexStatsOutput {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "DexStats: APR={:.2}%, APY={:.2}%, totalReturn={:.4}% (volatility={:.2}%, samples={}, \
+             dataQuality={:.0}%)",
+            self.base_apr * 100.0,
+            self.base_apy * 100.0,
+            self.total_return * 100.0,
+            self.yield_volatility * 100.0,
+            self.sample_count,
+            self.data_quality * 100.0
+        )
+    }
+}
 
-        // verify that the list is sorted
-        assert!(item.block_number > prior_block_number, "list not sorted");
+impl DexStatsOutput {
+    /// A Sharpe-like ratio of the yield, its excess over `risk_free_apr` per unit of
+    /// `yield_volatility`, for comparing pools by their risk-adjusted yield. `None` without any
+    /// volatility, as for a flat series or fewer than two changes, where the ratio is undefined.
+    pub fn sharpe_ratio(&self, risk_free_apr: f64) -> Option<f64> {
+        (self.yield_volatility > 0.0)
+            .then(|| (self.base_yield - risk_free_apr) / self.yield_volatility)
+    }
 
-        // verify that the list is approximately daily
-        // note: we may need to do this differently to account for chain pausing
-        let block_delta = item.block_number - prior_block_number;
-        assert!(block_delta == BLOCK_GRANULARITY, "provided data not at correct granularity")
+    /// Compares these stats against `other`; positive differences mean `self` is higher.
+    pub fn diff(&self, other: &Self) -> DexStatsDiff {
+        DexStatsDiff {
+            base_yield: Delta::between(self.base_yield, other.base_yield),
+            base_apr: Delta::between(self.base_apr, other.base_apr),
+            base_apy: Delta::between(self.base_apy, other.base_apy),
+            yield_volatility: Delta::between(self.yield_volatility, other.yield_volatility),
+            sample_count: self.sample_count as i64 - other.sample_count as i64,
+            data_quality: Delta::between(self.data_quality, other.data_quality),
+        }
     }
+}
 
-    // get every skip item to calculate rolling change
-    let mut resampled = Vec::new();
-    for (index, item) in input.iter().rev().enumerate() {
-        if index % skip == 0 {
-            resampled.push(item);
+/// The difference between two [`DexStatsOutput`]s, see [`DexStatsOutput::diff`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DexStatsDiff {
+    pub base_yield: Delta,
+    pub base_apr: Delta,
+    pub base_apy: Delta,
+    pub yield_volatility: Delta,
+    pub sample_count: i64,
+    pub data_quality: Delta,
+}
+
+impl DexStatsDiff {
+    /// Describes the yield difference in basis points, e.g. "pool A yields 12.5 bps more than pool
+    /// B".
+    pub fn describe(&self, name: &str, other_name: &str) -> String {
+        let bps = self.base_yield.absolute * 10_000.0;
+        if bps == 0.0 {
+            return format!("{name} yields the same as {other_name}");
         }
+        let direction = if bps > 0.0 { "more" } else { "less" };
+
+        format!("{name} yields {:.1} bps {direction} than {other_name}", bps.abs())
+    }
+}
+
+/// Absolute and relative difference of a single value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Delta {
+    pub absolute: f64,
+    /// The absolute difference as a fraction of the other value; `None` if that is zero.
+    pub relative: Option<f64>,
+}
+
+impl Delta {
+    fn between(value: f64, other: f64) -> Self {
+        let absolute = value - other;
+        let relative = (other != 0.0).then(|| absolute / other.abs());
+
+        Delta { absolute, relative }
     }
-    resampled.reverse();
+}
+
+/// Asserts that the window ends at the sample taken at `commitment_block`, so that the yield is
+/// computed up to exactly the state the output commits to.
+pub fn verify_window_end(input: &[DexStatsInput], commitment_block: u64) {
+    let end = input.last().expect("input data not long enough").block_number;
+    assert!(
+        end == commitment_block,
+        "window ends at block {end}, not at the committed block {commitment_block}"
+    );
+}
+
+/// Asserts that a window aligned to [`SampleAlignment::Midnight`] ends at the sample of the last
+/// UTC midnight up to `head_timestamp`, the one of the block the output commits to, so that no
+/// trailing day is left out of the yield.
+pub fn verify_midnight_window_end(input: &[DexStatsInput], head_timestamp: u64) {
+    let end = input.last().expect("input data not long enough");
+    let last_midnight = head_timestamp / DAY_IN_SECONDS;
+    assert!(
+        midnight_day(end.timestamp) == Some(last_midnight),
+        "window ends at block {}, not at the last midnight before the committed block",
+        end.block_number
+    );
+}
+
+/// The value of a pool's reserves in ETH: the ETH balance, coin 0, plus the LST balance, coin 1,
+/// valued at its backing.
+pub fn pool_tvl(eth_balance: U256, lst_balance: U256, lst_backing: U256) -> U256 {
+    eth_balance + wad::mul_wad(lst_balance, lst_backing)
+}
 
-    assert!(resampled.len() > 0, "resampled data insufficient");
+/// Drops the samples within `depth` blocks of `head`, which may not be finalized yet.
+pub fn exclude_unfinalized(input: &[DexStatsInput], head: u64, depth: u64) -> &[DexStatsInput] {
+    let end = input.partition_point(|item| item.block_number.saturating_add(depth) <= head);
 
-    // TODO: switch to an ema
-    let mut total = 0.0;
-    let mut prior_backing = u256_to_f64(resampled.first().unwrap().lst_backing, 18);
-    let mut prior_timestamp = resampled.first().unwrap().timestamp;
-    for item in resampled[1..].iter() {
-        let time_delta_seconds = item.timestamp - prior_timestamp;
-        let annualizer = ((DAY_IN_SECONDS * 365) / time_delta_seconds) as f64;
+    &input[..end]
+}
+
+/// Drops the samples before block `from`.
+pub fn exclude_before(input: &[DexStatsInput], from: u64) -> &[DexStatsInput] {
+    let start = input.partition_point(|item| item.block_number < from);
+
+    &input[start..]
+}
 
-        let current = u256_to_f64(item.lst_backing, 18);
-        let change = (current / prior_backing - 1.0) * annualizer;
-        total += change;
-        prior_backing = current;
-        prior_timestamp = item.timestamp;
+/// The samples timed by their block numbers, [`PSEUDO_SECONDS_PER_BLOCK`] per block on from the
+/// first sample's timestamp, if any two consecutive ones are less than `min_time_delta` seconds
+/// apart; `None` if all are far enough apart to keep their timestamps. On an instant-mined chain,
+/// e.g. a local Anvil fork, many blocks share a timestamp, so that the changes between them can't
+/// be annualized over their time. The pseudo-time is only as good as the assumed block time.
+pub fn pseudo_timed(input: &[DexStatsInput], min_time_delta: u64) -> Option<Vec<DexStatsInput>> {
+    let first = input.first()?;
+    input
+        .windows(2)
+        .any(|pair| pair[1].timestamp.saturating_sub(pair[0].timestamp) < min_time_delta)
+        .then(|| {
+            input
+                .iter()
+                .map(|item| DexStatsInput {
+                    timestamp: first.timestamp
+                        + item.block_number.saturating_sub(first.block_number)
+                            * PSEUDO_SECONDS_PER_BLOCK,
+                    ..item.clone()
+                })
+                .collect()
+        })
+}
+
+/// Fills the missing samples of a validated series by linear interpolation between the observed
+/// samples around them.
+fn fill_gaps(input: &[DexStatsInput], granularity: u64) -> Vec<DexStatsInput> {
+    let mut filled = vec![input[0].clone()];
+    for (prior, item) in input.iter().zip(&input[1..]) {
+        let steps = (item.block_number - prior.block_number) / granularity;
+        for step in 1..steps {
+            filled.push(interpolate(prior, item, step, steps));
+        }
+        filled.push(item.clone());
     }
 
-    let base_yield = total / (resampled.len() - 1) as f64;
+    filled
+}
+
+/// Returns the point `step / steps` of the way from `prior` to `next`.
+fn interpolate(
+    prior: &DexStatsInput,
+    next: &DexStatsInput,
+    step: u64,
+    steps: u64,
+) -> DexStatsInput {
+    let timestamp = prior.timestamp + next.timestamp.saturating_sub(prior.timestamp) * step / steps;
+    let block_number = prior.block_number + (next.block_number - prior.block_number) * step / steps;
+
+    let (step, steps) = (U256::from(step), U256::from(steps));
+    let lerp = |prior: U256, next: U256| {
+        if next >= prior {
+            prior + (next - prior) * step / steps
+        } else {
+            prior - (prior - next) * step / steps
+        }
+    };
+    let lst_backing = lerp(prior.lst_backing, next.lst_backing);
+    let pool_tvl = prior.pool_tvl.zip(next.pool_tvl).map(|(prior, next)| lerp(prior, next));
 
-    DexStatsOutput { base_yield }
+    DexStatsInput { timestamp, block_number, lst_backing, interpolated: true, pool_tvl }
 }
 
 fn u256_to_f64(value: U256, units: u8) -> f64 {
@@ -122,42 +1341,1076 @@ fn u256_to_f64(value: U256, units: u8) -> f64 {
     str_fmt.parse::<f64>().unwrap()
 }
 
+/// Converts a yield into the signed 18-decimal fixed-point the journal commits to. Negative yields
+/// are kept as such rather than wrapping around.
+pub fn yield_to_wad(value: f64) -> I256 {
+    parse_units(&value.to_string(), 18).unwrap().into()
+}
+
+/// Converts a yield committed by [`yield_to_wad`] back.
+pub fn wad_to_yield(value: I256) -> f64 {
+    format_units(value, 18).unwrap().parse().unwrap()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn it_should_work() {
-        let b = U256::from_str_radix("100", 10).unwrap();
-        println!("b={:?}", b);
+    fn it_should_convert_u256_to_the_nearest_f64() {
+        let cases: [(U256, u8, f64); 9] = [
+            (U256::ZERO, 18, 0.0),
+            (U256::from(100), 0, 100.0),
+            (U256::from(1), 18, 1e-18),
+            (U256::from(1_050_000_000_000_000_000_u64), 18, 1.05),
+            (U256::from(1_087_261_467_917_236_491_u64), 18, 1.0872614679172365),
+            (U256::from(251_234_567_890_u64), 8, 2512.3456789),
+            (U256::from(123_456_789), 6, 123.456789),
+            // 2^53 + 1 is the first integer an f64 can't hold, and rounds to even
+            (U256::from(9_007_199_254_740_993_u64), 0, 9_007_199_254_740_992.0),
+            (U256::MAX, 18, 1.157920892373162e59),
+        ];
+
+        // exact, as the conversion should round correctly
+        for (value, units, expected) in cases {
+            assert_eq!(u256_to_f64(value, units), expected, "{value} with {units} decimals");
+        }
     }
 
     #[test]
-    fn it_should_calculate_backing_avg() {
-        let inputs = build_input(1716129570, &vec![100.0, 100.01, 100.10, 100.15, 100.25]);
+    fn it_should_accept_configured_targets() {
+        let pool = PoolConfig::CBETH_ETH;
+        pool.verify_target(CBETH_ADDRESS);
+        pool.verify_target(CURVE_POOL_ADDRESS);
+    }
 
-        let expected = 0.2279345389;
+    #[test]
+    #[should_panic(expected = "is not part of the pool config")]
+    fn it_should_reject_substituted_targets() {
+        PoolConfig::CBETH_ETH.verify_target(CBETH_CHAINLINK_ORACLE);
+    }
 
-        let res = calculate_dex_stats(&inputs, 1);
+    #[test]
+    #[should_panic(expected = "is not part of the pool config")]
+    fn it_should_reject_the_uncommitted_lp_token() {
+        PoolConfig::CBETH_ETH.verify_target(CURVE_LP_ADDRESS);
+    }
+
+    #[test]
+    #[should_panic(expected = "is not committed to by the journal")]
+    fn it_should_reject_an_oracle_the_journal_does_not_commit_to() {
+        let feed = PriceFeed { address: CBETH_CHAINLINK_ORACLE, decimals: 8 };
+        let params = GuestParams { price_feed: Some(feed), ..Default::default() };
+        params.verify_target(CBETH_CHAINLINK_ORACLE);
+        params.verify_target(CURVE_POOL_ADDRESS);
+
+        GuestParams::default().verify_target(CBETH_CHAINLINK_ORACLE);
+    }
+
+    #[test]
+    fn it_should_commit_the_sampling_parameters() {
+        let stats = fixture::journal();
+
+        let decoded = LstDexStats::abi_decode(&stats.abi_encode(), true).unwrap();
+        assert_eq!(decoded.pool, CURVE_POOL_ADDRESS);
+        assert_eq!(decoded.lst, CBETH_ADDRESS);
+        assert_eq!(decoded.granularityBlocks, 7200);
+        assert_eq!(decoded.windowBlocks, 21600);
+        assert_eq!(decoded.paramsDigest, GuestParams::default().digest());
+        assert_eq!(decoded.samplesDigest, stats.samplesDigest);
+        assert!(decoded.to_string().ends_with(&format!(
+            "granularityBlocks=7200, windowBlocks=21600, paramsDigest={}, samplesDigest={})",
+            GuestParams::default().digest(),
+            stats.samplesDigest
+        )));
+    }
+
+    #[test]
+    fn it_should_verify_the_committed_parameters() {
+        let stats = fixture::journal();
+        let expected = ExpectedParams {
+            pool: PoolConfig::CBETH_ETH,
+            head_block: 19_900_000,
+            window_blocks: BLOCKS_TO_QUERY,
+            granularity_blocks: BLOCK_GRANULARITY,
+            params_digest: GuestParams::default().digest(),
+            samples_digest: stats.samplesDigest,
+        };
+        stats.verify_parameters(&expected).unwrap();
+
+        let reth = ExpectedParams { pool: PoolConfig::RETH_ETH, ..expected };
+        assert_eq!(
+            stats.verify_parameters(&reth),
+            Err(ParamsMismatch::Pool {
+                expected: RETH_CURVE_POOL_ADDRESS,
+                committed: CURVE_POOL_ADDRESS
+            })
+        );
+        let earlier = ExpectedParams { head_block: 19_899_999, ..expected };
+        assert_eq!(
+            stats.verify_parameters(&earlier).unwrap_err().to_string(),
+            "journal commits to block 19900000, expected 19899999"
+        );
+        let week = ExpectedParams { window_blocks: 7 * BLOCK_GRANULARITY, ..expected };
+        assert_eq!(
+            stats.verify_parameters(&week),
+            Err(ParamsMismatch::Window { expected: 50_400, committed: 21_600 })
+        );
+        let hourly = ExpectedParams { granularity_blocks: 300, ..expected };
+        assert_eq!(
+            stats.verify_parameters(&hourly),
+            Err(ParamsMismatch::Granularity { expected: 300, committed: 7200 })
+        );
+
+        // the same yield over a USD feed is another methodology
+        let feed = PriceFeed { address: CBETH_CHAINLINK_ORACLE, decimals: 8 };
+        let usd = GuestParams { price_feed: Some(feed), ..Default::default() };
+        let usd = ExpectedParams { params_digest: usd.digest(), ..expected };
+        assert!(matches!(stats.verify_parameters(&usd), Err(ParamsMismatch::Params { .. })));
+        // as is the same window sampled at a cherry-picked block
+        let picked = samples_digest(&[19_878_400, 19_885_600, 19_893_000, 19_900_000]);
+        let picked = ExpectedParams { samples_digest: picked, ..expected };
+        assert!(matches!(stats.verify_parameters(&picked), Err(ParamsMismatch::Samples { .. })));
+    }
+
+    #[test]
+    fn it_should_commit_to_the_price_feeds() {
+        let feed = PriceFeed { address: CBETH_CHAINLINK_ORACLE, decimals: 8 };
+        let params = GuestParams { price_feed: Some(feed), ..Default::default() };
+        assert_eq!(
+            params.committed().priceFeed,
+            CommittedFeed { feed: CBETH_CHAINLINK_ORACLE, decimals: 8 }
+        );
+        assert_eq!(params.committed().referenceFeed.feed, Address::ZERO);
+
+        // every feed, its address as well as its decimals, changes the digest
+        let digests = [
+            GuestParams::default().digest(),
+            params.digest(),
+            GuestParams { price_feed: Some(PriceFeed { decimals: 18, ..feed }), ..params.clone() }
+                .digest(),
+            GuestParams { reference_feed: Some(feed), ..params.clone() }.digest(),
+            // the samples before the feeds exist are left out of the yield
+            GuestParams { denominated_from: Some(19_000_000), ..params.clone() }.digest(),
+            // as are the samples within the finality depth of the head
+            GuestParams { finality_depth: Some(64), ..params.clone() }.digest(),
+            GuestParams {
+                convex: Some(ConvexRewards { reward_pool: Address::ZERO, reward_feed: feed }),
+                ..params.clone()
+            }
+            .digest(),
+        ];
+        for (i, digest) in digests.iter().enumerate() {
+            assert!(!digests[..i].contains(digest), "digest {i} repeats");
+        }
+    }
+
+    #[test]
+    fn it_should_commit_to_the_backing_strategy() {
+        use backing::ReferenceBalance;
+
+        let reference = ReferenceBalance {
+            holder: Address::repeat_byte(0x22),
+            principal: U256::from(1_000_000_000_000_000_000_u64),
+        };
+        let with = |backing| GuestParams {
+            pool: PoolConfig { backing, ..PoolConfig::CBETH_ETH },
+            ..Default::default()
+        };
+        assert_eq!(
+            with(BackingKind::ReferenceBalance(reference)).committed().backing,
+            CommittedBacking { kind: 3, holder: reference.holder, principal: reference.principal }
+        );
+        assert_eq!(
+            with(BackingKind::Rebase).committed().backing,
+            CommittedBacking { kind: 1, holder: Address::ZERO, principal: U256::ZERO }
+        );
+
+        // every strategy, and the holder and principal of a reference balance, changes the digest
+        let digests = [
+            with(BackingKind::ExchangeRate).digest(),
+            with(BackingKind::Rebase).digest(),
+            with(BackingKind::RedemptionRate).digest(),
+            with(BackingKind::ReferenceBalance(reference)).digest(),
+            with(BackingKind::ReferenceBalance(ReferenceBalance {
+                holder: Address::repeat_byte(0x33),
+                ..reference
+            }))
+            .digest(),
+            with(BackingKind::ReferenceBalance(ReferenceBalance {
+                principal: reference.principal * U256::from(2),
+                ..reference
+            }))
+            .digest(),
+        ];
+        for (i, digest) in digests.iter().enumerate() {
+            assert!(!digests[..i].contains(digest), "digest {i} repeats");
+        }
+    }
+
+    #[test]
+    fn it_should_commit_to_the_stats_params() {
+        let default = DexStatsParams::default();
+        let ema = Aggregation::Ema { half_life_seconds: 7 * DAY_IN_SECONDS };
+        let params = DexStatsParams { aggregation: ema, ..default.clone() };
+        assert_eq!(params.committed().aggregation, 2);
+        assert_eq!(params.committed().emaHalfLifeSeconds, 7 * DAY_IN_SECONDS);
+
+        // every param but the journaled granularity changes the digest
+        let digests: Vec<_> = [
+            default.clone(),
+            params,
+            DexStatsParams { skip: 2, ..default.clone() },
+            DexStatsParams { skip_remainder: SkipRemainder::Reject, ..default.clone() },
+            DexStatsParams { mode: ChangeMode::Rolling { window: 3 }, ..default.clone() },
+            DexStatsParams { max_interpolated: 1, ..default.clone() },
+            DexStatsParams { day_count: DayCount::Actual360, ..default.clone() },
+            DexStatsParams { return_type: ReturnType::Log, ..default.clone() },
+            DexStatsParams { alignment: SampleAlignment::Midnight, ..default.clone() },
+            DexStatsParams { aggregation: Aggregation::TvlWeighted, ..default.clone() },
+            DexStatsParams { max_block_gap: Some(BLOCK_GRANULARITY), ..default.clone() },
+            DexStatsParams { min_time_delta: Some(12), ..default.clone() },
+            DexStatsParams { min_interval_seconds: Some(3600), ..default.clone() },
+        ]
+        .into_iter()
+        .map(|stats| GuestParams { stats, ..Default::default() }.digest())
+        .collect();
+        for (i, digest) in digests.iter().enumerate() {
+            assert!(!digests[..i].contains(digest), "digest {i} repeats");
+        }
+    }
+
+    #[test]
+    fn it_should_separate_the_incentive_yield() {
+        let reward_pool = Address::repeat_byte(0xcc);
+        let stats = LstDexStats {
+            rewardPool: reward_pool,
+            incentiveYield: U256::from(12_000_000_000_000_000_u64),
+            ..fixture::journal()
+        };
+
+        assert_eq!(stats.combined_yield(), Some(yield_to_wad(0.043)));
+        assert!(stats.to_string().starts_with(
+            "LstDexStats: baseYield=3.10%, incentiveYield=1.20%, combinedYield=4.30%"
+        ));
+        assert!(stats.to_string().contains(&format!("rewardPool={reward_pool}")));
+    }
+
+    #[test]
+    fn it_should_print_the_components_of_a_combined_yield_that_does_not_fit() {
+        let stats = LstDexStats {
+            baseYield: I256::MAX,
+            rewardPool: Address::repeat_byte(0xcc),
+            incentiveYield: U256::from(12_000_000_000_000_000_u64),
+            ..fixture::journal()
+        };
+        assert_eq!(stats.combined_yield(), None);
+        assert!(stats.to_string().contains("incentiveYield=1.20%"));
+        assert!(!stats.to_string().contains("combinedYield"));
+
+        // as is an incentive yield beyond any signed yield
+        let stats = LstDexStats { incentiveYield: U256::MAX, ..fixture::journal() };
+        assert_eq!(stats.combined_yield(), None);
+    }
+
+    #[test]
+    fn it_should_commit_a_negative_yield() {
+        let committed = yield_to_wad(-0.0125);
+        assert!(committed.is_negative());
+        assert_eq!(committed, -I256::from_raw(U256::from(12_500_000_000_000_000_u64)));
+
+        let stats = LstDexStats {
+            baseYield: committed,
+            rewardPool: Address::repeat_byte(0xcc),
+            incentiveYield: U256::from(20_000_000_000_000_000_u64),
+            ..fixture::journal()
+        };
+        let decoded = LstDexStats::abi_decode(&stats.abi_encode(), true).unwrap();
+        assert_eq!(decoded.baseYield, committed);
+        assert_eq!(wad_to_yield(decoded.baseYield), -0.0125);
+        assert_eq!(decoded.combined_yield(), Some(yield_to_wad(0.0075)));
+        assert!(decoded.to_string().starts_with(
+            "LstDexStats: baseYield=-1.25%, incentiveYield=2.00%, combinedYield=0.75%"
+        ));
+    }
+
+    fn context(index: usize, block_number: u64, timestamp: u64, lst_backing: u64) -> SampleContext {
+        SampleContext { index, block_number, timestamp, lst_backing: U256::from(lst_backing) }
+    }
+
+    #[test]
+    fn it_should_build_a_valid_series() {
+        let mut builder = DexStatsInputBuilder::new(BLOCK_GRANULARITY);
+        builder
+            .push(1_000, 0, U256::from(100))
+            .push(1_000 + DAY_IN_SECONDS, BLOCK_GRANULARITY, U256::from(101))
+            .push(1_000 + 2 * DAY_IN_SECONDS, 2 * BLOCK_GRANULARITY, U256::from(102));
+
+        let inputs = builder.build().unwrap();
+        assert_eq!(inputs.len(), 3);
+        assert_eq!(inputs[2].block_number, 2 * BLOCK_GRANULARITY);
+        assert!(inputs.iter().all(|input| !input.interpolated));
+    }
+
+    #[test]
+    fn it_should_reject_an_empty_series() {
+        assert_eq!(
+            DexStatsInputBuilder::new(BLOCK_GRANULARITY).build().unwrap_err(),
+            DexStatsError::Empty
+        );
+    }
+
+    #[test]
+    fn it_should_reject_unsorted_blocks() {
+        let mut builder = DexStatsInputBuilder::new(BLOCK_GRANULARITY);
+        builder.push(1_000, BLOCK_GRANULARITY, U256::from(100)).push(2_000, 0, U256::from(101));
+
+        assert_eq!(
+            builder.build().unwrap_err(),
+            DexStatsError::NotSorted {
+                prior: context(0, BLOCK_GRANULARITY, 1_000, 100),
+                sample: context(1, 0, 2_000, 101)
+            }
+        );
+    }
+
+    #[test]
+    fn it_should_reject_non_increasing_timestamps() {
+        let mut builder = DexStatsInputBuilder::new(BLOCK_GRANULARITY);
+        builder.push(1_000, 0, U256::from(100)).push(1_000, BLOCK_GRANULARITY, U256::from(101));
+
+        assert_eq!(
+            builder.build().unwrap_err(),
+            DexStatsError::TimestampNotIncreasing {
+                prior: context(0, 0, 1_000, 100),
+                sample: context(1, BLOCK_GRANULARITY, 1_000, 101)
+            }
+        );
+    }
+
+    #[test]
+    fn it_should_report_the_first_granularity_violation() {
+        let mut builder = DexStatsInputBuilder::new(BLOCK_GRANULARITY);
+        builder
+            .push(1_000, 0, U256::from(100))
+            .push(2_000, BLOCK_GRANULARITY, U256::from(101))
+            .push(3_000, 3 * BLOCK_GRANULARITY, U256::from(102))
+            .push(2_500, 2 * BLOCK_GRANULARITY, U256::from(103));
+
+        assert_eq!(
+            builder.build().unwrap_err(),
+            DexStatsError::Granularity {
+                prior: context(1, BLOCK_GRANULARITY, 2_000, 101),
+                sample: context(2, 3 * BLOCK_GRANULARITY, 3_000, 102),
+                granularity: BLOCK_GRANULARITY
+            }
+        );
+    }
+
+    #[test]
+    fn it_should_report_the_offending_samples() {
+        let mut inputs = build_input(1716129570, &[100.0, 100.5, 101.0, 101.25]);
+        inputs[2].block_number += 1;
+
+        let err = try_calculate_dex_stats(&inputs, &DexStatsParams::default()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "provided data not at correct granularity: sample 1 (block 7200, timestamp 1716215970, \
+             backing 100.500000000000000000) to sample 2 (block 14401, timestamp 1716302370, \
+             backing 101.000000000000000000) is 7201 blocks, expected 7200"
+        );
+    }
+
+    #[test]
+    fn it_should_report_gaps_longer_than_the_limit() {
+        let mut inputs = build_input(1716129570, &[100.0, 100.01, 100.10, 100.15]);
+        inputs.drain(1..3);
+
+        let params = DexStatsParams { max_interpolated: 1, ..Default::default() };
+        let err = try_calculate_dex_stats(&inputs, &params).unwrap_err();
+        assert_eq!(
+            err,
+            DexStatsError::TooManyMissing {
+                prior: SampleContext::new(0, &inputs[0]),
+                sample: SampleContext::new(1, &inputs[1]),
+                missing: 2,
+                max_interpolated: 1
+            }
+        );
+        assert!(err.to_string().contains("2 between sample 0 (block 0,"));
+    }
+
+    #[test]
+    fn it_should_cap_the_block_gap() {
+        let mut inputs = build_input(1716129570, &[100.0, 100.01, 100.10, 100.15, 100.25]);
+        inputs.drain(1..3);
+        let params = |max_block_gap| DexStatsParams {
+            max_interpolated: 2,
+            max_block_gap: Some(max_block_gap),
+            ..Default::default()
+        };
+
+        // a gap of two missing samples is three intervals
+        try_calculate_dex_stats(&inputs, &params(3 * BLOCK_GRANULARITY)).unwrap();
+        let err = try_calculate_dex_stats(&inputs, &params(3 * BLOCK_GRANULARITY - 1)).unwrap_err();
+        assert_eq!(
+            err,
+            DexStatsError::BlockGap {
+                prior: SampleContext::new(0, &inputs[0]),
+                sample: SampleContext::new(1, &inputs[1]),
+                max_block_gap: 3 * BLOCK_GRANULARITY - 1
+            }
+        );
+        assert!(err.to_string().contains("is 21600 blocks, at most 21599 allowed"));
+    }
+
+    #[test]
+    fn it_should_not_bias_log_returns_on_large_moves() {
+        // the series ends where it started, so there is no yield, but the simple returns of the
+        // up moves outweigh those of the down moves
+        let inputs = build_input(1716129570, &[100.0, 110.0, 100.0, 110.0, 100.0]);
+
+        let simple = calculate_dex_stats(&inputs, 1);
+        assert!(simple.base_yield > 1.0);
+
+        let log = calculate_dex_stats_with(
+            &inputs,
+            &DexStatsParams { return_type: ReturnType::Log, ..Default::default() },
+        );
+        assert!(log.base_yield.abs() <= 0.00000001);
+        assert!(log.base_apr.abs() <= 0.00000001);
+    }
+
+    #[test]
+    fn it_should_agree_on_small_moves() {
+        let inputs = build_input(1716129570, &[100.0, 100.01, 100.02, 100.03]);
+
+        let simple = calculate_dex_stats(&inputs, 1);
+        let log = calculate_dex_stats_with(
+            &inputs,
+            &DexStatsParams { return_type: ReturnType::Log, ..Default::default() },
+        );
+        // the log yield is an effective annual yield, so it compares to the simple APY
+        assert!((log.base_yield - simple.base_apy).abs() <= 0.0001);
+    }
+
+    #[test]
+    fn it_should_relate_the_total_return_to_the_annualized_yield() {
+        // growing by the same factor every day for 30 days
+        let backings: Vec<f64> = (0..=30).map(|day| 100.0 * 1.0001_f64.powi(day)).collect();
+        let inputs = build_input(1716129570, &backings);
+        let years = 30.0 / 365.0;
+
+        let simple = calculate_dex_stats(&inputs, 1);
+        assert!((simple.total_return - (1.0001_f64.powi(30) - 1.0)).abs() <= 1e-9);
+        // compounding the APY over the window gives it back, the APR alone falls short
+        assert!(((1.0 + simple.base_apy).powf(years) - 1.0 - simple.total_return).abs() <= 1e-9);
+        assert!(simple.base_apr * years < simple.total_return);
+
+        let params = DexStatsParams { return_type: ReturnType::Log, ..Default::default() };
+        let log = try_calculate_dex_stats(&inputs, &params).unwrap();
+        assert_eq!(log.total_return, simple.total_return);
+        assert!(((log.base_apr * years).exp_m1() - log.total_return).abs() <= 1e-9);
+    }
+
+    #[test]
+    fn it_should_diff_two_outputs() {
+        let output = |base_yield: f64, sample_count| DexStatsOutput {
+            base_yield,
+            base_apr: base_yield,
+            base_apy: base_yield,
+            total_return: 0.0,
+            changes: vec![base_yield; sample_count - 1],
+            yield_volatility: 0.0,
+            yield_std_error: 0.0,
+            sample_count,
+            data_quality: 1.0,
+            dropped_samples: 0,
+        };
+        let a = output(0.0325, 4);
+        let b = output(0.03125, 3);
+
+        let diff = a.diff(&b);
+        assert!((diff.base_yield.absolute - 0.00125).abs() <= 0.00000001);
+        assert!((diff.base_yield.relative.unwrap() - 0.04).abs() <= 0.00000001);
+        assert_eq!(diff.yield_volatility.absolute, 0.0);
+        assert_eq!(diff.yield_volatility.relative, None);
+        assert_eq!(diff.sample_count, 1);
+        assert_eq!(diff.describe("pool A", "pool B"), "pool A yields 12.5 bps more than pool B");
+        assert_eq!(
+            b.diff(&a).describe("pool B", "pool A"),
+            "pool B yields 12.5 bps less than pool A"
+        );
+        assert_eq!(a.diff(&a).describe("pool A", "pool A"), "pool A yields the same as pool A");
+    }
+
+    #[test]
+    fn it_should_accept_a_window_ending_at_the_commitment() {
+        let inputs = build_input(1716129570, &[100.0, 100.01, 100.02]);
+        verify_window_end(&inputs, 2 * BLOCK_GRANULARITY);
+    }
+
+    #[test]
+    #[should_panic(expected = "window ends at block 7200, not at the committed block 14400")]
+    fn it_should_reject_a_window_ending_before_the_commitment() {
+        let inputs = build_input(1716129570, &[100.0, 100.01, 100.02]);
+        verify_window_end(&inputs[..2], 2 * BLOCK_GRANULARITY);
+    }
+
+    #[test]
+    fn it_should_accept_a_midnight_window_ending_at_the_last_midnight() {
+        // midnight of 2024-05-19 on
+        let inputs = build_input(1716076800, &[100.0, 100.01, 100.02]);
+        // up to a block short of the next midnight
+        verify_midnight_window_end(&inputs, inputs[2].timestamp + DAY_IN_SECONDS - 12);
+    }
+
+    #[test]
+    #[should_panic(expected = "window ends at block 7200, not at the last midnight before the \
+                               committed block")]
+    fn it_should_reject_a_midnight_window_dropping_the_last_day() {
+        let inputs = build_input(1716076800, &[100.0, 100.01, 100.02]);
+        verify_midnight_window_end(&inputs[..2], inputs[2].timestamp + 12);
+    }
+
+    #[test]
+    fn it_should_resample_anchored_at_the_most_recent_item() {
+        let indices = |len, skip| resample_indices(len, skip).collect::<Vec<_>>();
+
+        assert_eq!(indices(5, 1), vec![0, 1, 2, 3, 4]);
+        assert_eq!(indices(5, 2), vec![0, 2, 4]);
+        assert_eq!(indices(6, 2), vec![1, 3, 5]);
+        assert_eq!(indices(7, 3), vec![0, 3, 6]);
+        assert_eq!(indices(8, 3), vec![1, 4, 7]);
+        assert_eq!(indices(9, 3), vec![2, 5, 8]);
+        assert_eq!(indices(3, 5), vec![2]);
+        assert_eq!(indices(0, 2), Vec::<usize>::new());
+
+        let inputs = build_input(1716129570, &[100.0, 100.01, 100.02, 100.03, 100.04, 100.05]);
+        let blocks: Vec<_> = resample(&inputs, 2).iter().map(|input| input.block_number).collect();
+        assert_eq!(blocks, vec![BLOCK_GRANULARITY, 3 * BLOCK_GRANULARITY, 5 * BLOCK_GRANULARITY]);
+    }
+
+    #[test]
+    fn it_should_report_the_samples_a_skip_drops() {
+        // 11 samples at a skip of 4: 10 intervals are two strides and a remainder of two samples
+        let retained: Vec<_> = resample_indices(11, 4).collect();
+        assert_eq!(retained, vec![2, 6, 10]);
+        assert_eq!(dropped_indices(11, 4), 0..2);
+        // the ones in between are stepped over, not dropped
+        assert_eq!(dropped_indices(9, 4), 0..0);
+        assert_eq!(dropped_indices(3, 5), 0..2);
+        assert_eq!(dropped_indices(0, 2), 0..0);
+
+        let inputs = build_input(1716129570, &[100.0; 11]);
+        let params = DexStatsParams { skip: 4, ..Default::default() };
+        let stats = try_calculate_dex_stats(&inputs, &params).unwrap();
+        assert_eq!((stats.sample_count, stats.dropped_samples), (3, 2));
+
+        let strict = DexStatsParams { skip_remainder: SkipRemainder::Reject, ..params.clone() };
+        let err = try_calculate_dex_stats(&inputs, &strict).unwrap_err();
+        assert_eq!(err, DexStatsError::SkipRemainder { len: 11, skip: 4, dropped: 2 });
+        assert_eq!(
+            err.to_string(),
+            "11 samples are not a whole number of strides of 4: the oldest 2 would be dropped"
+        );
+        assert!(try_calculate_dex_stats(&inputs[2..], &strict).is_ok());
+    }
+
+    #[test]
+    fn it_should_calculate_backing_avg() {
+        let inputs = build_input(1716129570, &vec![100.0, 100.01, 100.10, 100.15, 100.25]);
+
+        let expected = 0.2279345389;
+
+        let res = calculate_dex_stats(&inputs, 1);
         let delta = (res.base_yield - expected).abs();
         assert!(delta <= 0.00000001);
         println!("{:?}", res);
     }
 
-    fn build_input(start_timestamp: u64, input_values: &[f64]) -> Vec<DexStatsInput> {
-        input_values
+    #[test]
+    fn it_should_annualize_zero_change() {
+        assert_eq!(annualized_change(1.05, 1.05, DAY_IN_SECONDS, DayCount::Actual365), 0.0);
+    }
+
+    #[test]
+    fn it_should_annualize_positive_change() {
+        let change = annualized_change(100.0, 101.0, DAY_IN_SECONDS, DayCount::Actual365);
+        assert!((change - 3.65).abs() <= 0.00000001);
+
+        let change = annualized_change(100.0, 101.0, 2 * DAY_IN_SECONDS, DayCount::Actual360);
+        assert!((change - 1.8).abs() <= 0.00000001);
+    }
+
+    #[test]
+    fn it_should_annualize_negative_change() {
+        let change = annualized_change(100.0, 99.5, 7 * DAY_IN_SECONDS, DayCount::Actual36525);
+        assert!((change - (-0.005 * 365.25 / 7.0)).abs() <= 0.00000001);
+    }
+
+    #[test]
+    fn it_should_annualize_tiny_time_delta() {
+        // a 1e-9 change over a single second is 3.1536% per year
+        let change = annualized_change(1.0, 1.000000001, 1, DayCount::Actual365);
+        assert!((change - 0.031536).abs() <= 0.000001);
+    }
+
+    #[test]
+    fn it_should_annualize_an_uneven_interval_exactly() {
+        // 25 hours go 350.4 times into a year; an integer annualizer truncated that to 350
+        let change = annualized_change(100.0, 101.0, 25 * 60 * 60, DayCount::Actual365);
+        assert!((change - 3.504).abs() <= 1e-12, "{change}");
+
+        // and anything longer than half a year to 1
+        let change = annualized_change(100.0, 101.0, 200 * DAY_IN_SECONDS, DayCount::Actual365);
+        assert!((change - 0.01825).abs() <= 1e-12, "{change}");
+    }
+
+    #[test]
+    #[should_panic(expected = "zero time delta")]
+    fn it_should_reject_zero_time_delta() {
+        annualized_change(1.0, 1.0, 0, DayCount::Actual365);
+    }
+
+    #[test]
+    fn it_should_reject_another_input_version() {
+        assert_eq!(check_input_version(GUEST_INPUT_VERSION), Ok(()));
+        let err = check_input_version(GUEST_INPUT_VERSION + 1).unwrap_err();
+        assert_eq!(err, InputVersionError { found: 4, expected: 3 });
+        assert!(err.to_string().starts_with("unsupported guest input version 4, this guest reads"));
+    }
+
+    #[test]
+    fn it_should_time_a_zero_time_delta_series_by_its_blocks() {
+        let timed = build_input(1716129570, &[100.0, 100.01, 100.10, 100.15, 100.25]);
+        // instant-mined, every block has the same timestamp
+        let untimed: Vec<_> = timed
+            .iter()
+            .map(|item| DexStatsInput { timestamp: 1716129570, ..item.clone() })
+            .collect();
+
+        let err = try_calculate_dex_stats(&untimed, &DexStatsParams::default()).unwrap_err();
+        assert!(matches!(err, DexStatsError::TimestampNotIncreasing { .. }), "{err}");
+
+        // a day of 12 second blocks apart, as the timed series
+        let params = DexStatsParams { min_time_delta: Some(1), ..Default::default() };
+        let res = try_calculate_dex_stats(&untimed, &params).unwrap();
+        assert_eq!(res.base_yield, calculate_dex_stats(&timed, 1).base_yield);
+        // a series far enough apart keeps its own timestamps
+        assert!(pseudo_timed(&timed, DAY_IN_SECONDS).is_none());
+        assert!(pseudo_timed(&timed, DAY_IN_SECONDS + 1).is_some());
+    }
+
+    #[test]
+    fn it_should_derive_apy_from_apr() {
+        let inputs = build_input(1716129570, &vec![100.0, 100.01, 100.10, 100.15, 100.25]);
+
+        let res = calculate_dex_stats(&inputs, 1);
+        assert_eq!(res.base_apr, res.base_yield);
+
+        // daily samples compound daily
+        let expected = (1.0 + res.base_apr / 365.0).powf(365.0) - 1.0;
+        assert!((res.base_apy - expected).abs() <= 0.00000001);
+        assert!(res.base_apy > res.base_apr);
+
+        // every other sample compounds every other day
+        let res = calculate_dex_stats(&inputs, 2);
+        let expected = (1.0 + res.base_apr / 182.5).powf(182.5) - 1.0;
+        assert!((res.base_apy - expected).abs() <= 0.00000001);
+
+        assert!(res.to_string().starts_with("DexStats: APR="));
+    }
+
+    #[test]
+    fn it_should_yield_exactly_zero_for_a_flat_series() {
+        let inputs = build_input(1716129570, &vec![1.0345678912345; 60]);
+
+        let res = calculate_dex_stats(&inputs, 1);
+        assert_eq!(res.base_yield, 0.0);
+        assert_eq!(res.base_apy, 0.0);
+        assert_eq!(res.yield_volatility, 0.0);
+        assert!(res.changes.iter().all(|&change| change == 0.0));
+    }
+
+    #[test]
+    fn it_should_calculate_yield_volatility() {
+        let inputs = build_input(1716129570, &vec![100.0, 100.01, 100.10, 100.15, 100.25]);
+
+        let res = calculate_dex_stats(&inputs, 1);
+        let mean = res.changes.iter().sum::<f64>() / 4.0;
+        let variance = res.changes.iter().map(|c| (c - mean).powi(2)).sum::<f64>() / 3.0;
+
+        assert_eq!(res.sample_count, 5);
+        assert!((res.yield_volatility - variance.sqrt()).abs() <= 0.00000001);
+
+        // a steady series has no volatility
+        let steady = calculate_dex_stats(&build_input(1716129570, &vec![100.0, 100.0, 100.0]), 1);
+        assert_eq!(steady.yield_volatility, 0.0);
+        assert_eq!(steady.yield_std_error, 0.0);
+    }
+
+    #[test]
+    fn it_should_score_a_steadier_yield_higher() {
+        let series = |growth: [f64; 4]| -> Vec<f64> {
+            let mut backing = 100.0;
+            (0..=60)
+                .map(|day| {
+                    backing *= 1.0 + growth[day % 4];
+                    backing
+                })
+                .collect()
+        };
+        let stats = |growth| calculate_dex_stats(&build_input(1716129570, &series(growth)), 1);
+
+        // the same mean daily growth, one evenly and one in swings
+        let steady = stats([0.0001, 0.00011, 0.00009, 0.0001]);
+        let noisy = stats([0.0001, 0.0003, -0.0001, 0.0001]);
+        let (steady_ratio, noisy_ratio) =
+            (steady.sharpe_ratio(0.0).unwrap(), noisy.sharpe_ratio(0.0).unwrap());
+        assert!(steady_ratio > noisy_ratio, "{steady_ratio} <= {noisy_ratio}");
+        // a risk-free rate above the yield turns it negative
+        assert!(steady.sharpe_ratio(0.05).unwrap() < 0.0);
+
+        // undefined without volatility
+        let flat = calculate_dex_stats(&build_input(1716129570, &vec![100.0; 10]), 1);
+        assert_eq!(flat.sharpe_ratio(0.0), None);
+    }
+
+    #[test]
+    fn it_should_narrow_the_std_error_with_more_samples() {
+        // the same noisy daily growth, observed for longer
+        let series = |days: usize| -> Vec<f64> {
+            let mut backing = 100.0;
+            (0..=days)
+                .map(|day| {
+                    backing *= 1.0 + [0.0001, 0.00005, 0.00015, 0.0001][day % 4];
+                    backing
+                })
+                .collect()
+        };
+
+        let errors: Vec<f64> = [8, 32, 128]
+            .iter()
+            .map(|&days| {
+                let res = calculate_dex_stats(&build_input(1716129570, &series(days)), 1);
+                let n = res.changes.len() as f64;
+                assert!((res.yield_std_error - res.yield_volatility / n.sqrt()).abs() <= 1e-12);
+                res.yield_std_error
+            })
+            .collect();
+
+        // four times the samples, about half the error, as the volatility stays put
+        assert!(errors[0] > errors[1] && errors[1] > errors[2], "{errors:?}");
+        assert!((errors[1] / errors[0] - 0.5).abs() < 0.1, "{errors:?}");
+        assert!((errors[2] / errors[1] - 0.5).abs() < 0.1, "{errors:?}");
+    }
+
+    #[test]
+    fn it_should_contrast_point_to_point_with_rolling() {
+        let values = vec![100.0, 100.01, 100.10, 100.15, 100.25];
+        let inputs = build_input(1716129570, &values);
+
+        let point = calculate_dex_stats(&inputs, 1);
+        let rolling = calculate_dex_stats_with(
+            &inputs,
+            &DexStatsParams { mode: ChangeMode::Rolling { window: 3 }, ..Default::default() },
+        );
+
+        // point-to-point has one change per interval, the 3-sample window one per full window
+        assert_eq!(point.changes.len(), 4);
+        assert_eq!(rolling.changes.len(), 3);
+
+        // each rolling change spans two days
+        let expected: Vec<f64> =
+            (2..values.len()).map(|i| (values[i] / values[i - 2] - 1.0) * 365.0 / 2.0).collect();
+        for (actual, expected) in rolling.changes.iter().zip(&expected) {
+            assert!((actual - expected).abs() <= 0.00000001);
+        }
+        let expected_yield = expected.iter().sum::<f64>() / expected.len() as f64;
+        assert!((rolling.base_yield - expected_yield).abs() <= 0.00000001);
+        assert!((rolling.base_yield - point.base_yield).abs() > 0.001);
+    }
+
+    #[test]
+    fn it_should_match_point_to_point_with_two_sample_window() {
+        let inputs = build_input(1716129570, &vec![100.0, 100.01, 100.10, 100.15, 100.25]);
+
+        let point = calculate_dex_stats(&inputs, 1);
+        let rolling = calculate_dex_stats_with(
+            &inputs,
+            &DexStatsParams { mode: ChangeMode::Rolling { window: 2 }, ..Default::default() },
+        );
+
+        assert_eq!(point.changes, rolling.changes);
+    }
+
+    #[test]
+    fn it_should_exclude_unfinalized_samples() {
+        let inputs = build_input(1716129570, &vec![100.0, 100.01, 100.10, 100.15, 100.25]);
+        let head = inputs[4].block_number;
+
+        // only the head sample is within 64 blocks of the head
+        let finalized = exclude_unfinalized(&inputs, head, 64);
+        assert_eq!(finalized.len(), 4);
+
+        // so whatever the head reports doesn't change the yield
+        let mut perturbed = inputs.clone();
+        perturbed[4].lst_backing = U256::from(1);
+        let res = calculate_dex_stats(finalized, 1);
+        let perturbed_res = calculate_dex_stats(exclude_unfinalized(&perturbed, head, 64), 1);
+        assert_eq!(res.base_yield, perturbed_res.base_yield);
+        assert_eq!(res.base_yield, calculate_dex_stats(&inputs[..4], 1).base_yield);
+
+        assert_eq!(exclude_unfinalized(&inputs, head, 0).len(), 5);
+        assert_eq!(exclude_unfinalized(&inputs, head, BLOCK_GRANULARITY + 1).len(), 3);
+    }
+
+    #[test]
+    fn it_should_exclude_samples_before_a_block() {
+        let inputs = build_input(1716129570, &[100.0, 100.01, 100.10, 100.15, 100.25]);
+
+        assert_eq!(exclude_before(&inputs, 0).len(), 5);
+        // the feed was deployed between the first two samples
+        let denominated = exclude_before(&inputs, 1);
+        assert_eq!(denominated[0].block_number, BLOCK_GRANULARITY);
+        assert_eq!(exclude_before(&inputs, 4 * BLOCK_GRANULARITY).len(), 1);
+        assert!(exclude_before(&inputs, 4 * BLOCK_GRANULARITY + 1).is_empty());
+    }
+
+    #[test]
+    fn it_should_interpolate_a_missing_sample() {
+        let mut inputs = build_input(1716129570, &vec![100.0, 100.01, 100.10, 100.15, 100.25]);
+        let complete = calculate_dex_stats(&inputs, 1);
+        let missing = inputs.remove(2);
+
+        let params = DexStatsParams { max_interpolated: 1, ..Default::default() };
+        let res = calculate_dex_stats_with(&inputs, &params);
+
+        // the interpolated point sits halfway between its neighbours
+        let halfway = (100.01 + 100.15) / 2.0;
+        let expected_prior = (halfway / 100.01 - 1.0) * 365.0;
+        let expected_next = (100.15 / halfway - 1.0) * 365.0;
+        assert_eq!(res.changes.len(), 4);
+        assert!((res.changes[1] - expected_prior).abs() <= 0.00000001);
+        assert!((res.changes[2] - expected_next).abs() <= 0.00000001);
+        assert_eq!(res.changes[0], complete.changes[0]);
+        assert_eq!(res.changes[3], complete.changes[3]);
+
+        assert_eq!(res.data_quality, 0.8);
+        assert_eq!(complete.data_quality, 1.0);
+        assert_eq!(missing.block_number, 2 * BLOCK_GRANULARITY);
+    }
+
+    #[test]
+    #[should_panic(expected = "too many consecutive missing samples")]
+    fn it_should_reject_gaps_longer_than_the_limit() {
+        let mut inputs = build_input(1716129570, &vec![100.0, 100.01, 100.10, 100.15, 100.25]);
+        inputs.drain(1..3);
+
+        let params = DexStatsParams { max_interpolated: 1, ..Default::default() };
+        calculate_dex_stats_with(&inputs, &params);
+    }
+
+    #[test]
+    #[should_panic(expected = "provided data not at correct granularity")]
+    fn it_should_reject_gaps_without_interpolation() {
+        let mut inputs = build_input(1716129570, &vec![100.0, 100.01, 100.10, 100.15, 100.25]);
+        inputs.remove(2);
+
+        calculate_dex_stats(&inputs, 1);
+    }
+
+    #[test]
+    fn it_should_accept_samples_aligned_to_midnight() {
+        // midnight of 2024-05-19, with the blocks nearest to each midnight a little off the stride
+        let mut inputs = build_input(1716076800, &vec![100.0, 100.01, 100.10, 100.15]);
+        for (input, offset) in inputs.iter_mut().zip([0_i64, -3, 5, 2]) {
+            input.block_number = input.block_number.saturating_add_signed(offset);
+            input.timestamp = input.timestamp.saturating_add_signed(offset * 12);
+        }
+        assert!(matches!(
+            try_calculate_dex_stats(&inputs, &DexStatsParams::default()),
+            Err(DexStatsError::Granularity { .. })
+        ));
+
+        let params = DexStatsParams { alignment: SampleAlignment::Midnight, ..Default::default() };
+        assert_eq!(try_calculate_dex_stats(&inputs, &params).unwrap().sample_count, 4);
+
+        // noon is no midnight
+        inputs[2].timestamp += DAY_IN_SECONDS / 2;
+        let err = try_calculate_dex_stats(&inputs, &params).unwrap_err();
+        assert!(matches!(err, DexStatsError::NotAligned { ref sample, .. } if sample.index == 2));
+        assert_eq!(midnight_day(1716076800 - 59), Some(19862));
+        assert_eq!(midnight_day(1716076800 + 61), None);
+    }
+
+    #[test]
+    fn it_should_annualize_an_irregular_schedule_by_time() {
+        // a day, then two days, then half a day of the same 3.65% growth rate
+        let start = 1716129570;
+        let offsets =
+            [0, DAY_IN_SECONDS, 3 * DAY_IN_SECONDS, 3 * DAY_IN_SECONDS + DAY_IN_SECONDS / 2];
+        let backings = [1.0, 1.0001, 1.0003, 1.00035];
+        let inputs: Vec<_> = offsets
             .iter()
-            .enumerate()
-            .map(|(i, &v)| {
-                let lst_backing =
-                    U256::from_str_radix(&((v * 10_f64.powf(18.0)).to_string()), 10).unwrap();
-                let timestamp = start_timestamp + (i as u64 * DAY_IN_SECONDS); // Increment timestamp by 1 day for each input value
-                DexStatsInput {
-                    timestamp,
-                    block_number: (i as u64 * BLOCK_GRANULARITY),
-                    lst_backing,
-                }
+            .zip(backings)
+            .map(|(&offset, backing)| DexStatsInput {
+                timestamp: start + offset,
+                block_number: offset / 12,
+                lst_backing: U256::from((backing * 1e18) as u128),
+                interpolated: false,
+                pool_tvl: None,
             })
-            .collect()
+            .collect();
+        assert!(try_calculate_dex_stats(&inputs, &DexStatsParams::default()).is_err());
+
+        let params = DexStatsParams { alignment: SampleAlignment::Irregular, ..Default::default() };
+        let res = try_calculate_dex_stats(&inputs, &params).unwrap();
+        // nothing was interpolated into the longer interval
+        assert_eq!(res.sample_count, 4);
+        assert_eq!(res.data_quality, 1.0);
+        for change in &res.changes {
+            assert!((change - 0.0365).abs() < 0.0001, "{:?}", res.changes);
+        }
+
+        let mut unordered = inputs.clone();
+        unordered.swap(1, 2);
+        assert!(matches!(
+            try_calculate_dex_stats(&unordered, &params),
+            Err(DexStatsError::NotSorted { .. })
+        ));
+    }
+
+    #[test]
+    fn it_should_merge_intervals_too_short_to_annualize() {
+        // daily samples at a steady 3.65% a year, with one taken 12 seconds after another and off
+        // by the rounding noise of a rebase
+        let mut inputs = fixture::daily_inputs(0..=5, fixture::steady_backing);
+        inputs.insert(
+            3,
+            DexStatsInput {
+                timestamp: inputs[2].timestamp + 12,
+                block_number: inputs[2].block_number + 1,
+                lst_backing: inputs[2].lst_backing + U256::from(1_000_000_000_000_u64),
+                interpolated: false,
+                pool_tvl: None,
+            },
+        );
+        let params = DexStatsParams { alignment: SampleAlignment::Irregular, ..Default::default() };
+
+        // a millionth over 12 seconds annualizes to 260%, a tenfold mean
+        let noisy = try_calculate_dex_stats(&inputs, &params).unwrap();
+        assert!(noisy.base_yield > 0.3, "{}", noisy.base_yield);
+
+        let floored = DexStatsParams { min_interval_seconds: Some(3600), ..params };
+        let res = try_calculate_dex_stats(&inputs, &floored).unwrap();
+        assert_eq!(res.sample_count, 6);
+        assert!((res.base_yield - 0.0365).abs() < 0.0005, "{}", res.base_yield);
+
+        // a short last interval keeps the head sample
+        let samples: Vec<_> = inputs.iter().collect();
+        let merged = merge_short_intervals(samples[..4].to_vec(), 3600);
+        assert_eq!(merged.len(), 3);
+        assert_eq!(merged[2].block_number, inputs[3].block_number);
+    }
+
+    #[test]
+    fn it_should_aggregate_with_a_custom_aggregator() {
+        struct Max;
+
+        impl Aggregator for Max {
+            fn aggregate(&self, changes: &[IntervalChange]) -> f64 {
+                changes.iter().map(|change| change.annualized).fold(f64::MIN, f64::max)
+            }
+        }
+
+        let inputs = build_input(1716129570, &vec![100.0, 100.01, 100.10, 100.15, 100.25]);
+        let params = DexStatsParams::default();
+        let mean = try_calculate_dex_stats(&inputs, &params).unwrap();
+        let max = try_calculate_dex_stats_with_aggregator(&inputs, &params, &Max).unwrap();
+
+        // the largest move is the 0.1% of the last day
+        assert!((max.base_yield - mean.changes[3]).abs() < 1e-12);
+        assert!(max.base_yield > mean.base_yield);
+        assert_eq!(max.changes, mean.changes);
+        assert_eq!(max.yield_volatility, mean.yield_volatility);
+
+        let median = DexStatsParams { aggregation: Aggregation::Median, ..Default::default() };
+        let median = try_calculate_dex_stats(&inputs, &median).unwrap();
+        assert!((median.base_yield - (mean.changes[1] + mean.changes[2]) / 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn it_should_recompute_the_yield_without_the_largest_moves() {
+        // two days with a 0.3% jump among days of 0.01%
+        let values = [100.0, 100.01, 100.31, 100.32, 100.33, 100.63, 100.64];
+        let inputs = build_input(1716129570, &values);
+        let params = DexStatsParams::default();
+        let full = try_calculate_dex_stats(&inputs, &params).unwrap();
+
+        let trimmed = yield_excluding_largest(&inputs, &params, 2).unwrap();
+        let kept = [0, 2, 3, 5].map(|index| full.changes[index]);
+        assert!((trimmed - kept.iter().sum::<f64>() / 4.0).abs() < 1e-12, "{trimmed}");
+        assert!(trimmed < full.base_yield / 5.0);
+        // dropping only one of the jumps keeps the other one in
+        let one = yield_excluding_largest(&inputs, &params, 1).unwrap();
+        assert!(trimmed < one && one < full.base_yield);
+        assert_eq!(yield_excluding_largest(&inputs, &params, 0).unwrap(), full.base_yield);
+        assert_eq!(
+            yield_excluding_largest(&inputs, &params, 6),
+            Err(DexStatsError::ExcludesAll { excluded: 6, changes: 6 })
+        );
+        // a series too short for the exclusion fails the same way when aggregated directly
+        let aggregator = ExcludingLargest { inner: Aggregation::Mean, excluded: 2 };
+        assert!(matches!(
+            try_calculate_dex_stats_with_aggregator(&inputs[..3], &params, &aggregator),
+            Err(DexStatsError::ExcludesAll { excluded: 2, changes: 2 })
+        ));
+    }
+
+    #[test]
+    fn it_should_weight_the_changes_by_tvl() {
+        let mut inputs = build_input(1716129570, &[100.0, 100.02, 100.04, 100.05, 100.06]);
+        let params = DexStatsParams::default();
+        let equal = try_calculate_dex_stats(&inputs, &params).unwrap();
+
+        // the pool shrinks as its yield falls, so its early, high-yield days weigh more
+        let tvl = |eth: u64| Some(U256::from(eth) * U256::from(10).pow(U256::from(18)));
+        for (input, eth) in inputs.iter_mut().zip([1000, 800, 400, 200, 100]) {
+            input.pool_tvl = tvl(eth);
+        }
+        let params = DexStatsParams { aggregation: Aggregation::TvlWeighted, ..params };
+        let weighted = try_calculate_dex_stats(&inputs, &params).unwrap();
+
+        let c = &equal.changes;
+        let expected = (c[0] * 1000.0 + c[1] * 800.0 + c[2] * 400.0 + c[3] * 200.0) / 2400.0;
+        assert!((weighted.base_yield - expected).abs() < 1e-12);
+        assert!(weighted.base_yield > equal.base_yield * 1.1);
+        assert_eq!(weighted.changes, equal.changes);
+
+        // an interpolated sample takes the TVL between its neighbours'
+        inputs.remove(2);
+        let params = DexStatsParams { max_interpolated: 1, ..params };
+        let filled = try_calculate_dex_stats(&inputs, &params).unwrap();
+        assert_eq!(filled.sample_count, 5);
+
+        inputs[1].pool_tvl = None;
+        assert!(matches!(
+            try_calculate_dex_stats(&inputs, &params),
+            Err(DexStatsError::MissingTvl { sample: SampleContext { index: 1, .. } })
+        ));
+    }
+
+    fn build_input(start_timestamp: u64, input_values: &[f64]) -> Vec<DexStatsInput> {
+        let mut builder = DexStatsInputBuilder::new(BLOCK_GRANULARITY);
+        for (i, &v) in input_values.iter().enumerate() {
+            let lst_backing =
+                U256::from_str_radix(&((v * 10_f64.powf(18.0)).to_string()), 10).unwrap();
+            let timestamp = start_timestamp + (i as u64 * DAY_IN_SECONDS); // Increment timestamp by 1 day for each input value
+            builder.push(timestamp, i as u64 * BLOCK_GRANULARITY, lst_backing);
+        }
+
+        builder.build().unwrap()
     }
 }
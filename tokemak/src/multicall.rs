@@ -0,0 +1,214 @@
+//! Bundles the view calls made against each sampled block into a single Multicall3 `aggregate3`
+//! call, so that every block costs one preflight instead of one per queried value.
+//!
+//! The multicall is executed inside the verified view call environment like any other call, so the
+//! per-call results decoded from it are just as trustworthy as individually executed calls.
+
+use core::{convert::Infallible, slice};
+
+use alloy_primitives::{address, Address, U256};
+use alloy_sol_types::{sol, SolCall};
+
+use crate::{
+    backing::{BackingStrategy, ViewCaller},
+    PoolConfig,
+};
+
+/// Multicall3 is deployed at the same address on every major EVM chain.
+pub const MULTICALL3_ADDRESS: Address = address!("cA11bde05977b3631167028862bE2a173976CA11");
+
+sol! {
+    interface IMulticall3 {
+        struct Call3 {
+            address target;
+            bool allowFailure;
+            bytes callData;
+        }
+
+        struct Call3Result {
+            bool success;
+            bytes returnData;
+        }
+
+        function aggregate3(Call3[] calldata calls)
+            external
+            payable
+            returns (Call3Result[] memory returnData);
+    }
+}
+
+/// Builds a sub-call of `call` against `target` that reverts the whole multicall on failure.
+pub fn call3<C: SolCall>(target: Address, call: &C) -> IMulticall3::Call3 {
+    IMulticall3::Call3 { target, allowFailure: false, callData: call.abi_encode().into() }
+}
+
+/// Decodes the return value of a single sub-call.
+pub fn decode_return<C: SolCall>(result: &IMulticall3::Call3Result) -> C::Return {
+    assert!(result.success, "multicall sub-call failed");
+    C::abi_decode_returns(&result.returnData, true).expect("invalid multicall return data")
+}
+
+/// The multicall issued against every sampled block: the calls of the pool's backing strategy. The
+/// order of the sub-calls is the order in which [`decode_backing`] expects their results.
+pub fn backing_calls(pool: &PoolConfig) -> IMulticall3::aggregate3Call {
+    let mut recorder = Recorder::default();
+    pool.backing.backing(pool.lst, &mut recorder).unwrap();
+
+    IMulticall3::aggregate3Call { calls: recorder.calls }
+}
+
+/// Asserts that every sub-call targets one of the pool's contracts.
+pub fn verify_targets(calls: &IMulticall3::aggregate3Call, pool: &PoolConfig) {
+    for call in &calls.calls {
+        pool.verify_target(call.target);
+    }
+}
+
+/// Derives the LST backing from the results of [`backing_calls`].
+pub fn decode_backing(pool: &PoolConfig, ret: &IMulticall3::aggregate3Return) -> U256 {
+    let mut replay = Replay { results: ret.returnData.iter() };
+    let backing = pool.backing.backing(pool.lst, &mut replay).unwrap();
+    assert!(replay.results.next().is_none(), "unexpected number of multicall results");
+
+    backing
+}
+
+/// Number of 32-byte words of placeholder return data, enough for any static return type of the
+/// strategies.
+const PLACEHOLDER_WORDS: usize = 8;
+
+/// Records the calls a strategy makes, answering them with placeholder return data.
+#[derive(Default)]
+pub(crate) struct Recorder {
+    pub(crate) calls: Vec<IMulticall3::Call3>,
+}
+
+impl ViewCaller for Recorder {
+    type Error = Infallible;
+
+    fn call<C: SolCall>(&mut self, target: Address, call: C) -> Result<C::Return, Infallible> {
+        self.calls.push(call3(target, &call));
+        // every word set to one, so that a strategy dividing by a queried value doesn't panic
+        let mut word = [0_u8; 32];
+        word[31] = 1;
+        let placeholder = word.repeat(PLACEHOLDER_WORDS);
+        // a multicall returns a placeholder result per sub-call
+        let data = if C::SELECTOR == IMulticall3::aggregate3Call::SELECTOR {
+            let multicall = IMulticall3::aggregate3Call::abi_decode(&call.abi_encode(), true)
+                .expect("invalid multicall");
+            let results: Vec<_> = multicall
+                .calls
+                .iter()
+                .map(|_| IMulticall3::Call3Result {
+                    success: true,
+                    returnData: placeholder.clone().into(),
+                })
+                .collect();
+            IMulticall3::aggregate3Call::abi_encode_returns(&(results,))
+        } else {
+            placeholder
+        };
+        Ok(C::abi_decode_returns(&data, false).expect("unsupported return type"))
+    }
+}
+
+/// Answers the calls of a strategy from the results of the multicall, in order.
+struct Replay<'a> {
+    results: slice::Iter<'a, IMulticall3::Call3Result>,
+}
+
+impl ViewCaller for Replay<'_> {
+    type Error = Infallible;
+
+    fn call<C: SolCall>(&mut self, _: Address, _: C) -> Result<C::Return, Infallible> {
+        let result = self.results.next().expect("unexpected number of multicall results");
+        Ok(decode_return::<C>(result))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backing::{BackingKind, IRebasingLst},
+        cbETHInterface, CBETH_ADDRESS,
+    };
+
+    #[test]
+    fn it_should_match_individual_calls() {
+        let rate = U256::from(1_067_123_456_789_012_345_u64);
+
+        // what the node returns when exchangeRate() is called directly
+        let individual_data = cbETHInterface::exchangeRateCall::abi_encode_returns(&(rate,));
+        let individual =
+            cbETHInterface::exchangeRateCall::abi_decode_returns(&individual_data, true)
+                .unwrap()
+                ._0;
+
+        // the multicall must forward exactly the calldata of the individual call
+        let calls = backing_calls(&PoolConfig::CBETH_ETH);
+        assert_eq!(calls.calls[0].target, CBETH_ADDRESS);
+        verify_targets(&calls, &PoolConfig::CBETH_ETH);
+        assert_eq!(
+            calls.calls[0].callData.to_vec(),
+            cbETHInterface::exchangeRateCall {}.abi_encode()
+        );
+
+        // and wraps the individual return data unchanged
+        let multicall_data =
+            IMulticall3::aggregate3Call::abi_encode_returns(&(vec![IMulticall3::Call3Result {
+                success: true,
+                returnData: individual_data.into(),
+            }],));
+        let ret = IMulticall3::aggregate3Call::abi_decode_returns(&multicall_data, true).unwrap();
+
+        assert_eq!(decode_backing(&PoolConfig::CBETH_ETH, &ret), individual);
+    }
+
+    #[test]
+    fn it_should_bundle_every_call_of_the_strategy() {
+        let wad = U256::from(10).pow(U256::from(18));
+        let pool = PoolConfig { backing: BackingKind::Rebase, ..PoolConfig::CBETH_ETH };
+
+        let calls = backing_calls(&pool);
+        let selectors: Vec<_> =
+            calls.calls.iter().map(|call| call.callData[..4].to_vec()).collect();
+        assert_eq!(
+            selectors,
+            vec![
+                IRebasingLst::getTotalPooledEtherCall::SELECTOR.to_vec(),
+                IRebasingLst::getTotalSharesCall::SELECTOR.to_vec()
+            ]
+        );
+
+        let result = |value: U256| IMulticall3::Call3Result {
+            success: true,
+            returnData: value.to_be_bytes::<32>().to_vec().into(),
+        };
+        let ret = IMulticall3::aggregate3Return {
+            returnData: vec![result(U256::from(3) * wad), result(U256::from(2) * wad)],
+        };
+        assert_eq!(decode_backing(&pool, &ret), U256::from(15) * wad / U256::from(10));
+    }
+
+    #[test]
+    #[should_panic(expected = "is not part of the pool config")]
+    fn it_should_reject_substituted_targets() {
+        let mut calls = backing_calls(&PoolConfig::CBETH_ETH);
+        calls.calls[0].target = Address::repeat_byte(0x42);
+
+        verify_targets(&calls, &PoolConfig::CBETH_ETH);
+    }
+
+    #[test]
+    #[should_panic(expected = "multicall sub-call failed")]
+    fn it_should_reject_failed_sub_calls() {
+        let ret = IMulticall3::aggregate3Return {
+            returnData: vec![IMulticall3::Call3Result {
+                success: false,
+                returnData: vec![].into(),
+            }],
+        };
+        decode_backing(&PoolConfig::CBETH_ETH, &ret);
+    }
+}
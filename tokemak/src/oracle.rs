@@ -0,0 +1,135 @@
+//! Chainlink price feeds used to re-denominate the ETH backing into another asset.
+//!
+//! Feeds differ in how many decimals their answers carry (8 is the convention for USD pairs, 18
+//! for ETH pairs), so the decimal count is part of the feed configuration and every answer is
+//! normalized to 18 decimals before it touches the backing.
+
+use alloy_primitives::{Address, I256, U256};
+use serde::{Deserialize, Serialize};
+
+use crate::wad::{div_wad, mul_wad};
+
+/// Decimals of the fixed-point values the backing is expressed in.
+pub const WAD_DECIMALS: u8 = 18;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PriceFeed {
+    pub address: Address,
+    /// Decimals of the feed's answers, as reported by its `decimals()` or overridden by the user.
+    pub decimals: u8,
+}
+
+impl PriceFeed {
+    /// Scales a raw `latestRoundData` answer to 18 decimals.
+    pub fn scale_answer(&self, answer: I256) -> U256 {
+        assert!(!answer.is_negative(), "negative oracle answer");
+        let raw = answer.into_raw();
+
+        if self.decimals <= WAD_DECIMALS {
+            raw * U256::from(10).pow(U256::from(WAD_DECIMALS - self.decimals))
+        } else {
+            raw / U256::from(10).pow(U256::from(self.decimals - WAD_DECIMALS))
+        }
+    }
+
+    /// Converts an 18-decimal amount of the feed's base asset into its quote asset, e.g. an ETH
+    /// backing into USD with an ETH/USD feed.
+    pub fn denominate(&self, backing: U256, answer: I256) -> U256 {
+        mul_wad(backing, self.scale_answer(answer))
+    }
+
+    /// Converts an 18-decimal amount of the feed's quote asset into its base asset, e.g. a USD
+    /// backing into BTC with a BTC/USD feed.
+    pub fn to_base(&self, value: U256, answer: I256) -> U256 {
+        let price = self.scale_answer(answer);
+        assert!(price > U256::ZERO, "zero oracle answer");
+
+        div_wad(value, price)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feed(decimals: u8) -> PriceFeed {
+        PriceFeed { address: Address::ZERO, decimals }
+    }
+
+    #[test]
+    fn it_should_scale_answers_by_feed_decimals() {
+        let expected = U256::from(3_000_500_000_000_000_000_000_u128);
+
+        // the same 3000.5 price as reported by feeds of different precision
+        assert_eq!(feed(8).scale_answer(I256::try_from(300_050_000_000_i64).unwrap()), expected);
+        assert_eq!(feed(6).scale_answer(I256::try_from(3_000_500_000_i64).unwrap()), expected);
+        assert_eq!(
+            feed(18).scale_answer(I256::try_from(3_000_500_000_000_000_000_000_i128).unwrap()),
+            expected
+        );
+        assert_eq!(
+            feed(20).scale_answer(I256::try_from(300_050_000_000_000_000_000_000_i128).unwrap()),
+            expected
+        );
+    }
+
+    #[test]
+    fn it_should_denominate_backing() {
+        // 1.05 ETH of backing at 2000 USD per ETH from a 6 decimal feed
+        let backing = U256::from(1_050_000_000_000_000_000_u128);
+        let answer = I256::try_from(2_000_000_000_i64).unwrap();
+
+        let denominated = feed(6).denominate(backing, answer);
+        assert_eq!(denominated, U256::from(2_100_000_000_000_000_000_000_u128));
+    }
+
+    #[test]
+    fn it_should_denominate_in_a_reference_asset() {
+        use crate::{calculate_dex_stats, DexStatsInput, BLOCK_GRANULARITY, DAY_IN_SECONDS};
+
+        // ETH/USD from an 8 decimal feed, BTC/USD from an 18 decimal one
+        let eth_usd = feed(8);
+        let btc_usd = feed(18);
+        let backings = [1.0, 1.0001, 1.0002, 1.0003];
+        let eth_prices = [2000, 2000, 2000, 2000];
+        let btc_prices = [40000, 40004, 40008, 40012];
+
+        let inputs: Vec<_> = (0..backings.len())
+            .map(|i| {
+                let backing = U256::from((backings[i] * 1e18) as u128);
+                let usd = eth_usd
+                    .denominate(backing, I256::try_from(eth_prices[i] * 100_000_000_i64).unwrap());
+                let btc = btc_usd.to_base(
+                    usd,
+                    I256::try_from(btc_prices[i] as i128 * 1_000_000_000_000_000_000).unwrap(),
+                );
+                DexStatsInput {
+                    timestamp: i as u64 * DAY_IN_SECONDS,
+                    block_number: i as u64 * BLOCK_GRANULARITY,
+                    lst_backing: btc,
+                    interpolated: false,
+                    pool_tvl: None,
+                }
+            })
+            .collect();
+
+        // BTC appreciating against USD lowers the yield measured in BTC terms
+        let res = calculate_dex_stats(&inputs, 1);
+        let expected: f64 = (1..backings.len())
+            .map(|i| {
+                let prior = backings[i - 1] / btc_prices[i - 1] as f64;
+                let current = backings[i] / btc_prices[i] as f64;
+                (current / prior - 1.0) * 365.0
+            })
+            .sum::<f64>()
+            / 3.0;
+        assert!((res.base_yield - expected).abs() <= 0.000001);
+        assert!(res.base_yield < 0.0365);
+    }
+
+    #[test]
+    #[should_panic(expected = "negative oracle answer")]
+    fn it_should_reject_negative_answers() {
+        feed(8).scale_answer(I256::try_from(-1).unwrap());
+    }
+}
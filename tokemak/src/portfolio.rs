@@ -0,0 +1,337 @@
+//! The yield of a portfolio of positions across several pools, weighted by the capital allocated
+//! to each rather than averaged.
+//!
+//! A Tokemak autopool moves its capital between destinations over time; an [`AllocationSchedule`]
+//! gives its weights from block to block, and the blended yield weights each destination's yield
+//! by its share of the capital averaged over the window. That is exact for yields steady over the
+//! window and an approximation otherwise, as it doesn't line up the reallocations with when each
+//! destination yielded.
+
+use core::fmt;
+
+/// How far the weights of a portfolio may sum away from 1, for weights rounded when written down.
+pub const WEIGHT_SUM_TOLERANCE: f64 = 1e-6;
+
+/// The share of the portfolio's capital in one pool.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Allocation {
+    pub name: &'static str,
+    pub weight: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum PortfolioError {
+    #[error("empty portfolio")]
+    Empty,
+    #[error("pool {0} is allocated more than once")]
+    Duplicate(&'static str),
+    #[error("weight {weight} of pool {name} is not positive")]
+    NonPositiveWeight { name: &'static str, weight: f64 },
+    #[error("portfolio weights sum to {0}, not 1")]
+    WeightSum(f64),
+    #[error("no allocations in the schedule")]
+    EmptySchedule,
+    #[error("the allocation at block {block} has {found} weights for {expected} destinations")]
+    WeightCount { block: u64, expected: usize, found: usize },
+    #[error("weight {weight} of destination {name} at block {block} is negative")]
+    NegativeWeight { name: &'static str, block: u64, weight: f64 },
+    #[error("weight of destination {name} at block {block} is not a number")]
+    NotANumber { name: &'static str, block: u64 },
+    #[error("the allocation at block {block} does not follow the one before it")]
+    NotIncreasing { block: u64 },
+    #[error("the allocation schedule starts at block {first}, after the window from {from}")]
+    NotCovered { from: u64, first: u64 },
+}
+
+/// Allocations whose weights are positive and sum to about 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Portfolio {
+    allocations: Vec<Allocation>,
+}
+
+impl Portfolio {
+    pub fn new(allocations: Vec<Allocation>) -> Result<Self, PortfolioError> {
+        if allocations.is_empty() {
+            return Err(PortfolioError::Empty);
+        }
+        for (i, allocation) in allocations.iter().enumerate() {
+            if allocations[..i].iter().any(|prior| prior.name == allocation.name) {
+                return Err(PortfolioError::Duplicate(allocation.name));
+            }
+            if allocation.weight.is_nan() || allocation.weight <= 0.0 {
+                return Err(PortfolioError::NonPositiveWeight {
+                    name: allocation.name,
+                    weight: allocation.weight,
+                });
+            }
+        }
+        let sum: f64 = allocations.iter().map(|allocation| allocation.weight).sum();
+        if (sum - 1.0).abs() > WEIGHT_SUM_TOLERANCE {
+            return Err(PortfolioError::WeightSum(sum));
+        }
+
+        Ok(Portfolio { allocations })
+    }
+
+    pub fn allocations(&self) -> &[Allocation] {
+        &self.allocations
+    }
+
+    /// Weights the base yield of each pool, given in the order of the allocations.
+    ///
+    /// Panics unless there is one yield per allocation.
+    pub fn weighted_yield(&self, base_yields: &[f64]) -> PortfolioYield {
+        assert_eq!(base_yields.len(), self.allocations.len(), "one yield per allocation");
+        let pools: Vec<_> = self
+            .allocations
+            .iter()
+            .zip(base_yields)
+            .map(|(&allocation, &base_yield)| PoolYield { allocation, base_yield })
+            .collect();
+
+        PortfolioYield { base_yield: pools.iter().map(PoolYield::contribution).sum(), pools }
+    }
+}
+
+/// The base yield of one pool of a portfolio.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PoolYield {
+    pub allocation: Allocation,
+    pub base_yield: f64,
+}
+
+impl PoolYield {
+    /// What the pool adds to the portfolio yield.
+    pub fn contribution(&self) -> f64 {
+        self.allocation.weight * self.base_yield
+    }
+}
+
+/// The weights of the destinations from `block_number` on, until the next entry of the schedule.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllocationEntry {
+    pub block_number: u64,
+    /// In the order of the schedule's destinations.
+    pub weights: Vec<f64>,
+}
+
+/// An autopool's allocation to its destinations over time. The weights of every entry are
+/// non-negative, a destination it is out of having a weight of zero, and sum to about 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllocationSchedule {
+    destinations: Vec<&'static str>,
+    entries: Vec<AllocationEntry>,
+}
+
+impl AllocationSchedule {
+    pub fn new(
+        destinations: Vec<&'static str>,
+        entries: Vec<AllocationEntry>,
+    ) -> Result<Self, PortfolioError> {
+        if entries.is_empty() {
+            return Err(PortfolioError::EmptySchedule);
+        }
+        for (i, name) in destinations.iter().enumerate() {
+            if destinations[..i].contains(name) {
+                return Err(PortfolioError::Duplicate(name));
+            }
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            let block = entry.block_number;
+            if i > 0 && block <= entries[i - 1].block_number {
+                return Err(PortfolioError::NotIncreasing { block });
+            }
+            if entry.weights.len() != destinations.len() {
+                return Err(PortfolioError::WeightCount {
+                    block,
+                    expected: destinations.len(),
+                    found: entry.weights.len(),
+                });
+            }
+            for (&name, &weight) in destinations.iter().zip(&entry.weights) {
+                if weight.is_nan() {
+                    return Err(PortfolioError::NotANumber { name, block });
+                }
+                if weight < 0.0 {
+                    return Err(PortfolioError::NegativeWeight { name, block, weight });
+                }
+            }
+            let sum: f64 = entry.weights.iter().sum();
+            if (sum - 1.0).abs() > WEIGHT_SUM_TOLERANCE {
+                return Err(PortfolioError::WeightSum(sum));
+            }
+        }
+
+        Ok(AllocationSchedule { destinations, entries })
+    }
+
+    pub fn destinations(&self) -> &[&'static str] {
+        &self.destinations
+    }
+
+    /// The weight of each destination averaged over the blocks from `from` up to `to`, each entry
+    /// counting for the blocks it holds over. Fails if the schedule starts after `from`.
+    pub fn average_weights(&self, from: u64, to: u64) -> Result<Vec<f64>, PortfolioError> {
+        let first = self.entries[0].block_number;
+        if first > from {
+            return Err(PortfolioError::NotCovered { from, first });
+        }
+        if to <= from {
+            // a window of a single block holds the weights in force at it
+            let entry = self.entries.iter().rev().find(|entry| entry.block_number <= from);
+            return Ok(entry.unwrap().weights.clone());
+        }
+
+        let mut averages = vec![0.0; self.destinations.len()];
+        for (i, entry) in self.entries.iter().enumerate() {
+            let start = entry.block_number.max(from);
+            let end = self.entries.get(i + 1).map_or(to, |next| next.block_number.min(to));
+            if end <= start {
+                continue;
+            }
+            let share = (end - start) as f64 / (to - from) as f64;
+            for (average, weight) in averages.iter_mut().zip(&entry.weights) {
+                *average += weight * share;
+            }
+        }
+
+        Ok(averages)
+    }
+
+    /// The yield the autopool earned over the blocks from `from` to `to`, from the base yield of
+    /// each destination over them, in the order of the destinations.
+    ///
+    /// Panics unless there is one yield per destination.
+    pub fn blended_yield(
+        &self,
+        base_yields: &[f64],
+        from: u64,
+        to: u64,
+    ) -> Result<PortfolioYield, PortfolioError> {
+        assert_eq!(base_yields.len(), self.destinations.len(), "one yield per destination");
+        let pools: Vec<_> = self
+            .destinations
+            .iter()
+            .zip(self.average_weights(from, to)?)
+            .zip(base_yields)
+            .map(|((&name, weight), &base_yield)| PoolYield {
+                allocation: Allocation { name, weight },
+                base_yield,
+            })
+            .collect();
+
+        Ok(PortfolioYield { base_yield: pools.iter().map(PoolYield::contribution).sum(), pools })
+    }
+}
+
+/// The weighted yield of a portfolio with the per-pool breakdown.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PortfolioYield {
+    pub base_yield: f64,
+    pub pools: Vec<PoolYield>,
+}
+
+impl fmt::Display for PortfolioYield {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "portfolio yield {:.2}% (", self.base_yield * 100.0)?;
+        for (i, pool) in self.pools.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(
+                f,
+                "{} {:.2}% at {:.0}% weight",
+                pool.allocation.name,
+                pool.base_yield * 100.0,
+                pool.allocation.weight * 100.0
+            )?;
+        }
+        write!(f, ")")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocation(name: &'static str, weight: f64) -> Allocation {
+        Allocation { name, weight }
+    }
+
+    #[test]
+    fn it_should_weight_the_pool_yields_by_allocation() {
+        let portfolio = Portfolio::new(vec![allocation("cbeth", 0.6), allocation("reth", 0.4)]);
+        let weighted = portfolio.unwrap().weighted_yield(&[0.03, 0.035]);
+
+        // 0.6 * 3% + 0.4 * 3.5%, where a simple average would be 3.25%
+        assert!((weighted.base_yield - 0.032).abs() < 1e-12);
+        assert!((weighted.pools[1].contribution() - 0.014).abs() < 1e-12);
+        assert_eq!(
+            weighted.to_string(),
+            "portfolio yield 3.20% (cbeth 3.00% at 60% weight, reth 3.50% at 40% weight)"
+        );
+    }
+
+    #[test]
+    fn it_should_reject_invalid_weights() {
+        assert_eq!(Portfolio::new(vec![]), Err(PortfolioError::Empty));
+        assert_eq!(
+            Portfolio::new(vec![allocation("cbeth", 0.6), allocation("reth", 0.3)]),
+            Err(PortfolioError::WeightSum(0.8999999999999999))
+        );
+        assert_eq!(
+            Portfolio::new(vec![allocation("cbeth", 1.2), allocation("reth", -0.2)]),
+            Err(PortfolioError::NonPositiveWeight { name: "reth", weight: -0.2 })
+        );
+        assert_eq!(
+            Portfolio::new(vec![allocation("cbeth", 0.5), allocation("cbeth", 0.5)]),
+            Err(PortfolioError::Duplicate("cbeth"))
+        );
+        // rounded weights are fine
+        let thirds = ["cbeth", "reth", "steth"].map(|name| allocation(name, 0.333_333_3));
+        assert!(Portfolio::new(thirds.to_vec()).is_ok());
+    }
+
+    #[test]
+    fn it_should_blend_the_yields_of_shifting_weights() {
+        // the autopool moves from mostly cbETH to mostly rETH at block 400
+        let entry = |block_number, weights: [f64; 2]| AllocationEntry {
+            block_number,
+            weights: weights.to_vec(),
+        };
+        let schedule = AllocationSchedule::new(
+            vec!["cbeth", "reth"],
+            vec![entry(100, [0.8, 0.2]), entry(400, [0.2, 0.8])],
+        )
+        .unwrap();
+
+        // 3/4 of the window at 80/20, 1/4 at 20/80
+        let weights = schedule.average_weights(100, 500).unwrap();
+        assert!((weights[0] - 0.65).abs() < 1e-12 && (weights[1] - 0.35).abs() < 1e-12);
+        let blended = schedule.blended_yield(&[0.03, 0.04], 100, 500).unwrap();
+        assert!((blended.base_yield - (0.65 * 0.03 + 0.35 * 0.04)).abs() < 1e-12);
+        assert_eq!(
+            blended.to_string(),
+            "portfolio yield 3.35% (cbeth 3.00% at 65% weight, reth 4.00% at 35% weight)"
+        );
+        // after the move the rETH yield dominates
+        let later = schedule.blended_yield(&[0.03, 0.04], 400, 500).unwrap();
+        assert!((later.base_yield - 0.038).abs() < 1e-12);
+
+        assert_eq!(
+            schedule.average_weights(50, 500).unwrap_err(),
+            PortfolioError::NotCovered { from: 50, first: 100 }
+        );
+
+        let invalid =
+            |weights| AllocationSchedule::new(vec!["cbeth", "reth"], vec![entry(100, weights)]);
+        assert_eq!(
+            invalid([1.2, -0.2]).unwrap_err(),
+            PortfolioError::NegativeWeight { name: "reth", block: 100, weight: -0.2 }
+        );
+        assert_eq!(
+            invalid([f64::NAN, 1.0]).unwrap_err(),
+            PortfolioError::NotANumber { name: "cbeth", block: 100 }
+        );
+    }
+}
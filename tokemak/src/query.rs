@@ -0,0 +1,248 @@
+//! The view calls made against every sampled block, defined once for the host preflight and the
+//! guest. The guest can only execute calls the host preflighted, in state the host fetched for
+//! them, so the two must issue the same calls; both run a [`PoolQuerySet`] through their own
+//! [`ViewCaller`] rather than each assembling the calls of a sample.
+//!
+//! The query set also checks that every call targets a contract the journal commits to: the pool
+//! or the LST, which it names, or a price feed or the Convex reward pool, which it names or commits
+//! to through its params digest, see [`GuestParams::committed`]. The host still chooses these
+//! contracts, but a verifier can tell which they were from the journal. The multicall is the only
+//! other target, at an address the guest is built with.
+
+use alloy_primitives::{Address, U256};
+use alloy_sol_types::SolCall;
+
+use crate::{
+    aggregate::Aggregation,
+    backing::{BackingStrategy, ViewCaller},
+    convex::{IConvexRewardPool, RewardSample},
+    multicall::{self, IMulticall3, Recorder, MULTICALL3_ADDRESS},
+    pool_tvl, ChainlinkInterface, CurvePoolInterface, GuestParams, QueryMode,
+};
+
+/// The values queried at a sampled block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolQuery {
+    /// The ETH backing of the LST.
+    pub exchange_rate: U256,
+    /// The backing re-denominated with the price feeds, or the ETH backing before they exist.
+    pub backing: U256,
+    /// The pool's TVL in ETH, if queried.
+    pub tvl: Option<U256>,
+    /// The state of the Convex reward pool, if configured.
+    pub rewards: Option<RewardQuote>,
+}
+
+/// The Convex reward pool state at a sampled block, see [`RewardSample`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RewardQuote {
+    pub reward_rate: U256,
+    pub total_supply: U256,
+    pub period_finish: U256,
+    pub virtual_price: U256,
+    pub reward_price: U256,
+}
+
+impl RewardQuote {
+    /// The reward sample of the block at `timestamp`.
+    pub fn at(self, timestamp: u64) -> RewardSample {
+        RewardSample {
+            timestamp,
+            reward_rate: self.reward_rate,
+            total_supply: self.total_supply,
+            period_finish: self.period_finish,
+            virtual_price: self.virtual_price,
+            reward_price: self.reward_price,
+        }
+    }
+}
+
+/// The ordered set of view calls of a sample under the guest params: the backing strategy's,
+/// bundled into a multicall or not, the price feeds once they exist, the Convex reward pool and,
+/// optionally, the pool's balances.
+#[derive(Debug, Clone, Copy)]
+pub struct PoolQuerySet<'a> {
+    params: &'a GuestParams,
+    tvl: bool,
+}
+
+impl<'a> PoolQuerySet<'a> {
+    /// The query set of `params`, querying the TVL if the stats are TVL-weighted.
+    pub fn new(params: &'a GuestParams) -> Self {
+        PoolQuerySet { params, tvl: params.stats.aggregation == Aggregation::TvlWeighted }
+    }
+
+    /// Also queries the pool's TVL, or doesn't.
+    pub fn with_tvl(self, tvl: bool) -> Self {
+        PoolQuerySet { tvl, ..self }
+    }
+
+    /// The calls made at block `block_number`, in order, as multicall sub-calls of their target
+    /// and calldata.
+    pub fn calls(&self, block_number: u64) -> Vec<IMulticall3::Call3> {
+        let mut recorder = Recorder::default();
+        self.query(block_number, &mut recorder).unwrap();
+
+        recorder.calls
+    }
+
+    /// Queries the sample at block `block_number` through `caller`.
+    pub fn query<V: ViewCaller>(
+        &self,
+        block_number: u64,
+        caller: &mut V,
+    ) -> Result<PoolQuery, V::Error> {
+        let params = self.params;
+        let pool = &params.pool;
+        let exchange_rate = match params.query_mode {
+            QueryMode::Individual => {
+                let mut caller = CommittedCaller { caller: &mut *caller, params };
+                pool.backing.backing(pool.lst, &mut caller)?
+            }
+            QueryMode::Multicall => {
+                let calls = multicall::backing_calls(pool);
+                multicall::verify_targets(&calls, pool);
+                multicall::decode_backing(pool, &caller.call(MULTICALL3_ADDRESS, calls)?)
+            }
+        };
+        let mut caller = CommittedCaller { caller, params };
+        // the weight of the interval the sample starts, valued at the ETH backing
+        let tvl = if self.tvl {
+            let balance = |coin: u64| CurvePoolInterface::balancesCall { _0: U256::from(coin) };
+            let eth_balance = caller.call(pool.pool, balance(0))?._0;
+            let lst_balance = caller.call(pool.pool, balance(1))?._0;
+            Some(pool_tvl(eth_balance, lst_balance, exchange_rate))
+        } else {
+            None
+        };
+
+        // before the feeds exist, the sample keeps its ETH backing, and the guest leaves it out
+        let denominated = params.denominated_from.map_or(true, |from| block_number >= from);
+        let backing = match params.price_feed.filter(|_| denominated) {
+            Some(feed) => {
+                let answer =
+                    caller.call(feed.address, ChainlinkInterface::latestRoundDataCall {})?;
+                feed.denominate(exchange_rate, answer._1)
+            }
+            None => exchange_rate,
+        };
+        let backing = match params.reference_feed.filter(|_| denominated) {
+            Some(feed) => {
+                let answer =
+                    caller.call(feed.address, ChainlinkInterface::latestRoundDataCall {})?;
+                feed.to_base(backing, answer._1)
+            }
+            None => backing,
+        };
+
+        // the reward stream and what it is paid on, priced in ETH
+        let rewards = match params.convex {
+            Some(convex) => {
+                let reward_pool = convex.reward_pool;
+                let reward_rate =
+                    caller.call(reward_pool, IConvexRewardPool::rewardRateCall {})?._0;
+                let total_supply =
+                    caller.call(reward_pool, IConvexRewardPool::totalSupplyCall {})?._0;
+                let period_finish =
+                    caller.call(reward_pool, IConvexRewardPool::periodFinishCall {})?._0;
+                let virtual_price =
+                    caller.call(pool.pool, CurvePoolInterface::get_virtual_priceCall {})?._0;
+                let answer = caller
+                    .call(convex.reward_feed.address, ChainlinkInterface::latestRoundDataCall {})?;
+                Some(RewardQuote {
+                    reward_rate,
+                    total_supply,
+                    period_finish,
+                    virtual_price,
+                    reward_price: convex.reward_feed.scale_answer(answer._1),
+                })
+            }
+            None => None,
+        };
+
+        Ok(PoolQuery { exchange_rate, backing, tvl, rewards })
+    }
+}
+
+/// Makes calls that must each target a contract the journal commits to, see
+/// [`GuestParams::verify_target`].
+struct CommittedCaller<'a, V> {
+    caller: &'a mut V,
+    params: &'a GuestParams,
+}
+
+impl<V: ViewCaller> ViewCaller for CommittedCaller<'_, V> {
+    type Error = V::Error;
+
+    fn call<C: SolCall>(&mut self, target: Address, call: C) -> Result<C::Return, V::Error> {
+        self.params.verify_target(target);
+        self.caller.call(target, call)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        backing::BackingKind, cbETHInterface, convex::ConvexRewards, oracle::PriceFeed,
+        DexStatsParams, PoolConfig, CBETH_ADDRESS,
+    };
+
+    fn params() -> GuestParams {
+        GuestParams {
+            pool: PoolConfig::CBETH_ETH,
+            query_mode: QueryMode::Individual,
+            stats: DexStatsParams { aggregation: Aggregation::TvlWeighted, ..Default::default() },
+            price_feed: Some(PriceFeed { address: Address::repeat_byte(0xf1), decimals: 8 }),
+            reference_feed: None,
+            denominated_from: Some(1_000),
+            finality_depth: None,
+            convex: Some(ConvexRewards {
+                reward_pool: Address::repeat_byte(0xc0),
+                reward_feed: PriceFeed { address: Address::repeat_byte(0xf2), decimals: 18 },
+            }),
+        }
+    }
+
+    /// The target and selector of every call.
+    fn signatures(calls: &[IMulticall3::Call3]) -> Vec<(Address, [u8; 4])> {
+        calls.iter().map(|call| (call.target, call.callData[..4].try_into().unwrap())).collect()
+    }
+
+    #[test]
+    fn it_should_emit_the_calls_of_the_config() {
+        let params = params();
+        let pool = PoolConfig::CBETH_ETH.pool;
+        let latest_round = ChainlinkInterface::latestRoundDataCall::SELECTOR;
+
+        assert_eq!(
+            signatures(&PoolQuerySet::new(&params).calls(1_000)),
+            vec![
+                (CBETH_ADDRESS, cbETHInterface::exchangeRateCall::SELECTOR),
+                (pool, CurvePoolInterface::balancesCall::SELECTOR),
+                (pool, CurvePoolInterface::balancesCall::SELECTOR),
+                (Address::repeat_byte(0xf1), latest_round),
+                (Address::repeat_byte(0xc0), IConvexRewardPool::rewardRateCall::SELECTOR),
+                (Address::repeat_byte(0xc0), IConvexRewardPool::totalSupplyCall::SELECTOR),
+                (Address::repeat_byte(0xc0), IConvexRewardPool::periodFinishCall::SELECTOR),
+                (pool, CurvePoolInterface::get_virtual_priceCall::SELECTOR),
+                (Address::repeat_byte(0xf2), latest_round),
+            ]
+        );
+
+        // before the feed exists, without the TVL and bundled into a multicall
+        let params = GuestParams { query_mode: QueryMode::Multicall, convex: None, ..params };
+        let calls = PoolQuerySet::new(&params).with_tvl(false).calls(999);
+        assert_eq!(
+            signatures(&calls),
+            vec![(MULTICALL3_ADDRESS, IMulticall3::aggregate3Call::SELECTOR)]
+        );
+
+        let params = GuestParams {
+            pool: PoolConfig { backing: BackingKind::RedemptionRate, ..PoolConfig::CBETH_ETH },
+            query_mode: QueryMode::Individual,
+            ..params
+        };
+        assert_eq!(PoolQuerySet::new(&params).with_tvl(false).calls(999).len(), 1);
+    }
+}
@@ -0,0 +1,106 @@
+//! An APY reported on chain, e.g. by Tokemak or the pool itself, to sanity-check the computed
+//! yield against.
+//!
+//! The two are rarely computed the same way: the reference may look back over another window,
+//! include fees or incentives, or compound differently. A gap of some basis points is expected;
+//! only a large divergence points at a problem on either side.
+
+use core::fmt;
+
+use alloy_primitives::Address;
+use alloy_sol_types::sol;
+
+use crate::{backing::ViewCaller, u256_to_f64};
+
+sol! {
+    interface IReferenceApy {
+        // the APY as an 18-decimal fraction, e.g. 0.035e18 for 3.5%
+        function apy() external view returns (uint256);
+    }
+}
+
+/// A contract exposing [`IReferenceApy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReferenceApy {
+    pub contract: Address,
+}
+
+impl ReferenceApy {
+    /// Queries the reference APY, as a fraction.
+    pub fn query<V: ViewCaller>(&self, caller: &mut V) -> Result<f64, V::Error> {
+        let apy = caller.call(self.contract, IReferenceApy::apyCall {})?._0;
+
+        Ok(u256_to_f64(apy, 18))
+    }
+}
+
+/// The computed yield next to the reference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ApyComparison {
+    /// The computed yield as an APY, compounded at the sampling frequency, rather than the simple
+    /// rate the headline yield is by default.
+    pub computed: f64,
+    pub reference: f64,
+}
+
+impl ApyComparison {
+    /// How much higher the computed yield is than the reference.
+    pub fn difference(&self) -> f64 {
+        self.computed - self.reference
+    }
+
+    /// Whether the two are further apart than `tolerance`, an absolute difference in yield.
+    pub fn diverges(&self, tolerance: f64) -> bool {
+        self.difference().abs() > tolerance
+    }
+}
+
+impl fmt::Display for ApyComparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "computed {:.2}% vs reference {:.2}%, {:+.1} bps",
+            self.computed * 100.0,
+            self.reference * 100.0,
+            self.difference() * 10_000.0
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::U256;
+    use alloy_sol_types::SolCall;
+    use std::convert::Infallible;
+
+    /// A reference contract reporting a fixed APY.
+    struct MockReference {
+        contract: Address,
+        apy: U256,
+    }
+
+    impl ViewCaller for MockReference {
+        type Error = Infallible;
+
+        fn call<C: SolCall>(&mut self, target: Address, _: C) -> Result<C::Return, Infallible> {
+            assert_eq!((target, C::SELECTOR), (self.contract, IReferenceApy::apyCall::SELECTOR));
+            Ok(C::abi_decode_returns(&self.apy.to_be_bytes::<32>(), true).unwrap())
+        }
+    }
+
+    #[test]
+    fn it_should_compare_to_the_reference_apy() {
+        let contract = Address::repeat_byte(0xa9);
+        let mut caller = MockReference { contract, apy: U256::from(35_000_000_000_000_000_u64) };
+
+        let reference = ReferenceApy { contract }.query(&mut caller).unwrap();
+        assert_eq!(reference, 0.035);
+
+        let comparison = ApyComparison { computed: 0.0365, reference };
+        assert!((comparison.difference() - 0.0015).abs() < 1e-12);
+        assert!(comparison.diverges(0.001));
+        assert!(!comparison.diverges(0.005));
+        assert_eq!(comparison.to_string(), "computed 3.65% vs reference 3.50%, +15.0 bps");
+    }
+}
@@ -0,0 +1,83 @@
+//! 18-decimal fixed-point arithmetic on U256, the representation of backings, prices and balances.
+//!
+//! Products are formed in a 512-bit intermediate before dividing, so combining two large
+//! 18-decimal quantities neither overflows midway nor loses its low digits to an early division.
+
+use alloy_primitives::{U256, U512};
+
+/// One, as an 18-decimal fixed-point number.
+pub const WAD: U256 = U256::from_limbs([1_000_000_000_000_000_000, 0, 0, 0]);
+
+/// Multiplies two 18-decimal numbers, `a * b / 1e18`, rounding down.
+///
+/// Panics if the result doesn't fit in a U256.
+pub fn mul_wad(a: U256, b: U256) -> U256 {
+    mul_div(a, b, WAD)
+}
+
+/// Divides two 18-decimal numbers, `a * 1e18 / b`, rounding down.
+///
+/// Panics if `b` is zero or the result doesn't fit in a U256.
+pub fn div_wad(a: U256, b: U256) -> U256 {
+    mul_div(a, WAD, b)
+}
+
+/// Computes `a * b / denominator` with a 512-bit intermediate, rounding down.
+///
+/// Panics if `denominator` is zero or the result doesn't fit in a U256.
+pub fn mul_div(a: U256, b: U256, denominator: U256) -> U256 {
+    assert!(!denominator.is_zero(), "fixed-point division by zero");
+    let product = U512::from(a) * U512::from(b);
+
+    U256::uint_try_from(product / U512::from(denominator)).expect("fixed-point result overflows")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wad(value: u128) -> U256 {
+        U256::from(value) * WAD
+    }
+
+    #[test]
+    fn it_should_multiply_and_divide_fixed_point_numbers() {
+        let rate = U256::from(1_050_000_000_000_000_000_u64);
+        assert_eq!(mul_wad(wad(40), rate), wad(42));
+        assert_eq!(div_wad(wad(42), rate), wad(40));
+        assert_eq!(mul_wad(rate, WAD), rate);
+    }
+
+    #[test]
+    fn it_should_keep_full_precision() {
+        // (1 + 1e-18)^2 = 1 + 2e-18 + 1e-36, of which the last term is below the precision
+        let tick = WAD + U256::from(1);
+        assert_eq!(mul_wad(tick, tick), WAD + U256::from(2));
+
+        // dividing by 1e18 before multiplying would round 0.5 * 0.5 down to zero
+        let half = WAD / U256::from(2);
+        assert_eq!(mul_wad(half, half), U256::from(250_000_000_000_000_000_u64));
+        assert_eq!(div_wad(WAD, wad(3)), U256::from(333_333_333_333_333_333_u64));
+    }
+
+    #[test]
+    fn it_should_not_overflow_in_the_intermediate() {
+        // the product of these exceeds a U256, the result doesn't
+        assert_eq!(mul_wad(U256::MAX, WAD), U256::MAX);
+        assert_eq!(div_wad(U256::MAX, U256::MAX), WAD);
+        let large = U256::MAX / U256::from(3);
+        assert_eq!(mul_div(large, wad(3), wad(3)), large);
+    }
+
+    #[test]
+    #[should_panic(expected = "fixed-point result overflows")]
+    fn it_should_reject_results_beyond_u256() {
+        mul_wad(U256::MAX, wad(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "fixed-point division by zero")]
+    fn it_should_reject_division_by_zero() {
+        div_wad(WAD, U256::ZERO);
+    }
+}